@@ -0,0 +1,52 @@
+// Per-pixel convergence for adaptive sampling, appended after the
+// adaptive sampling bindings. The running mean and variance of the
+// sample luminance are kept with Welford's algorithm in a float
+// target next to the accumulation target.
+
+const PIXEL_STATS_EPSILON: f32 = 1e-4;
+
+struct PixelStats {
+    mean: f32,
+    // sum of squared differences from the mean
+    m2: f32,
+    count: u32,
+};
+
+// Statistics of all earlier samples, empty on the first iteration
+fn pixel_stats_load(pixel: vec2u) -> PixelStats {
+    if (uniforms.iteration == 0u) {
+        return PixelStats(0.0, 0.0, 0u);
+    }
+    let stats = textureLoad(varianceTexture, pixel, 0);
+    return PixelStats(stats.x, stats.y, u32(stats.z));
+}
+
+// Standard error of the mean relative to the mean
+fn pixel_relative_error(stats: PixelStats) -> f32 {
+    if (stats.count < 2u) {
+        return 1e30;
+    }
+    let n = f32(stats.count);
+    let variance = stats.m2 / (n - 1.0);
+    return sqrt(variance / n) / max(stats.mean, PIXEL_STATS_EPSILON);
+}
+
+// A threshold of zero disables adaptive sampling
+fn pixel_converged(stats: PixelStats) -> bool {
+    return adaptive.threshold > 0.0
+        && stats.count >= adaptive.min_samples
+        && pixel_relative_error(stats) < adaptive.threshold;
+}
+
+fn pixel_stats_add(stats: PixelStats, radiance: vec3f) -> PixelStats {
+    let x = dot(radiance, vec3f(0.2126, 0.7152, 0.0722));
+    let count = stats.count + 1u;
+    let delta = x - stats.mean;
+    let mean = stats.mean + delta / f32(count);
+    let m2 = stats.m2 + delta * (x - mean);
+    return PixelStats(mean, m2, count);
+}
+
+fn pixel_stats_output(stats: PixelStats) -> vec4f {
+    return vec4f(stats.mean, stats.m2, f32(stats.count), 0.0);
+}
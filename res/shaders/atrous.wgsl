@@ -0,0 +1,62 @@
+// Edge-avoiding à-trous wavelet denoiser, one iteration per pass
+// [Dammertz et al., Edge-Avoiding À-Trous Wavelet Transform
+//  for fast Global Illumination Filtering, HPG 2010]
+// Keep in sync with the CPU version in src/denoise.rs
+
+#import fullscreen
+
+fn distance_squared(a: vec3f, b: vec3f) -> f32 {
+    let d = a - b;
+    return dot(d, d);
+}
+
+// A sigma of zero or less disables the guide
+fn edge_stop(distance_squared: f32, sigma: f32) -> f32 {
+    if (sigma > 0.0) {
+        return exp(-distance_squared / (sigma * sigma));
+    }
+    return 1.0;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // B3 spline
+    var kernel = array<f32, 5>(1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0);
+
+    let size = vec2i(textureDimensions(denoiseInput));
+    let p = vec2i(in.clip_position.xy);
+    let step = 1 << denoise.iteration;
+    let sigma_color = denoise.sigma_color * exp2(-f32(denoise.iteration));
+
+    let color_p = textureLoad(denoiseInput, p, 0).rgb;
+    let albedo_p = textureLoad(aovAlbedo, p, 0).rgb;
+    let normal_p = textureLoad(aovNormal, p, 0).xyz;
+    let depth_p = textureLoad(aovDepth, p, 0).r;
+
+    var sum = vec3f(0.0);
+    var weight_sum = 0.0;
+    for (var j = 0; j < 5; j++) {
+        for (var i = 0; i < 5; i++) {
+            let q = p + vec2i(i - 2, j - 2) * step;
+            if (any(q < vec2i(0)) || any(q >= size)) {
+                continue;
+            }
+            let color_q = textureLoad(denoiseInput, q, 0).rgb;
+            let albedo_q = textureLoad(aovAlbedo, q, 0).rgb;
+            let normal_q = textureLoad(aovNormal, q, 0).xyz;
+            let depth_q = textureLoad(aovDepth, q, 0).r;
+            let relative_depth = abs(depth_p - depth_q) / max(abs(depth_p), 1e-4);
+
+            let weight = kernel[i] * kernel[j]
+                * edge_stop(distance_squared(color_p, color_q), sigma_color)
+                * edge_stop(distance_squared(albedo_p, albedo_q), denoise.sigma_albedo)
+                * edge_stop(distance_squared(normal_p, normal_q), denoise.sigma_normal)
+                * edge_stop(relative_depth * relative_depth, denoise.sigma_depth);
+
+            sum += weight * color_q;
+            weight_sum += weight;
+        }
+    }
+
+    return vec4f(sum / weight_sum, 1.0);
+}
@@ -28,7 +28,7 @@ fn intersect_trimesh(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> bo
             for (var j = 0u; j < node_count; j++) {
                 let obj_idx = treeIds[node_id + j];
 
-                if (intersect_triangle_indexed(r, hit, obj_idx)) {
+                if (intersect_primitive(r, hit, obj_idx)) {
                     (*r).tmax = (*hit).dist;
                     found = true;
                 }
@@ -80,6 +80,8 @@ fn intersect_trimesh(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> bo
     return false;
 }
 
+// Any hit between tmin and tmax, the traversal stops at the first
+// triangle hit, enough for shadow rays. The BVH has one as well.
 fn intersect_trimesh_immediate_return(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> bool
 {
     var branch_lvl: u32 = 0u;
@@ -96,21 +98,17 @@ fn intersect_trimesh_immediate_return(r: ptr<function, Ray>, hit: ptr<function,
             // A leaf was found
             let node_count = tree_node.x>>2u;
             let node_id = tree_node.y;
-            var found = false;
-            
+
             for (var j = 0u; j < node_count; j++) {
                 let obj_idx = treeIds[node_id + j];
 
-                if (intersect_triangle_indexed(r, hit, obj_idx)) {
+                if (intersect_primitive(r, hit, obj_idx)) {
                     (*r).tmax = (*hit).dist;
                     return true;
-                    //found = true;
                 }
             }
 
-            if (found) {
-                return true;
-            } else if (branch_lvl == 0u) {
+            if (branch_lvl == 0u) {
                 return false;
             } else {
                 branch_lvl--;
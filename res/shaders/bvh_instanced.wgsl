@@ -0,0 +1,120 @@
+// Two-level traversal of instancing::TwoLevelBvh, the nodes of both levels are in bvh_node.wgsl.
+// The ray is moved into the object space of every instance whose box it hits, its direction
+// is not normalized there, so the hit distances are the same in both spaces.
+
+const MAX_LEVEL = 50u;
+const TLAS_MAX_LEVEL = 32u;
+
+//var<storage> bvh_triangles: array<u32>;
+//var<storage> tlas_nodes: array<BvhNode>;
+//var<storage> instances: array<Instance>; // Instance is generated from instancing::GpuInstance
+
+var<private> node_stack: array<u32, MAX_LEVEL>;
+var<private> tlas_stack: array<u32, TLAS_MAX_LEVEL>;
+
+// Bottom-level BVH below `root`, like intersect_bvh in bvh.wgsl
+fn intersect_blas(r: ptr<function, Ray>, hit: ptr<function, HitRecord>, root: u32, any_hit: bool) -> bool {
+    let ray_dir_inv = 1.0 / (*r).direction;
+    let ray_orig = (*r).origin;
+    var top = 1u;
+    node_stack[0] = root;
+    var found = false;
+    for (var step = 0u; step < 1000u && top > 0u; step++) {
+        top -= 1u;
+        let current_node_index = node_stack[top];
+        let current_node = bvh_nodes[current_node_index];
+        if (intersect_bb2(ray_dir_inv, ray_orig, current_node, (*r).tmax)) {
+            let offset = current_node.offset_ptr;
+            let n_primitives = node_primitives(current_node);
+            if (n_primitives > 0u) {
+                for (var i = 0u; i < n_primitives; i++) {
+                    if (intersect_primitive(r, hit, bvh_triangles[offset + i])) {
+                        (*r).tmax = (*hit).dist;
+                        found = true;
+                        if (any_hit) {
+                            return true;
+                        }
+                    }
+                }
+            } else if ((*r).direction[node_axis(current_node)] < 0.0) {
+                node_stack[top] = current_node_index + 1u;
+                node_stack[top + 1u] = offset;
+                top += 2u;
+            } else {
+                node_stack[top] = offset;
+                node_stack[top + 1u] = current_node_index + 1u;
+                top += 2u;
+            }
+        }
+    }
+    return found;
+}
+
+// Moves the ray into object space for the bottom-level traversal
+// and the closer hit it found back into world space
+fn intersect_instance(r: ptr<function, Ray>, hit: ptr<function, HitRecord>, instance: Instance, any_hit: bool) -> bool {
+    let world_to_object = instance.world_to_object;
+    var object_ray = *r;
+    object_ray.origin = world_to_object * vec4f((*r).origin, 1.0);
+    object_ray.direction = world_to_object * vec4f((*r).direction, 0.0);
+    if (!intersect_blas(&object_ray, hit, instance.blas_root, any_hit)) {
+        return false;
+    }
+    (*r).tmax = object_ray.tmax;
+    (*hit).position = ray_at(*r, (*hit).dist);
+    // normals go with the transpose of the inverse, row vector times matrix
+    let linear = mat3x3f(world_to_object[0], world_to_object[1], world_to_object[2]);
+    (*hit).normal = normalize((*hit).normal * linear);
+    return true;
+}
+
+// Top-level BVH, its leaves point into the instances
+fn intersect_bvh_instanced(r: ptr<function, Ray>, hit: ptr<function, HitRecord>, any_hit: bool) -> bool {
+    let ray_dir_inv = 1.0 / (*r).direction;
+    let ray_orig = (*r).origin;
+    var top = 1u;
+    tlas_stack[0] = 0u;
+    var found = false;
+    for (var step = 0u; step < 1000u && top > 0u; step++) {
+        top -= 1u;
+        let current_node_index = tlas_stack[top];
+        let current_node = tlas_nodes[current_node_index];
+        if (intersect_bb2(ray_dir_inv, ray_orig, current_node, (*r).tmax)) {
+            let offset = current_node.offset_ptr;
+            let n_instances = node_primitives(current_node);
+            if (n_instances > 0u) {
+                for (var i = 0u; i < n_instances; i++) {
+                    if (intersect_instance(r, hit, instances[offset + i], any_hit)) {
+                        found = true;
+                        if (any_hit) {
+                            return true;
+                        }
+                    }
+                }
+            } else if ((*r).direction[node_axis(current_node)] < 0.0) {
+                tlas_stack[top] = current_node_index + 1u;
+                tlas_stack[top + 1u] = offset;
+                top += 2u;
+            } else {
+                tlas_stack[top] = offset;
+                tlas_stack[top + 1u] = current_node_index + 1u;
+                top += 2u;
+            }
+        }
+    }
+    return found;
+}
+
+fn intersect_trimesh(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> bool {
+    return intersect_bvh_instanced(r, hit, false);
+}
+
+// Any hit between tmin and tmax, like the one of the BSP tree
+fn intersect_trimesh_immediate_return(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> bool {
+    return intersect_bvh_instanced(r, hit, true);
+}
+
+fn intersect_min_max(r: ptr<function, Ray>) -> bool
+{
+    return true;
+}
@@ -0,0 +1,98 @@
+// Traversal of the quantized BVH4 of quantized_bvh::GpuQuantizedNode. A node stores
+// the boxes of its children in one byte per bound, relative to its own box.
+
+//var<storage> bvh_nodes: array<BvhQuantizedNode>; // BvhQuantizedNode is generated from quantized_bvh::GpuQuantizedNode
+//var<storage> bvh_triangles: array<u32>;
+
+// Has to match quantized_bvh::QUANTIZED_INTERIOR
+const QUANTIZED_INTERIOR = 255u;
+// Every node pushes up to three more nodes than it pops
+const QUANTIZED_STACK_SIZE = 64u;
+
+var<private> quantized_stack: array<u32, QUANTIZED_STACK_SIZE>;
+
+// Byte `slot` of every component
+fn quantized_bytes(v: vec3u, slot: u32) -> vec3u {
+    return (v >> vec3u(8u * slot)) & vec3u(0xffu);
+}
+
+// Steps along x, y and z, the powers of two are built from their bits
+// like quantized_bvh::step, the exponents are already biased by 127
+fn quantized_steps(node: BvhQuantizedNode) -> vec3f {
+    let exponents = (vec3u(node.exponents) >> vec3u(0u, 8u, 16u)) & vec3u(0xffu);
+    return bitcast<vec3f>(exponents << vec3u(23u));
+}
+
+// Decodes a bound like QuantizationFrame::decode, the product with
+// a power of two is exact, so only the addition rounds, like on the CPU
+fn quantized_decode(node: BvhQuantizedNode, steps: vec3f, q: vec3u) -> vec3f {
+    return node.origin + vec3f(q) * steps;
+}
+
+fn intersect_quantized_box(ray_dir_inv: vec3f, ray_orig: vec3f, bbox_min: vec3f, bbox_max: vec3f, t_max: f32) -> bool {
+    let near = (bbox_min - ray_orig) * ray_dir_inv;
+    let far = (bbox_max - ray_orig) * ray_dir_inv;
+    let t_near = min(near, far);
+    let t_far = max(near, far);
+    let t0 = max(max(t_near.x, t_near.y), max(t_near.z, 0.0));
+    let t1 = min(min(t_far.x, t_far.y), min(t_far.z, t_max));
+    return t0 <= t1;
+}
+
+fn intersect_bvh_quantized(r: ptr<function, Ray>, hit: ptr<function, HitRecord>, any_hit: bool) -> bool {
+    let ray_dir_inv = 1.0 / (*r).direction;
+    let ray_orig = (*r).origin;
+    var found = false;
+    quantized_stack[0] = 0u;
+    var stack_top = 1u;
+    // like the depth limit of intersect_bvh, this keeps a broken tree from hanging the GPU
+    for (var step = 0u; step < 1000u && stack_top > 0u; step++) {
+        stack_top -= 1u;
+        let node = bvh_nodes[quantized_stack[stack_top]];
+        let steps = quantized_steps(node);
+        for (var slot = 0u; slot < 4u; slot++) {
+            let info = (node.child_info >> (8u * slot)) & 0xffu;
+            if (info == 0u) {
+                continue;
+            }
+            let bbox_min = quantized_decode(node, steps, quantized_bytes(node.child_min, slot));
+            let bbox_max = quantized_decode(node, steps, quantized_bytes(node.child_max, slot));
+            if (!intersect_quantized_box(ray_dir_inv, ray_orig, bbox_min, bbox_max, (*r).tmax)) {
+                continue;
+            }
+            let child = node.children[slot];
+            if (info == QUANTIZED_INTERIOR) {
+                if (stack_top < QUANTIZED_STACK_SIZE) {
+                    quantized_stack[stack_top] = child;
+                    stack_top += 1u;
+                }
+                continue;
+            }
+            for (var i = 0u; i < info; i++) {
+                let obj_idx = bvh_triangles[child+i];
+                if(intersect_primitive(r, hit, obj_idx)) {
+                    (*r).tmax = (*hit).dist;
+                    found = true;
+                    if (any_hit) {
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+    return found;
+}
+
+fn intersect_trimesh(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> bool {
+    return intersect_bvh_quantized(r, hit, false);
+}
+
+// Any hit between tmin and tmax, like the one of the BSP tree
+fn intersect_trimesh_immediate_return(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> bool {
+    return intersect_bvh_quantized(r, hit, true);
+}
+
+fn intersect_min_max(r: ptr<function, Ray>) -> bool
+{
+    return true;
+}
@@ -0,0 +1,58 @@
+// Stackless traversal of hlbvh::BvhLayout::Threaded, the nodes are in bvh_node.wgsl
+// The nodes are in depth first order and the offset of an interior node points
+// past its subtree, so a missed box skips to it and anything else goes to the
+// next node. The traversal ends when the index leaves the array.
+
+//var<storage> bvh_triangles: array<u32>;
+
+// Children are always visited in order, the closest hit is found by shrinking tmax
+fn intersect_bvh_threaded(r: ptr<function, Ray>, hit: ptr<function, HitRecord>, any_hit: bool) -> bool {
+    let ray_dir_inv = 1.0 / (*r).direction;
+    let ray_orig = (*r).origin;
+    let n_nodes = arrayLength(&bvh_nodes);
+    var current_node_index = 0u;
+    var found = false;
+    // every step moves forward, so n_nodes steps are enough. Without the bound
+    // llvmpipe kept some invocations spinning and got wrong results
+    for (var step = 0u; step < n_nodes; step++) {
+        if (current_node_index >= n_nodes) {
+            break;
+        }
+        let current_node = bvh_nodes[current_node_index];
+        let offset = current_node.offset_ptr;
+        let n_primitives = node_primitives(current_node);
+        if (intersect_bb2(ray_dir_inv, ray_orig, current_node, (*r).tmax)) {
+            for (var i = 0u; i < n_primitives; i++) {
+                let obj_idx = bvh_triangles[offset+i];
+                if(intersect_primitive(r, hit, obj_idx)) {
+                    (*r).tmax = (*hit).dist;
+                    found = true;
+                    if (any_hit) {
+                        return true;
+                    }
+                }
+            }
+            current_node_index += 1u;
+        // leaves continue with the next node, interior nodes skip their subtree
+        } else if (n_primitives > 0u) {
+            current_node_index += 1u;
+        } else {
+            current_node_index = offset;
+        }
+    }
+    return found;
+}
+
+fn intersect_trimesh(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> bool {
+    return intersect_bvh_threaded(r, hit, false);
+}
+
+// Any hit between tmin and tmax, like the one of the BSP tree
+fn intersect_trimesh_immediate_return(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> bool {
+    return intersect_bvh_threaded(r, hit, true);
+}
+
+fn intersect_min_max(r: ptr<function, Ray>) -> bool
+{
+    return true;
+}
@@ -0,0 +1,82 @@
+// Traversal of the wide BVH of hlbvh::Bvh::collapse. The children of a node are
+// a range of records, so every node fetched tests the boxes of all its children.
+
+//var<storage> bvh_nodes: array<BvhWideNode>; // BvhWideNode is generated from hlbvh::GpuWideNode
+//var<storage> bvh_triangles: array<u32>;
+
+// Has to match hlbvh::WIDE_NODE_INTERIOR
+const WIDE_NODE_INTERIOR = 0x80000000u;
+// Ranges are pushed as first_record << WIDE_COUNT_BITS | number_of_records,
+// hlbvh::MAX_BVH_WIDTH has to fit into the lower bits
+const WIDE_COUNT_BITS = 4u;
+// Every node pushes up to width - 1 more ranges than it pops,
+// the BVH8 of the teapot needs 35
+const WIDE_STACK_SIZE = 96u;
+
+var<private> wide_stack: array<u32, WIDE_STACK_SIZE>;
+
+fn intersect_wide_box(ray_dir_inv: vec3f, ray_orig: vec3f, node: BvhWideNode, t_max: f32) -> bool {
+    let near = (node.bbox_min - ray_orig) * ray_dir_inv;
+    let far = (node.bbox_max - ray_orig) * ray_dir_inv;
+    let t_near = min(near, far);
+    let t_far = max(near, far);
+    let t0 = max(max(t_near.x, t_near.y), max(t_near.z, 0.0));
+    let t1 = min(min(t_far.x, t_far.y), min(t_far.z, t_max));
+    return t0 <= t1;
+}
+
+fn intersect_bvh_wide(r: ptr<function, Ray>, hit: ptr<function, HitRecord>, any_hit: bool) -> bool {
+    let ray_dir_inv = 1.0 / (*r).direction;
+    let ray_orig = (*r).origin;
+    var found = false;
+    // the range of the root record
+    wide_stack[0] = 1u;
+    var stack_top = 1u;
+    // like the depth limit of intersect_bvh, this keeps a broken tree from hanging the GPU
+    for (var step = 0u; step < 1000u && stack_top > 0u; step++) {
+        stack_top -= 1u;
+        let range = wide_stack[stack_top];
+        let first = range >> WIDE_COUNT_BITS;
+        let last = first + (range & ((1u << WIDE_COUNT_BITS) - 1u));
+        for (var child = first; child < last; child++) {
+            let node = bvh_nodes[child];
+            if (!intersect_wide_box(ray_dir_inv, ray_orig, node, (*r).tmax)) {
+                continue;
+            }
+            let offset = node.offset_ptr;
+            let entries = node.count & ~WIDE_NODE_INTERIOR;
+            if ((node.count & WIDE_NODE_INTERIOR) != 0u) {
+                if (stack_top < WIDE_STACK_SIZE) {
+                    wide_stack[stack_top] = (offset << WIDE_COUNT_BITS) | entries;
+                    stack_top += 1u;
+                }
+                continue;
+            }
+            for (var i = 0u; i < entries; i++) {
+                let obj_idx = bvh_triangles[offset+i];
+                if(intersect_primitive(r, hit, obj_idx)) {
+                    (*r).tmax = (*hit).dist;
+                    found = true;
+                    if (any_hit) {
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+    return found;
+}
+
+fn intersect_trimesh(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> bool {
+    return intersect_bvh_wide(r, hit, false);
+}
+
+// Any hit between tmin and tmax, like the one of the BSP tree
+fn intersect_trimesh_immediate_return(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> bool {
+    return intersect_bvh_wide(r, hit, true);
+}
+
+fn intersect_min_max(r: ptr<function, Ray>) -> bool
+{
+    return true;
+}
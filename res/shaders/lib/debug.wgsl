@@ -0,0 +1,5 @@
+// Debugging helpers
+
+fn error_shader() -> vec3f {
+    return vec3f(0.7, 0.0, 0.7);
+}
@@ -0,0 +1,13 @@
+// Fresnel reflectance
+
+fn fresnel_r(cos_thet_i: f32, cos_thet_t: f32, ni_over_nt: f32) -> f32 {
+    let ii = ni_over_nt * cos_thet_i;
+    let tt = 1.0 * cos_thet_t;
+    let ti = 1.0 * cos_thet_i;
+    let it = ni_over_nt * cos_thet_t;
+
+    let r1 = (ii - tt) / (ii + tt);
+    let r2 = (ti - it) / (ti + it);
+    let R = 0.5 * (r1 * r1 + r2 * r2);
+    return R;
+}
@@ -0,0 +1,20 @@
+// Full screen quad vertex stage shared by all passes
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.coords = vec2f(model.position.x, model.position.y);
+    out.clip_position = vec4<f32>(model.position, 1.0);
+    return out;
+}
@@ -0,0 +1,15 @@
+// Light samples
+
+struct Light {
+    l_i: vec3f, // intensity
+    w_i: vec3f, // incidence
+    dist: f32, // distance
+};
+
+fn light_init() -> Light {
+    return Light(
+        vec3f(0.0),
+        vec3f(0.0),
+        999999.0,
+    );
+}
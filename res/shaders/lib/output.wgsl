@@ -0,0 +1,52 @@
+// Render targets written by the path tracing shaders. The AOVs are
+// filled from the HitRecord and materials of the including shader.
+
+const NO_OBJECT: u32 = 0xffffffffu;
+
+struct FragmentOutput {
+    @location(0) frame: vec4f,
+    @location(1) accum: vec4f,
+    // AOVs
+    @location(2) albedo: vec4f,
+    @location(3) normal: vec4f,
+    @location(4) depth: f32,
+    @location(5) ids: vec2u,
+    @location(6) samples: u32,
+    // adaptive sampling statistics
+    @location(7) variance: vec4f,
+}
+
+// First hit information written to the AOV targets
+struct Aov {
+    albedo: vec3f,
+    normal: vec3f,
+    depth: f32,
+    object: u32,
+    material: u32,
+};
+
+fn aov_init() -> Aov {
+    return Aov(
+        vec3f(0.0),
+        vec3f(0.0),
+        0.0,
+        NO_OBJECT,
+        NO_OBJECT,
+    );
+}
+
+fn aov_from_hit(hit: ptr<function, HitRecord>, primary: Ray) -> Aov {
+    var aov = aov_init();
+    let forward = normalize(uniforms.camera_look_at - uniforms.camera_pos);
+    aov.normal = (*hit).normal;
+    aov.depth = (*hit).dist * dot(primary.direction, forward);
+    aov.object = (*hit).object;
+    if ((*hit).object != NO_OBJECT) {
+        aov.albedo = get_material(hit).diffuse.rgb;
+        aov.material = (*hit).material;
+    } else {
+        // the spheres of the exercise shaders have no material
+        aov.albedo = vec3f(1.0);
+    }
+    return aov;
+}
@@ -0,0 +1,36 @@
+// Pseudo random numbers
+
+// PRNG xorshift seed generator by NVIDIA
+fn prng_xorshift_seed_generator(val0: u32, val1: u32) -> u32 {
+      let N = 16u; // User specified number of iterations
+      var v0: u32 = val0;
+      var v1: u32 = val1;
+      var s0: u32 = 0u;
+
+      for(var n = 0u; n < N; n++) {
+        s0 += 0x9e3779b9u;
+        v0 += ((v1<<4u)+0xa341316cu)^(v1+s0)^((v1>>5u)+0xc8013ea4u);
+        v1 += ((v0<<4u)+0xad90777du)^(v0+s0)^((v0>>5u)+0x7e95761eu);
+      }
+
+      return v0;
+}
+
+// Generate random unsigned int in [0, 2^31)
+fn mcg31(prev: ptr<function, u32>) -> u32 {
+    let LCG_A = 1977654935u; // Multiplier from Hui-Ching Tang [EJOR 2007]
+    *prev = (LCG_A * (*prev)) & 0x7FFFFFFFu;
+    return *prev;
+}
+
+// Generate random float in [0, 1)
+fn rnd(prev: ptr<function, u32>) -> f32
+{
+    return f32(mcg31(prev)) / f32(0x80000000u);
+}
+
+// Generate random unsigned int in [0, 2^31)
+fn rnd_int(prev: ptr<function, u32>) -> u32
+{
+    return mcg31(prev);
+}
@@ -0,0 +1,21 @@
+// Rays, the minimum distance ETA is defined by each scene shader
+
+struct Ray {
+    direction: vec3f,
+    origin: vec3f,
+    tmax: f32,
+    tmin: f32,
+};
+
+fn ray_init(direction: vec3f, origin: vec3f) -> Ray {
+    return Ray(
+        direction,
+        origin,
+        5000.0,
+        ETA,
+    );
+}
+
+fn ray_at(r: Ray, dist: f32) -> vec3f {
+    return r.origin + r.direction * dist;
+}
@@ -0,0 +1,27 @@
+// Direction sampling helpers
+
+// Given a direction vector v sampled around the z-axis of a
+// local coordinate system, this function applies the same
+// rotation to v as is needed to rotate the z-axis to the
+// actual direction n that v should have been sampled around
+// [Frisvad, Journal of Graphics Tools 16, 2012;
+//  Duff et al., Journal of Computer Graphics Techniques 6, 2017].
+fn rotate_to_normal(normal: vec3f, v: vec3f) -> vec3f
+{
+    let signbit = sign(normal.z + 1.0e-16);
+    let a = -1.0/(1.0 + abs(normal.z));
+    let b = normal.x*normal.y*a;
+    return vec3f(1.0 + normal.x*normal.x*a, b, -signbit*normal.x)*v.x
+      + vec3f(signbit*b, signbit*(1.0 + normal.y*normal.y*a), -normal.y)*v.y
+      + normal*v.z;
+}
+
+// Given spherical coordinates, where theta is the
+// polar angle and phi is the azimuthal angle, this
+// function returns the corresponding direction vector
+fn spherical_direction(sin_theta: f32, cos_theta: f32, phi: f32) -> vec3f
+{
+    let sin_phi = sin(phi);
+    let cos_phi = cos(phi);
+    return vec3f(sin_theta*cos_phi, sin_theta*sin_phi, cos_theta);
+}
@@ -0,0 +1,360 @@
+#import fullscreen
+#import ray
+#import light
+#import random
+#import sampling
+#import output
+#import debug
+
+// Path tracer shared by both backends: fs_main traces the whole path of a
+// pixel, the wavefront backend runs the same functions one bounce at a time
+// (res/shaders/wavefront.wgsl), so the two can be compared on equal terms.
+
+const PI = 3.14159265359;
+// Minimum ray distance relative to the coordinates of the ray origin,
+// the scenes range from the Cornell box (~500) to the dragon (~0.2)
+const ETA = 0.00002;
+
+const BACKGROUND_COLOR: vec3f = vec3f(0.0, 0.0, 0.0);
+
+const SHADER_TYPE_LAMBERTIAN: u32 = 0u;
+const SHADER_TYPE_MIRROR: u32 = 2u;
+const SHADER_TYPE_NORMAL: u32 = 5u;
+const SHADER_TYPE_BASECOLOR: u32 = 6u;
+
+const MAX_DEPTH: u32 = 50u;
+
+// Used when the scene has no emissive triangles
+const DIRECTIONAL_LIGHT: vec3f = vec3f(-1.0, -1.0, -1.0);
+const DIRECTIONAL_LIGHT_INTENSITY: f32 = 3.14159265359;
+
+struct HitRecord {
+    dist: f32,
+    position: vec3f,
+    normal: vec3f,
+    material: u32,
+    object: u32,
+    shader: u32,
+};
+
+fn hit_record_init() -> HitRecord {
+    return HitRecord(
+        0.0,
+        vec3f(0.0),
+        vec3f(0.0),
+        0u,
+        NO_OBJECT,
+        SHADER_TYPE_LAMBERTIAN,
+    );
+}
+
+fn get_material(hit: ptr<function, HitRecord>) -> Material {
+    return materials[(*hit).material];
+}
+
+// What happens at a path vertex, filled by shade
+struct Scatter {
+    // light reaching the camera from this vertex
+    radiance: vec3f,
+    // the path continues with the next ray
+    continues: bool,
+    next: Ray,
+    throughput: vec3f,
+    // emission is counted at the next hit, only after specular bounces
+    emit: bool,
+    // direct light, added to the path if the shadow ray is not blocked
+    has_shadow_ray: bool,
+    shadow_ray: Ray,
+    shadow_radiance: vec3f,
+};
+
+fn scatter_init() -> Scatter {
+    return Scatter(
+        vec3f(0.0),
+        false,
+        ray_init(vec3f(0.0), vec3f(0.0)),
+        vec3f(0.0),
+        false,
+        false,
+        ray_init(vec3f(0.0), vec3f(0.0)),
+        vec3f(0.0),
+    );
+}
+
+fn ray_epsilon(origin: vec3f) -> f32 {
+    let p = abs(origin);
+    return ETA * max(1.0, max(p.x, max(p.y, p.z)));
+}
+
+fn offset_ray(direction: vec3f, origin: vec3f) -> Ray {
+    var ray = ray_init(direction, origin);
+    ray.tmin = ray_epsilon(origin);
+    return ray;
+}
+
+fn triangle_area(v0: vec3f, v1: vec3f, v2: vec3f) -> f32 {
+    let e0 = v0 - v1;
+    let e1 = v0 - v2;
+    let cr = cross(e0, e1);
+    return 0.5 * sqrt(dot(cr, cr));
+}
+
+// Pixel centers in [-0.5, 0.5], y up
+fn pixel_uv(pixel: vec2u) -> vec2f {
+    let res = vec2f(uniforms.resolution);
+    let ndc = (vec2f(pixel) + 0.5) / res * 2.0 - 1.0;
+    return vec2f(ndc.x, -ndc.y) * 0.5;
+}
+
+fn camera_jitter(rand: ptr<function, u32>) -> vec2f {
+    return vec2f(rnd(rand), rnd(rand)) / f32(uniforms.resolution.y);
+}
+
+fn get_camera_ray(uv: vec2f, jitter: vec2f) -> Ray {
+    let e = uniforms.camera_pos;
+    let p = uniforms.camera_look_at;
+    let u = uniforms.camera_up;
+    let v = normalize(p - e);
+    let d = uniforms.camera_constant;
+    let aspect = uniforms.aspect_ratio;
+
+    let b1 = normalize(cross(v, u));
+    let b2 = cross(b1, v);
+
+    let q = normalize(b1 * (uv.x + jitter.x) * aspect + b2 * (uv.y + jitter.y) + v * d);
+
+    return offset_ray(q, e);
+}
+
+fn intersect_scene(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> bool {
+    // the planes first, so the traversal starts with their tmax
+    let unbounded = intersect_unbounded(r, hit);
+    return intersect_trimesh(r, hit) || unbounded;
+}
+
+// Any hit is enough for shadow rays
+fn occluded(ray: Ray) -> bool {
+    var r = ray;
+    var hit = hit_record_init();
+    return intersect_unbounded(&r, &hit) || intersect_trimesh_immediate_return(&r, &hit);
+}
+
+// Fragment shader
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    let pixel = vec2u(in.clip_position.xy);
+    let stats = pixel_stats_load(pixel);
+    if (pixel_converged(stats)) {
+        // all targets keep the values of the last traced sample
+        discard;
+    }
+    atomicAdd(&activePixels, 1u);
+    let launch_idx = pixel.y * uniforms.resolution.x + pixel.x;
+    var t = prng_xorshift_seed_generator(launch_idx, uniforms.iteration);
+
+    let primary = get_camera_ray(pixel_uv(pixel), camera_jitter(&t));
+    var r = primary;
+    var throughput = vec3f(1.0);
+    var emit = true;
+    var result = vec3f(0.0);
+    var aov = aov_init();
+    for (var depth = 0u; depth < MAX_DEPTH; depth++) {
+        var hit = hit_record_init();
+        if (!intersect_scene(&r, &hit)) {
+            result += BACKGROUND_COLOR * throughput;
+            break;
+        }
+        if (depth == 0u) {
+            aov = aov_from_hit(&hit, primary);
+        }
+        let scatter = shade(r, &hit, throughput, emit, &t);
+        result += scatter.radiance;
+        if (scatter.has_shadow_ray && !occluded(scatter.shadow_ray)) {
+            result += scatter.shadow_radiance;
+        }
+        if (!scatter.continues) {
+            break;
+        }
+        r = scatter.next;
+        throughput = scatter.throughput;
+        emit = scatter.emit;
+    }
+
+    return accumulate(pixel, stats, result, aov);
+}
+
+// Adds a sample to the targets, pixels can have different sample
+// counts with adaptive sampling
+fn accumulate(pixel: vec2u, stats: PixelStats, result: vec3f, aov: Aov) -> FragmentOutput {
+    let curr_sum = textureLoad(renderTexture, pixel, 0).rgb * f32(stats.count);
+    let accum_color = (result + curr_sum) / f32(stats.count + 1u);
+    let new_stats = pixel_stats_add(stats, result);
+
+    var output = FragmentOutput(
+        vec4f(accum_color, 1.0),
+        max(vec4f(accum_color, 1.0), vec4f(0.0)),
+        vec4f(aov.albedo, 1.0),
+        vec4f(aov.normal, 1.0),
+        aov.depth,
+        vec2u(aov.object, aov.material),
+        new_stats.count,
+        pixel_stats_output(new_stats),
+    );
+    if (any(result < vec3f(0.0)) || any(accum_color < vec3f(0.0))) {
+        output.frame = vec4f(error_shader(), 1.0);
+    }
+    return output;
+}
+
+fn intersect_triangle_indexed(r: ptr<function, Ray>, hit: ptr<function, HitRecord>, v: u32) -> bool {
+    let v0 = combinedBuffer[indexBuffer[v].x].position.xyz;
+    let v1 = combinedBuffer[indexBuffer[v].y].position.xyz;
+    let v2 = combinedBuffer[indexBuffer[v].z].position.xyz;
+
+    let ray = *r;
+    let w_i = ray.direction;
+    let e0 = v1 - v0;
+    let e1 = v2 - v0;
+    let o_to_v0 = v0 - ray.origin;
+    let normal = cross(e0, e1);
+
+    let nom = cross(o_to_v0, w_i);
+    let denom = dot(w_i, normal);
+    if (abs(denom) < 1e-20) {
+        return false;
+    }
+
+    let beta = dot(nom, e1) / denom;
+    let gamma = -dot(nom, e0) / denom;
+    let distance = dot(o_to_v0, normal) / denom;
+    if (beta < 0.0 || gamma < 0.0 || beta + gamma > 1.0 || distance > ray.tmax || distance < ray.tmin) {
+        return false;
+    }
+
+    (*r).tmax = distance;
+    (*hit).dist = distance;
+    (*hit).position = ray_at(ray, distance);
+    (*hit).normal = normalize(normal);
+    (*hit).material = indexBuffer[v].w;
+    (*hit).object = v;
+
+    return true;
+}
+
+fn sample_area_light(pos: vec3f, idx: u32, rand: ptr<function, u32>) -> Light {
+    let light_triangle: vec4u = indexBuffer[lightIndices[idx]];
+    let v0 = combinedBuffer[light_triangle.x].position.xyz;
+    let v1 = combinedBuffer[light_triangle.y].position.xyz;
+    let v2 = combinedBuffer[light_triangle.z].position.xyz;
+    let area = triangle_area(v0, v1, v2);
+    let l_e = materials[light_triangle.w].ambient.xyz;
+    let psi1 = sqrt(rnd(rand));
+    let psi2 = rnd(rand);
+    let alpha = 1.0 - psi1;
+    let beta = (1.0 - psi2) * psi1;
+    let gamma = psi2 * psi1;
+    let normal = normalize(cross((v0 - v1), (v0 - v2)));
+
+    let sampled_point = v0 * alpha + v1 * beta + v2 * gamma;
+
+    let light_direction = sampled_point - pos;
+    let cos_l = max(dot(normalize(-light_direction), normal), 0.0);
+    let distance = length(light_direction);
+    var light = light_init();
+    light.l_i = (l_e * area) * cos_l / (distance * distance);
+    light.w_i = light_direction / distance;
+    light.dist = distance;
+    return light;
+}
+
+// A random emissive triangle, or the directional light if there is none
+fn sample_light(pos: vec3f, rand: ptr<function, u32>) -> Light {
+    // the first light index is a placeholder
+    let light_tris = arrayLength(&lightIndices) - 1u;
+    if (light_tris == 0u) {
+        var light = light_init();
+        light.l_i = vec3f(DIRECTIONAL_LIGHT_INTENSITY);
+        light.w_i = -normalize(DIRECTIONAL_LIGHT);
+        return light;
+    }
+    let idx = (rnd_int(rand) % light_tris) + 1u;
+    var light = sample_area_light(pos, idx, rand);
+    light.l_i *= f32(light_tris);
+    return light;
+}
+
+fn shade(r: Ray, hit: ptr<function, HitRecord>, throughput: vec3f, emit: bool, rand: ptr<function, u32>) -> Scatter {
+    switch ((*hit).shader) {
+        case 0u: {
+            return lambertian(hit, throughput, emit, rand);
+        }
+        case 2u: {
+            return mirror(r, hit, throughput);
+        }
+        case 5u: {
+            var scatter = scatter_init();
+            scatter.radiance = ((*hit).normal + 1.0) * 0.5;
+            return scatter;
+        }
+        case 6u: {
+            let material = get_material(hit);
+            var scatter = scatter_init();
+            scatter.radiance = material.diffuse.rgb + material.ambient.rgb;
+            return scatter;
+        }
+        default: {
+            var scatter = scatter_init();
+            scatter.radiance = error_shader();
+            return scatter;
+        }
+    }
+}
+
+fn lambertian(hit: ptr<function, HitRecord>, throughput: vec3f, emit: bool, rand: ptr<function, u32>) -> Scatter {
+    let material = get_material(hit);
+    let brdf = material.diffuse.rgb / PI;
+    let position = (*hit).position;
+    let normal = (*hit).normal;
+    var scatter = scatter_init();
+
+    // Emission only counts for camera rays and after specular bounces,
+    // everything else is covered by sampling the lights
+    if (emit) {
+        scatter.radiance = material.ambient.rgb * throughput;
+    }
+
+    let light = sample_light(position, rand);
+    let cos_theta = dot(normal, light.w_i);
+    if (cos_theta > 0.0) {
+        scatter.has_shadow_ray = true;
+        scatter.shadow_ray = offset_ray(light.w_i, position);
+        scatter.shadow_ray.tmax = light.dist - scatter.shadow_ray.tmin;
+        scatter.shadow_radiance = brdf * cos_theta * light.l_i * throughput;
+    }
+
+    // Russian roulette decides whether to trace further
+    let prob_reflection = (brdf.r + brdf.g + brdf.b) / 3.0;
+    if (rnd(rand) < prob_reflection) {
+        let xi1 = rnd(rand);
+        let xi2 = rnd(rand);
+        let theta = acos(sqrt(1.0 - xi1));
+        let phi = 2.0 * PI * xi2;
+        let direction = rotate_to_normal(normal, spherical_direction(sin(theta), cos(theta), phi));
+
+        scatter.continues = true;
+        scatter.next = offset_ray(direction, position);
+        scatter.throughput = throughput * brdf * PI / prob_reflection;
+        scatter.emit = false;
+    }
+    return scatter;
+}
+
+fn mirror(r: Ray, hit: ptr<function, HitRecord>, throughput: vec3f) -> Scatter {
+    var scatter = scatter_init();
+    scatter.continues = true;
+    scatter.next = offset_ray(reflect(r.direction, (*hit).normal), (*hit).position);
+    scatter.throughput = throughput;
+    scatter.emit = true;
+    return scatter;
+}
@@ -0,0 +1,228 @@
+// Presentation pass: scales the rendered frame to the window.
+// The letterboxing is done with the viewport, so this shader
+// only has to deal with zooming, panning and filtering.
+// The frame holds linear radiance, exposure, tone mapping and
+// the sRGB encoding are all applied here. Instead of the frame
+// the denoised frame or one of the AOVs can be shown.
+// With tiled rendering the tiles still to be traced for the
+// current sample are dimmed.
+
+#import fullscreen
+
+const FILTER_NEAREST: u32 = 0u;
+const FILTER_BILINEAR: u32 = 1u;
+
+const TONE_MAPPING_CLAMP: u32 = 0u;
+const TONE_MAPPING_REINHARD: u32 = 1u;
+const TONE_MAPPING_ACES: u32 = 2u;
+const TONE_MAPPING_AGX: u32 = 3u;
+
+const AOV_BEAUTY: u32 = 0u;
+const AOV_ALBEDO: u32 = 1u;
+const AOV_NORMAL: u32 = 2u;
+const AOV_DEPTH: u32 = 3u;
+const AOV_OBJECT_ID: u32 = 4u;
+const AOV_MATERIAL_ID: u32 = 5u;
+const AOV_SAMPLE_COUNT: u32 = 6u;
+
+const NO_OBJECT: u32 = 0xffffffffu;
+
+const TILE_EDGE_COLOR: vec3f = vec3f(1.0, 0.8, 0.0);
+
+// log2 range of the AgX encoding around middle grey (0.18)
+const AGX_MIN_EV: f32 = -12.47393;
+const AGX_MAX_EV: f32 = 4.026069;
+
+// Extended Reinhard, maps the white point to exactly 1.0
+fn reinhard(x: vec3f, white: f32) -> vec3f {
+    return x * (1.0 + x / (white * white)) / (1.0 + x);
+}
+
+// Krzysztof Narkowicz, "ACES Filmic Tone Mapping Curve", 2016
+fn aces_fit(x: vec3f) -> vec3f {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return (x * (a * x + b)) / (x * (c * x + d) + e);
+}
+
+fn aces(x: vec3f, white: f32) -> vec3f {
+    return aces_fit(x) / aces_fit(vec3f(white));
+}
+
+// Benjamin Wrensch, "Minimal AgX implementation", 2023
+// 6th order polynomial fit of the AgX default contrast curve
+fn agx_contrast(x: vec3f) -> vec3f {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2
+        - 40.14 * x4 * x
+        + 31.96 * x4
+        - 6.868 * x2 * x
+        + 0.4298 * x2
+        + 0.1191 * x
+        - 0.00232;
+}
+
+fn agx(x: vec3f, white: f32) -> vec3f {
+    let agx_mat = mat3x3f(
+        vec3f(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3f(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3f(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let agx_mat_inv = mat3x3f(
+        vec3f(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3f(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3f(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    // the white point is placed at the top of the AgX range
+    let agx_white = exp2(AGX_MAX_EV);
+    let scaled = max(x * (agx_white / white), vec3f(0.0));
+
+    var v = agx_mat * scaled;
+    v = clamp(log2(max(v, vec3f(1e-10))), vec3f(AGX_MIN_EV), vec3f(AGX_MAX_EV));
+    v = (v - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+    v = agx_contrast(v);
+    v = agx_mat_inv * v;
+    // the curve outputs display encoded values, go back to linear, the
+    // inverse matrix can move saturated colors slightly out of [0, 1]
+    return pow(saturate(v), vec3f(2.2));
+}
+
+fn tone_map(x: vec3f) -> vec3f {
+    let white = present.white_point;
+    // case selectors have to be literals
+    switch present.tone_mapping {
+        case 1u: { // TONE_MAPPING_REINHARD
+            return reinhard(x, white);
+        }
+        case 2u: { // TONE_MAPPING_ACES
+            return aces(x, white);
+        }
+        case 3u: { // TONE_MAPPING_AGX
+            return agx(x, white);
+        }
+        default: { // TONE_MAPPING_CLAMP
+            return x / white;
+        }
+    }
+}
+
+// IEC 61966-2-1 sRGB opto-electronic transfer function
+fn srgb_oetf(x: vec3f) -> vec3f {
+    let low = x * 12.92;
+    let high = 1.055 * pow(x, vec3f(1.0 / 2.4)) - 0.055;
+    return select(high, low, x <= vec3f(0.0031308));
+}
+
+// Anton Mikhailov, "Turbo, An Improved Rainbow Colormap", 2019
+// polynomial approximation, x in [0, 1]
+fn turbo(x: f32) -> vec3f {
+    let r4 = vec4f(0.13572138, 4.61539260, -42.66032258, 132.13108234);
+    let g4 = vec4f(0.09140261, 2.19418839, 4.84296658, -14.18503333);
+    let b4 = vec4f(0.10667330, 12.64194608, -60.58204836, 110.36276771);
+    let r2 = vec2f(-152.94239396, 59.28637943);
+    let g2 = vec2f(4.27729857, 2.82956604);
+    let b2 = vec2f(-89.90310912, 27.34824973);
+
+    let v4 = vec4f(1.0, x, x * x, x * x * x);
+    let v2 = v4.zw * v4.z;
+    return vec3f(
+        dot(v4, r4) + dot(v2, r2),
+        dot(v4, g4) + dot(v2, g2),
+        dot(v4, b4) + dot(v2, b2),
+    );
+}
+
+// Random but stable color per id, PCG hash
+fn id_color(id: u32) -> vec3f {
+    if (id == NO_OBJECT) {
+        return vec3f(0.0);
+    }
+    var h = id * 747796405u + 2891336453u;
+    h = ((h >> ((h >> 28u) + 4u)) ^ h) * 277803737u;
+    h = (h >> 22u) ^ h;
+    return vec3f(f32(h & 255u), f32((h >> 8u) & 255u), f32((h >> 16u) & 255u)) / 255.0;
+}
+
+fn aov_color(view_uv: vec2f) -> vec3f {
+    let size = textureDimensions(aovAlbedo);
+    let pixel = min(vec2u(saturate(view_uv) * vec2f(size)), size - 1u);
+    switch present.aov {
+        case 1u: { // AOV_ALBEDO
+            return textureLoad(aovAlbedo, pixel, 0).rgb;
+        }
+        case 2u: { // AOV_NORMAL
+            return textureLoad(aovNormal, pixel, 0).xyz * 0.5 + 0.5;
+        }
+        case 3u: { // AOV_DEPTH
+            let depth = textureLoad(aovDepth, pixel, 0).r;
+            return tone_map(vec3f(depth * exp2(present.exposure)));
+        }
+        case 4u: { // AOV_OBJECT_ID
+            return id_color(textureLoad(aovIds, pixel, 0).x);
+        }
+        case 5u: { // AOV_MATERIAL_ID
+            return id_color(textureLoad(aovIds, pixel, 0).y);
+        }
+        case 6u: { // AOV_SAMPLE_COUNT
+            // heatmap relative to the samples taken by unconverged pixels
+            let samples = f32(textureLoad(aovSamples, pixel, 0).r);
+            let color = turbo(saturate(samples / f32(present.sample_budget)));
+            // the colormap is display encoded, go back to linear
+            return pow(saturate(color), vec3f(2.2));
+        }
+        default: {
+            return vec3f(0.0);
+        }
+    }
+}
+
+// Dims the tiles of the current sample that have not been traced yet
+fn tile_progress(view_uv: vec2f, display: vec3f) -> vec3f {
+    let tile_size = present.tile_size;
+    if (tile_size == 0u) {
+        return display;
+    }
+    let size = textureDimensions(frameTexture);
+    let pixel = min(vec2u(saturate(view_uv) * vec2f(size)), size - 1u);
+    let tile = pixel / tile_size;
+    let columns = (size.x + tile_size - 1u) / tile_size;
+    var color = display;
+    if (tile.y * columns + tile.x >= present.tiles_done) {
+        color *= 0.5;
+    }
+    if (any(pixel % tile_size == vec2u(0u))) {
+        color = mix(color, TILE_EDGE_COLOR, 0.5);
+    }
+    return color;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // clip space has y pointing up, textures have y pointing down
+    let uv = vec2f(in.coords.x, -in.coords.y) * 0.5 + 0.5;
+    let view_uv = (uv - 0.5) / present.zoom + present.center;
+
+    var nearest = textureSample(frameTexture, frameSamplerNearest, view_uv);
+    var bilinear = textureSample(frameTexture, frameSamplerBilinear, view_uv);
+    if (present.denoised != 0u) {
+        nearest = textureSample(denoisedTexture, frameSamplerNearest, view_uv);
+        bilinear = textureSample(denoisedTexture, frameSamplerBilinear, view_uv);
+    }
+    let color = select(nearest, bilinear, present.filter_mode == FILTER_BILINEAR);
+
+    let radiance = max(color.rgb, vec3f(0.0)) * exp2(present.exposure);
+    var display = saturate(tone_map(radiance));
+    if (present.aov != AOV_BEAUTY) {
+        display = saturate(aov_color(view_uv));
+    }
+    display = tile_progress(view_uv, display);
+    if (present.encode_srgb != 0u) {
+        display = srgb_oetf(display);
+    }
+
+    return vec4f(display, 1.0);
+}
@@ -0,0 +1,106 @@
+// Analytic primitives of primitives::GpuPrimitive, appended with the primitive buffer.
+// The leaves of the BVH and BSP tree reference them with ANALYTIC_PRIMITIVE set,
+// planes are unbounded and tested for every ray by intersect_unbounded instead.
+// Writes the dist, position, normal, material and object of the HitRecord.
+
+// Has to match accobj::ANALYTIC_PRIMITIVE
+const ANALYTIC_PRIMITIVE = 0x80000000u;
+// Have to match the kinds in primitives.rs
+const PRIMITIVE_SPHERE = 0u;
+const PRIMITIVE_PLANE = 1u;
+const PRIMITIVE_QUAD = 2u;
+const PRIMITIVE_BOX = 3u;
+
+//var<storage> primitives: array<Primitive>; // Primitive is generated from primitives::GpuPrimitive
+
+fn intersect_primitive(r: ptr<function, Ray>, hit: ptr<function, HitRecord>, reference: u32) -> bool {
+    if ((reference & ANALYTIC_PRIMITIVE) == 0u) {
+        return intersect_triangle_indexed(r, hit, reference);
+    }
+    return intersect_analytic(r, hit, reference);
+}
+
+fn intersect_unbounded(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> bool {
+    var found = false;
+    for (var i = 0u; i < arrayLength(&primitives); i++) {
+        // not in one condition, naga calls intersect_analytic before evaluating &&
+        if (primitives[i].kind == PRIMITIVE_PLANE) {
+            if (intersect_analytic(r, hit, i | ANALYTIC_PRIMITIVE)) {
+                found = true;
+            }
+        }
+    }
+    return found;
+}
+
+fn intersect_plane(r: Ray, point: vec3f, normal: vec3f) -> f32 {
+    let denom = dot(r.direction, normal);
+    if (abs(denom) < 1e-20) {
+        return -1.0;
+    }
+    return dot(point - r.origin, normal) / denom;
+}
+
+fn intersect_analytic(r: ptr<function, Ray>, hit: ptr<function, HitRecord>, reference: u32) -> bool {
+    let primitive = primitives[reference & ~ANALYTIC_PRIMITIVE];
+    let ray = *r;
+    var dist = -1.0;
+    var normal = vec3f(0.0);
+    if (primitive.kind == PRIMITIVE_SPHERE) {
+        let oc = ray.origin - primitive.a;
+        let a = dot(ray.direction, ray.direction);
+        let half_b = dot(oc, ray.direction);
+        let c = dot(oc, oc) - primitive.b.x * primitive.b.x;
+        let discriminant = half_b * half_b - a * c;
+        if (discriminant < 0.0) {
+            return false;
+        }
+        dist = (-half_b - sqrt(discriminant)) / a;
+        if (dist < ray.tmin) {
+            dist = (-half_b + sqrt(discriminant)) / a;
+        }
+        normal = (ray_at(ray, dist) - primitive.a) / primitive.b.x;
+    } else if (primitive.kind == PRIMITIVE_PLANE) {
+        dist = intersect_plane(ray, primitive.a, primitive.b);
+        normal = primitive.b;
+    } else if (primitive.kind == PRIMITIVE_QUAD) {
+        normal = cross(primitive.b, primitive.c);
+        dist = intersect_plane(ray, primitive.a, normal);
+        let q = ray_at(ray, dist) - primitive.a;
+        let w = normal / dot(normal, normal);
+        let alpha = dot(w, cross(q, primitive.c));
+        let beta = dot(w, cross(primitive.b, q));
+        if (alpha < 0.0 || alpha > 1.0 || beta < 0.0 || beta > 1.0) {
+            return false;
+        }
+        normal = normalize(normal);
+    } else if (primitive.kind == PRIMITIVE_BOX) {
+        let near = (primitive.a - ray.origin) / ray.direction;
+        let far = (primitive.b - ray.origin) / ray.direction;
+        let t0 = max(max(min(near.x, far.x), min(near.y, far.y)), min(near.z, far.z));
+        let t1 = min(min(max(near.x, far.x), max(near.y, far.y)), max(near.z, far.z));
+        if (t0 > t1) {
+            return false;
+        }
+        dist = select(t1, t0, t0 >= ray.tmin);
+        // the face is on the axis the hit point is furthest out
+        let half_extent = (primitive.b - primitive.a) * 0.5;
+        let d = (ray_at(ray, dist) - (primitive.a + half_extent)) / half_extent;
+        let axis = select(select(2u, 1u, abs(d.y) >= abs(d.z)), 0u, abs(d.x) >= max(abs(d.y), abs(d.z)));
+        normal[axis] = sign(d[axis]);
+    } else {
+        return false;
+    }
+    if (dist > ray.tmax || dist < ray.tmin) {
+        return false;
+    }
+
+    (*r).tmax = dist;
+    (*hit).dist = dist;
+    (*hit).position = ray_at(ray, dist);
+    (*hit).normal = normal;
+    (*hit).material = primitive.material;
+    (*hit).object = reference;
+
+    return true;
+}
@@ -0,0 +1,10 @@
+// Dispatch of the traversals for scenes without analytic primitives, appended with
+// the placeholder primitive buffer instead of primitives.wgsl. Every reference is a triangle.
+
+fn intersect_primitive(r: ptr<function, Ray>, hit: ptr<function, HitRecord>, reference: u32) -> bool {
+    return intersect_triangle_indexed(r, hit, reference);
+}
+
+fn intersect_unbounded(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> bool {
+    return false;
+}
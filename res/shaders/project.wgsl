@@ -1,3 +1,7 @@
+#import fullscreen
+#import light
+#import debug
+
 const PI = 3.14159265359;
 const ETA = 0.00001;
 
@@ -29,15 +33,6 @@ const MAX_DEPTH: i32 = 10;
 //@group(2) @binding(1)
 //var<storage> indexBuffer: array<vec4u>;
 
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-};
-
-struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
-    @location(0) coords: vec2<f32>,
-};
-
 struct Ray {
     direction: vec3f,
     origin: vec3f,
@@ -65,20 +60,6 @@ struct Camera {
     constant: f32,
 };
 
-struct Light {
-    l_i: vec3f, // intensity
-    w_i: vec3f, // incidence
-    dist: f32, // distance
-};
-
-fn light_init() -> Light {
-    return Light(
-        vec3f(0.0),
-        vec3f(0.0),
-        999999.0,
-    );
-}
-
 struct HitRecord {
     has_hit: bool,
     material: u32,
@@ -118,16 +99,6 @@ fn triangle_area(v0: vec3f, v1: vec3f, v2: vec3f) -> f32 {
     return 0.5 * sqrt(dot(cr, cr));
 }
 
-@vertex
-fn vs_main(
-    model: VertexInput,
-) -> VertexOutput {
-    var out: VertexOutput;
-    out.coords = vec2f(model.position.x, model.position.y);
-    out.clip_position = vec4<f32>(model.position, 1.0);
-    return out;
-}
-
 fn get_camera_ray(uv: vec2f, sample: u32) -> Ray {
     let e = uniforms.camera_pos;
     let p = uniforms.camera_look_at;
@@ -181,7 +152,7 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let multiplier = 1.0 / f32(subdiv * subdiv);
     result = result * multiplier;
 
-    return vec4f(pow(result, vec3f(1.5/1.0)), bgcolor.a);
+    return vec4f(result, bgcolor.a);
 }
 
 fn intersect_scene_bsp(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> bool {
@@ -321,7 +292,6 @@ fn mirror(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> vec3f {
 
 }
 
-
 fn shade_normal(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> vec3f {
     return ((*hit).normal + 1.0) * 0.5;
 }
@@ -332,6 +302,3 @@ fn shade_base_color(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> vec
     return color;
 }
 
-fn error_shader() -> vec3f {
-    return vec3f(0.7, 0.0, 0.7);
-}
@@ -1,11 +1,4 @@
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-};
-
-struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
-    @location(0) coords: vec2<f32>,
-};
+#import fullscreen
 
 struct Ray {
     direction: vec3f,
@@ -30,16 +23,6 @@ struct Camera {
     constant: f32,
 };
 
-@vertex
-fn vs_main(
-    model: VertexInput,
-) -> VertexOutput {
-    var out: VertexOutput;
-    out.coords = vec2f(model.position.x, model.position.y);
-    out.clip_position = vec4<f32>(model.position, 1.0);
-    return out;
-}
-
 fn get_camera_ray(uv: vec2f) -> Ray {
     let e = uniforms.camera_pos;
     let p = uniforms.camera_look_at;
@@ -67,6 +50,5 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
 
     result = (r.direction + 1.0) / 2.0;
 
-
     return vec4f(result, 1.0);
 }
\ No newline at end of file
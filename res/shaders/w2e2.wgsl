@@ -1,5 +1,10 @@
 // Vertex shader
 
+#import fullscreen
+#import ray
+#import light
+#import debug
+
 const PI = 3.14159265359;
 const ETA = 0.00001;
 
@@ -15,35 +20,6 @@ const SHADER_TYPE_DEFAULT: u32 = 0u;
 
 const MAX_DEPTH: i32 = 10;
 
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-};
-
-struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
-    @location(0) coords: vec2<f32>,
-};
-
-struct Ray {
-    direction: vec3f,
-    origin: vec3f,
-    tmax: f32,
-    tmin: f32,
-};
-
-fn ray_init(direction: vec3f, origin: vec3f) -> Ray {
-    return Ray(
-        direction,
-        origin,
-        5000.0,
-        ETA,
-    );
-}
-
-fn ray_at(r: Ray, dist: f32) -> vec3f {
-    return r.origin + r.direction * dist;
-}
-
 struct Camera {
     origin: vec3f,
     direction: vec3f,
@@ -51,20 +27,6 @@ struct Camera {
     constant: f32,
 };
 
-struct Light {
-    l_i: vec3f, // intensity
-    w_i: vec3f, // incidence
-    dist: f32, // distance
-};
-
-fn light_init() -> Light {
-    return Light(
-        vec3f(0.0),
-        vec3f(0.0),
-        999999.0,
-    );
-}
-
 struct HitRecord {
     has_hit: bool,
     depth: i32,
@@ -111,16 +73,6 @@ fn hit_record_init() -> HitRecord {
     );
 }
 
-@vertex
-fn vs_main(
-    model: VertexInput,
-) -> VertexOutput {
-    var out: VertexOutput;
-    out.coords = vec2f(model.position.x, model.position.y);
-    out.clip_position = vec4<f32>(model.position, 1.0);
-    return out;
-}
-
 fn get_camera_ray(uv: vec2f) -> Ray {
     let e = uniforms.camera_pos;
     let p = uniforms.camera_look_at;
@@ -163,7 +115,7 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
         }
     }
 
-    return vec4f(pow(result, vec3f(1.5/1.0)), bgcolor.a);
+    return vec4f(result, bgcolor.a);
 }
 
 fn intersect_scene(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> bool {
@@ -363,6 +315,3 @@ fn shade_base_color(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> vec
     return (*hit).shader.base_color;
 }
 
-fn error_shader() -> vec3f {
-    return vec3f(0.7, 0.0, 0.7);
-}
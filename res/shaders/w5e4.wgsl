@@ -1,5 +1,8 @@
 // Vertex shader
 
+#import fullscreen
+#import ray
+
 const PI = 3.14159265359;
 const ETA = 0.00001;
 
@@ -18,40 +21,11 @@ const SHADER_TYPE_DEFAULT: u32 = 0u;
 
 const MAX_DEPTH: i32 = 10;
 
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-};
-
-struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
-    @location(0) coords: vec2<f32>,
-};
-
 struct FragmentOutput {
     @location(0) frame: vec4f,
     @location(1) accum: vec4f,
 }
 
-struct Ray {
-    direction: vec3f,
-    origin: vec3f,
-    tmax: f32,
-    tmin: f32,
-};
-
-fn ray_init(direction: vec3f, origin: vec3f) -> Ray {
-    return Ray(
-        direction,
-        origin,
-        5000.0,
-        ETA,
-    );
-}
-
-fn ray_at(r: Ray, dist: f32) -> vec3f {
-    return r.origin + r.direction * dist;
-}
-
 struct Camera {
     origin: vec3f,
     direction: vec3f,
@@ -91,16 +65,6 @@ fn set_material(hit: ptr<function, HitRecord>, index: u32) {
     (*hit).material = index;
 }
 
-@vertex
-fn vs_main(
-    model: VertexInput,
-) -> VertexOutput {
-    var out: VertexOutput;
-    out.coords = vec2f(model.position.x, model.position.y);
-    out.clip_position = vec4<f32>(model.position, 1.0);
-    return out;
-}
-
 fn get_camera_ray(uv: vec2f, sample: u32) -> Ray {
     let e = uniforms.camera_pos;
     let p = uniforms.camera_look_at;
@@ -148,7 +112,7 @@ fn fs_main(in: VertexOutput) -> FragmentOutput {
     }
 
     let output = FragmentOutput(
-        vec4f(pow(result, vec3f(1.5/1.0)), bgcolor.a),
+        vec4f(result, bgcolor.a),
         vec4f(0.0),
     );
     return output;
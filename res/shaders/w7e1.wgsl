@@ -1,3 +1,10 @@
+#import fullscreen
+#import ray
+#import light
+#import random
+#import output
+#import debug
+
 const PI = 3.14159265359;
 const ETA = 0.001;
 
@@ -29,40 +36,6 @@ const MAX_DEPTH: i32 = 10;
 //@group(2) @binding(1)
 //var<storage> indexBuffer: array<vec4u>;
 
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-};
-
-struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
-    @location(0) coords: vec2<f32>,
-};
-
-struct FragmentOutput {
-    @location(0) frame: vec4f,
-    @location(1) accum: vec4f,
-}
-
-struct Ray {
-    direction: vec3f,
-    origin: vec3f,
-    tmax: f32,
-    tmin: f32,
-};
-
-fn ray_init(direction: vec3f, origin: vec3f) -> Ray {
-    return Ray(
-        direction,
-        origin,
-        5000.0,
-        ETA,
-    );
-}
-
-fn ray_at(r: Ray, dist: f32) -> vec3f {
-    return r.origin + r.direction * dist;
-}
-
 struct Camera {
     origin: vec3f,
     direction: vec3f,
@@ -70,20 +43,6 @@ struct Camera {
     constant: f32,
 };
 
-struct Light {
-    l_i: vec3f, // intensity
-    w_i: vec3f, // incidence
-    dist: f32, // distance
-};
-
-fn light_init() -> Light {
-    return Light(
-        vec3f(0.0),
-        vec3f(0.0),
-        999999.0,
-    );
-}
-
 struct HitRecord {
     has_hit: bool,
     depth: i32,
@@ -95,6 +54,7 @@ struct HitRecord {
     diffuse: vec3f,
     uv0: vec2f,
     material: u32,
+    object: u32,
     // shader properties
     shader: ShaderType,
     ior1_over_ior2: f32,
@@ -114,6 +74,7 @@ fn hit_record_init() -> HitRecord {
         vec3f(0.0),
         vec2f(0.0),
         0u,
+        NO_OBJECT,
         // shader properties
         SHADER_TYPE_NO_RENDER,
         1.0,
@@ -137,44 +98,6 @@ fn triangle_area(v0: vec3f, v1: vec3f, v2: vec3f) -> f32 {
     return 0.5 * sqrt(dot(cr, cr));
 }
 
-// PRNG xorshift seed generator by NVIDIA
-fn prng_xorshift_seed_generator(val0: u32, val1: u32) -> u32 {
-      let N = 16u; // User specified number of iterations
-      var v0: u32 = val0;
-      var v1: u32 = val1;
-      var s0: u32 = 0u;
-
-      for(var n = 0u; n < N; n++) {
-        s0 += 0x9e3779b9u;
-        v0 += ((v1<<4u)+0xa341316cu)^(v1+s0)^((v1>>5u)+0xc8013ea4u);
-        v1 += ((v0<<4u)+0xad90777du)^(v0+s0)^((v0>>5u)+0x7e95761eu);
-      }
-
-      return v0;
-}
-
- // Generate random unsigned int in [0, 2^31)
- fn mcg31(prev: ptr<function, u32>) -> u32 {
-    let LCG_A = 1977654935u; // Multiplier from Hui-Ching Tang [EJOR 2007]
-    *prev = (LCG_A * (*prev)) & 0x7FFFFFFFu;
-    return *prev;
-}
-// Generate random float in [0, 1)
-fn rnd(prev: ptr<function, u32>) -> f32
-{
-    return f32(mcg31(prev)) / f32(0x80000000u);
-}
-
-@vertex
-fn vs_main(
-    model: VertexInput,
-) -> VertexOutput {
-    var out: VertexOutput;
-    out.coords = vec2f(model.position.x, model.position.y);
-    out.clip_position = vec4<f32>(model.position, 1.0);
-    return out;
-}
-
 fn get_camera_ray(uv: vec2f, jitter: vec2f) -> Ray {
     let e = uniforms.camera_pos;
     let p = uniforms.camera_look_at;
@@ -206,15 +129,27 @@ fn fs_main(in: VertexOutput) -> FragmentOutput {
     let coord_x: u32 = u32(in.clip_position.x);
     let res_x: u32 = uniforms.resolution.x;
     let launch_idx = coord_y*uniforms.resolution.x + coord_x;
+    let pixel = vec2u(in.clip_position.xy);
+    var stats = pixel_stats_load(pixel);
+    if (pixel_converged(stats)) {
+        // all targets keep the values of the last traced sample
+        discard;
+    }
+    atomicAdd(&activePixels, 1u);
     var t = prng_xorshift_seed_generator(launch_idx, uniforms.iteration);
     let jitter = vec2f(rnd(&t), rnd(&t))/f32(uniforms.resolution.y);
     
     var result = vec3f(0.0);
     // each loop is one bounce
-    var r = get_camera_ray(uv, jitter);
+    let primary = get_camera_ray(uv, jitter);
+    var r = primary;
     var hit = hit_record_init();
+    var aov = aov_init();
     for (var i = 0; i < max_depth; i++) {
         if (intersect_scene_bsp(&r, &hit)) {
+            if (i == 0) {
+                aov = aov_from_hit(&hit, primary);
+            }
             result += shade(&r, &hit);
         } else {
             result += bgcolor.rgb; break;
@@ -225,12 +160,20 @@ fn fs_main(in: VertexOutput) -> FragmentOutput {
         }
     }
     
-    let curr_sum = textureLoad(renderTexture, vec2u(in.clip_position.xy), 0).rgb*f32(uniforms.iteration);
-    let accum_color = (result + curr_sum)/f32(uniforms.iteration + 1u);
+    // pixels can have different sample counts with adaptive sampling
+    let curr_sum = textureLoad(renderTexture, pixel, 0).rgb*f32(stats.count);
+    let accum_color = (result + curr_sum)/f32(stats.count + 1u);
+    stats = pixel_stats_add(stats, result);
 
     let output = FragmentOutput(
-        vec4f(pow(accum_color, vec3f(1.5/1.0)), bgcolor.a),
+        vec4f(accum_color, bgcolor.a),
         vec4f(accum_color, 1.0),
+        vec4f(aov.albedo, 1.0),
+        vec4f(aov.normal, 1.0),
+        aov.depth,
+        vec2u(aov.object, aov.material),
+        stats.count,
+        pixel_stats_output(stats),
     );
     return output;
 }
@@ -291,6 +234,7 @@ fn intersect_triangle_indexed(r: ptr<function, Ray>, hit: ptr<function, HitRecor
     (*hit).position = pos;
     (*hit).normal = normalize(n0 * (1.0 - beta - gamma) + n1 * beta + n2 * gamma);
     set_material(hit, material);
+    (*hit).object = v;
 
     return true;
 }
@@ -316,6 +260,7 @@ fn intersect_sphere(r: ptr<function, Ray>, hit: ptr<function, HitRecord>, center
 
     (*r).tmax = root;
     (*hit).dist = root;
+    (*hit).object = NO_OBJECT;
     let pos = ray_at(ray, root);
     let normal = normalize(pos - center);
     (*hit).position = pos;
@@ -507,7 +452,6 @@ fn transmit(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> vec3f {
     return vec3f(0.0, 0.0, 0.0);
 }
 
-
 fn shade_normal(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> vec3f {
     return ((*hit).normal + 1.0) * 0.5;
 }
@@ -518,6 +462,3 @@ fn shade_base_color(r: ptr<function, Ray>, hit: ptr<function, HitRecord>) -> vec
     return color;
 }
 
-fn error_shader() -> vec3f {
-    return vec3f(0.7, 0.0, 0.7);
-}
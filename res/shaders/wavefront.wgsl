@@ -0,0 +1,176 @@
+// Wavefront path tracing kernels, appended after the wavefront bindings.
+// Every bounce of all paths is traced by separate passes over queues:
+// shadow rays of the last bounce, intersection, shading, then
+// wavefront_prepare swaps the queues and writes the dispatch arguments.
+// The scene shader provides the path tracer (res/shaders/path_tracer.wgsl):
+// HitRecord, hit_record_init, pixel_uv, camera_jitter, get_camera_ray,
+// offset_ray, intersect_scene, occluded, shade, accumulate and MAX_DEPTH.
+
+// Has to match bindings::wavefront::WORKGROUP_SIZE, naga
+// only takes literals in @workgroup_size
+const WORKGROUP_SIZE: u32 = 256u;
+
+// The pixel was traced this frame
+const PATH_TRACED: u32 = 1u;
+// Emission is counted at the next hit
+const PATH_EMIT: u32 = 2u;
+
+fn workgroups(count: u32) -> u32 {
+    return (count + WORKGROUP_SIZE - 1u) / WORKGROUP_SIZE;
+}
+
+// The ray queue is split in two halves, one is read while the
+// rays of the next bounce are pushed to the other
+fn queue_offset(bounce: u32) -> u32 {
+    return (bounce % 2u) * arrayLength(&paths);
+}
+
+fn push_ray(bounce: u32, path: u32) {
+    let slot = atomicAdd(&queues.next_rays, 1u);
+    rayQueue[queue_offset(bounce + 1u) + slot] = path;
+}
+
+fn path_ray(path: PathState) -> Ray {
+    var ray = ray_init(path.direction, path.origin);
+    ray.tmin = path.tmin;
+    return ray;
+}
+
+@compute @workgroup_size(256)
+fn wavefront_generate(@builtin(global_invocation_id) id: vec3u) {
+    // x, y, width and height
+    let tile = queues.tile;
+    if (id.x >= tile.z * tile.w) {
+        return;
+    }
+    let pixel = tile.xy + vec2u(id.x % tile.z, id.x / tile.z);
+    let index = pixel.y * uniforms.resolution.x + pixel.x;
+    aovs[index] = PathAov(vec3f(0.0), NO_OBJECT, vec3f(0.0), NO_OBJECT, 0.0);
+    let stats = pixel_stats_load(pixel);
+    if (pixel_converged(stats)) {
+        paths[index].flags = 0u;
+        return;
+    }
+    atomicAdd(&activePixels, 1u);
+    // same random numbers as the fragment backend
+    var seed = prng_xorshift_seed_generator(index, uniforms.iteration);
+    let ray = get_camera_ray(pixel_uv(pixel), camera_jitter(&seed));
+    paths[index] = PathState(
+        ray.origin,
+        seed,
+        ray.direction,
+        0u,
+        vec3f(1.0),
+        PATH_TRACED | PATH_EMIT,
+        vec3f(0.0),
+        ray.tmin,
+    );
+    push_ray(0u, index);
+}
+
+@compute @workgroup_size(1)
+fn wavefront_prepare() {
+    let rays = atomicExchange(&queues.next_rays, 0u);
+    let shadows = atomicExchange(&queues.next_shadows, 0u);
+    queues.rays = rays;
+    queues.shadows = shadows;
+    queues.bounce += 1u;
+    queues.dispatch = array<u32, 6>(workgroups(rays), 1u, 1u, workgroups(shadows), 1u, 1u);
+}
+
+@compute @workgroup_size(256)
+fn wavefront_intersect(@builtin(global_invocation_id) id: vec3u) {
+    if (id.x >= queues.rays) {
+        return;
+    }
+    let index = rayQueue[queue_offset(queues.bounce) + id.x];
+    var r = path_ray(paths[index]);
+    var hit = hit_record_init();
+    if (intersect_scene(&r, &hit)) {
+        hits[index] = PathHit(hit.position, hit.object, hit.normal, hit.material);
+    } else {
+        hits[index].object = NO_OBJECT;
+    }
+}
+
+@compute @workgroup_size(256)
+fn wavefront_shade(@builtin(global_invocation_id) id: vec3u) {
+    if (id.x >= queues.rays) {
+        return;
+    }
+    let bounce = queues.bounce;
+    let index = rayQueue[queue_offset(bounce) + id.x];
+    var path = paths[index];
+    let stored = hits[index];
+    let r = path_ray(path);
+    if (stored.object == NO_OBJECT) {
+        paths[index].radiance += BACKGROUND_COLOR * path.throughput;
+        return;
+    }
+
+    var hit = hit_record_init();
+    hit.dist = distance(stored.position, r.origin);
+    hit.position = stored.position;
+    hit.normal = stored.normal;
+    hit.material = stored.material;
+    hit.object = stored.object;
+    if (path.depth == 0u) {
+        let aov = aov_from_hit(&hit, r);
+        aovs[index] = PathAov(aov.albedo, aov.object, aov.normal, aov.material, aov.depth);
+    }
+
+    var seed = path.seed;
+    let scatter = shade(r, &hit, path.throughput, (path.flags & PATH_EMIT) != 0u, &seed);
+    path.seed = seed;
+    path.radiance += scatter.radiance;
+    path.depth += 1u;
+    if (scatter.has_shadow_ray) {
+        let slot = atomicAdd(&queues.next_shadows, 1u);
+        shadowRays[slot] = ShadowRay(
+            scatter.shadow_ray.origin,
+            index,
+            scatter.shadow_ray.direction,
+            scatter.shadow_ray.tmin,
+            scatter.shadow_radiance,
+            scatter.shadow_ray.tmax,
+        );
+    }
+    if (scatter.continues && path.depth < MAX_DEPTH) {
+        path.origin = scatter.next.origin;
+        path.direction = scatter.next.direction;
+        path.tmin = scatter.next.tmin;
+        path.throughput = scatter.throughput;
+        path.flags = select(PATH_TRACED, PATH_TRACED | PATH_EMIT, scatter.emit);
+        push_ray(bounce, index);
+    }
+    paths[index] = path;
+}
+
+@compute @workgroup_size(256)
+fn wavefront_shadow(@builtin(global_invocation_id) id: vec3u) {
+    if (id.x >= queues.shadows) {
+        return;
+    }
+    let shadow = shadowRays[id.x];
+    var r = ray_init(shadow.direction, shadow.origin);
+    r.tmin = shadow.tmin;
+    r.tmax = shadow.tmax;
+    if (!occluded(r)) {
+        // every path has at most one shadow ray per bounce
+        paths[shadow.path].radiance += shadow.radiance;
+    }
+}
+
+// Accumulates the traced paths into the render targets
+@fragment
+fn fs_wavefront(in: VertexOutput) -> FragmentOutput {
+    let pixel = vec2u(in.clip_position.xy);
+    let index = pixel.y * uniforms.resolution.x + pixel.x;
+    let path = paths[index];
+    if ((path.flags & PATH_TRACED) == 0u) {
+        discard;
+    }
+    let stored = aovs[index];
+    let aov = Aov(stored.albedo, stored.normal, stored.depth, stored.object, stored.material);
+    return accumulate(pixel, pixel_stats_load(pixel), path.radiance, aov);
+}
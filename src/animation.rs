@@ -0,0 +1,142 @@
+/// Vertex animations of the objects of a scene. The model is deformed on the CPU every
+/// frame and its BVH refitted to the moved vertices instead of rebuilt, see `AnimatedMesh`.
+
+use std::{f32::consts::TAU, ops::Range};
+
+use rayon::prelude::*;
+
+use crate::{
+    data_structures::{
+        bbox::Bbox,
+        hlbvh::Bvh,
+        vector::{vec3f, Vec3f32, Vec4f32},
+    },
+    mesh::Mesh,
+};
+
+/// Triangles per leaf of the BVH of an animated mesh, like `Mesh::bvh`
+const MAX_PRIMS: u32 = 4;
+
+/// Deformation of the rest pose of an object over time, `period` is in seconds
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum VertexAnimation {
+    /// A wave running up the y axis moves the vertices along their normals
+    Wobble { amplitude: f32, wavelength: f32, period: f32 },
+    /// Rotation around the vertical axis through the center of the object, by an
+    /// angle growing from 0 at the bottom to `max_angle` radians at the top
+    /// that swings back and forth
+    Twist { max_angle: f32, period: f32 },
+}
+
+impl VertexAnimation {
+    /// Position and normal of a vertex at `time`, `bounds` are the bounds of the rest pose
+    fn animate(&self, position: Vec3f32, normal: Vec3f32, bounds: &Bbox, time: f32) -> (Vec3f32, Vec3f32) {
+        match *self {
+            VertexAnimation::Wobble { amplitude, wavelength, period } => {
+                let offset = amplitude * (TAU * (position.1 / wavelength - time / period)).sin();
+                (position + normal * offset, normal)
+            }
+            VertexAnimation::Twist { max_angle, period } => {
+                let height = (position.1 - bounds.min.1) / bounds.extent_dim(1).max(f32::EPSILON);
+                let angle = max_angle * height * (TAU * time / period).sin();
+                let (sin, cos) = angle.sin_cos();
+                let rotate = |v: Vec3f32| vec3f(cos * v.0 + sin * v.2, v.1, cos * v.2 - sin * v.0);
+                let center = vec3f(bounds.center().0, 0.0, bounds.center().2);
+                (rotate(position - center) + center, rotate(normal))
+            }
+        }
+    }
+}
+
+/// The vertices of an object in the merged mesh of a scene and how they move
+#[derive(Debug, Clone)]
+pub struct AnimatedVertices {
+    pub vertices: Range<usize>,
+    pub animation: VertexAnimation,
+}
+
+/// A mesh with animated vertices and the BVH that is traversed on the GPU
+pub struct AnimatedMesh {
+    /// Animated vertices with the bounds of their rest pose
+    animated: Vec<(AnimatedVertices, Bbox)>,
+    rest_vertices: Vec<Vec4f32>,
+    rest_normals: Vec<Vec4f32>,
+    /// The mesh at the time of the last `update`
+    pub mesh: Mesh,
+    pub bvh: Bvh,
+}
+
+impl AnimatedMesh {
+    /// Fails for a mesh without triangles to build the BVH over
+    /// or animated vertices outside of the mesh
+    pub fn new(mesh: Mesh, animated: Vec<AnimatedVertices>) -> anyhow::Result<Self> {
+        anyhow::ensure!(!mesh.indices.is_empty(), "An animated mesh needs triangles");
+        for AnimatedVertices { vertices, .. } in &animated {
+            anyhow::ensure!(
+                vertices.start <= vertices.end && vertices.end <= mesh.vertices.len(),
+                "Animated vertices {vertices:?} are outside of the {} vertices of the mesh",
+                mesh.vertices.len()
+            );
+        }
+        let animated = animated
+            .into_iter()
+            .map(|animated| {
+                let bounds = mesh.vertices[animated.vertices.clone()].iter().fold(Bbox::new(), |mut bounds, vertex| {
+                    bounds.include_vertex(vertex.xyz());
+                    bounds
+                });
+                (animated, bounds)
+            })
+            .collect();
+        Ok(Self {
+            animated,
+            rest_vertices: mesh.vertices.clone(),
+            rest_normals: mesh.normals.clone(),
+            bvh: Bvh::new(&mesh, MAX_PRIMS, false),
+            mesh,
+        })
+    }
+
+    /// Deforms the mesh to `time` in seconds and refits the BVH, it is rebuilt once
+    /// refitting degraded it too much. Returns whether the BVH was rebuilt, its
+    /// node count can differ then while a refitted BVH has the same layout.
+    pub fn update(&mut self, time: f32) -> bool {
+        for (AnimatedVertices { vertices: range, animation }, bounds) in &self.animated {
+            let Mesh { vertices, normals, .. } = &mut self.mesh;
+            vertices[range.clone()]
+                .par_iter_mut()
+                .zip(normals[range.clone()].par_iter_mut())
+                .zip(self.rest_vertices[range.clone()].par_iter().zip(&self.rest_normals[range.clone()]))
+                .for_each(|((vertex, normal), (rest_vertex, rest_normal))| {
+                    let (position, direction) = animation.animate(rest_vertex.xyz(), rest_normal.xyz(), bounds, time);
+                    *vertex = Vec4f32::from((position.0, position.1, position.2, rest_vertex.3));
+                    *normal = Vec4f32::from((direction.0, direction.1, direction.2, rest_normal.3));
+                });
+        }
+
+        self.bvh.refit(&self.mesh);
+        if self.bvh.needs_rebuild() {
+            self.bvh = Bvh::new(&self.mesh, MAX_PRIMS, false);
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod animation_test {
+    use super::*;
+
+    #[test]
+    fn animated_vertices_outside_of_the_mesh_are_rejected() {
+        let teapot = Mesh::from_obj("res/models/teapot.obj").expect("Failed to load model");
+        let animation = VertexAnimation::Wobble { amplitude: 0.1, wavelength: 1.0, period: 1.0 };
+        let inside = AnimatedVertices { vertices: 0..teapot.vertices.len(), animation };
+        let outside = AnimatedVertices { vertices: 1..teapot.vertices.len() + 1, animation };
+        assert!(AnimatedMesh::new(teapot.clone(), vec![inside]).is_ok());
+        assert!(AnimatedMesh::new(teapot.clone(), vec![outside]).is_err());
+
+        let empty = Mesh { indices: vec![], ..teapot };
+        assert!(AnimatedMesh::new(empty, vec![]).is_err());
+    }
+}
@@ -0,0 +1,11 @@
+//! Benchmark binary for the bind groups split by update frequency
+
+use raytracer_wgpu_lib::benchmark_bind_groups;
+
+fn main() {
+    let frames = 1000;
+    if let Err(err) = benchmark_bind_groups(frames) {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+}
@@ -1,6 +1,11 @@
+use raytracer_wgpu_lib::animation::{AnimatedMesh, AnimatedVertices, VertexAnimation};
 use raytracer_wgpu_lib::data_structures::bvh_util::BvhConstructionTime;
-use raytracer_wgpu_lib::data_structures::hlbvh::Bvh;
-use raytracer_wgpu_lib::data_structures::bsp_tree::BspTree;
+use raytracer_wgpu_lib::data_structures::bvh_traversal::{random_rays, traverse, traverse_quantized, traverse_wide, Ray, TraversalResult};
+use raytracer_wgpu_lib::data_structures::hlbvh::{Bvh, BvhLayout, GpuNode, GpuWideNode};
+use raytracer_wgpu_lib::data_structures::quantized_bvh::GpuQuantizedNode;
+use raytracer_wgpu_lib::data_structures::sbvh::SBVH_ALPHA;
+use raytracer_wgpu_lib::data_structures::vector::Vec4u32;
+use raytracer_wgpu_lib::data_structures::bsp_tree::{BspParams, BspTree};
 use raytracer_wgpu_lib::mesh::Mesh;
 
 use std::ops::{AddAssign, DivAssign};
@@ -21,7 +26,7 @@ fn run_benchmark(runs: u32) {
     let model_dragon = Mesh::from_obj("res/models/dragon.obj").expect("Failed to load model");
 
     // Performance scaling with triangles
-    println!("Performance scaling with triangles (1/4):");
+    println!("Performance scaling with triangles (1/8):");
     let bvh_teapot_4_mt =
     run_bvh(&model_teapot, 4, false, runs).display("BVH: Teapot (6,320), 4, MT");
     let bvh_bunny_4_mt =
@@ -31,7 +36,7 @@ fn run_benchmark(runs: u32) {
     println!("----------------------------------");
 
     // Performance scaling with leaf primitives:
-    println!("\nPerformance scaling with maximum leaf primitives (2/4):");
+    println!("\nPerformance scaling with maximum leaf primitives (2/8):");
     run_bvh(&model_dragon, 1, false, runs).display("BVH: Dragon, 1, MT");
     run_bvh(&model_dragon, 2, false, runs).display("BVH: Dragon, 2, MT");
     bvh_dragon_4_mt.display("BVH: Dragon, 4, MT");
@@ -42,7 +47,7 @@ fn run_benchmark(runs: u32) {
     println!("----------------------------------");
 
     // Multithreaded performance scaling:
-    println!("\nMultithreaded performance scaling (3/4):");
+    println!("\nMultithreaded performance scaling (3/8):");
     bvh_dragon_4_mt.display("BVH: Dragon, 4, MT");
     let bvh_dragon_4_st = 
     run_bvh(&model_dragon, 4, true, runs).display("BVH: Dragon, 4, ST");
@@ -52,7 +57,7 @@ fn run_benchmark(runs: u32) {
     println!("----------------------------------");
 
     // Comparison with BSP
-    println!("\nPerformance comparison with the BSP (4/4):");
+    println!("\nPerformance comparison with the BSP (4/8):");
     println!("\nTeapot:");
     bvh_teapot_4_mt.display("BVH: Teapot, 4, MT");
     run_single_bsp(&model_teapot, 20, 4, runs).display("BSP: Teapot, 4, dep: 20");
@@ -69,6 +74,46 @@ fn run_benchmark(runs: u32) {
     run_single_bsp(&model_dragon, 20, 8, runs).display("BSP: Dragon, 8, dep: 20");
     println!("----------------------------------");
 
+    // Stack based against stackless traversal
+    println!("\nTraversal of the BVH layouts, 10,000 random rays (5/8):");
+    println!("\nTeapot:");
+    run_traversal(&model_teapot, 10_000);
+    println!("\nBunny:");
+    run_traversal(&model_bunny, 10_000);
+    println!("\nDragon:");
+    run_traversal(&model_dragon, 10_000);
+    println!("----------------------------------");
+
+    // Collapsing and quantizing the binary BVH
+    println!("\nWide and quantized BVHs, 10,000 random rays (6/8):");
+    println!("\nTeapot:");
+    run_collapse(&model_teapot, 10_000);
+    println!("\nBunny:");
+    run_collapse(&model_bunny, 10_000);
+    println!("\nDragon:");
+    run_collapse(&model_dragon, 10_000);
+    println!("----------------------------------");
+
+    // Refitting an animated mesh against rebuilding its BVH every frame
+    println!("\nRefitting a twisting mesh, 60 frames (7/8):");
+    println!("\nTeapot:");
+    run_refit(&model_teapot, 60);
+    println!("\nBunny:");
+    run_refit(&model_bunny, 60);
+    println!("\nDragon:");
+    run_refit(&model_dragon, 60);
+    println!("----------------------------------");
+
+    // Spatial splits against object splits and the BSP tree
+    println!("\nSBVH against HLBVH and BSP, 10,000 random rays (8/8):");
+    println!("\nTeapot:");
+    run_sbvh(&model_teapot, 10_000);
+    println!("\nBunny:");
+    run_sbvh(&model_bunny, 10_000);
+    println!("\nDragon:");
+    run_sbvh(&model_dragon, 10_000);
+    println!("----------------------------------");
+
     println!("\nAll done.");
 }
 
@@ -88,12 +133,182 @@ fn run_bvh(model: &Mesh, max_prims: u32, single_threaded: bool, runs: u32) -> Bv
     total
 }
 
+/// Traverses the same rays through both layouts and checks that they agree
+fn run_traversal(model: &Mesh, rays: usize) {
+    let bvh = Bvh::new(model, 4, false);
+    let triangles = bvh.triangles();
+    let rays = random_rays(&bvh.bbox(), rays, 0);
+    let run = |layout: BvhLayout| {
+        let nodes = bvh.flatten_layout(layout);
+        let timer = Instant::now();
+        let results: Vec<TraversalResult> = rays
+            .iter()
+            .map(|ray: &Ray| traverse(&nodes, &triangles, model, ray, layout, false))
+            .collect();
+        (timer.elapsed(), results)
+    };
+    let (stack_time, stack) = run(BvhLayout::Stack);
+    let (threaded_time, threaded) = run(BvhLayout::Threaded);
+    let visited = |results: &[TraversalResult]| {
+        results.iter().map(|r| r.visited_nodes as f32).sum::<f32>() / results.len() as f32
+    };
+    println!("Stack:");
+    println!("  time:          {:?}", stack_time);
+    println!("  visited nodes: {:.1}", visited(&stack));
+    println!("Threaded:");
+    println!("  time:          {:?}", threaded_time);
+    println!("  visited nodes: {:.1}", visited(&threaded));
+    let mismatches = stack
+        .iter()
+        .zip(threaded.iter())
+        .filter(|(s, t)| s.distance != t.distance)
+        .count();
+    println!("  mismatched hits: {mismatches}");
+}
+
+/// Node count, memory and node fetches per ray of the binary, the wide and the quantized BVHs
+fn run_collapse(model: &Mesh, rays: usize) {
+    let bvh = Bvh::new(model, 4, false);
+    let triangles = bvh.triangles();
+    let rays = random_rays(&bvh.bbox(), rays, 0);
+    let binary = bvh.flatten();
+    let binary_memory = binary.len() * std::mem::size_of::<GpuNode>();
+    let binary_hits: Vec<TraversalResult> = rays
+        .iter()
+        .map(|ray| traverse(&binary, &triangles, model, ray, BvhLayout::Stack, false))
+        .collect();
+    let visited = |results: &[TraversalResult]| {
+        results.iter().map(|r| r.visited_nodes as f32).sum::<f32>() / results.len() as f32
+    };
+    println!("Binary:");
+    println!("  nodes:         {}", binary.len());
+    println!("  memory:        {} KiB", binary_memory / 1024);
+    println!("  visited nodes: {:.1}", visited(&binary_hits));
+    for width in [4, 8] {
+        let timer = Instant::now();
+        let nodes = bvh.collapse(width);
+        let collapse_time = timer.elapsed();
+        let memory = nodes.len() * std::mem::size_of::<GpuWideNode>();
+        let hits: Vec<TraversalResult> = rays
+            .iter()
+            .map(|ray| traverse_wide(&nodes, &triangles, model, ray, false))
+            .collect();
+        let mismatches = hits
+            .iter()
+            .zip(binary_hits.iter())
+            .filter(|(w, b)| w.distance != b.distance)
+            .count();
+        println!("BVH{width}:");
+        println!("  collapse:      {:?}", collapse_time);
+        println!("  nodes:         {} ({:.1}% fewer)", nodes.len(), 100.0 - 100.0 * nodes.len() as f32 / binary.len() as f32);
+        println!("  memory:        {} KiB ({:.1}% less)", memory / 1024, 100.0 - 100.0 * memory as f32 / binary_memory as f32);
+        println!("  visited nodes: {:.1}", visited(&hits));
+        println!("  mismatched hits: {mismatches}");
+    }
+
+    let timer = Instant::now();
+    let nodes = bvh.quantize();
+    let quantize_time = timer.elapsed();
+    let memory = nodes.len() * std::mem::size_of::<GpuQuantizedNode>();
+    let hits: Vec<TraversalResult> = rays
+        .iter()
+        .map(|ray| traverse_quantized(&nodes, &triangles, model, ray, false))
+        .collect();
+    let mismatches = hits
+        .iter()
+        .zip(binary_hits.iter())
+        .filter(|(q, b)| q.distance != b.distance)
+        .count();
+    println!("Quantized BVH4:");
+    println!("  quantize:      {:?}", quantize_time);
+    println!("  nodes:         {} ({:.1}% fewer)", nodes.len(), 100.0 - 100.0 * nodes.len() as f32 / binary.len() as f32);
+    println!("  memory:        {} KiB ({:.1}% less)", memory / 1024, 100.0 - 100.0 * memory as f32 / binary_memory as f32);
+    println!("  visited nodes: {:.1}", visited(&hits));
+    println!("  mismatched hits: {mismatches}");
+}
+
+fn run_refit(model: &Mesh, frames: u32) {
+    let animation = VertexAnimation::Twist { max_angle: 1.0, period: 2.0 };
+    let vertices = 0..model.vertices.len();
+    let mut animated = AnimatedMesh::new(model.clone(), vec![AnimatedVertices { vertices, animation }])
+        .expect("Failed to animate model");
+    let (mut refit_time, mut build_time) = (Duration::ZERO, Duration::ZERO);
+    let (mut rebuilds, mut max_degradation) = (0, 1.0f32);
+    for frame in 1..=frames {
+        let timer = Instant::now();
+        // refitted and rebuilt once degraded
+        rebuilds += animated.update(frame as f32 / frames as f32) as u32;
+        refit_time += timer.elapsed();
+        max_degradation = max_degradation.max(animated.bvh.degradation());
+
+        let timer = Instant::now();
+        let rebuilt = Bvh::new(&animated.mesh, 4, false);
+        build_time += timer.elapsed();
+        if frame == frames {
+            println!("  SAH cost refitted: {:.1}", animated.bvh.sah_cost());
+            println!("  SAH cost rebuilt:  {:.1}", rebuilt.sah_cost());
+        }
+    }
+    println!("  animate + refit:   {:?} per frame", refit_time / frames);
+    println!("  rebuild:           {:?} per frame", build_time / frames);
+    println!("  rebuilds:          {rebuilds}");
+    println!("  max degradation:   {max_degradation:.2}");
+}
+
+fn run_sbvh(model: &Mesh, rays: usize) {
+    let timer = Instant::now();
+    let hlbvh = Bvh::new(model, 4, false);
+    let hlbvh_time = timer.elapsed();
+    let timer = Instant::now();
+    let sbvh = Bvh::new_sbvh(model, 4, SBVH_ALPHA);
+    let sbvh_time = timer.elapsed();
+    let rays = random_rays(&hlbvh.bbox(), rays, 0);
+
+    for (name, bvh, time) in [("HLBVH", &hlbvh, hlbvh_time), ("SBVH", &sbvh, sbvh_time)] {
+        let nodes = bvh.flatten();
+        let triangles = bvh.triangles();
+        let memory = nodes.len() * std::mem::size_of::<GpuNode>() + triangles.len() * std::mem::size_of::<u32>();
+        let visited = rays
+            .iter()
+            .map(|ray| traverse(&nodes, &triangles, model, ray, BvhLayout::Stack, false).visited_nodes as f32)
+            .sum::<f32>()
+            / rays.len() as f32;
+        println!("{name}:");
+        println!("  build:         {:?}", time);
+        println!("  SAH cost:      {:.2}", bvh.sah_cost());
+        println!("  nodes:         {}", nodes.len());
+        println!("  references:    {} ({:.1}% duplicates)", triangles.len(), 100.0 * triangles.len() as f32 / model.indices.len() as f32 - 100.0);
+        println!("  memory:        {} KiB", memory / 1024);
+        println!("  visited nodes: {:.1}", visited);
+    }
+
+    let timer = Instant::now();
+    let bsp = BspTree::new(model.bboxes(), BspParams::default());
+    let bsp_time = timer.elapsed();
+    let (planes, nodes) = bsp.bsp_array();
+    let references = bsp.primitive_ids().len();
+    let memory = planes.len() * std::mem::size_of::<f32>()
+        + nodes.len() * std::mem::size_of::<Vec4u32>()
+        + references * std::mem::size_of::<u32>();
+    println!("BSP:");
+    println!("  build:         {:?}", bsp_time);
+    println!("  SAH cost:      {:.2}", bsp.sah_cost());
+    println!("  nodes:         {}", nodes.len());
+    println!("  references:    {} ({:.1}% duplicates)", references, 100.0 * references as f32 / model.indices.len() as f32 - 100.0);
+    println!("  memory:        {} KiB", memory / 1024);
+}
+
 fn run_single_bsp(model: &Mesh, max_depth: u32, max_leaf_objects: u32, runs: u32) -> BspConstructionTime {
     let mut total = BspConstructionTime::default();
     for _ in 0..runs {
         let mut current = BspConstructionTime::default();
         let mut now = Instant::now();
-        let bsp = BspTree::new(model.bboxes(), max_depth, max_leaf_objects);
+        let params = BspParams {
+            max_depth,
+            max_objects_on_leaf: max_leaf_objects,
+            ..Default::default()
+        };
+        let bsp = BspTree::new(model.bboxes(), params);
         current.subdivision = now.elapsed();
         now = Instant::now();
         let _ = bsp.bsp_array();
@@ -0,0 +1,13 @@
+//! Checks the shaders of all scenes and the presentation pass without a GPU
+
+use raytracer_wgpu_lib::validate_shaders;
+
+fn main() {
+    match validate_shaders() {
+        Ok(()) => println!("All shaders are valid."),
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    }
+}
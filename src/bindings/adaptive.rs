@@ -0,0 +1,378 @@
+/// Adaptive sampling: the path tracing shaders keep a running mean and
+/// variance per pixel (Welford's algorithm) and stop tracing pixels whose
+/// relative error is below a threshold. Traced pixels are counted so the
+/// renderer can stop once every pixel has converged.
+
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use super::{
+    wgsl_struct::{wgsl_struct, WgslStruct},
+    Bindable, BufferOwner, WgslBindDescriptor, WgslSource, SCENE_VISIBILITY,
+};
+
+/// Convergence test appended to the shaders, see `pixel_converged`
+const ADAPTIVE_SHADER: &str = "res/shaders/adaptive.wgsl";
+
+/// mean, M2 and sample count of the luminance
+const VARIANCE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+
+pub const DEFAULT_THRESHOLD: f32 = 0.02;
+pub const DEFAULT_MIN_SAMPLES: u32 = 16;
+
+#[repr(C, align(16))]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct AdaptiveUniform {
+    /// Relative error below which a pixel is converged, 0 disables
+    threshold: f32,
+    /// Samples taken before a pixel may converge, the variance
+    /// estimate of the first few samples is unreliable
+    min_samples: u32,
+    _padding: [u32; 2],
+}
+
+static_assertions::assert_eq_size!(AdaptiveUniform, [u32; 4]);
+
+// the trailing padding is declared, WGSL would end the struct after min_samples
+wgsl_struct!(AdaptiveUniform as Adaptive {
+    threshold: f32,
+    min_samples: u32,
+    _padding: vec2u,
+});
+
+pub struct AdaptiveSamplingGpu {
+    uniforms: AdaptiveUniform,
+    buffer: wgpu::Buffer,
+    /// Written by the shaders as an extra color target
+    source: (wgpu::Texture, wgpu::TextureView),
+    /// Read by the shaders, copied from the source after every frame
+    destination: (wgpu::Texture, wgpu::TextureView),
+    /// Number of pixels traced in the last frame
+    active_pixels: wgpu::Buffer,
+    active_pixels_readback: wgpu::Buffer,
+}
+
+impl AdaptiveSamplingGpu {
+    pub fn new(device: &wgpu::Device, size: (u32, u32)) -> Self {
+        let uniforms = AdaptiveUniform {
+            threshold: 0.0,
+            min_samples: DEFAULT_MIN_SAMPLES,
+            _padding: [0; 2],
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Adaptive sampling uniform buffer"),
+            contents: bytemuck::cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let active_pixels = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Active pixels buffer"),
+            size: std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let active_pixels_readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Active pixels readback buffer"),
+            size: std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            uniforms,
+            buffer,
+            source: Self::build(device, size, wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC),
+            destination: Self::build(device, size, wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST),
+            active_pixels,
+            active_pixels_readback,
+        }
+    }
+
+    fn build(
+        device: &wgpu::Device,
+        size: (u32, u32),
+        usage: wgpu::TextureUsages,
+    ) -> (wgpu::Texture, wgpu::TextureView) {
+        let extent = wgpu::Extent3d {
+            width: size.0,
+            height: size.1,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Variance Target"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: VARIANCE_FORMAT,
+            usage,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        (texture, view)
+    }
+
+    pub fn change_dimension(&mut self, device: &wgpu::Device, new_size: (u32, u32)) {
+        self.source = Self::build(device, new_size, self.source.0.usage());
+        self.destination = Self::build(device, new_size, self.destination.0.usage());
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.uniforms.threshold > 0.0
+    }
+
+    pub fn update(&mut self, enabled: bool, threshold: f32, min_samples: u32) {
+        self.uniforms.threshold = if enabled { threshold.max(0.0) } else { 0.0 };
+        // the variance needs at least two samples
+        self.uniforms.min_samples = min_samples.max(2);
+    }
+
+    /// Color target following the AOV targets of the render pipeline
+    pub fn color_target() -> Option<wgpu::ColorTargetState> {
+        Some(wgpu::ColorTargetState {
+            format: VARIANCE_FORMAT,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL,
+        })
+    }
+
+    /// Shaders that do not write the statistics leave the target cleared
+    pub fn color_attachment(&self, clear: bool) -> Option<wgpu::RenderPassColorAttachment<'_>> {
+        Some(wgpu::RenderPassColorAttachment {
+            view: &self.source.1,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: match clear {
+                    true => wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    false => wgpu::LoadOp::Load,
+                },
+                store: true,
+            },
+        })
+    }
+
+    /// Has to be recorded before the render pass
+    pub fn encode_reset(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.clear_buffer(&self.active_pixels, 0, None);
+    }
+
+    /// Has to be recorded after the render pass, makes the statistics
+    /// available to the next frame and the active pixels to the CPU
+    pub fn encode_copy(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.copy_texture_to_texture(
+            self.source.0.as_image_copy(),
+            self.destination.0.as_image_copy(),
+            self.source.0.size(),
+        );
+        encoder.copy_buffer_to_buffer(
+            &self.active_pixels,
+            0,
+            &self.active_pixels_readback,
+            0,
+            std::mem::size_of::<u32>() as u64,
+        );
+    }
+
+    /// Number of pixels traced in the last submitted frame, waits for the GPU
+    pub fn active_pixels(&self, device: &wgpu::Device) -> Result<u32> {
+        let slice = self.active_pixels_readback.slice(..);
+        let (sender, receiver) = crossbeam_channel::bounded(1);
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+
+        let count = bytemuck::pod_read_unaligned::<u32>(&slice.get_mapped_range());
+        self.active_pixels_readback.unmap();
+        Ok(count)
+    }
+
+    /// Shader definitions of the bindings, known without a device
+    pub fn bind_descriptor() -> Vec<WgslBindDescriptor<'static>> {
+        vec![
+            WgslBindDescriptor {
+                struct_def: Some(AdaptiveUniform::WGSL),
+                bind_type: Some("uniform"),
+                var_name: "adaptive",
+                var_type: "Adaptive",
+                extra_code: None,
+            },
+            WgslBindDescriptor {
+                struct_def: None,
+                bind_type: None,
+                var_name: "varianceTexture",
+                var_type: "texture_2d<f32>",
+                extra_code: None,
+            },
+            WgslBindDescriptor {
+                struct_def: None,
+                bind_type: Some("storage, read_write"),
+                var_name: "activePixels",
+                var_type: "atomic<u32>",
+                extra_code: Some(WgslSource::File(ADAPTIVE_SHADER)),
+            },
+        ]
+    }
+}
+
+impl BufferOwner for AdaptiveSamplingGpu {
+    fn update_buffer(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniforms]));
+    }
+}
+
+impl Bindable for AdaptiveSamplingGpu {
+    fn get_layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+        vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: SCENE_VISIBILITY,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: SCENE_VISIBILITY,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: SCENE_VISIBILITY,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ]
+    }
+
+    fn get_bind_group_entries(&self) -> Vec<wgpu::BindGroupEntry> {
+        vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: self.buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&self.destination.1),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: self.active_pixels.as_entire_binding(),
+            },
+        ]
+    }
+
+    fn get_bind_descriptor(&self) -> Vec<WgslBindDescriptor> {
+        Self::bind_descriptor()
+    }
+}
+
+#[cfg(test)]
+mod adaptive_test {
+    use super::*;
+    use crate::bindings::{
+        append_shader_definitions,
+        gpu_test::{self, TestUniform},
+        preprocess::PreprocessedShader,
+        uniform::UniformGpu,
+    };
+    use rand::Rng;
+    use rand::SeedableRng;
+    use rand_pcg::Lcg64Xsh32;
+
+    const LUMINANCE: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+    /// Statistics of the first `i + 1` samples for every `i`, the last component is `w`
+    fn accumulate(samples: &[[f32; 3]], uniforms: Option<AdaptiveUniform>, w: &str) -> Option<Vec<[f32; 4]>> {
+        // adaptive.wgsl is appended with the bindings like in the scene shaders
+        let mut shader = PreprocessedShader::default();
+        let groups = [vec![UniformGpu::bind_descriptor()], vec![AdaptiveSamplingGpu::bind_descriptor()]];
+        append_shader_definitions(&mut shader, &groups).unwrap();
+        let uniforms = uniforms.as_ref().map(bytemuck::bytes_of);
+        let uniforms = uniforms
+            .map(|contents| TestUniform { group: 1, binding: 0, contents })
+            .into_iter()
+            .collect::<Vec<_>>();
+        let inputs = samples.iter().map(|&[r, g, b]| [r, g, b, 0.0]).collect::<Vec<_>>();
+        let body = format!(
+            "    var stats = PixelStats(0.0, 0.0, 0u);
+    for (var sample = 0u; sample <= i; sample++) {{
+        stats = pixel_stats_add(stats, testInputs[sample].rgb);
+    }}
+    return vec4f(stats.mean, stats.m2, f32(stats.count), {w});"
+        );
+        gpu_test::run(shader, &uniforms, 2, &inputs, inputs.len(), &body)
+    }
+
+    fn uniforms(threshold: f32, min_samples: u32) -> AdaptiveUniform {
+        AdaptiveUniform { threshold, min_samples, _padding: [0; 2] }
+    }
+
+    #[test]
+    fn running_statistics_match_two_passes() {
+        let mut rng = Lcg64Xsh32::seed_from_u64(7);
+        let samples = (0..1000)
+            .map(|_| [rng.gen_range(0.0..4.0), rng.gen_range(0.0..2.0), rng.gen_range(0.0..8.0)])
+            .collect::<Vec<[f32; 3]>>();
+        let Some(outputs) = accumulate(&samples, None, "pixel_relative_error(stats)") else {
+            return;
+        };
+        let [mean_gpu, m2, count, relative_error] = outputs[samples.len() - 1];
+
+        let luminance = samples
+            .iter()
+            .map(|radiance| radiance.iter().zip(LUMINANCE).map(|(c, w)| (c * w) as f64).sum::<f64>())
+            .collect::<Vec<_>>();
+        let n = luminance.len() as f64;
+        let mean = luminance.iter().sum::<f64>() / n;
+        let variance = luminance.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / (n - 1.0);
+
+        assert_eq!(count, 1000.0);
+        assert!((mean_gpu as f64 - mean).abs() < 1e-4 * mean, "{mean_gpu} != {mean}");
+        let running_variance = m2 as f64 / (n - 1.0);
+        assert!((running_variance - variance).abs() < 1e-3 * variance, "{running_variance} != {variance}");
+        // the standard error of the mean relative to the mean
+        let expected_error = (variance / n).sqrt() / mean;
+        assert!((relative_error as f64 - expected_error).abs() < 1e-3 * expected_error, "{relative_error} != {expected_error}");
+        // a single sample has no variance estimate
+        assert!(outputs[0][3] > 1e29, "{:?}", outputs[0]);
+    }
+
+    #[test]
+    fn pixels_converge_after_min_samples_below_the_threshold() {
+        let mut rng = Lcg64Xsh32::seed_from_u64(11);
+        let converged = "select(0.0, 1.0, pixel_converged(stats))";
+        let adaptive = uniforms(DEFAULT_THRESHOLD, DEFAULT_MIN_SAMPLES);
+
+        // a smooth pixel is below the threshold early on but has to take min_samples
+        let smooth = (0..64).map(|_| [1.0 + rng.gen_range(-0.01..0.01); 3]).collect::<Vec<_>>();
+        let Some(outputs) = accumulate(&smooth, Some(adaptive), converged) else {
+            return;
+        };
+        for [_, _, count, converged] in outputs {
+            assert_eq!(converged == 1.0, count as u32 >= DEFAULT_MIN_SAMPLES, "{count}");
+        }
+        // a threshold of zero disables adaptive sampling
+        let outputs = accumulate(&smooth, Some(uniforms(0.0, DEFAULT_MIN_SAMPLES)), converged).unwrap();
+        assert!(outputs.iter().all(|output| output[3] == 0.0));
+
+        // a noisy pixel keeps sampling after min_samples until its error is below the threshold
+        let noisy = (0..2000).map(|_| [rng.gen_range(0.0..2.0); 3]).collect::<Vec<_>>();
+        let outputs = accumulate(&noisy, Some(adaptive), converged).unwrap();
+        let first = outputs.iter().position(|output| output[3] == 1.0).expect("the noisy pixel never converged");
+        // the standard error of a uniform distribution on [0, 2] is below 2% after 834 samples
+        assert!((600..1100).contains(&first), "{first}");
+    }
+}
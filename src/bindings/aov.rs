@@ -0,0 +1,395 @@
+/// Arbitrary output variables (AOVs), extra render targets written by the
+/// path tracing shaders next to the beauty image. They hold first hit
+/// information which is used for denoising, compositing and debugging.
+
+use std::{fs::File, io::BufWriter, path::{Path, PathBuf}};
+
+use anyhow::*;
+use image::{codecs::hdr::HdrEncoder, Rgb, RgbaImage};
+
+use super::{texture::RenderFrame, Bindable, WgslBindDescriptor};
+use crate::denoise::{self, DenoiseParams, FloatImage, Guides};
+
+/// Written where a ray did not hit any triangle
+pub const NO_OBJECT: u32 = u32::MAX;
+
+/// Formats of the AOV render targets in shader location order,
+/// starting at location 2 after the frame and accumulation targets
+const FORMATS: [wgpu::TextureFormat; 5] = [
+    // albedo
+    wgpu::TextureFormat::Rgba16Float,
+    // world space shading normal
+    wgpu::TextureFormat::Rgba16Float,
+    // linear depth
+    wgpu::TextureFormat::R32Float,
+    // triangle and material id
+    wgpu::TextureFormat::Rg32Uint,
+    // sample count
+    wgpu::TextureFormat::R32Uint,
+];
+
+const VAR_NAMES: [&str; 5] = ["aovAlbedo", "aovNormal", "aovDepth", "aovIds", "aovSamples"];
+
+const ALBEDO: usize = 0;
+const NORMAL: usize = 1;
+const DEPTH: usize = 2;
+const IDS: usize = 3;
+const SAMPLES: usize = 4;
+
+pub struct AovTargets {
+    textures: Vec<wgpu::Texture>,
+    views: Vec<wgpu::TextureView>,
+}
+
+impl AovTargets {
+    pub fn new(device: &wgpu::Device, size: (u32, u32)) -> Self {
+        let (textures, views) = Self::build(device, size);
+        Self { textures, views }
+    }
+
+    fn build(device: &wgpu::Device, size: (u32, u32)) -> (Vec<wgpu::Texture>, Vec<wgpu::TextureView>) {
+        let extent = wgpu::Extent3d {
+            width: size.0,
+            height: size.1,
+            depth_or_array_layers: 1,
+        };
+        let textures = FORMATS
+            .iter()
+            .zip(VAR_NAMES)
+            .map(|(format, name)| {
+                device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some(name),
+                    size: extent,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: *format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING
+                        | wgpu::TextureUsages::COPY_SRC,
+                    view_formats: &[],
+                })
+            })
+            .collect::<Vec<_>>();
+        let views = textures
+            .iter()
+            .map(|texture| texture.create_view(&wgpu::TextureViewDescriptor::default()))
+            .collect();
+        (textures, views)
+    }
+
+    pub fn change_dimension(&mut self, device: &wgpu::Device, new_size: (u32, u32)) {
+        let (textures, views) = Self::build(device, new_size);
+        self.textures = textures;
+        self.views = views;
+    }
+
+    /// Color targets following the frame and accumulation targets of the
+    /// render pipeline. Shaders that do not write the AOVs leave them cleared.
+    pub fn color_targets() -> Vec<Option<wgpu::ColorTargetState>> {
+        FORMATS
+            .iter()
+            .map(|format| {
+                Some(wgpu::ColorTargetState {
+                    format: *format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })
+            })
+            .collect()
+    }
+
+    /// The AOVs are only cleared on the first iteration, pixels that are no
+    /// longer traced because of adaptive sampling keep their values
+    pub fn color_attachments(&self, clear: bool) -> Vec<Option<wgpu::RenderPassColorAttachment<'_>>> {
+        self.views
+            .iter()
+            .enumerate()
+            .map(|(idx, view)| {
+                let clear_color = if idx == IDS {
+                    wgpu::Color {
+                        r: NO_OBJECT as f64,
+                        g: NO_OBJECT as f64,
+                        b: 0.0,
+                        a: 0.0,
+                    }
+                } else {
+                    wgpu::Color::TRANSPARENT
+                };
+                Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: match clear {
+                            true => wgpu::LoadOp::Clear(clear_color),
+                            false => wgpu::LoadOp::Load,
+                        },
+                        store: true,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    /// Reads back the frame and all AOVs and writes them next to each other.
+    /// Float outputs are saved as Radiance HDR, the normal remapped to [0, 1]
+    /// as the format can not hold negative values. Integer outputs are saved
+    /// as PNG with the 32 bit value packed little endian into RGBA.
+    pub fn save(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        frame: &RenderFrame,
+        prefix: &str,
+        denoise: Option<&DenoiseParams>,
+    ) -> Result<Vec<PathBuf>> {
+        if let Some(parent) = Path::new(prefix).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let size = frame.texture.size();
+        let (width, height) = (size.width, size.height);
+        let mut saved = vec![];
+        let mut path = |name: &str| {
+            let path = PathBuf::from(format!("{prefix}_{name}"));
+            saved.push(path.clone());
+            path
+        };
+
+        let beauty = decode_rgba16f(&read_texture(device, queue, &frame.texture)?);
+        write_hdr(&path("beauty.hdr"), width, height, beauty.iter().map(|p| [p[0], p[1], p[2]]))?;
+
+        let albedo = decode_rgba16f(&read_texture(device, queue, &self.textures[ALBEDO])?);
+        write_hdr(&path("albedo.hdr"), width, height, albedo.iter().map(|p| [p[0], p[1], p[2]]))?;
+
+        let normal = decode_rgba16f(&read_texture(device, queue, &self.textures[NORMAL])?);
+        write_hdr(
+            &path("normal.hdr"),
+            width,
+            height,
+            normal.iter().map(|p| [p[0] * 0.5 + 0.5, p[1] * 0.5 + 0.5, p[2] * 0.5 + 0.5]),
+        )?;
+
+        let depth = decode_u32(&read_texture(device, queue, &self.textures[DEPTH])?);
+        write_hdr(&path("depth.hdr"), width, height, depth.iter().map(|d| [f32::from_bits(*d); 3]))?;
+
+        let ids = decode_u32(&read_texture(device, queue, &self.textures[IDS])?);
+        write_packed_u32(&path("object_id.png"), width, height, ids.iter().step_by(2).copied())?;
+        write_packed_u32(&path("material_id.png"), width, height, ids.iter().skip(1).step_by(2).copied())?;
+
+        let samples = decode_u32(&read_texture(device, queue, &self.textures[SAMPLES])?);
+        write_packed_u32(&path("samples.png"), width, height, samples.into_iter())?;
+
+        if let Some(params) = denoise {
+            let (w, h) = (width as usize, height as usize);
+            let rgb = |pixels: &Vec<[f32; 4]>| FloatImage::new(w, h, pixels.iter().map(|p| [p[0], p[1], p[2]]).collect());
+            let albedo = rgb(&albedo);
+            let normal = rgb(&normal);
+            let depth = FloatImage::new(w, h, depth.iter().map(|d| [f32::from_bits(*d)]).collect());
+            let guides = Guides {
+                albedo: &albedo,
+                normal: &normal,
+                depth: &depth,
+            };
+            let denoised = denoise::atrous(&rgb(&beauty), &guides, params);
+            write_hdr(&path("denoised.hdr"), width, height, denoised.pixels.into_iter())?;
+        }
+
+        Ok(saved)
+    }
+
+    /// Shader definitions of the bindings, known without a device
+    pub fn bind_descriptor() -> Vec<WgslBindDescriptor<'static>> {
+        FORMATS
+            .iter()
+            .zip(VAR_NAMES)
+            .map(|(format, var_name)| WgslBindDescriptor {
+                struct_def: None,
+                bind_type: None,
+                var_name,
+                var_type: match format.sample_type(None) {
+                    Some(wgpu::TextureSampleType::Uint) => "texture_2d<u32>",
+                    _ => "texture_2d<f32>",
+                },
+                extra_code: None,
+            })
+            .collect()
+    }
+}
+
+impl Bindable for AovTargets {
+    fn get_layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+        FORMATS
+            .iter()
+            .enumerate()
+            .map(|(idx, format)| {
+                let sample_type = match format.sample_type(None) {
+                    Some(wgpu::TextureSampleType::Uint) => wgpu::TextureSampleType::Uint,
+                    // only read with textureLoad
+                    _ => wgpu::TextureSampleType::Float { filterable: false },
+                };
+                wgpu::BindGroupLayoutEntry {
+                    binding: idx as u32,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type,
+                    },
+                    count: None,
+                }
+            })
+            .collect()
+    }
+
+    fn get_bind_group_entries(&self) -> Vec<wgpu::BindGroupEntry> {
+        self.views
+            .iter()
+            .enumerate()
+            .map(|(idx, view)| wgpu::BindGroupEntry {
+                binding: idx as u32,
+                resource: wgpu::BindingResource::TextureView(view),
+            })
+            .collect()
+    }
+
+    fn get_bind_descriptor(&self) -> Vec<WgslBindDescriptor> {
+        Self::bind_descriptor()
+    }
+}
+
+/// Copies a whole texture to the CPU, the rows are returned without padding
+fn read_texture(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture) -> Result<Vec<u8>> {
+    let size = texture.size();
+    let bytes_per_pixel = texture
+        .format()
+        .block_size(None)
+        .ok_or(anyhow!("Can not read back texture of format {:?}", texture.format()))?;
+    let unpadded_bytes_per_row = size.width * bytes_per_pixel;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Buffer"),
+        size: (padded_bytes_per_row * size.height) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_bytes_per_row),
+                rows_per_image: Some(size.height),
+            },
+        },
+        size,
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let (sender, receiver) = crossbeam_channel::bounded(1);
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::Maintain::Wait);
+    receiver.recv()??;
+
+    let data = slice.get_mapped_range();
+    let pixels = data
+        .chunks(padded_bytes_per_row as usize)
+        .flat_map(|row| &row[..unpadded_bytes_per_row as usize])
+        .copied()
+        .collect();
+    drop(data);
+    buffer.unmap();
+
+    Ok(pixels)
+}
+
+/// IEEE 754 half precision to single precision
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = ((half >> 15) as u32) << 31;
+    let exponent = ((half >> 10) & 0x1f) as u32;
+    let mantissa = (half & 0x3ff) as u32;
+    let bits = match (exponent, mantissa) {
+        (0, 0) => sign,
+        // subnormal, the value is mantissa * 2^-24
+        (0, _) => {
+            let value = mantissa as f32 * (-24.0f32).exp2();
+            return if sign != 0 { -value } else { value };
+        }
+        // infinity and NaN
+        (0x1f, _) => sign | 0x7f80_0000 | (mantissa << 13),
+        _ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+    };
+    f32::from_bits(bits)
+}
+
+fn decode_rgba16f(bytes: &[u8]) -> Vec<[f32; 4]> {
+    bytes
+        .chunks_exact(8)
+        .map(|pixel| {
+            let channel = |i: usize| f16_to_f32(u16::from_le_bytes([pixel[2 * i], pixel[2 * i + 1]]));
+            [channel(0), channel(1), channel(2), channel(3)]
+        })
+        .collect()
+}
+
+fn decode_u32(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(4)
+        .map(|value| u32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+        .collect()
+}
+
+fn write_hdr(path: &Path, width: u32, height: u32, pixels: impl Iterator<Item = [f32; 3]>) -> Result<()> {
+    // Radiance HDR can only store positive values
+    let pixels = pixels
+        .map(|p| Rgb(p.map(|c| if c.is_finite() { c.max(0.0) } else { 0.0 })))
+        .collect::<Vec<_>>();
+    let writer = BufWriter::new(File::create(path)?);
+    HdrEncoder::new(writer).encode(&pixels, width as usize, height as usize)?;
+    Ok(())
+}
+
+fn pack_u32(values: impl Iterator<Item = u32>) -> Vec<u8> {
+    values.flat_map(u32::to_le_bytes).collect()
+}
+
+fn write_packed_u32(path: &Path, width: u32, height: u32, values: impl Iterator<Item = u32>) -> Result<()> {
+    let image = RgbaImage::from_raw(width, height, pack_u32(values))
+        .ok_or(anyhow!("Wrong number of values for a {width}x{height} image"))?;
+    image.save(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod aov_test {
+    use super::*;
+
+    #[test]
+    fn half_to_float() {
+        assert_eq!(f16_to_f32(0x0000), 0.0);
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0xc000), -2.0);
+        assert_eq!(f16_to_f32(0x3555), 0.33325195);
+        assert_eq!(f16_to_f32(0x7bff), 65504.0);
+        assert_eq!(f16_to_f32(0x0001), 5.9604645e-8);
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+        assert!(f16_to_f32(0x7e00).is_nan());
+    }
+
+    #[test]
+    fn packed_ids_round_trip() {
+        let ids = [0, 1, 256, 123_456_789, NO_OBJECT];
+        let bytes = pack_u32(ids.into_iter());
+        assert_eq!(bytes.len(), ids.len() * 4);
+        assert_eq!(decode_u32(&bytes), ids);
+    }
+}
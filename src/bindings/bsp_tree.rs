@@ -1,20 +1,39 @@
 use wgpu::util::DeviceExt;
 
-use crate::{data_structures::bsp_tree::{BspTreeIntermediate, BspTree}, bindings::WgslBindDescriptor};
+use crate::{data_structures::{bsp_tree::{BspTreeIntermediate, BspTree}, hlbvh::BvhLayout}, bindings::WgslBindDescriptor, scenes::TraverseType};
 
-use super::{Bindable, IntoGpu, WgslSource, bvh::BvhGpu};
+use super::{Bindable, IntoGpu, WgslSource, bvh::{BvhGpu, InstancedBvhGpu, WideBvhGpu}, SCENE_VISIBILITY};
 
 pub enum TraversalStructure {
     Bsp(BspTreeGpu),
     Bvh(BvhGpu),
+    WideBvh(WideBvhGpu),
+    Instanced(InstancedBvhGpu),
     None,
 }
 
+impl TraversalStructure {
+    /// Shader definitions of the bindings, known without a device,
+    /// `bsp_max_depth` is the depth the BSP tree is built with
+    pub fn bind_descriptor(traverse_type: TraverseType, bsp_max_depth: u32) -> Vec<WgslBindDescriptor<'static>> {
+        match traverse_type {
+            TraverseType::Bsp => BspTreeGpu::bind_descriptor(bsp_max_depth),
+            TraverseType::Bvh | TraverseType::Sbvh => BvhGpu::bind_descriptor(BvhLayout::Stack),
+            TraverseType::ThreadedBvh => BvhGpu::bind_descriptor(BvhLayout::Threaded),
+            TraverseType::Bvh4 | TraverseType::Bvh8 => WideBvhGpu::bind_descriptor(false),
+            TraverseType::QuantizedBvh => WideBvhGpu::bind_descriptor(true),
+            TraverseType::Instanced => InstancedBvhGpu::bind_descriptor(),
+        }
+    }
+}
+
 impl Bindable for TraversalStructure {
     fn get_layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
         match self {
             TraversalStructure::Bsp(bsp_tree) => bsp_tree.get_layout_entries(),
             TraversalStructure::Bvh(bvh) => bvh.get_layout_entries(),
+            TraversalStructure::WideBvh(bvh) => bvh.get_layout_entries(),
+            TraversalStructure::Instanced(bvh) => bvh.get_layout_entries(),
             TraversalStructure::None => vec![],
         }
     }
@@ -23,6 +42,8 @@ impl Bindable for TraversalStructure {
         match self {
             TraversalStructure::Bsp(bsp_tree) => bsp_tree.get_bind_group_entries(),
             TraversalStructure::Bvh(bvh) => bvh.get_bind_group_entries(),
+            TraversalStructure::WideBvh(bvh) => bvh.get_bind_group_entries(),
+            TraversalStructure::Instanced(bvh) => bvh.get_bind_group_entries(),
             TraversalStructure::None => vec![],
         }
     }
@@ -31,6 +52,8 @@ impl Bindable for TraversalStructure {
         match self {
             TraversalStructure::Bsp(bsp_tree) => bsp_tree.get_bind_descriptor(),
             TraversalStructure::Bvh(bvh) => bvh.get_bind_descriptor(),
+            TraversalStructure::WideBvh(bvh) => bvh.get_bind_descriptor(),
+            TraversalStructure::Instanced(bvh) => bvh.get_bind_descriptor(),
             TraversalStructure::None => vec![],
         }
     }
@@ -50,7 +73,7 @@ impl Bindable for BspTreeGpu {
         vec![
             wgpu::BindGroupLayoutEntry {
                 binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
+                visibility: SCENE_VISIBILITY,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Uniform,
                     has_dynamic_offset: false,
@@ -60,7 +83,7 @@ impl Bindable for BspTreeGpu {
             },
             wgpu::BindGroupLayoutEntry {
                 binding: 1,
-                visibility: wgpu::ShaderStages::FRAGMENT,
+                visibility: SCENE_VISIBILITY,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Storage { read_only: true },
                     has_dynamic_offset: false,
@@ -70,7 +93,7 @@ impl Bindable for BspTreeGpu {
             },
             wgpu::BindGroupLayoutEntry {
                 binding: 2,
-                visibility: wgpu::ShaderStages::FRAGMENT,
+                visibility: SCENE_VISIBILITY,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Storage { read_only: true },
                     has_dynamic_offset: false,
@@ -80,7 +103,7 @@ impl Bindable for BspTreeGpu {
             },
             wgpu::BindGroupLayoutEntry {
                 binding: 3,
-                visibility: wgpu::ShaderStages::FRAGMENT,
+                visibility: SCENE_VISIBILITY,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Storage { read_only: true },
                     has_dynamic_offset: false,
@@ -113,6 +136,50 @@ impl Bindable for BspTreeGpu {
     }
 
     fn get_bind_descriptor(&self) -> Vec<WgslBindDescriptor> {
+        Self::bind_descriptor(self._intermediates.max_depth)
+    }
+}
+
+impl BspTreeGpu {
+    pub fn new(device: &wgpu::Device, bsp_tree_data: BspTreeIntermediate) -> Self {
+        let bbox_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bounding Box Uniform"),
+            contents: bytemuck::cast_slice(&[bsp_tree_data.bbox]),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+        });
+
+        let ids_slice = bsp_tree_data.ids.as_slice();
+        let ids_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("BSP id buffer"),
+            contents: bytemuck::cast_slice(ids_slice),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+        });
+
+        let bsp_tree_slice = bsp_tree_data.bsp_tree.as_slice();
+        let bsp_tree_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("BSP tree buffer"),
+            contents: bytemuck::cast_slice(bsp_tree_slice),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+        });
+
+        let bsp_planes_slice = bsp_tree_data.bsp_planes.as_slice();
+        let bsp_planes_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("BSP plane buffer"),
+            contents: bytemuck::cast_slice(bsp_planes_slice),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+        });
+
+        BspTreeGpu {
+            _intermediates: bsp_tree_data,
+            bbox_buffer,
+            ids_buffer,
+            bsp_tree_buffer,
+            bsp_planes_buffer,
+        }
+    }
+
+    /// Shader definitions of the bindings, known without a device
+    pub fn bind_descriptor(max_depth: u32) -> Vec<WgslBindDescriptor<'static>> {
         let aabb_definition =       
 "struct Aabb {
     min: vec3f,
@@ -121,10 +188,10 @@ impl Bindable for BspTreeGpu {
     _padding2: f32,
 };";
 
-let max_depth = 
+let max_level = 
 format!("
-const MAX_LEVEL = {}u;
-", self._intermediates.max_depth);
+const MAX_LEVEL = {max_depth}u;
+");
 
         let aabb_code = "res/shaders/aabb.wgsl";
 
@@ -150,7 +217,7 @@ const MAX_LEVEL = {}u;
                 bind_type: Some("storage"),
                 var_name: "bspTree",
                 var_type: "array<vec4u>",
-                extra_code: Some(WgslSource::Str(max_depth)),
+                extra_code: Some(WgslSource::Str(max_level)),
             },
             WgslBindDescriptor {
                 struct_def: None,
@@ -163,45 +230,6 @@ const MAX_LEVEL = {}u;
     }
 }
 
-impl BspTreeGpu {
-    pub fn new(device: &wgpu::Device, bsp_tree_data: BspTreeIntermediate) -> Self {
-        let bbox_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Bounding Box Uniform"),
-            contents: bytemuck::cast_slice(&[bsp_tree_data.bbox]),
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
-        });
-
-        let ids_slice = bsp_tree_data.ids.as_slice();
-        let ids_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("BSP id buffer"),
-            contents: bytemuck::cast_slice(ids_slice),
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
-        });
-
-        let bsp_tree_slice = bsp_tree_data.bsp_tree.as_slice();
-        let bsp_tree_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("BSP tree buffer"),
-            contents: bytemuck::cast_slice(bsp_tree_slice),
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
-        });
-
-        let bsp_planes_slice = bsp_tree_data.bsp_planes.as_slice();
-        let bsp_planes_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("BSP plane buffer"),
-            contents: bytemuck::cast_slice(bsp_planes_slice),
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
-        });
-
-        BspTreeGpu {
-            _intermediates: bsp_tree_data,
-            bbox_buffer,
-            ids_buffer,
-            bsp_tree_buffer,
-            bsp_planes_buffer,
-        }
-    }
-}
-
 impl IntoGpu for BspTree {
     type Output = BspTreeGpu;
 
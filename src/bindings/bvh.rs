@@ -1,38 +1,35 @@
 use wgpu::util::DeviceExt;
 
-use crate::{bindings::WgslSource, data_structures::{bvh::{self}, hlbvh::{self, GpuNode}}};
+use crate::{bindings::WgslSource, data_structures::{bvh::{self}, hlbvh::{self, BvhLayout, GpuNode, GpuWideNode}, instancing::{FlatTwoLevelBvh, GpuInstance, TwoLevelBvh}, quantized_bvh::GpuQuantizedNode}};
 
-use super::{Bindable, WgslBindDescriptor, IntoGpu};
+use super::{wgsl_struct::WgslStruct, Bindable, WgslBindDescriptor, IntoGpu, SCENE_VISIBILITY};
 
 pub struct BvhGpu {
     pub bvh_buffer: wgpu::Buffer,
     pub bvh_triangles_buffer: wgpu::Buffer,
+    /// Layout the nodes were flattened with, selects the traversal shader
+    pub layout: BvhLayout,
+}
+
+/// The nodes and the triangles of the leaves, all BVH types bind read only storage buffers
+fn storage_layout_entries(count: u32) -> Vec<wgpu::BindGroupLayoutEntry> {
+    (0..count)
+        .map(|binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: SCENE_VISIBILITY,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        })
+        .collect()
 }
 
 impl Bindable for BvhGpu {
     fn get_layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
-        vec![
-            wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-            wgpu::BindGroupLayoutEntry {
-                binding: 1,
-                visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }
-        ]
+        storage_layout_entries(2)
     }
 
     fn get_bind_group_entries(&self) -> Vec<wgpu::BindGroupEntry> {
@@ -49,54 +46,235 @@ impl Bindable for BvhGpu {
     }
 
     fn get_bind_descriptor(&self) -> Vec<WgslBindDescriptor> {
+        Self::bind_descriptor(self.layout)
+    }
+}
+
+impl BvhGpu {
+    pub fn new(device: &wgpu::Device, nodes: Vec<GpuNode>, triangles: &Vec<u32>, layout: BvhLayout) -> Self {
+        let nodes_slice = nodes.as_slice();
+        let bvh_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("BVH nodes buffer"),
+            contents: bytemuck::cast_slice(nodes_slice),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+        });
+        let triangles_slice = triangles.as_slice();
+        let bvh_triangles_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("BVH triangles buffer"),
+            contents: bytemuck::cast_slice(triangles_slice),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+        });
+        BvhGpu {
+            bvh_buffer,
+            bvh_triangles_buffer,
+            layout,
+        }
+    }
+
+    /// Overwrites the nodes with those of `bvh` after `hlbvh::Bvh::refit`, the
+    /// topology and thus the buffer size and the triangles have to be unchanged
+    pub fn write_refitted(&self, queue: &wgpu::Queue, bvh: &hlbvh::Bvh) {
+        queue.write_buffer(&self.bvh_buffer, 0, bytemuck::cast_slice(&bvh.flatten_layout(self.layout)));
+    }
+
+    /// Shader definitions of the bindings, known without a device
+    pub fn bind_descriptor(layout: BvhLayout) -> Vec<WgslBindDescriptor<'static>> {
+        let traversal_code = match layout {
+            BvhLayout::Stack => "res/shaders/bvh.wgsl",
+            BvhLayout::Threaded => "res/shaders/bvh_threaded.wgsl",
+        };
 
-        let bvh_code = "res/shaders/bvh.wgsl";
-        
         vec![
             WgslBindDescriptor {
-                struct_def: None,
+                struct_def: Some(GpuNode::WGSL),
                 bind_type: Some("storage"),
                 var_name: "bvh_nodes",
                 var_type: "array<BvhNode>",
-                extra_code: Some(WgslSource::File(bvh_code)),
+                extra_code: Some(WgslSource::File("res/shaders/bvh_node.wgsl")),
             },
             WgslBindDescriptor {
                 struct_def: None,
                 bind_type: Some("storage"),
                 var_name: "bvh_triangles",
                 var_type: "array<u32>",
-                extra_code: None,
+                extra_code: Some(WgslSource::File(traversal_code)),
             }
         ]
     }
 }
 
-impl BvhGpu {
-    pub fn new(device: &wgpu::Device, nodes: Vec<GpuNode>, triangles: &Vec<u32>) -> Self {
-        let nodes_slice = nodes.as_slice();
+/// Wide BVH from `hlbvh::Bvh::collapse`, traversed by bvh_wide.wgsl,
+/// or its quantized nodes, traversed by bvh_quantized.wgsl
+pub struct WideBvhGpu {
+    pub bvh_buffer: wgpu::Buffer,
+    pub bvh_triangles_buffer: wgpu::Buffer,
+    /// The nodes are `GpuQuantizedNode`s instead of `GpuWideNode`s
+    pub quantized: bool,
+}
+
+impl Bindable for WideBvhGpu {
+    fn get_layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+        storage_layout_entries(2)
+    }
+
+    fn get_bind_group_entries(&self) -> Vec<wgpu::BindGroupEntry> {
+        vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: self.bvh_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: self.bvh_triangles_buffer.as_entire_binding(),
+            },
+        ]
+    }
+
+    fn get_bind_descriptor(&self) -> Vec<WgslBindDescriptor> {
+        Self::bind_descriptor(self.quantized)
+    }
+}
+
+impl WideBvhGpu {
+    /// `nodes` are `GpuWideNode`s or with `quantized` `GpuQuantizedNode`s
+    pub fn new<T: bytemuck::Pod>(device: &wgpu::Device, nodes: &[T], triangles: &[u32], quantized: bool) -> Self {
         let bvh_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("BVH nodes buffer"),
-            contents: bytemuck::cast_slice(nodes_slice),
+            label: Some("Wide BVH nodes buffer"),
+            contents: bytemuck::cast_slice(nodes),
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
         });
-        let triangles_slice = triangles.as_slice();
         let bvh_triangles_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("BVH triangles buffer"),
-            contents: bytemuck::cast_slice(triangles_slice),
+            label: Some("Wide BVH triangles buffer"),
+            contents: bytemuck::cast_slice(triangles),
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
         });
-        BvhGpu {
+        WideBvhGpu {
             bvh_buffer,
             bvh_triangles_buffer,
+            quantized,
+        }
+    }
+
+    /// Shader definitions of the bindings, the same for every width
+    pub fn bind_descriptor(quantized: bool) -> Vec<WgslBindDescriptor<'static>> {
+        let (struct_def, var_type, traversal_code) = match quantized {
+            false => (GpuWideNode::WGSL, "array<BvhWideNode>", "res/shaders/bvh_wide.wgsl"),
+            true => (GpuQuantizedNode::WGSL, "array<BvhQuantizedNode>", "res/shaders/bvh_quantized.wgsl"),
+        };
+
+        vec![
+            WgslBindDescriptor {
+                struct_def: Some(struct_def),
+                bind_type: Some("storage"),
+                var_name: "bvh_nodes",
+                var_type,
+                extra_code: None,
+            },
+            WgslBindDescriptor {
+                struct_def: None,
+                bind_type: Some("storage"),
+                var_name: "bvh_triangles",
+                var_type: "array<u32>",
+                extra_code: Some(WgslSource::File(traversal_code)),
+            }
+        ]
+    }
+}
+
+/// Two-level BVH from `instancing::TwoLevelBvh`, traversed by bvh_instanced.wgsl
+pub struct InstancedBvhGpu {
+    pub blas_buffer: wgpu::Buffer,
+    pub blas_triangles_buffer: wgpu::Buffer,
+    pub tlas_buffer: wgpu::Buffer,
+    pub instances_buffer: wgpu::Buffer,
+}
+
+impl Bindable for InstancedBvhGpu {
+    fn get_layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+        storage_layout_entries(4)
+    }
+
+    fn get_bind_group_entries(&self) -> Vec<wgpu::BindGroupEntry> {
+        [&self.blas_buffer, &self.blas_triangles_buffer, &self.tlas_buffer, &self.instances_buffer]
+            .into_iter()
+            .enumerate()
+            .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect()
+    }
+
+    fn get_bind_descriptor(&self) -> Vec<WgslBindDescriptor> {
+        Self::bind_descriptor()
+    }
+}
+
+impl InstancedBvhGpu {
+    pub fn new(device: &wgpu::Device, bvh: &FlatTwoLevelBvh) -> Self {
+        let storage_buffer = |label, contents| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+            })
+        };
+        InstancedBvhGpu {
+            blas_buffer: storage_buffer("BLAS nodes buffer", bytemuck::cast_slice(&bvh.blas_nodes)),
+            blas_triangles_buffer: storage_buffer("BLAS triangles buffer", bytemuck::cast_slice(&bvh.blas_triangles)),
+            tlas_buffer: storage_buffer("TLAS nodes buffer", bytemuck::cast_slice(&bvh.tlas_nodes)),
+            instances_buffer: storage_buffer("Instances buffer", bytemuck::cast_slice(&bvh.instances)),
         }
     }
+
+    /// Shader definitions of the bindings, known without a device
+    pub fn bind_descriptor() -> Vec<WgslBindDescriptor<'static>> {
+        vec![
+            WgslBindDescriptor {
+                struct_def: Some(GpuNode::WGSL),
+                bind_type: Some("storage"),
+                var_name: "bvh_nodes",
+                var_type: "array<BvhNode>",
+                extra_code: Some(WgslSource::File("res/shaders/bvh_node.wgsl")),
+            },
+            WgslBindDescriptor {
+                struct_def: None,
+                bind_type: Some("storage"),
+                var_name: "bvh_triangles",
+                var_type: "array<u32>",
+                extra_code: None,
+            },
+            WgslBindDescriptor {
+                struct_def: None,
+                bind_type: Some("storage"),
+                var_name: "tlas_nodes",
+                var_type: "array<BvhNode>",
+                extra_code: None,
+            },
+            WgslBindDescriptor {
+                struct_def: Some(GpuInstance::WGSL),
+                bind_type: Some("storage"),
+                var_name: "instances",
+                var_type: "array<Instance>",
+                extra_code: Some(WgslSource::File("res/shaders/bvh_instanced.wgsl")),
+            },
+        ]
+    }
+}
+
+impl IntoGpu for TwoLevelBvh {
+    type Output = InstancedBvhGpu;
+
+    fn into_gpu(&self, device: &wgpu::Device) -> Self::Output {
+        InstancedBvhGpu::new(device, &self.flatten())
+    }
 }
 
 impl IntoGpu for bvh::Bvh {
     type Output = BvhGpu;
 
     fn into_gpu(&self, device: &wgpu::Device) -> Self::Output {
-        BvhGpu::new(device, self.flatten(), self.triangles())
+        BvhGpu::new(device, self.flatten(), self.triangles(), BvhLayout::Stack)
     }
 }
 
@@ -104,6 +282,23 @@ impl IntoGpu for hlbvh::Bvh {
     type Output = BvhGpu;
 
     fn into_gpu(&self, device: &wgpu::Device) -> Self::Output {
-        BvhGpu::new(device, self.flatten(), &self.triangles())
+        BvhGpu::new(device, self.flatten(), &self.triangles(), BvhLayout::Stack)
+    }
+}
+
+impl hlbvh::Bvh {
+    /// Uploads the nodes flattened with `layout`
+    pub fn into_gpu_layout(&self, device: &wgpu::Device, layout: BvhLayout) -> BvhGpu {
+        BvhGpu::new(device, self.flatten_layout(layout), &self.triangles(), layout)
+    }
+
+    /// Uploads the BVH collapsed to `width` children per node
+    pub fn into_gpu_wide(&self, device: &wgpu::Device, width: usize) -> WideBvhGpu {
+        WideBvhGpu::new(device, &self.collapse(width), &self.triangles(), false)
+    }
+
+    /// Uploads the quantized BVH4
+    pub fn into_gpu_quantized(&self, device: &wgpu::Device) -> WideBvhGpu {
+        WideBvhGpu::new(device, &self.quantize(), &self.triangles(), true)
     }
 }
\ No newline at end of file
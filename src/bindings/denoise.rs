@@ -0,0 +1,263 @@
+use super::{texture::RenderFrame, Bindable, BufferOwner, WgslBindDescriptor};
+use crate::denoise::{DenoiseParams, MAX_ITERATIONS};
+
+use wgpu::util::DeviceExt;
+
+#[repr(C, align(16))]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DenoiseUniform {
+    /// The distance between the kernel taps is 2^iteration
+    iteration: u32,
+    sigma_color: f32,
+    sigma_albedo: f32,
+    sigma_normal: f32,
+    sigma_depth: f32,
+    _padding: [u32; 3],
+}
+
+static_assertions::assert_eq_size!(DenoiseUniform, [u32; 8]);
+
+/// Runs the à-trous filter as one full screen pass per iteration,
+/// ping-ponging between two textures and writing the last iteration
+/// into the output which is shown by the presentation pass
+pub struct DenoiserGpu {
+    enabled: bool,
+    params: DenoiseParams,
+    /// One buffer per iteration as they are all recorded in one encoder
+    uniform_buffers: Vec<wgpu::Buffer>,
+    ping_pong: [(wgpu::Texture, wgpu::TextureView); 2],
+    output: (wgpu::Texture, wgpu::TextureView),
+}
+
+/// Bindings of a single iteration
+pub struct DenoiseStep<'a> {
+    uniform: &'a wgpu::Buffer,
+    input: &'a wgpu::TextureView,
+}
+
+impl DenoiserGpu {
+    pub fn new(device: &wgpu::Device, size: (u32, u32), format: wgpu::TextureFormat) -> Self {
+        let params = DenoiseParams::default();
+        let uniform_buffers = (0..MAX_ITERATIONS)
+            .map(|iteration| {
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Denoise uniform buffer"),
+                    contents: bytemuck::cast_slice(&[DenoiseUniform::new(&params, iteration)]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                })
+            })
+            .collect();
+
+        Self {
+            enabled: false,
+            params,
+            uniform_buffers,
+            ping_pong: [
+                Self::build(device, size, format),
+                Self::build(device, size, format),
+            ],
+            output: Self::build(device, size, format),
+        }
+    }
+
+    fn build(
+        device: &wgpu::Device,
+        size: (u32, u32),
+        format: wgpu::TextureFormat,
+    ) -> (wgpu::Texture, wgpu::TextureView) {
+        let extent = wgpu::Extent3d {
+            width: size.0,
+            height: size.1,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Denoise Target"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        (texture, view)
+    }
+
+    pub fn change_dimension(&mut self, device: &wgpu::Device, new_size: (u32, u32)) {
+        let format = self.output.0.format();
+        self.ping_pong = [
+            Self::build(device, new_size, format),
+            Self::build(device, new_size, format),
+        ];
+        self.output = Self::build(device, new_size, format);
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn params(&self) -> &DenoiseParams {
+        &self.params
+    }
+
+    pub fn update(&mut self, enabled: bool, params: DenoiseParams) {
+        self.enabled = enabled;
+        self.params = DenoiseParams {
+            iterations: params.iterations.clamp(1, MAX_ITERATIONS),
+            ..params
+        };
+    }
+
+    /// Bindings and render target of every iteration, the first one reads the frame
+    pub fn steps<'a>(&'a self, frame: &'a RenderFrame) -> Vec<(DenoiseStep<'a>, &'a wgpu::TextureView)> {
+        let iterations = self.params.iterations as usize;
+        (0..iterations)
+            .map(|iteration| {
+                let input = match iteration {
+                    0 => &frame.view,
+                    _ => &self.ping_pong[(iteration - 1) % 2].1,
+                };
+                let output = if iteration + 1 == iterations {
+                    &self.output.1
+                } else {
+                    &self.ping_pong[iteration % 2].1
+                };
+                let step = DenoiseStep {
+                    uniform: &self.uniform_buffers[iteration],
+                    input,
+                };
+                (step, output)
+            })
+            .collect()
+    }
+
+    /// Shader definitions of the bindings, known without a device
+    pub fn bind_descriptor() -> Vec<WgslBindDescriptor<'static>> {
+        vec![WgslBindDescriptor {
+            struct_def: None,
+            bind_type: None,
+            var_name: "denoisedTexture",
+            var_type: "texture_2d<f32>",
+            extra_code: None,
+        }]
+    }
+}
+
+impl DenoiseUniform {
+    fn new(params: &DenoiseParams, iteration: u32) -> Self {
+        Self {
+            iteration,
+            sigma_color: params.sigma_color,
+            sigma_albedo: params.sigma_albedo,
+            sigma_normal: params.sigma_normal,
+            sigma_depth: params.sigma_depth,
+            _padding: [0; 3],
+        }
+    }
+}
+
+impl BufferOwner for DenoiserGpu {
+    fn update_buffer(&self, queue: &wgpu::Queue) {
+        for (iteration, buffer) in self.uniform_buffers.iter().enumerate() {
+            let uniform = DenoiseUniform::new(&self.params, iteration as u32);
+            queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[uniform]));
+        }
+    }
+}
+
+/// The result as seen by the presentation pass
+impl Bindable for DenoiserGpu {
+    fn get_layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+        vec![wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        }]
+    }
+
+    fn get_bind_group_entries(&self) -> Vec<wgpu::BindGroupEntry> {
+        vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(&self.output.1),
+        }]
+    }
+
+    fn get_bind_descriptor(&self) -> Vec<WgslBindDescriptor> {
+        Self::bind_descriptor()
+    }
+}
+
+impl<'a> Bindable for DenoiseStep<'a> {
+    fn get_layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+        vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            },
+        ]
+    }
+
+    fn get_bind_group_entries(&self) -> Vec<wgpu::BindGroupEntry> {
+        vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: self.uniform.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(self.input),
+            },
+        ]
+    }
+
+    fn get_bind_descriptor(&self) -> Vec<WgslBindDescriptor> {
+        let struct_def = Some(
+            "struct Denoise {
+    iteration: u32,
+    sigma_color: f32,
+    sigma_albedo: f32,
+    sigma_normal: f32,
+    sigma_depth: f32,
+};",
+        );
+
+        vec![
+            WgslBindDescriptor {
+                struct_def,
+                bind_type: Some("uniform"),
+                var_name: "denoise",
+                var_type: "Denoise",
+                extra_code: None,
+            },
+            WgslBindDescriptor {
+                struct_def: None,
+                bind_type: None,
+                var_name: "denoiseInput",
+                var_type: "texture_2d<f32>",
+                extra_code: None,
+            },
+        ]
+    }
+}
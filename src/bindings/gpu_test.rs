@@ -0,0 +1,163 @@
+//! Runs functions of the real shaders on a headless device, so their tests check the
+//! WGSL itself instead of a CPU copy. A compute entry point is appended to the assembled
+//! shader, it reads `testInputs` and writes one vec4f per invocation to `testOutputs`.
+//! Without an adapter that supports compute shaders the tests are skipped.
+
+use wgpu::util::DeviceExt;
+
+use super::preprocess::PreprocessedShader;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+/// A uniform buffer of the shader, the bindings are numbered like in `append_shader_definitions`.
+/// Only the uniforms the tested functions read are in the derived layout and can be given.
+pub struct TestUniform<'a> {
+    pub group: u32,
+    pub binding: u32,
+    pub contents: &'a [u8],
+}
+
+pub fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::default();
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))?;
+    if !adapter
+        .get_downlevel_capabilities()
+        .flags
+        .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+    {
+        return None;
+    }
+    let descriptor = wgpu::DeviceDescriptor {
+        label: None,
+        features: wgpu::Features::empty(),
+        limits: adapter.limits(),
+    };
+    pollster::block_on(adapter.request_device(&descriptor, None)).ok()
+}
+
+/// Evaluates `body`, the body of `fn test_output(i: u32) -> vec4f`, for every output.
+/// The inputs and outputs are bound in `io_group`, after the groups of the shader.
+/// `None` when there is no device to run it on.
+pub fn run(
+    mut shader: PreprocessedShader,
+    uniforms: &[TestUniform],
+    io_group: u32,
+    inputs: &[[f32; 4]],
+    outputs: usize,
+    body: &str,
+) -> Option<Vec<[f32; 4]>> {
+    let Some((device, queue)) = device() else {
+        eprintln!("No adapter with compute shaders, skipping the shader test");
+        return None;
+    };
+    shader.append_generated(
+        "test entry point",
+        &format!(
+            "@group({io_group}) @binding(0)
+var<storage, read> testInputs: array<vec4f>;
+@group({io_group}) @binding(1)
+var<storage, read_write> testOutputs: array<vec4f>;
+
+fn test_output(i: u32) -> vec4f {{
+{body}
+}}
+
+@compute @workgroup_size({WORKGROUP_SIZE})
+fn test_main(@builtin(global_invocation_id) id: vec3u) {{
+    if (id.x < arrayLength(&testOutputs)) {{
+        testOutputs[id.x] = test_output(id.x);
+    }}
+}}"
+        ),
+    );
+
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Shader test"),
+        source: wgpu::ShaderSource::Wgsl(shader.source.as_str().into()),
+    });
+    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Shader test"),
+        layout: None,
+        module: &module,
+        entry_point: "test_main",
+    });
+
+    let storage = |contents: &[u8], usage| {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents,
+            usage: wgpu::BufferUsages::STORAGE | usage,
+        })
+    };
+    // storage buffers can not be empty
+    let inputs = match inputs.is_empty() {
+        true => &[[0.0; 4]],
+        false => inputs,
+    };
+    let input_buffer = storage(bytemuck::cast_slice(inputs), wgpu::BufferUsages::empty());
+    let output_buffer = storage(&vec![0; outputs * 16], wgpu::BufferUsages::COPY_SRC);
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: output_buffer.size(),
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let uniform_buffers = uniforms
+        .iter()
+        .map(|uniform| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: uniform.contents,
+                usage: wgpu::BufferUsages::UNIFORM,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // the layout is derived from the entry point, unused bindings are left out of it
+    let bind_groups = (0..=io_group)
+        .map(|group| {
+            let entries = match group == io_group {
+                true => vec![
+                    wgpu::BindGroupEntry { binding: 0, resource: input_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 1, resource: output_buffer.as_entire_binding() },
+                ],
+                false => uniforms
+                    .iter()
+                    .zip(&uniform_buffers)
+                    .filter(|(uniform, _)| uniform.group == group)
+                    .map(|(uniform, buffer)| wgpu::BindGroupEntry {
+                        binding: uniform.binding,
+                        resource: buffer.as_entire_binding(),
+                    })
+                    .collect(),
+            };
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &pipeline.get_bind_group_layout(group),
+                entries: &entries,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+        pass.set_pipeline(&pipeline);
+        for (group, bind_group) in bind_groups.iter().enumerate() {
+            pass.set_bind_group(group as u32, bind_group, &[]);
+        }
+        pass.dispatch_workgroups((outputs as u32).div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+    encoder.copy_buffer_to_buffer(&output_buffer, 0, &readback, 0, output_buffer.size());
+    queue.submit(Some(encoder.finish()));
+    if let Some(err) = pollster::block_on(device.pop_error_scope()) {
+        panic!("{}", shader.source_map.remap_errors(&err.to_string()));
+    }
+
+    let slice = readback.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |result| result.expect("Could not map the test outputs"));
+    device.poll(wgpu::Maintain::Wait);
+    let results = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+    Some(results)
+}
@@ -1,12 +1,59 @@
-use std::{fs::File, path::Path, io::prelude::*};
+use std::path::Path;
 
+use anyhow::Result;
+
+use preprocess::PreprocessedShader;
+
+pub mod adaptive;
+pub mod aov;
 pub mod bsp_tree;
 pub mod bvh;
+pub mod denoise;
+#[cfg(test)]
+pub mod gpu_test;
 pub mod mesh;
+pub mod preprocess;
+pub mod present;
+pub mod primitives;
 pub mod storage_mesh;
 pub mod texture;
 pub mod uniform;
+pub mod validate;
 pub mod vertex;
+pub mod wavefront;
+pub mod wgsl_struct;
+
+/// Visibility of the scene resources, they are read by the fragment
+/// shaders and by the compute passes of the wavefront backend
+pub const SCENE_VISIBILITY: wgpu::ShaderStages = wgpu::ShaderStages::FRAGMENT.union(wgpu::ShaderStages::COMPUTE);
+
+/// The bind groups of the scene shaders by how often their resources
+/// change, so only the ones that did have to be recreated
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BindGroupFrequency {
+    /// Buffers whose contents are written every frame, the bind group stays
+    Frame,
+    /// Geometry and traversal structures, recreated by loading a scene
+    Scene,
+    /// Buffers and textures sized by the rendering resolution, recreated on resize
+    Targets,
+    /// Textures of the scene, recreated by loading a scene
+    Textures,
+}
+
+impl BindGroupFrequency {
+    /// In the order of the group ids
+    pub const ALL: [BindGroupFrequency; 4] = [
+        BindGroupFrequency::Frame,
+        BindGroupFrequency::Scene,
+        BindGroupFrequency::Targets,
+        BindGroupFrequency::Textures,
+    ];
+
+    pub fn group_id(self) -> usize {
+        self as usize
+    }
+}
 
 pub trait Bindable {
     fn get_layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry>;
@@ -49,20 +96,28 @@ pub fn create_bind_groups(
         label: None, // Some("uniform_bind_group"),
     })
 }
-pub fn create_shader_definitions(vec_of_descriptors: &Vec<Vec<WgslBindDescriptor>>) -> String {
-    let mut string = String::new();
-    vec_of_descriptors
-        .iter()
-        .flat_map(|v| v)
-        .enumerate()
-        .for_each(|(idx, descriptor)| {
-            string.push_str(&generate_wgsl_string(
-                0,
-                idx as u32,
-                descriptor,
-            ));
-        });
-    string
+/// Appends the definitions of the bindings to the shader, each part is
+/// recorded in its source map so errors in it can be located as well.
+/// The descriptors are given per bind group, the bindings of every group
+/// are numbered from 0 like in `create_bind_group_layouts`.
+pub fn append_shader_definitions(
+    shader: &mut PreprocessedShader,
+    groups: &[Vec<Vec<WgslBindDescriptor>>],
+) -> Result<()> {
+    groups.iter().enumerate().try_for_each(|(group_id, vec_of_descriptors)| {
+        vec_of_descriptors
+            .iter()
+            .flat_map(|v| v)
+            .enumerate()
+            .try_for_each(|(idx, descriptor)| {
+                append_wgsl_definition(
+                    shader,
+                    group_id as u32,
+                    idx as u32,
+                    descriptor,
+                )
+            })
+    })
 }
 
 pub trait BufferOwner {
@@ -90,11 +145,12 @@ pub struct WgslBindDescriptor<'a> {
     pub extra_code: Option<WgslSource<'a>>,
 }
 
-fn generate_wgsl_string(
+fn append_wgsl_definition(
+    shader: &mut PreprocessedShader,
     group_id: u32,   // auto pick
     binding_id: u32, // auto pick
     bind_descriptor: &WgslBindDescriptor,
-) -> String {
+) -> Result<()> {
     assert!(group_id < 4);
 
     let WgslBindDescriptor {
@@ -110,29 +166,21 @@ fn generate_wgsl_string(
     } else {
         "".into()
     };
-    let mut string: String;
-    let extra_code = match extra_code {
-        None => "",
-        Some(name) => match &name {
-            WgslSource::Str(string) => string,
-            WgslSource::File(path) => {
-                let mut file = File::open(Path::new(path)).expect(format!("File path {path} is invalid").as_str());
-                string = String::new();
-                file.read_to_string(&mut string).expect(format!("failed to read {path}").as_str());
-                &string
-            }
-        },
-    };
-
-    format!(
-        "
-    {}\n
-    @group({group_id}) @binding({binding_id})\n
-    var{bind_type} {var_name}: {var_type};\n
-    {}\n",
-        struct_def.unwrap_or(""),
-        extra_code
-    )
+    if let Some(struct_def) = struct_def {
+        shader.append_generated(&format!("struct of {var_name}"), struct_def);
+    }
+    shader.append_generated(
+        &format!("binding {var_name}"),
+        &format!("@group({group_id}) @binding({binding_id})\nvar{bind_type} {var_name}: {var_type};"),
+    );
+    match extra_code {
+        None => Ok(()),
+        Some(WgslSource::Str(string)) => {
+            shader.append_generated(&format!("code of {var_name}"), string);
+            Ok(())
+        }
+        Some(WgslSource::File(path)) => shader.append_file_contents(Path::new(path)),
+    }
 }
 
 #[cfg(test)]
@@ -156,7 +204,9 @@ mod tests {
         let group_id = 0;
         let binding_id = 0;
 
-        generate_wgsl_string(
+        let mut shader = PreprocessedShader::default();
+        append_wgsl_definition(
+            &mut shader,
             group_id,
             binding_id,
             &WgslBindDescriptor {
@@ -166,6 +216,37 @@ mod tests {
                 var_type,
                 extra_code: None,
             },
-        );
+        )
+        .unwrap();
+
+        assert!(shader.source.ends_with("@group(0) @binding(0)\nvar<uniform> uniforms: Uniform;\n"));
+        assert_eq!(shader.source_map.locate(1), Some((Path::new("<struct of uniforms>"), 1)));
+        assert_eq!(shader.source_map.locate(9), Some((Path::new("<binding uniforms>"), 2)));
+        assert!(shader.source_map.files().is_empty());
+    }
+
+    #[test]
+    fn bindings_are_numbered_per_group() {
+        let descriptor = |var_name| WgslBindDescriptor {
+            struct_def: None,
+            bind_type: Some("uniform"),
+            var_name,
+            var_type: "f32",
+            extra_code: None,
+        };
+        let mut shader = PreprocessedShader::default();
+        append_shader_definitions(
+            &mut shader,
+            &[
+                vec![vec![descriptor("a"), descriptor("b")]],
+                vec![],
+                vec![vec![descriptor("c")], vec![descriptor("d")]],
+            ],
+        )
+        .unwrap();
+
+        assert!(shader.source.contains("@group(0) @binding(1)\nvar<uniform> b: f32;"));
+        assert!(shader.source.contains("@group(2) @binding(0)\nvar<uniform> c: f32;"));
+        assert!(shader.source.contains("@group(2) @binding(1)\nvar<uniform> d: f32;"));
     }
 }
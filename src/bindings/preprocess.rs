@@ -0,0 +1,355 @@
+/// WGSL preprocessor resolving `#include "path"` and `#import name` directives.
+/// Includes are resolved relative to the including file, imports refer to
+/// `res/shaders/lib/<name>.wgsl`. Every file is pasted at most once per
+/// shader, so shared files act as if they had include guards and cycles
+/// are harmless. A source map keeps track of where each line came from,
+/// including the definitions generated from the bindings appended later,
+/// so compiler errors can point back to the original file and line.
+
+use std::{
+    fs::File,
+    io::prelude::*,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::*;
+
+/// Directory of the shared shader modules used by `#import`
+pub const SHADER_LIB: &str = "res/shaders/lib";
+
+/// Name given to lines past the end of the source map, which only
+/// happens if code was appended to the source without recording it
+const GENERATED: &str = "<bindings>";
+
+/// Markers codespan puts after the gutter of a diagnostic:
+/// `┌─ location`, `│ code`, `· (skipped lines)` and `= note`
+const GUTTER_MARKERS: [&str; 4] = ["┌─", "│", "·", "="];
+
+#[derive(Clone, Debug, PartialEq)]
+struct SourceFile {
+    path: PathBuf,
+    /// Code that does not exist on disk, e.g. a binding declaration
+    generated: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct SourceLine {
+    /// Index into [`SourceMap::files`]
+    file: usize,
+    /// 1-based line number in that file
+    line: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+    lines: Vec<SourceLine>,
+}
+
+#[derive(Default)]
+pub struct PreprocessedShader {
+    pub source: String,
+    pub source_map: SourceMap,
+}
+
+/// A line of a diagnostic, split into the line number in the gutter and what follows it
+struct DiagnosticLine<'a> {
+    /// Width of the gutter in the original message
+    width: usize,
+    number: Option<usize>,
+    marker: &'a str,
+    rest: &'a str,
+}
+
+impl SourceMap {
+    /// Every file on disk the shader was assembled from, the root file first
+    pub fn files(&self) -> Vec<&Path> {
+        self.files
+            .iter()
+            .filter(|file| !file.generated)
+            .map(|file| file.path.as_path())
+            .collect()
+    }
+
+    /// File and line of a 1-based line of the preprocessed source.
+    /// Lines after the mapped source are not part of the map.
+    pub fn locate(&self, line: usize) -> Option<(&Path, usize)> {
+        let source_line = self.lines.get(line.checked_sub(1)?)?;
+        Some((&self.files[source_line.file].path, source_line.line))
+    }
+
+    /// Rewrites the `┌─ name:line:column` locations of naga/wgpu error messages and
+    /// the line numbers of their snippets to the file and line the code came from
+    pub fn remap_errors(&self, message: &str) -> String {
+        let lines = message.lines().map(|line| (line, Self::parse_diagnostic_line(line))).collect::<Vec<_>>();
+        let numbers = lines
+            .iter()
+            .filter_map(|(_, diagnostic)| diagnostic.as_ref()?.number)
+            .map(|number| self.locate(number).map_or(number, |(_, line)| line));
+        // the gutter has to fit the new numbers, which may be longer
+        let width = lines
+            .iter()
+            .filter_map(|(_, diagnostic)| Some(diagnostic.as_ref()?.width))
+            .chain(numbers.map(|number| number.to_string().len()))
+            .max()
+            .unwrap_or(0);
+
+        lines
+            .into_iter()
+            .map(|(line, diagnostic)| match diagnostic {
+                Some(DiagnosticLine { number, marker, rest, .. }) => {
+                    let number = number
+                        .map(|number| self.locate(number).map_or(number, |(_, line)| line).to_string())
+                        .unwrap_or_default();
+                    let location = match marker {
+                        "┌─" => self.remap_location(rest.trim_start()),
+                        _ => None,
+                    };
+                    let gutter = match width {
+                        0 if number.is_empty() => String::new(),
+                        _ => format!("{number:>width$} "),
+                    };
+                    match location {
+                        Some(location) => format!("{gutter}{marker} {location}"),
+                        None => format!("{gutter}{marker}{rest}"),
+                    }
+                }
+                None => line.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Splits `  12 │ code` and friends, anything else is not part of a snippet
+    fn parse_diagnostic_line(line: &str) -> Option<DiagnosticLine<'_>> {
+        let (start, marker) = GUTTER_MARKERS
+            .iter()
+            .filter_map(|marker| Some((line.find(marker)?, *marker)))
+            .min_by_key(|(start, _)| *start)?;
+        let gutter = &line[..start];
+        let number = match gutter.trim() {
+            "" => None,
+            number => Some(number.parse::<usize>().ok()?),
+        };
+        // a gutter ends in a space, unless there is none
+        (gutter.is_empty() || gutter.ends_with(' ')).then_some(DiagnosticLine {
+            width: gutter.len().saturating_sub(1),
+            number,
+            marker,
+            rest: &line[start + marker.len()..],
+        })
+    }
+
+    fn remap_location(&self, location: &str) -> Option<String> {
+        let mut parts = location.trim_end().rsplitn(3, ':');
+        let column = parts.next()?.parse::<usize>().ok()?;
+        let line = parts.next()?.parse::<usize>().ok()?;
+        parts.next()?;
+        Some(match self.locate(line) {
+            Some((file, line)) => format!("{}:{line}:{column}", file.display()),
+            None => format!("{GENERATED}:{}:{column}", line.saturating_sub(self.lines.len())),
+        })
+    }
+
+    /// Index of the file, which is added if it is not known yet
+    fn add_file(&mut self, path: PathBuf, generated: bool) -> usize {
+        let file = SourceFile { path, generated };
+        self.files.iter().position(|known| *known == file).unwrap_or_else(|| {
+            self.files.push(file);
+            self.files.len() - 1
+        })
+    }
+}
+
+impl PreprocessedShader {
+    /// Appends code that does not come from a file, its errors are reported as `<name>:line`
+    pub fn append_generated(&mut self, name: &str, code: &str) {
+        let file = self.source_map.add_file(PathBuf::from(format!("<{name}>")), true);
+        self.append_lines(code, file);
+    }
+
+    /// Appends the contents of a file as they are, directives are not resolved
+    pub fn append_file_contents(&mut self, path: &Path) -> Result<()> {
+        let code = read_file(path)?;
+        let file = self.source_map.add_file(normalize(path), false);
+        self.append_lines(&code, file);
+        Ok(())
+    }
+
+    fn append_lines(&mut self, code: &str, file: usize) {
+        for (idx, line) in code.lines().enumerate() {
+            push_line(self, line, file, idx + 1);
+        }
+    }
+}
+
+/// Reads and preprocesses the shader at `path`
+pub fn preprocess_file(path: &Path) -> Result<PreprocessedShader> {
+    preprocess_with(path, &read_file)
+}
+
+fn read_file(path: &Path) -> Result<String> {
+    let mut source = String::new();
+    File::open(path)
+        .with_context(|| format!("Could not open shader {}", path.display()))?
+        .read_to_string(&mut source)?;
+    Ok(source)
+}
+
+fn preprocess_with(path: &Path, read: &dyn Fn(&Path) -> Result<String>) -> Result<PreprocessedShader> {
+    let mut shader = PreprocessedShader::default();
+    append_file(&mut shader, &normalize(path), read)?;
+    Ok(shader)
+}
+
+fn append_file(
+    shader: &mut PreprocessedShader,
+    path: &Path,
+    read: &dyn Fn(&Path) -> Result<String>,
+) -> Result<()> {
+    // registered before reading the contents, which also breaks cycles
+    let file = shader.source_map.add_file(path.to_path_buf(), false);
+    let source = read(path)?;
+
+    for (idx, line) in source.lines().enumerate() {
+        let line_number = idx + 1;
+        let include = parse_directive(line)
+            .map_err(|err| anyhow!("{}:{line_number}: {err}", path.display()))?
+            .map(|directive| match directive {
+                Directive::Include(relative) => normalize(&path.parent().unwrap_or(Path::new("")).join(relative)),
+                Directive::Import(name) => normalize(&Path::new(SHADER_LIB).join(format!("{name}.wgsl"))),
+            });
+
+        match include {
+            Some(include) if !shader.source_map.files().contains(&include.as_path()) => {
+                append_file(shader, &include, read)
+                    .with_context(|| format!("included from {}:{line_number}", path.display()))?;
+            }
+            // already included, keep the line numbering
+            Some(_) => push_line(shader, "", file, line_number),
+            None => push_line(shader, line, file, line_number),
+        }
+    }
+    Ok(())
+}
+
+fn push_line(shader: &mut PreprocessedShader, line: &str, file: usize, line_number: usize) {
+    shader.source.push_str(line);
+    shader.source.push('\n');
+    shader.source_map.lines.push(SourceLine { file, line: line_number });
+}
+
+enum Directive<'a> {
+    Include(&'a str),
+    Import(&'a str),
+}
+
+/// WGSL has no use for `#`, so every line starting with one is a directive
+fn parse_directive(line: &str) -> Result<Option<Directive<'_>>> {
+    let Some(directive) = line.trim().strip_prefix('#') else {
+        return Ok(None);
+    };
+    let (keyword, argument) = directive.split_once(char::is_whitespace).unwrap_or((directive, ""));
+    let argument = argument.trim();
+    match keyword {
+        "include" => argument
+            .strip_prefix('"')
+            .and_then(|argument| argument.strip_suffix('"'))
+            .filter(|path| !path.is_empty())
+            .map(|path| Some(Directive::Include(path)))
+            .ok_or(anyhow!("Expected #include \"path\", found #{directive}")),
+        "import" if !argument.is_empty() && argument.chars().all(|c| c.is_alphanumeric() || c == '_') => {
+            Ok(Some(Directive::Import(argument)))
+        }
+        "import" => Err(anyhow!("Expected #import name, found #{directive}")),
+        _ => Err(anyhow!("Unknown preprocessor directive #{directive}")),
+    }
+}
+
+/// Lexically resolves `.` and `..`, so the same file is always known by the same path
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(normalized.components().next_back(), Some(Component::Normal(_))) => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod preprocess_test {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn preprocess_files(root: &str, files: &[(&str, &str)]) -> Result<PreprocessedShader> {
+        let files = files
+            .iter()
+            .map(|(path, source)| (PathBuf::from(path), source.to_string()))
+            .collect::<HashMap<_, _>>();
+        preprocess_with(Path::new(root), &|path| {
+            files
+                .get(path)
+                .cloned()
+                .ok_or(anyhow!("No such file {}", path.display()))
+        })
+    }
+
+    #[test]
+    fn files_are_included_once() {
+        let shader = preprocess_files(
+            "shaders/scene.wgsl",
+            &[
+                ("shaders/scene.wgsl", "#import ray\n#include \"common.wgsl\"\nfn main() {}"),
+                ("shaders/common.wgsl", "#import ray\nconst A = 1;"),
+                ("res/shaders/lib/ray.wgsl", "struct Ray {};"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(shader.source, "struct Ray {};\n\nconst A = 1;\nfn main() {}\n");
+        assert_eq!(
+            shader.source_map.files(),
+            [
+                PathBuf::from("shaders/scene.wgsl"),
+                PathBuf::from("res/shaders/lib/ray.wgsl"),
+                PathBuf::from("shaders/common.wgsl"),
+            ]
+        );
+    }
+
+    #[test]
+    fn lines_map_back_to_their_file() {
+        let shader = preprocess_files(
+            "a/scene.wgsl",
+            &[
+                ("a/scene.wgsl", "// scene\n#include \"../b/inc.wgsl\"\nfn main() {}"),
+                ("b/inc.wgsl", "#include \"../a/scene.wgsl\"\nfn f() {}\nfn g() {}"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(shader.source_map.locate(1), Some((Path::new("a/scene.wgsl"), 1)));
+        assert_eq!(shader.source_map.locate(3), Some((Path::new("b/inc.wgsl"), 2)));
+        assert_eq!(shader.source_map.locate(5), Some((Path::new("a/scene.wgsl"), 3)));
+        assert_eq!(shader.source_map.locate(6), None);
+
+        let message = "error: expected ';'\n  ┌─ wgsl:4:5\n  │";
+        assert_eq!(
+            shader.source_map.remap_errors(message),
+            "error: expected ';'\n  ┌─ b/inc.wgsl:3:5\n  │"
+        );
+        assert!(shader.source_map.remap_errors("┌─ Shader:8:1").ends_with("<bindings>:3:1"));
+    }
+
+    #[test]
+    fn bad_directives_are_reported() {
+        for source in ["#include common.wgsl", "#import ../ray", "#define A 1"] {
+            let err = preprocess_files("scene.wgsl", &[("scene.wgsl", source)]).err().unwrap();
+            assert!(err.to_string().starts_with("scene.wgsl:1: "), "{err}");
+        }
+        assert!(preprocess_files("scene.wgsl", &[("scene.wgsl", "#include \"missing.wgsl\"")]).is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use super::{
+    wgsl_struct::{wgsl_struct, WgslStruct},
+    Bindable, BufferOwner, WgslBindDescriptor,
+};
+use crate::command::{Aov, PresentFilter, ToneMapping};
+
+use wgpu::util::DeviceExt;
+
+#[repr(C, align(16))]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PresentUniform {
+    /// Center of the view in texture coordinates
+    center: [f32; 2],
+    /// Magnification, 1.0 shows the whole image
+    zoom: f32,
+    /// 0 for nearest, 1 for bilinear filtering
+    filter_mode: u32,
+    /// Exposure in stops (EV), the radiance is scaled by 2^exposure
+    exposure: f32,
+    /// Radiance that is mapped to display white
+    white_point: f32,
+    /// Tone mapping operator, see [`ToneMapping`]
+    tone_mapping: u32,
+    /// 1 when the sRGB OETF must be applied in the shader,
+    /// 0 when the surface format already encodes sRGB
+    encode_srgb: u32,
+    /// Output that is shown, see [`Aov`]
+    aov: u32,
+    /// 1 when the denoised frame is shown instead of the raw one
+    denoised: u32,
+    /// Highest possible per pixel sample count, normalizes the heatmap
+    sample_budget: u32,
+    /// Tile size of tiled rendering, 0 hides the progress overlay
+    tile_size: u32,
+    /// Tiles of the current sample traced so far, in row major order
+    tiles_done: u32,
+    _padding0: u32,
+    _padding1: [u32; 2],
+}
+
+static_assertions::assert_eq_size!(PresentUniform, [u32; 16]);
+
+// the trailing padding is declared, WGSL would end the struct after tiles_done
+wgsl_struct!(PresentUniform as Present {
+    center: vec2f,
+    zoom: f32,
+    filter_mode: u32,
+    exposure: f32,
+    white_point: f32,
+    tone_mapping: u32,
+    encode_srgb: u32,
+    aov: u32,
+    denoised: u32,
+    sample_budget: u32,
+    tile_size: u32,
+    tiles_done: u32,
+    _padding0: u32,
+    _padding1: vec2u,
+});
+
+pub const MAX_ZOOM: f32 = 64.0;
+pub const MIN_WHITE_POINT: f32 = 0.01;
+
+/// Uniforms for the presentation pass that scales the
+/// rendered frame to the window
+pub struct PresentUniformGpu {
+    uniforms: PresentUniform,
+    buffer: wgpu::Buffer,
+}
+
+impl PresentUniformGpu {
+    pub fn new(device: &wgpu::Device, surface_format: wgpu::TextureFormat) -> Self {
+        let mut uniforms = PresentUniform::new();
+        uniforms.encode_srgb = !surface_format.is_srgb() as u32;
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Present uniform buffer"),
+            contents: bytemuck::cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        Self { uniforms, buffer }
+    }
+
+    pub fn update_filter(&mut self, filter: PresentFilter) {
+        self.uniforms.filter_mode = filter as u32;
+    }
+
+    pub fn update_zoom(&mut self, zoom: f32) {
+        self.uniforms.zoom = zoom.clamp(1.0, MAX_ZOOM);
+    }
+
+    /// Pan is given as the offset of the view center from the image
+    /// center in texture coordinates
+    pub fn update_pan(&mut self, pan: (f32, f32)) {
+        self.uniforms.center = [
+            (0.5 + pan.0).clamp(0.0, 1.0),
+            (0.5 + pan.1).clamp(0.0, 1.0),
+        ];
+    }
+
+    pub fn update_aov(&mut self, aov: Aov) {
+        self.uniforms.aov = aov as u32;
+    }
+
+    pub fn update_denoised(&mut self, denoised: bool) {
+        self.uniforms.denoised = denoised as u32;
+    }
+
+    pub fn update_sample_budget(&mut self, sample_budget: u32) {
+        self.uniforms.sample_budget = sample_budget.max(1);
+    }
+
+    /// Shows which tiles of the current sample have been traced, a tile size of 0 hides it
+    pub fn update_tile_progress(&mut self, tile_size: u32, tiles_done: u32) {
+        self.uniforms.tile_size = tile_size;
+        self.uniforms.tiles_done = tiles_done;
+    }
+
+    pub fn update_tone_mapping(&mut self, operator: ToneMapping, exposure: f32, white_point: f32) {
+        self.uniforms.tone_mapping = operator as u32;
+        self.uniforms.exposure = exposure;
+        self.uniforms.white_point = white_point.max(MIN_WHITE_POINT);
+    }
+
+    /// Shader definitions of the bindings, known without a device
+    pub fn bind_descriptor() -> Vec<WgslBindDescriptor<'static>> {
+        vec![WgslBindDescriptor {
+            struct_def: Some(PresentUniform::WGSL),
+            bind_type: Some("uniform"),
+            var_name: "present",
+            var_type: "Present",
+            extra_code: None,
+        }]
+    }
+}
+
+impl PresentUniform {
+    pub fn new() -> Self {
+        Self {
+            center: [0.5, 0.5],
+            zoom: 1.0,
+            filter_mode: PresentFilter::default() as u32,
+            exposure: 0.0,
+            white_point: 1.0,
+            tone_mapping: ToneMapping::default() as u32,
+            encode_srgb: 1,
+            aov: Aov::default() as u32,
+            denoised: 0,
+            sample_budget: 1,
+            tile_size: 0,
+            tiles_done: 0,
+            _padding0: 0,
+            _padding1: [0; 2],
+        }
+    }
+}
+
+impl BufferOwner for PresentUniformGpu {
+    fn update_buffer(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniforms]));
+    }
+}
+
+impl Bindable for PresentUniformGpu {
+    fn get_layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+        vec![wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }]
+    }
+
+    fn get_bind_group_entries(&self) -> Vec<wgpu::BindGroupEntry> {
+        vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: self.buffer.as_entire_binding(),
+        }]
+    }
+
+    fn get_bind_descriptor(&self) -> Vec<WgslBindDescriptor> {
+        Self::bind_descriptor()
+    }
+}
+
+#[cfg(test)]
+mod present_test {
+    use super::*;
+    use crate::{
+        bindings::gpu_test::{self, TestUniform},
+        render_state::RenderState,
+    };
+
+    /// `tone_map` of present.wgsl for every input color, `None` without a device
+    fn tone_map(tone_mapping: ToneMapping, white_point: f32, colors: &[[f32; 3]]) -> Option<Vec<[f32; 3]>> {
+        let mut uniforms = PresentUniform::new();
+        uniforms.tone_mapping = tone_mapping as u32;
+        uniforms.white_point = white_point;
+        let inputs = colors.iter().map(|&[r, g, b]| [r, g, b, 0.0]).collect::<Vec<_>>();
+        let outputs = gpu_test::run(
+            RenderState::present_shader().unwrap(),
+            &[TestUniform { group: 0, binding: 0, contents: bytemuck::bytes_of(&uniforms) }],
+            1,
+            &inputs,
+            inputs.len(),
+            "    return vec4f(tone_map(testInputs[i].rgb), 0.0);",
+        )?;
+        Some(outputs.iter().map(|&[r, g, b, _]| [r, g, b]).collect())
+    }
+
+    #[test]
+    fn clamp_and_reinhard_map_the_white_point_to_one() {
+        for white in [MIN_WHITE_POINT, 0.5, 1.0, 4.0, 100.0] {
+            let colors = [[0.0; 3], [white * 0.5; 3], [white; 3]];
+            let Some(clamped) = tone_map(ToneMapping::Clamp, white, &colors) else {
+                return;
+            };
+            assert_eq!(clamped[0], [0.0; 3]);
+            assert!(clamped[1].iter().all(|c| (c - 0.5).abs() < 1e-6), "{white}: {clamped:?}");
+            assert!(clamped[2].iter().all(|c| (c - 1.0).abs() < 1e-6), "{white}: {clamped:?}");
+
+            let reinhard = tone_map(ToneMapping::Reinhard, white, &colors).unwrap();
+            assert_eq!(reinhard[0], [0.0; 3]);
+            assert!(reinhard[2].iter().all(|c| (c - 1.0).abs() < 1e-5), "{white}: {reinhard:?}");
+        }
+    }
+
+    #[test]
+    fn filmic_curves_are_monotonic_and_bounded() {
+        for tone_mapping in [ToneMapping::AcesFilmic, ToneMapping::AgX] {
+            for white in [0.5, 1.0, 8.0] {
+                // grey and a saturated color up to the white point
+                for color in [[1.0, 1.0, 1.0], [1.0, 0.2, 0.05]] {
+                    let colors = (0..=256)
+                        .map(|step| color.map(|c| c * white * step as f32 / 256.0))
+                        .collect::<Vec<_>>();
+                    let Some(mapped) = tone_map(tone_mapping, white, &colors) else {
+                        return;
+                    };
+                    for (step, pair) in mapped.windows(2).enumerate() {
+                        for (c, p) in pair[1].iter().zip(pair[0]) {
+                            assert!((0.0..=1.0).contains(c), "{tone_mapping:?} {white} {step}: {pair:?}");
+                            assert!(*c >= p - 1e-6, "{tone_mapping:?} {white} {step}: {pair:?}");
+                        }
+                    }
+                    // grey reaches display white at the white point
+                    if color[1] == 1.0 {
+                        assert!(mapped[256].iter().all(|c| (c - 1.0).abs() < 0.01), "{tone_mapping:?} {white}: {:?}", mapped[256]);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn srgb_oetf_matches_the_standard() {
+        // the linear segment ends at 0.0031308, reference values of IEC 61966-2-1
+        let reference = [
+            (0.0, 0.0),
+            (0.001, 0.01292),
+            (0.0031308, 0.04044994),
+            (0.0031309, 0.04045113),
+            (0.18, 0.46135613),
+            (0.5, 0.735357),
+            (1.0, 1.0),
+        ];
+        let inputs = reference.iter().map(|&(x, _)| [x; 4]).collect::<Vec<_>>();
+        let Some(outputs) = gpu_test::run(
+            RenderState::present_shader().unwrap(),
+            &[],
+            1,
+            &inputs,
+            inputs.len(),
+            "    return vec4f(srgb_oetf(testInputs[i].rgb), 0.0);",
+        ) else {
+            return;
+        };
+        for ((x, expected), output) in reference.iter().zip(outputs) {
+            assert!((output[0] - expected).abs() < 2e-6, "{x}: {} != {expected}", output[0]);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use wgpu::util::DeviceExt;
+
+use crate::{
+    bindings::WgslSource,
+    mesh::Mesh,
+    primitives::GpuPrimitive,
+};
+
+use super::{wgsl_struct::WgslStruct, Bindable, WgslBindDescriptor, SCENE_VISIBILITY};
+
+/// The analytic primitives of a mesh, bound with every mesh so the traversals
+/// can call intersect_primitive whether the scene has primitives or not
+pub struct PrimitivesGpu {
+    primitives_buffer: wgpu::Buffer,
+    /// The mesh has analytic primitives, selects primitives.wgsl over primitives_none.wgsl
+    analytic: bool,
+}
+
+impl PrimitivesGpu {
+    pub fn new(device: &wgpu::Device, mesh: &Mesh) -> Self {
+        let mut primitives = mesh.primitives.iter().map(GpuPrimitive::from).collect::<Vec<_>>();
+        let analytic = !primitives.is_empty();
+        // wgpu does not support zero sized buffers, nothing references the placeholder
+        if !analytic {
+            primitives.push(bytemuck::Zeroable::zeroed());
+        }
+        let primitives_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Analytic Primitives Buffer"),
+            contents: bytemuck::cast_slice(primitives.as_slice()),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+        });
+        Self {
+            primitives_buffer,
+            analytic,
+        }
+    }
+
+    /// Shader definitions of the bindings, known without a device
+    pub fn bind_descriptor(analytic: bool) -> Vec<WgslBindDescriptor<'static>> {
+        let dispatch_code = match analytic {
+            true => "res/shaders/primitives.wgsl",
+            false => "res/shaders/primitives_none.wgsl",
+        };
+        vec![WgslBindDescriptor {
+            struct_def: Some(GpuPrimitive::WGSL),
+            bind_type: Some("storage"),
+            var_name: "primitives",
+            var_type: "array<Primitive>",
+            extra_code: Some(WgslSource::File(dispatch_code)),
+        }]
+    }
+}
+
+impl Bindable for PrimitivesGpu {
+    fn get_layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+        vec![wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: SCENE_VISIBILITY,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }]
+    }
+
+    fn get_bind_group_entries(&self) -> Vec<wgpu::BindGroupEntry> {
+        vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: self.primitives_buffer.as_entire_binding(),
+        }]
+    }
+
+    fn get_bind_descriptor(&self) -> Vec<WgslBindDescriptor> {
+        Self::bind_descriptor(self.analytic)
+    }
+}
@@ -1,12 +1,22 @@
 use wgpu::util::DeviceExt;
 
-use crate::{bindings::WgslBindDescriptor, data_structures::vector::Vec4f32, mesh::Mesh};
-
-use super::Bindable;
+use crate::{
+    bindings::WgslBindDescriptor,
+    data_structures::vector::Vec4f32,
+    mesh::{Material, Mesh},
+    scenes::VertexType,
+};
+
+use super::{
+    wgsl_struct::{wgsl_struct, WgslStruct},
+    primitives::PrimitivesGpu,
+    Bindable, SCENE_VISIBILITY,
+};
 
 pub struct StorageMeshGpu {
     geometry: GeometryGpu,
     materials: MaterialsGpu,
+    primitives: PrimitivesGpu,
 }
 
 impl StorageMeshGpu {
@@ -14,6 +24,7 @@ impl StorageMeshGpu {
         Self {
             geometry: GeometryGpu::Split(GeometryGpuSplit::new(device, mesh)),
             materials: MaterialsGpu::new(device, mesh),
+            primitives: PrimitivesGpu::new(device, mesh),
         }
     }
 
@@ -21,26 +32,56 @@ impl StorageMeshGpu {
         Self {
             geometry: GeometryGpu::Combined(GeometryGpuCombined::new(device, mesh)),
             materials: MaterialsGpu::new(device, mesh),
+            primitives: PrimitivesGpu::new(device, mesh),
         }
     }
+
+    /// Overwrites the vertices and normals with those of `mesh`, which differs
+    /// from the uploaded mesh only by where its vertices are, e.g. an `AnimatedMesh`
+    pub fn write_vertices(&self, queue: &wgpu::Queue, mesh: &Mesh) {
+        match &self.geometry {
+            GeometryGpu::Split(split) => {
+                queue.write_buffer(&split.vertex_buffer, 0, bytemuck::cast_slice(&mesh.vertices));
+                queue.write_buffer(&split.vertex_normal_buffer, 0, bytemuck::cast_slice(&mesh.normals));
+            }
+            GeometryGpu::Combined(combined) => {
+                queue.write_buffer(&combined.combined_buffer, 0, bytemuck::cast_slice(&GeometryGpuCombined::interleave(mesh)));
+            }
+        }
+    }
+
+    /// Shader definitions of the bindings, known without a device,
+    /// `analytic` if the mesh has analytic primitives
+    pub fn bind_descriptor(vertex_type: VertexType, analytic: bool) -> Vec<WgslBindDescriptor<'static>> {
+        let mut bind_descriptors = match vertex_type {
+            VertexType::Split => GeometryGpuSplit::bind_descriptor(),
+            VertexType::Combined => GeometryGpuCombined::bind_descriptor(),
+        };
+        bind_descriptors.append(&mut MaterialsGpu::bind_descriptor());
+        bind_descriptors.append(&mut PrimitivesGpu::bind_descriptor(analytic));
+        bind_descriptors
+    }
 }
 
 impl Bindable for StorageMeshGpu {
     fn get_layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
         let mut layout_entries = self.geometry.get_layout_entries();
         layout_entries.append(&mut self.materials.get_layout_entries());
+        layout_entries.append(&mut self.primitives.get_layout_entries());
         layout_entries
     }
 
     fn get_bind_group_entries(&self) -> Vec<wgpu::BindGroupEntry> {
         let mut bind_group_entries = self.geometry.get_bind_group_entries();
         bind_group_entries.append(&mut self.materials.get_bind_group_entries());
+        bind_group_entries.append(&mut self.primitives.get_bind_group_entries());
         bind_group_entries
     }
 
     fn get_bind_descriptor(&self) -> Vec<WgslBindDescriptor> {
         let mut bind_descriptors = self.geometry.get_bind_descriptor();
         bind_descriptors.append(&mut self.materials.get_bind_descriptor());
+        bind_descriptors.append(&mut self.primitives.get_bind_descriptor());
         bind_descriptors
     }
 }
@@ -107,6 +148,34 @@ impl GeometryGpuSplit {
             index_buffer,
         }
     }
+
+    /// Shader definitions of the bindings, known without a device
+    pub fn bind_descriptor() -> Vec<WgslBindDescriptor<'static>> {
+        // TODO: need to differentiate names
+        vec![
+            WgslBindDescriptor {
+                struct_def: None,
+                bind_type: Some("storage"),
+                var_name: "vertexBuffer",
+                var_type: "array<vec4f>",
+                extra_code: None,
+            },
+            WgslBindDescriptor {
+                struct_def: None,
+                bind_type: Some("storage"),
+                var_name: "normalBuffer",
+                var_type: "array<vec4f>",
+                extra_code: None,
+            },
+            WgslBindDescriptor {
+                struct_def: None,
+                bind_type: Some("storage"),
+                var_name: "indexBuffer",
+                var_type: "array<vec4u>",
+                extra_code: None,
+            },
+        ]
+    }
 }
 
 impl Bindable for GeometryGpuSplit {
@@ -115,7 +184,7 @@ impl Bindable for GeometryGpuSplit {
             wgpu::BindGroupLayoutEntry {
                 // vertex position
                 binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
+                visibility: SCENE_VISIBILITY,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Storage { read_only: true },
                     has_dynamic_offset: false,
@@ -126,7 +195,7 @@ impl Bindable for GeometryGpuSplit {
             wgpu::BindGroupLayoutEntry {
                 // vertex normal
                 binding: 1,
-                visibility: wgpu::ShaderStages::FRAGMENT,
+                visibility: SCENE_VISIBILITY,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Storage { read_only: true },
                     has_dynamic_offset: false,
@@ -137,7 +206,7 @@ impl Bindable for GeometryGpuSplit {
             wgpu::BindGroupLayoutEntry {
                 // index buffer
                 binding: 2,
-                visibility: wgpu::ShaderStages::FRAGMENT,
+                visibility: SCENE_VISIBILITY,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Storage { read_only: true },
                     has_dynamic_offset: false,
@@ -166,30 +235,7 @@ impl Bindable for GeometryGpuSplit {
     }
 
     fn get_bind_descriptor(&self) -> Vec<WgslBindDescriptor> {
-        // TODO: need to differentiate names
-        vec![
-            WgslBindDescriptor {
-                struct_def: None,
-                bind_type: Some("storage"),
-                var_name: "vertexBuffer",
-                var_type: "array<vec4f>",
-                extra_code: None,
-            },
-            WgslBindDescriptor {
-                struct_def: None,
-                bind_type: Some("storage"),
-                var_name: "normalBuffer",
-                var_type: "array<vec4f>",
-                extra_code: None,
-            },
-            WgslBindDescriptor {
-                struct_def: None,
-                bind_type: Some("storage"),
-                var_name: "indexBuffer",
-                var_type: "array<vec4u>",
-                extra_code: None,
-            },
-        ]
+        Self::bind_descriptor()
     }
 }
 
@@ -205,17 +251,14 @@ struct CombinedVertexNormal {
     normal: Vec4f32,
 }
 
+wgsl_struct!(CombinedVertexNormal as VertexNormal {
+    position: vec4f = vertex,
+    normal: vec4f,
+});
+
 impl GeometryGpuCombined {
     pub fn new(device: &wgpu::Device, mesh: &Mesh) -> Self {
-        let combined_slice = mesh
-            .vertices
-            .iter()
-            .zip(&mesh.normals)
-            .map(|(vertex, normal)| CombinedVertexNormal {
-                vertex: *vertex,
-                normal: *normal,
-            })
-            .collect::<Vec<_>>();
+        let combined_slice = Self::interleave(mesh);
         let combined_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Model Vertex Buffer Combined"),
             contents: bytemuck::cast_slice(&combined_slice),
@@ -233,6 +276,37 @@ impl GeometryGpuCombined {
             index_buffer,
         }
     }
+
+    fn interleave(mesh: &Mesh) -> Vec<CombinedVertexNormal> {
+        mesh.vertices
+            .iter()
+            .zip(&mesh.normals)
+            .map(|(vertex, normal)| CombinedVertexNormal {
+                vertex: *vertex,
+                normal: *normal,
+            })
+            .collect()
+    }
+
+    /// Shader definitions of the bindings, known without a device
+    pub fn bind_descriptor() -> Vec<WgslBindDescriptor<'static>> {
+        vec![
+            WgslBindDescriptor {
+                struct_def: Some(CombinedVertexNormal::WGSL),
+                bind_type: Some("storage"),
+                var_name: "combinedBuffer",
+                var_type: "array<VertexNormal>",
+                extra_code: None,
+            },
+            WgslBindDescriptor {
+                struct_def: None,
+                bind_type: Some("storage"),
+                var_name: "indexBuffer",
+                var_type: "array<vec4u>",
+                extra_code: None,
+            },
+        ]
+    }
 }
 
 impl Bindable for GeometryGpuCombined {
@@ -241,7 +315,7 @@ impl Bindable for GeometryGpuCombined {
             wgpu::BindGroupLayoutEntry {
                 // vertex combined
                 binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
+                visibility: SCENE_VISIBILITY,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Storage { read_only: true },
                     has_dynamic_offset: false,
@@ -252,7 +326,7 @@ impl Bindable for GeometryGpuCombined {
             wgpu::BindGroupLayoutEntry {
                 // index buffer
                 binding: 1,
-                visibility: wgpu::ShaderStages::FRAGMENT,
+                visibility: SCENE_VISIBILITY,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Storage { read_only: true },
                     has_dynamic_offset: false,
@@ -277,26 +351,7 @@ impl Bindable for GeometryGpuCombined {
     }
 
     fn get_bind_descriptor(&self) -> Vec<WgslBindDescriptor> {
-        let struct_def = "struct VertexNormal {
-    position: vec4f,
-    normal: vec4f,
-};";
-        vec![
-            WgslBindDescriptor {
-                struct_def: Some(struct_def),
-                bind_type: Some("storage"),
-                var_name: "combinedBuffer",
-                var_type: "array<VertexNormal>",
-                extra_code: None,
-            },
-            WgslBindDescriptor {
-                struct_def: None,
-                bind_type: Some("storage"),
-                var_name: "indexBuffer",
-                var_type: "array<vec4u>",
-                extra_code: None,
-            },
-        ]
+        Self::bind_descriptor()
     }
 }
 
@@ -344,6 +399,26 @@ impl MaterialsGpu {
             light_sources_buffer,
         }
     }
+
+    /// Shader definitions of the bindings, known without a device
+    pub fn bind_descriptor() -> Vec<WgslBindDescriptor<'static>> {
+        vec![
+            WgslBindDescriptor {
+                struct_def: Some(Material::WGSL),
+                bind_type: Some("storage"),
+                var_name: "materials",
+                var_type: "array<Material>",
+                extra_code: None,
+            },
+            WgslBindDescriptor {
+                struct_def: None,
+                bind_type: Some("storage"),
+                var_name: "lightIndices",
+                var_type: "array<u32>",
+                extra_code: None,
+            },
+        ]
+    }
 }
 
 impl Bindable for MaterialsGpu {
@@ -352,7 +427,7 @@ impl Bindable for MaterialsGpu {
             wgpu::BindGroupLayoutEntry {
                 // materials
                 binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
+                visibility: SCENE_VISIBILITY,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Storage { read_only: true },
                     has_dynamic_offset: false,
@@ -363,7 +438,7 @@ impl Bindable for MaterialsGpu {
             wgpu::BindGroupLayoutEntry {
                 // materials
                 binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
+                visibility: SCENE_VISIBILITY,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Storage { read_only: true },
                     has_dynamic_offset: false,
@@ -388,28 +463,6 @@ impl Bindable for MaterialsGpu {
     }
 
     fn get_bind_descriptor(&self) -> Vec<WgslBindDescriptor> {
-        let material_struct_def = "struct Material {
-            diffuse: vec4f,
-            ambient: vec4f,
-            specular: vec4f,
-            emissive: u32,
-        };";
-
-        vec![
-            WgslBindDescriptor {
-                struct_def: Some(material_struct_def),
-                bind_type: Some("storage"),
-                var_name: "materials",
-                var_type: "array<Material>",
-                extra_code: None,
-            },
-            WgslBindDescriptor {
-                struct_def: None,
-                bind_type: Some("storage"),
-                var_name: "lightIndices",
-                var_type: "array<u32>",
-                extra_code: None,
-            },
-        ]
+        Self::bind_descriptor()
     }
 }
@@ -16,6 +16,17 @@ pub struct TextureInfo {
     pub samplers: [bool; 3],
 }
 
+impl TextureInfo {
+    /// Names of the default, bilinear and nearest samplers, if the texture has them
+    pub fn sampler_names(&self) -> [Option<String>; 3] {
+        [
+            self.samplers[0].then(|| self.sampler_name.clone()),
+            self.samplers[0].then(|| format!("{}_bilinear", self.sampler_name)),
+            self.samplers[0].then(|| format!("{}_nearest", self.sampler_name)),
+        ]
+    }
+}
+
 pub struct Texture {
     pub name: String,
     _texture: wgpu::Texture,
@@ -58,31 +69,40 @@ impl Texture {
         let (texture, view, sampler_default, sampler_bilinear, sampler_no_filtering) =
             Self::build(device, queue, img, &info.name);
 
+        let [name_default, name_bilinear, name_no_filtering] = info.sampler_names();
+
         Ok(Self {
             name: info.name,
             _texture: texture,
             view,
-            sampler_default: if info.samplers[0] {
-                Some((format!("{}", info.sampler_name), sampler_default))
-            } else {
-                None
-            },
-            sampler_bilinear: if info.samplers[0] {
-                Some((format!("{}_bilinear", info.sampler_name), sampler_bilinear))
-            } else {
-                None
-            },
-            sampler_no_filtering: if info.samplers[0] {
-                Some((
-                    format!("{}_nearest", info.sampler_name),
-                    sampler_no_filtering,
-                ))
-            } else {
-                None
-            },
+            sampler_default: name_default.map(|name| (name, sampler_default)),
+            sampler_bilinear: name_bilinear.map(|name| (name, sampler_bilinear)),
+            sampler_no_filtering: name_no_filtering.map(|name| (name, sampler_no_filtering)),
         })
     }
 
+    /// Shader definitions of the bindings, known without a device
+    pub fn bind_descriptor<'a>(
+        name: &'a str,
+        sampler_names: impl IntoIterator<Item = &'a str>,
+    ) -> Vec<WgslBindDescriptor<'a>> {
+        let mut bind_descriptors = vec![WgslBindDescriptor {
+            struct_def: None,
+            bind_type: None,
+            var_name: name,
+            var_type: "texture_2d<f32>",
+            extra_code: None,
+        }];
+        bind_descriptors.extend(sampler_names.into_iter().map(|name| WgslBindDescriptor {
+            struct_def: None,
+            bind_type: None,
+            var_name: name,
+            var_type: "sampler",
+            extra_code: None,
+        }));
+        bind_descriptors
+    }
+
     fn build(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
@@ -243,42 +263,13 @@ impl Bindable for Texture {
     }
 
     fn get_bind_descriptor(&self) -> Vec<WgslBindDescriptor> {
-        let mut bind_descriptors = vec![WgslBindDescriptor {
-            struct_def: None,
-            bind_type: None,
-            var_name: self.name.as_str(),
-            var_type: "texture_2d<f32>",
-            extra_code: None,
-        }];
-        if let Some((name, _)) = &self.sampler_default {
-            bind_descriptors.push(WgslBindDescriptor {
-                struct_def: None,
-                bind_type: None,
-                var_name: name.as_str(),
-                var_type: "sampler",
-                extra_code: None,
-            });
-        }
-        if let Some((name, _)) = &self.sampler_bilinear {
-            bind_descriptors.push(WgslBindDescriptor {
-                struct_def: None,
-                bind_type: None,
-                var_name: name.as_str(),
-                var_type: "sampler",
-                extra_code: None,
-            });
-        }
-        if let Some((name, _)) = &self.sampler_no_filtering {
-            bind_descriptors.push(WgslBindDescriptor {
-                struct_def: None,
-                bind_type: None,
-                var_name: name.as_str(),
-                var_type: "sampler",
-                extra_code: None,
-            });
-        }
-
-        bind_descriptors
+        Self::bind_descriptor(
+            &self.name,
+            [&self.sampler_default, &self.sampler_bilinear, &self.sampler_no_filtering]
+                .into_iter()
+                .flatten()
+                .map(|(name, _)| name.as_str()),
+        )
     }
 }
 
@@ -327,6 +318,17 @@ impl RenderDestination {
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
     }
+
+    /// Shader definitions of the bindings, known without a device
+    pub fn bind_descriptor() -> Vec<WgslBindDescriptor<'static>> {
+        vec![WgslBindDescriptor {
+            struct_def: None,
+            bind_type: None,
+            var_name: "renderTexture",
+            var_type: "texture_2d<f32>",
+            extra_code: None,
+        }]
+    }
 }
 
 impl Bindable for RenderDestination {
@@ -351,13 +353,7 @@ impl Bindable for RenderDestination {
     }
 
     fn get_bind_descriptor(&self) -> Vec<WgslBindDescriptor> {
-        vec![WgslBindDescriptor {
-            struct_def: None,
-            bind_type: None,
-            var_name: "renderTexture",
-            var_type: "texture_2d<f32>",
-            extra_code: None,
-        }]
+        Self::bind_descriptor()
     }
 }
 
@@ -398,10 +394,159 @@ impl RenderSource {
         self.texture = texture;
         self.view = view;
     }
+}
 
-    pub fn update_view(&mut self) {
-        self.view = self
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+/// Display frame written by the path tracer at the rendering resolution
+/// which is then scaled to the window by the presentation pass
+pub struct RenderFrame {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    format: wgpu::TextureFormat,
+    sampler_nearest: wgpu::Sampler,
+    sampler_bilinear: wgpu::Sampler,
+}
+
+impl RenderFrame {
+    pub fn new(device: &wgpu::Device, size: (u32, u32), format: wgpu::TextureFormat) -> Self {
+        let (texture, view) = Self::build(device, size, format);
+        let sampler_nearest = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let sampler_bilinear = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            format,
+            sampler_nearest,
+            sampler_bilinear,
+        }
+    }
+
+    fn build(
+        device: &wgpu::Device,
+        size: (u32, u32),
+        format: wgpu::TextureFormat,
+    ) -> (wgpu::Texture, wgpu::TextureView) {
+        let extent = wgpu::Extent3d {
+            width: size.0,
+            height: size.1,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Render Frame"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        (texture, view)
+    }
+
+    pub fn change_dimension(&mut self, device: &wgpu::Device, new_size: (u32, u32)) {
+        let (texture, view) = Self::build(device, new_size, self.format);
+        self.texture = texture;
+        self.view = view;
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    /// Shader definitions of the bindings, known without a device
+    pub fn bind_descriptor() -> Vec<WgslBindDescriptor<'static>> {
+        vec![
+            WgslBindDescriptor {
+                struct_def: None,
+                bind_type: None,
+                var_name: "frameTexture",
+                var_type: "texture_2d<f32>",
+                extra_code: None,
+            },
+            WgslBindDescriptor {
+                struct_def: None,
+                bind_type: None,
+                var_name: "frameSamplerNearest",
+                var_type: "sampler",
+                extra_code: None,
+            },
+            WgslBindDescriptor {
+                struct_def: None,
+                bind_type: None,
+                var_name: "frameSamplerBilinear",
+                var_type: "sampler",
+                extra_code: None,
+            },
+        ]
+    }
+}
+
+impl Bindable for RenderFrame {
+    fn get_layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+        vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ]
+    }
+
+    fn get_bind_group_entries(&self) -> Vec<wgpu::BindGroupEntry> {
+        vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&self.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&self.sampler_nearest),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(&self.sampler_bilinear),
+            },
+        ]
+    }
+
+    fn get_bind_descriptor(&self) -> Vec<WgslBindDescriptor> {
+        Self::bind_descriptor()
     }
 }
@@ -1,4 +1,7 @@
-use super::{Bindable, BufferOwner, WgslBindDescriptor};
+use super::{
+    wgsl_struct::{wgsl_struct, WgslStruct},
+    Bindable, BufferOwner, WgslBindDescriptor, SCENE_VISIBILITY,
+};
 use crate::camera::Camera;
 
 use wgpu::util::DeviceExt;
@@ -33,6 +36,21 @@ struct Uniform {
     canvas_resolution: [u32; 2],
 }
 
+wgsl_struct!(Uniform as Uniform {
+    camera_pos: vec3f,
+    camera_constant: f32,
+    camera_look_at: vec3f,
+    aspect_ratio: f32,
+    camera_up: vec3f,
+    selection1: u32,
+    selection2: u32,
+    subdivision_level: u32,
+    use_texture: u32,
+    iteration: u32,
+    uv_scale: vec2f,
+    resolution: vec2u = canvas_resolution,
+});
+
 pub const MAX_SUBDIVISION: u32 = 10;
 
 pub struct UniformGpu {
@@ -139,6 +157,31 @@ impl UniformGpu {
     pub fn update_resolution(&mut self, resolution: (u32, u32)) {
         self.uniforms.canvas_resolution = resolution.into()
     }
+
+    /// Shader definitions of the bindings, known without a device
+    pub fn bind_descriptor() -> Vec<WgslBindDescriptor<'static>> {
+        let struct_def = Some(Uniform::WGSL);
+
+        //@group(0) @binding(2)
+        //var<storage> jitter: array<vec2f>;
+
+        vec![
+            WgslBindDescriptor {
+                struct_def,
+                bind_type: Some("uniform"),
+                var_name: "uniforms",
+                var_type: "Uniform",
+                extra_code: None,
+            },
+            WgslBindDescriptor {
+                struct_def: None,
+                bind_type: Some("storage"),
+                var_name: "jitter",
+                var_type: "array<vec2f>",
+                extra_code: None,
+            },
+        ]
+    }
 }
 
 impl Uniform {
@@ -180,7 +223,7 @@ impl Bindable for UniformGpu {
         vec![
             wgpu::BindGroupLayoutEntry {
                 binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
+                visibility: SCENE_VISIBILITY,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Uniform,
                     has_dynamic_offset: false,
@@ -190,7 +233,7 @@ impl Bindable for UniformGpu {
             },
             wgpu::BindGroupLayoutEntry {
                 binding: 1,
-                visibility: wgpu::ShaderStages::FRAGMENT,
+                visibility: SCENE_VISIBILITY,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Storage { read_only: true },
                     has_dynamic_offset: false,
@@ -212,42 +255,7 @@ impl Bindable for UniformGpu {
     }
 
     fn get_bind_descriptor(&self) -> Vec<WgslBindDescriptor> {
-        let struct_def = Some(
-            "struct Uniform {
-    camera_pos: vec3f,
-    camera_constant: f32,
-    camera_look_at: vec3f,
-    aspect_ratio: f32,
-    camera_up: vec3f,
-    selection1: u32,
-    selection2: u32,
-    subdivision_level: u32,
-    use_texture: u32,
-    iteration: u32,
-    uv_scale: vec2f,
-    resolution: vec2u,
-};",
-        );
-
-        //@group(0) @binding(2)
-        //var<storage> jitter: array<vec2f>;
-
-        vec![
-            WgslBindDescriptor {
-                struct_def,
-                bind_type: Some("uniform"),
-                var_name: "uniforms",
-                var_type: "Uniform",
-                extra_code: None,
-            },
-            WgslBindDescriptor {
-                struct_def: None,
-                bind_type: Some("storage"),
-                var_name: "jitter",
-                var_type: "array<vec2f>",
-                extra_code: None,
-            },
-        ]
+        Self::bind_descriptor()
     }
 }
 
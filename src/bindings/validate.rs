@@ -0,0 +1,108 @@
+/// Offline shader validation with naga, the shader translator used by wgpu.
+/// Finds the errors `create_shader_module` would report without needing a GPU.
+
+use anyhow::*;
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+
+use super::preprocess::PreprocessedShader;
+
+/// Parses and validates the shader including the definitions generated from the
+/// bindings. Only the capabilities of a device without optional features are assumed.
+pub fn validate_shader(shader: &PreprocessedShader) -> Result<()> {
+    let source = &shader.source;
+    let remap = |message: String| anyhow!(shader.source_map.remap_errors(&message));
+
+    let module = naga::front::wgsl::parse_str(source).map_err(|err| remap(err.emit_to_string(source)))?;
+    Validator::new(ValidationFlags::all(), Capabilities::empty())
+        .validate(&module)
+        .map_err(|err| remap(err.emit_to_string(source)))?;
+    Ok(())
+}
+
+/// Locations of the color targets the fragment shader `entry_point` writes
+pub fn fragment_locations(shader: &PreprocessedShader, entry_point: &str) -> Result<Vec<u32>> {
+    let source = &shader.source;
+    let module = naga::front::wgsl::parse_str(source)
+        .map_err(|err| anyhow!(shader.source_map.remap_errors(&err.emit_to_string(source))))?;
+    let function = module
+        .entry_points
+        .iter()
+        .find(|entry| entry.stage == naga::ShaderStage::Fragment && entry.name == entry_point)
+        .map(|entry| &entry.function)
+        .ok_or_else(|| anyhow!("No fragment entry point {entry_point}"))?;
+    let location = |binding: Option<&naga::Binding>| match binding {
+        Some(naga::Binding::Location { location, .. }) => Some(*location),
+        _ => None,
+    };
+    Ok(match &function.result {
+        Some(naga::FunctionResult { binding: Some(binding), .. }) => location(Some(binding)).into_iter().collect(),
+        Some(naga::FunctionResult { ty, binding: None }) => match &module.types[*ty].inner {
+            naga::TypeInner::Struct { members, .. } => {
+                members.iter().filter_map(|member| location(member.binding.as_ref())).collect()
+            }
+            _ => vec![],
+        },
+        None => vec![],
+    })
+}
+
+#[cfg(test)]
+mod validate_test {
+    use super::*;
+    use crate::bindings::{append_shader_definitions, preprocess::preprocess_file, WgslBindDescriptor, WgslSource};
+
+    fn validate_source(name: &str, source: &str) -> Result<()> {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, source)?;
+        let mut shader = preprocess_file(&path)?;
+        shader.append_generated("bindings", "@group(0) @binding(0)\nvar<uniform> value: f32;");
+        validate_shader(&shader)
+    }
+
+    #[test]
+    fn errors_point_to_the_shader_file() {
+        assert!(validate_source("validate_ok.wgsl", "fn f() -> f32 {\n    return value;\n}\n").is_ok());
+
+        let err = validate_source("validate_type.wgsl", "// wrong type\nfn f() -> u32 {\n    return value;\n}\n").unwrap_err();
+        assert!(err.to_string().contains("validate_type.wgsl:2:"), "{err}");
+        let err = validate_source("validate_parse.wgsl", "fn f() -> f32 {\n    return valeu;\n}\n").unwrap_err();
+        assert!(err.to_string().contains("validate_parse.wgsl:2:"), "{err}");
+    }
+
+    #[test]
+    fn errors_in_binding_code_point_to_its_file() {
+        let extra = std::env::temp_dir().join("validate_extra.wgsl");
+        std::fs::write(&extra, "fn g() -> f32 {\n    return valeu;\n}\n").unwrap();
+        let shader_path = std::env::temp_dir().join("validate_main.wgsl");
+        std::fs::write(&shader_path, "// main\n".repeat(10)).unwrap();
+
+        let mut shader = preprocess_file(&shader_path).unwrap();
+        let descriptor = WgslBindDescriptor {
+            struct_def: None,
+            bind_type: Some("uniform"),
+            var_name: "value",
+            var_type: "f32",
+            extra_code: Some(WgslSource::File(extra.to_str().unwrap())),
+        };
+        append_shader_definitions(&mut shader, &[vec![vec![descriptor]]]).unwrap();
+
+        // the error is on line 14 of the whole source, the snippet shows the line in the file
+        let err = validate_shader(&shader).unwrap_err().to_string();
+        assert!(err.contains(&format!("┌─ {}:2:12", extra.display())), "{err}");
+        assert!(err.contains("2 │     return valeu;"), "{err}");
+        assert!(!err.contains("14 │"), "{err}");
+    }
+
+    #[test]
+    fn fragment_locations_of_structs_and_single_outputs() {
+        let path = std::env::temp_dir().join("validate_outputs.wgsl");
+        let source = "struct Output {\n    @location(0) frame: vec4f,\n    @location(2) albedo: vec4f,\n}\n\n\
+            @fragment\nfn fs_main() -> Output {\n    return Output(vec4f(0.0), vec4f(1.0));\n}\n\n\
+            @fragment\nfn fs_frame() -> @location(0) vec4f {\n    return vec4f(0.0);\n}\n";
+        std::fs::write(&path, source).unwrap();
+        let shader = preprocess_file(&path).unwrap();
+        assert_eq!(fragment_locations(&shader, "fs_main").unwrap(), vec![0, 2]);
+        assert_eq!(fragment_locations(&shader, "fs_frame").unwrap(), vec![0]);
+        assert!(fragment_locations(&shader, "fs_missing").is_err());
+    }
+}
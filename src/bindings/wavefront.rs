@@ -0,0 +1,443 @@
+/// Wavefront path tracing: instead of one fragment shader invocation tracing
+/// the whole path of a pixel, every bounce of all paths is traced by a few
+/// small compute passes over queues of rays in storage buffers. The path
+/// state lives in buffers between the passes, a fragment pass at the end
+/// accumulates it into the same render targets the fragment backend writes.
+/// The kernels are in res/shaders/wavefront.wgsl.
+
+use super::{
+    wgsl_struct::{wgsl_struct, WgslStruct},
+    Bindable, WgslBindDescriptor, WgslSource,
+};
+use crate::tiles::Tile;
+
+/// Threads per workgroup of the kernels, also written out in the shader
+pub const WORKGROUP_SIZE: u32 = 256;
+/// Bounces dispatched per frame, paths are terminated by the scene shader
+/// (`MAX_DEPTH`) before this as long as it does not trace deeper
+pub const MAX_BOUNCES: u32 = 50;
+
+const WAVEFRONT_SHADER: &str = "res/shaders/wavefront.wgsl";
+
+// The structs below are only filled by the shaders, the Rust types
+// document and check their layout and give the buffer sizes.
+
+#[allow(dead_code)]
+#[repr(C, align(16))]
+#[derive(Debug, Copy, Clone)]
+struct PathState {
+    origin: [f32; 3],
+    seed: u32,
+    direction: [f32; 3],
+    depth: u32,
+    throughput: [f32; 3],
+    flags: u32,
+    radiance: [f32; 3],
+    tmin: f32,
+}
+
+wgsl_struct!(PathState as PathState {
+    origin: vec3f,
+    seed: u32,
+    direction: vec3f,
+    depth: u32,
+    throughput: vec3f,
+    flags: u32,
+    radiance: vec3f,
+    tmin: f32,
+});
+
+/// Closest hit of the last intersected ray of a path
+#[allow(dead_code)]
+#[repr(C, align(16))]
+#[derive(Debug, Copy, Clone)]
+struct PathHit {
+    position: [f32; 3],
+    object: u32,
+    normal: [f32; 3],
+    material: u32,
+}
+
+wgsl_struct!(PathHit as PathHit {
+    position: vec3f,
+    object: u32,
+    normal: vec3f,
+    material: u32,
+});
+
+/// First hit of a path, written to the AOV targets when accumulating
+#[allow(dead_code)]
+#[repr(C, align(16))]
+#[derive(Debug, Copy, Clone)]
+struct PathAov {
+    albedo: [f32; 3],
+    object: u32,
+    normal: [f32; 3],
+    material: u32,
+    depth: f32,
+    _padding: [u32; 3],
+}
+
+wgsl_struct!(PathAov as PathAov {
+    albedo: vec3f,
+    object: u32,
+    normal: vec3f,
+    material: u32,
+    depth: f32,
+});
+
+/// Adds `radiance` to the path if the segment is not blocked
+#[allow(dead_code)]
+#[repr(C, align(16))]
+#[derive(Debug, Copy, Clone)]
+struct ShadowRay {
+    origin: [f32; 3],
+    path: u32,
+    direction: [f32; 3],
+    tmin: f32,
+    radiance: [f32; 3],
+    tmax: f32,
+}
+
+wgsl_struct!(ShadowRay as ShadowRay {
+    origin: vec3f,
+    path: u32,
+    direction: vec3f,
+    tmin: f32,
+    radiance: vec3f,
+    tmax: f32,
+});
+
+/// Queue lengths, the dispatch arguments come first so they
+/// can be copied to the indirect buffer in one piece
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct Queues {
+    ray_dispatch: [u32; 3],
+    shadow_dispatch: [u32; 3],
+    next_rays: u32,
+    next_shadows: u32,
+    rays: u32,
+    shadows: u32,
+    bounce: u32,
+    _padding: u32,
+    tile: [u32; 4],
+}
+
+const QUEUES_WGSL: &str = "struct Queues {
+    // workgroups of the ray kernels, then of the shadow ray kernel
+    dispatch: array<u32, 6>,
+    // rays pushed for the next bounce
+    next_rays: atomic<u32>,
+    next_shadows: atomic<u32>,
+    // rays of the current bounce
+    rays: u32,
+    shadows: u32,
+    bounce: u32,
+    // x, y, width and height of the traced tile
+    tile: vec4u,
+};";
+
+const DISPATCH_SIZE: u64 = std::mem::offset_of!(Queues, next_rays) as u64;
+const SHADOW_DISPATCH_OFFSET: u64 = std::mem::offset_of!(Queues, shadow_dispatch) as u64;
+const TILE_OFFSET: u64 = std::mem::offset_of!(Queues, tile) as u64;
+const TILE_SIZE: u64 = std::mem::size_of::<[u32; 4]>() as u64;
+
+pub struct WavefrontGpu {
+    paths: wgpu::Buffer,
+    hits: wgpu::Buffer,
+    aovs: wgpu::Buffer,
+    /// Indices of the paths to trace, two queues swapped every bounce
+    ray_queue: wgpu::Buffer,
+    shadow_rays: wgpu::Buffer,
+    queues: wgpu::Buffer,
+    /// Copy of the dispatch arguments, a buffer can not be bound as
+    /// storage and used for indirect dispatches in the same pass
+    dispatch: wgpu::Buffer,
+    /// Tiles of the next submission, copied into the queues one at a time
+    tiles: wgpu::Buffer,
+    size: (u32, u32),
+}
+
+impl WavefrontGpu {
+    pub fn new(device: &wgpu::Device, size: (u32, u32)) -> Self {
+        let pixels = Self::pixels(size);
+        let queues = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Wavefront queues buffer"),
+            size: std::mem::size_of::<Queues>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let dispatch = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Wavefront dispatch buffer"),
+            size: DISPATCH_SIZE,
+            usage: wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            paths: Self::build(device, "Wavefront paths buffer", pixels * std::mem::size_of::<PathState>() as u64),
+            hits: Self::build(device, "Wavefront hits buffer", pixels * std::mem::size_of::<PathHit>() as u64),
+            aovs: Self::build(device, "Wavefront AOV buffer", pixels * std::mem::size_of::<PathAov>() as u64),
+            ray_queue: Self::build(device, "Wavefront ray queue buffer", 2 * pixels * std::mem::size_of::<u32>() as u64),
+            shadow_rays: Self::build(device, "Wavefront shadow ray buffer", pixels * std::mem::size_of::<ShadowRay>() as u64),
+            queues,
+            dispatch,
+            tiles: Self::build_tiles(device, 1),
+            size,
+        }
+    }
+
+    fn pixels(size: (u32, u32)) -> u64 {
+        size.0 as u64 * size.1 as u64
+    }
+
+    fn build(device: &wgpu::Device, label: &str, size: u64) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn build_tiles(device: &wgpu::Device, count: u64) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Wavefront tiles buffer"),
+            size: count * TILE_SIZE,
+            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Uploads the tiles traced by the next submission, in the order they are encoded
+    pub fn write_tiles(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, tiles: &[Tile]) {
+        let count = tiles.len() as u64;
+        if self.tiles.size() < count * TILE_SIZE {
+            self.tiles = Self::build_tiles(device, count);
+        }
+        let rects = tiles
+            .iter()
+            .map(|tile| [tile.x, tile.y, tile.width, tile.height])
+            .collect::<Vec<_>>();
+        queue.write_buffer(&self.tiles, 0, bytemuck::cast_slice(&rects));
+    }
+
+    pub fn change_dimension(&mut self, device: &wgpu::Device, new_size: (u32, u32)) {
+        if new_size != self.size {
+            *self = Self::new(device, new_size);
+        }
+    }
+
+    /// Shader definitions of the bindings, known without a device
+    pub fn bind_descriptor() -> Vec<WgslBindDescriptor<'static>> {
+        vec![
+            WgslBindDescriptor {
+                struct_def: Some(PathState::WGSL),
+                bind_type: Some("storage, read_write"),
+                var_name: "paths",
+                var_type: "array<PathState>",
+                extra_code: None,
+            },
+            WgslBindDescriptor {
+                struct_def: Some(PathHit::WGSL),
+                bind_type: Some("storage, read_write"),
+                var_name: "hits",
+                var_type: "array<PathHit>",
+                extra_code: None,
+            },
+            WgslBindDescriptor {
+                struct_def: Some(PathAov::WGSL),
+                bind_type: Some("storage, read_write"),
+                var_name: "aovs",
+                var_type: "array<PathAov>",
+                extra_code: None,
+            },
+            WgslBindDescriptor {
+                struct_def: None,
+                bind_type: Some("storage, read_write"),
+                var_name: "rayQueue",
+                var_type: "array<u32>",
+                extra_code: None,
+            },
+            WgslBindDescriptor {
+                struct_def: Some(ShadowRay::WGSL),
+                bind_type: Some("storage, read_write"),
+                var_name: "shadowRays",
+                var_type: "array<ShadowRay>",
+                extra_code: None,
+            },
+            WgslBindDescriptor {
+                struct_def: Some(QUEUES_WGSL),
+                bind_type: Some("storage, read_write"),
+                var_name: "queues",
+                var_type: "Queues",
+                extra_code: Some(WgslSource::File(WAVEFRONT_SHADER)),
+            },
+        ]
+    }
+
+    /// Copies the dispatch arguments written by the last `wavefront_prepare`
+    fn encode_dispatch_copy(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.copy_buffer_to_buffer(&self.queues, 0, &self.dispatch, 0, DISPATCH_SIZE);
+    }
+}
+
+impl Bindable for WavefrontGpu {
+    fn get_layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+        // the accumulation is a fragment pass
+        let visibility = [
+            wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+            wgpu::ShaderStages::COMPUTE,
+            wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+            wgpu::ShaderStages::COMPUTE,
+            wgpu::ShaderStages::COMPUTE,
+            wgpu::ShaderStages::COMPUTE,
+        ];
+        visibility
+            .into_iter()
+            .enumerate()
+            .map(|(binding, visibility)| wgpu::BindGroupLayoutEntry {
+                binding: binding as u32,
+                visibility,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            })
+            .collect()
+    }
+
+    fn get_bind_group_entries(&self) -> Vec<wgpu::BindGroupEntry> {
+        [
+            &self.paths,
+            &self.hits,
+            &self.aovs,
+            &self.ray_queue,
+            &self.shadow_rays,
+            &self.queues,
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(binding, buffer)| wgpu::BindGroupEntry {
+            binding: binding as u32,
+            resource: buffer.as_entire_binding(),
+        })
+        .collect()
+    }
+
+    fn get_bind_descriptor(&self) -> Vec<WgslBindDescriptor> {
+        Self::bind_descriptor()
+    }
+}
+
+/// The compute pipelines of the kernels, created from the scene shader
+/// with the layout of the render pipeline
+pub struct WavefrontPipelines {
+    generate: wgpu::ComputePipeline,
+    prepare: wgpu::ComputePipeline,
+    intersect: wgpu::ComputePipeline,
+    shade: wgpu::ComputePipeline,
+    shadow: wgpu::ComputePipeline,
+}
+
+impl WavefrontPipelines {
+    pub fn new(device: &wgpu::Device, layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule) -> Self {
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(layout),
+                module: shader,
+                entry_point,
+            })
+        };
+
+        Self {
+            generate: pipeline("wavefront_generate"),
+            prepare: pipeline("wavefront_prepare"),
+            intersect: pipeline("wavefront_intersect"),
+            shade: pipeline("wavefront_shade"),
+            shadow: pipeline("wavefront_shadow"),
+        }
+    }
+
+    /// Traces one sample of every active pixel of the `index`th tile written
+    /// with `write_tiles`, has to be recorded after the adaptive sampling
+    /// reset and before the render pass
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        wavefront: &WavefrontGpu,
+        bind_groups: &[wgpu::BindGroup],
+        index: usize,
+        tile: &Tile,
+    ) {
+        encoder.clear_buffer(&wavefront.queues, 0, None);
+        encoder.copy_buffer_to_buffer(&wavefront.tiles, index as u64 * TILE_SIZE, &wavefront.queues, TILE_OFFSET, TILE_SIZE);
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Wavefront Generate Pass"),
+            });
+            set_bind_groups(&mut pass, bind_groups);
+            pass.set_pipeline(&self.generate);
+            pass.dispatch_workgroups(tile.pixels().div_ceil(WORKGROUP_SIZE), 1, 1);
+            pass.set_pipeline(&self.prepare);
+            pass.dispatch_workgroups(1, 1, 1);
+        }
+
+        for _ in 0..MAX_BOUNCES {
+            wavefront.encode_dispatch_copy(encoder);
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Wavefront Bounce Pass"),
+            });
+            set_bind_groups(&mut pass, bind_groups);
+            // the shadow rays of the last bounce, before shading pushes new ones
+            pass.set_pipeline(&self.shadow);
+            pass.dispatch_workgroups_indirect(&wavefront.dispatch, SHADOW_DISPATCH_OFFSET);
+            pass.set_pipeline(&self.intersect);
+            pass.dispatch_workgroups_indirect(&wavefront.dispatch, 0);
+            pass.set_pipeline(&self.shade);
+            pass.dispatch_workgroups_indirect(&wavefront.dispatch, 0);
+            pass.set_pipeline(&self.prepare);
+            pass.dispatch_workgroups(1, 1, 1);
+        }
+
+        wavefront.encode_dispatch_copy(encoder);
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Wavefront Shadow Pass"),
+        });
+        set_bind_groups(&mut pass, bind_groups);
+        pass.set_pipeline(&self.shadow);
+        pass.dispatch_workgroups_indirect(&wavefront.dispatch, SHADOW_DISPATCH_OFFSET);
+    }
+}
+
+/// Binds the groups of the scene shader, the group ids are the indices
+fn set_bind_groups<'a>(pass: &mut wgpu::ComputePass<'a>, bind_groups: &'a [wgpu::BindGroup]) {
+    for (idx, group) in bind_groups.iter().enumerate() {
+        pass.set_bind_group(idx as u32, group, &[]);
+    }
+}
+
+#[cfg(test)]
+mod wavefront_test {
+    use super::*;
+
+    #[test]
+    fn shader_uses_the_workgroup_size() {
+        let source = std::fs::read_to_string(WAVEFRONT_SHADER).unwrap();
+        assert!(source.contains(&format!("const WORKGROUP_SIZE: u32 = {WORKGROUP_SIZE}u;")));
+        let sizes = source
+            .split("@workgroup_size(")
+            .skip(1)
+            .map(|rest| &rest[..rest.find(')').unwrap()])
+            .collect::<Vec<_>>();
+        assert!(!sizes.is_empty());
+        for size in sizes {
+            assert!(size == "1" || size == WORKGROUP_SIZE.to_string(), "@workgroup_size({size})");
+        }
+    }
+}
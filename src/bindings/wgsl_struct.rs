@@ -0,0 +1,169 @@
+/// WGSL struct declarations generated from the `#[repr(C)]` Rust types uploaded to the GPU.
+/// `wgsl_struct!` lists the fields the shaders see, padding fields are left out, and checks
+/// at compile time that every field is where the WGSL layout rules put it. Padding after
+/// the last field is only left out if WGSL rounds the struct size up to it as well.
+
+/// Size and alignment of a host-shareable WGSL type
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WgslType {
+    pub size: usize,
+    pub align: usize,
+}
+
+/// The WGSL types usable in `wgsl_struct!`, named like in WGSL.
+/// Not every type is used by a struct yet, the vectors are complete anyway.
+#[allow(non_upper_case_globals, dead_code)]
+pub mod types {
+    use super::WgslType;
+
+    pub const f32: WgslType = WgslType { size: 4, align: 4 };
+    pub const u32: WgslType = WgslType { size: 4, align: 4 };
+    pub const i32: WgslType = WgslType { size: 4, align: 4 };
+    pub const vec2f: WgslType = WgslType { size: 8, align: 8 };
+    pub const vec2u: WgslType = WgslType { size: 8, align: 8 };
+    pub const vec2i: WgslType = WgslType { size: 8, align: 8 };
+    pub const vec3f: WgslType = WgslType { size: 12, align: 16 };
+    pub const vec3u: WgslType = WgslType { size: 12, align: 16 };
+    pub const vec3i: WgslType = WgslType { size: 12, align: 16 };
+    pub const vec4f: WgslType = WgslType { size: 16, align: 16 };
+    pub const vec4u: WgslType = WgslType { size: 16, align: 16 };
+    pub const vec4i: WgslType = WgslType { size: 16, align: 16 };
+    pub const mat4x3f: WgslType = WgslType { size: 64, align: 16 };
+}
+
+pub trait WgslStruct {
+    /// WGSL declaration with the same layout as the Rust type
+    const WGSL: &'static str;
+}
+
+pub const fn round_up(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align) * align
+}
+
+/// Size of a field, selected by a closure like `|value: &T| &value.field`
+pub const fn field_size<T, F>(_field: fn(&T) -> &F) -> usize {
+    std::mem::size_of::<F>()
+}
+
+/// Implements [`WgslStruct`] for a Rust type, `RustType as WgslName { field: wgsl_type, .. }`.
+/// A field named differently in Rust is written as `wgsl_name: wgsl_type = rust_name`.
+macro_rules! wgsl_struct {
+    ($rust:ty as $wgsl:ident { $($field:ident: $ty:ident $(= $rust_field:ident)?),* $(,)? }) => {
+        impl $crate::bindings::wgsl_struct::WgslStruct for $rust {
+            const WGSL: &'static str = concat!(
+                "struct ", stringify!($wgsl), " {\n",
+                $("    ", stringify!($field), ": ", stringify!($ty), ",\n",)*
+                "};"
+            );
+        }
+
+        const _: () = {
+            let mut offset = 0;
+            let mut align = 1;
+            $(
+                $crate::bindings::wgsl_struct::wgsl_struct!(
+                    @field $rust, $wgsl, offset, align, $ty, [$($rust_field)? $field]
+                );
+            )*
+            assert!(
+                $crate::bindings::wgsl_struct::round_up(offset, align) == std::mem::size_of::<$rust>(),
+                concat!("The size of ", stringify!($rust), " does not match WGSL struct ", stringify!($wgsl)),
+            );
+        };
+    };
+    // the Rust name comes first if it is given
+    (@field $rust:ty, $wgsl:ident, $offset:ident, $align:ident, $ty:ident, [$rust_field:ident $($_field:ident)?]) => {
+        let ty = $crate::bindings::wgsl_struct::types::$ty;
+        $offset = $crate::bindings::wgsl_struct::round_up($offset, ty.align);
+        assert!(
+            $offset == std::mem::offset_of!($rust, $rust_field),
+            concat!(stringify!($rust), "::", stringify!($rust_field), " is not at its offset in WGSL struct ", stringify!($wgsl)),
+        );
+        assert!(
+            ty.size == $crate::bindings::wgsl_struct::field_size(|value: &$rust| &value.$rust_field),
+            concat!(stringify!($rust), "::", stringify!($rust_field), " does not have the size of ", stringify!($ty)),
+        );
+        $offset += ty.size;
+        $align = if ty.align > $align { ty.align } else { $align };
+    };
+}
+
+pub(crate) use wgsl_struct;
+
+#[cfg(test)]
+mod wgsl_struct_test {
+    use super::*;
+
+    #[repr(C, align(16))]
+    #[derive(Clone, Copy)]
+    struct Test {
+        direction: [f32; 3],
+        count: u32,
+        position: [f32; 3],
+        _padding: u32,
+        size: [u32; 2],
+        _padding2: [u32; 2],
+    }
+
+    wgsl_struct!(Test as TestStruct {
+        direction: vec3f,
+        count: u32,
+        origin: vec3f = position,
+        size: vec2u,
+    });
+
+    #[test]
+    fn declaration_follows_the_rust_type() {
+        assert_eq!(
+            Test::WGSL,
+            "struct TestStruct {\n    direction: vec3f,\n    count: u32,\n    origin: vec3f,\n    size: vec2u,\n};"
+        );
+    }
+
+    #[test]
+    fn types_follow_the_wgsl_layout_rules() {
+        let types = [
+            ("f32", types::f32),
+            ("u32", types::u32),
+            ("i32", types::i32),
+            ("vec2f", types::vec2f),
+            ("vec2u", types::vec2u),
+            ("vec2i", types::vec2i),
+            ("vec3f", types::vec3f),
+            ("vec3u", types::vec3u),
+            ("vec3i", types::vec3i),
+            ("vec4f", types::vec4f),
+            ("vec4u", types::vec4u),
+            ("vec4i", types::vec4i),
+            ("mat4x3f", types::mat4x3f),
+        ];
+        // let naga lay out a struct holding each type once
+        let source = types
+            .iter()
+            .map(|(name, _)| format!("struct Holder_{name} {{ value: {name}, }}\n"))
+            .collect::<String>();
+        let module = naga::front::wgsl::parse_str(&source).unwrap();
+        let mut layouter = naga::proc::Layouter::default();
+        layouter.update(module.to_ctx()).unwrap();
+
+        for (name, ty) in types {
+            let (handle, _) = module
+                .types
+                .iter()
+                .find(|(_, holder)| holder.name.as_deref() == Some(&format!("Holder_{name}")))
+                .unwrap();
+            let naga::TypeInner::Struct { members, .. } = &module.types[handle].inner else {
+                panic!("{name} holder is not a struct");
+            };
+            let layout = layouter[members[0].ty];
+            assert_eq!(
+                WgslType {
+                    size: layout.size as usize,
+                    align: layout.alignment.round_up(1) as usize,
+                },
+                ty,
+                "{name}"
+            );
+        }
+    }
+}
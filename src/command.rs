@@ -1,11 +1,15 @@
-/// Types for sending messages from the UI thread to the rendering thread
+/// Types for sending messages from the UI thread to the rendering thread and back
 /// Based on code shared by A.B. Sørensen in
 /// https://github.com/absorensen/the-guide/tree/main/m2_concurrency/code/egui-winit-wgpu-template
 /// Apache License 2.0
 
+use std::path::PathBuf;
+
 use winit::{dpi::PhysicalSize, event::{VirtualKeyCode, ElementState}};
 use strum_macros::{EnumIter, IntoStaticStr};
 
+use crate::denoise::DenoiseParams;
+
 #[derive(Debug)]
 pub enum Command {
     Resize { new_size: PhysicalSize<u32> },
@@ -18,16 +22,38 @@ pub enum Command {
     SetSamples { samples: u32, enabled: bool },
     SetTexture { use_texture: TextureUse, uv_scale: (f32, f32) },
     SetResolution { resolution: (u32, u32), display_mode: DisplayMode },
+    SetPresentation { filter: PresentFilter, zoom: f32, pan: (f32, f32), aov: Aov },
+    SetToneMapping { operator: ToneMapping, exposure: f32, white_point: f32 },
+    SetAdaptiveSampling { enabled: bool, threshold: f32, min_samples: u32 },
+    SetTiling { enabled: bool, tile_size: u32, tiles_per_submission: u32, show_progress: bool },
+    SetDenoise { enabled: bool, params: DenoiseParams },
+    SaveImages { prefix: String },
     KeyEvent {key: VirtualKeyCode, state: ElementState },
     Shutdown { value: bool },
 }
 
+/// Reported by the rendering thread to be shown in the control panel
+#[derive(Debug)]
+pub enum Status {
+    /// The shader of the current scene was (re)compiled
+    ShaderLoaded { path: PathBuf },
+    /// The shader failed to compile, the previous pipeline is still in use
+    ShaderError { path: PathBuf, message: String },
+    /// Every pixel reached the adaptive sampling threshold after `iterations` samples,
+    /// `None` once sampling continues
+    Convergence { iterations: Option<u32> },
+}
+
 #[derive(Copy, Clone, Default, Debug, EnumIter, IntoStaticStr, PartialEq)]
 pub enum DisplayMode {
-    /// window size has 1-to-1 correspondance with the rendering resolution
+    /// rendering resolution is independent from window size, scaled to the window
+    /// keeping its aspect ratio with black bars on the remaining sides
     #[default]
+    Letterbox,
+    /// window size has 1-to-1 correspondance with the rendering resolution
     Exact,
-    /// rendering resolution is independent from window size
+    /// rendering resolution is independent from window size, stretched to fill the
+    /// window even if that distorts the image
     Stretch,
     /// Window is automatically adjusted to fit either the horizontal or vertical maximum
     FitAuto,
@@ -35,6 +61,76 @@ pub enum DisplayMode {
     Window,
 }
 
+impl DisplayMode {
+    /// Rectangle (x, y, width, height) in window pixels that the rendered
+    /// image is presented in. Stretch fills the whole window, the other modes
+    /// keep the aspect ratio of the rendering and letterbox the remaining area.
+    ///
+    /// Exact and Window never upscale, Letterbox and FitAuto scale the image up
+    /// or down to fill as much of the window as possible.
+    pub fn present_viewport(&self, window_size: (u32, u32), resolution: (u32, u32)) -> [f32; 4] {
+        let (window_w, window_h) = (window_size.0.max(1) as f32, window_size.1.max(1) as f32);
+        let (res_w, res_h) = (resolution.0.max(1) as f32, resolution.1.max(1) as f32);
+        let fit = f32::min(window_w / res_w, window_h / res_h);
+        let scale = match self {
+            DisplayMode::Exact | DisplayMode::Window => f32::min(fit, 1.0),
+            DisplayMode::Letterbox | DisplayMode::FitAuto => fit,
+            DisplayMode::Stretch => return [0.0, 0.0, window_w, window_h],
+        };
+        let (width, height) = (res_w * scale, res_h * scale);
+        [
+            ((window_w - width) * 0.5).floor(),
+            ((window_h - height) * 0.5).floor(),
+            width,
+            height,
+        ]
+    }
+}
+
+/// Texture filtering used when the rendered image is scaled to the window
+#[derive(Copy, Clone, Default, Debug, EnumIter, IntoStaticStr, PartialEq)]
+pub enum PresentFilter {
+    /// Keep the pixels sharp, good for zooming in
+    #[default]
+    Nearest = 0,
+    Bilinear = 1,
+}
+
+/// Render output shown by the presentation pass. Only the shaders importing `output`
+/// write the AOVs, with other shaders they keep their clear values, i.e. nothing hit.
+#[derive(Copy, Clone, Default, Debug, EnumIter, IntoStaticStr, PartialEq)]
+pub enum Aov {
+    /// The tone mapped path traced image
+    #[default]
+    Beauty = 0,
+    /// Base color at the first hit
+    Albedo = 1,
+    /// World space shading normal at the first hit
+    Normal = 2,
+    /// Distance along the camera axis, shown with the exposure and tone mapping
+    Depth = 3,
+    /// Triangle index, shown as random colors
+    ObjectId = 4,
+    MaterialId = 5,
+    /// Samples accumulated in the pixel, shown as a turbo heatmap relative to the sample budget
+    SampleCount = 6,
+}
+
+/// Operator mapping the linear HDR radiance to the displayable range.
+/// Applied in the presentation pass, after exposure.
+#[derive(Copy, Clone, Default, Debug, EnumIter, IntoStaticStr, PartialEq)]
+pub enum ToneMapping {
+    /// Scale by the white point and clip
+    Clamp = 0,
+    /// Extended Reinhard, the white point maps to 1.0
+    #[default]
+    Reinhard = 1,
+    /// Narkowicz' fit of the ACES filmic curve
+    AcesFilmic = 2,
+    /// Troy Sobotka's AgX with the default look
+    AgX = 3,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd, EnumIter, IntoStaticStr)]
 pub enum ShaderType {
     Lambertian = 0,
@@ -54,3 +150,40 @@ pub enum TextureUse {
     Nearest = 3,
 }
 
+
+#[cfg(test)]
+mod command_test {
+    use super::*;
+
+    #[test]
+    fn present_viewport_exact() {
+        let viewport = DisplayMode::Exact.present_viewport((1000, 800), (500, 400));
+        assert_eq!(viewport, [250.0, 200.0, 500.0, 400.0]);
+        // never larger than the window
+        let viewport = DisplayMode::Exact.present_viewport((500, 400), (1000, 400));
+        assert_eq!(viewport, [0.0, 100.0, 500.0, 200.0]);
+    }
+
+    #[test]
+    fn present_viewport_letterbox() {
+        let viewport = DisplayMode::Letterbox.present_viewport((1420, 1080), (512, 512));
+        assert_eq!(viewport, [170.0, 0.0, 1080.0, 1080.0]);
+        let viewport = DisplayMode::Letterbox.present_viewport((1000, 1000), (800, 400));
+        assert_eq!(viewport, [0.0, 250.0, 1000.0, 500.0]);
+        // smaller windows scale the image down
+        let viewport = DisplayMode::Letterbox.present_viewport((400, 300), (800, 400));
+        assert_eq!(viewport, [0.0, 50.0, 400.0, 200.0]);
+    }
+
+    #[test]
+    fn present_viewport_fit_auto() {
+        let viewport = DisplayMode::FitAuto.present_viewport((1420, 1080), (512, 512));
+        assert_eq!(viewport, [170.0, 0.0, 1080.0, 1080.0]);
+    }
+
+    #[test]
+    fn present_viewport_stretch() {
+        let viewport = DisplayMode::Stretch.present_viewport((1420, 1080), (512, 512));
+        assert_eq!(viewport, [0.0, 0.0, 1420.0, 1080.0]);
+    }
+}
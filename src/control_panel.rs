@@ -24,11 +24,17 @@ use winit::{
 };
 
 use crate::{
-    command::{Command, DisplayMode, ShaderType, TextureUse},
+    command::{Aov, Command, DisplayMode, PresentFilter, ShaderType, Status, TextureUse, ToneMapping},
+    bindings::adaptive,
+    denoise::{DenoiseParams, MAX_ITERATIONS},
     gpu_handles::GPUHandles,
     scenes::SceneDescriptor,
+    tiles,
 };
 
+/// Height of the shader error pane before it starts scrolling
+const SHADER_ERROR_HEIGHT: f32 = 240.0;
+
 pub struct ControlPanel {
     pub window_id: WindowId,
     // Rendering state
@@ -50,8 +56,28 @@ pub struct ControlPanel {
     pixel_subdivision: u32,
     render_resolution: (u32, u32),
     display_mode: DisplayMode,
+    present_filter: PresentFilter,
+    present_zoom: f32,
+    present_pan: (f32, f32),
+    present_aov: Aov,
+    save_prefix: String,
+    tone_mapping: ToneMapping,
+    exposure: f32,
+    white_point: f32,
+    denoise_enabled: bool,
+    denoise_params: DenoiseParams,
     max_samples: u32,
     progressive_enabled: bool,
+    adaptive_enabled: bool,
+    adaptive_threshold: f32,
+    adaptive_min_samples: u32,
+    tiling_enabled: bool,
+    tile_size: u32,
+    tiles_per_submission: u32,
+    show_tile_progress: bool,
+    // Reported by the rendering thread
+    shader_status: Option<Status>,
+    converged_after: Option<u32>,
 }
 
 impl ControlPanel {
@@ -121,9 +147,28 @@ impl ControlPanel {
             texture_uv_scale: (0.2, 0.2),
             pixel_subdivision: 1,
             render_resolution: scenes[0].res,
-            display_mode: DisplayMode::Exact,
+            display_mode: DisplayMode::default(),
+            present_filter: PresentFilter::Nearest,
+            present_zoom: 1.0,
+            present_pan: (0.0, 0.0),
+            present_aov: Aov::Beauty,
+            save_prefix: "output/render".into(),
+            tone_mapping: ToneMapping::default(),
+            exposure: 0.0,
+            white_point: 1.0,
+            denoise_enabled: false,
+            denoise_params: DenoiseParams::default(),
             max_samples: 4096,
             progressive_enabled: false,
+            adaptive_enabled: false,
+            adaptive_threshold: adaptive::DEFAULT_THRESHOLD,
+            adaptive_min_samples: adaptive::DEFAULT_MIN_SAMPLES,
+            tiling_enabled: false,
+            tile_size: tiles::DEFAULT_TILE_SIZE,
+            tiles_per_submission: tiles::DEFAULT_TILES_PER_SUBMISSION,
+            show_tile_progress: true,
+            shader_status: None,
+            converged_after: None,
             window_id,
             current_scene: scenes[0].name.clone(),
             scenes,
@@ -141,6 +186,13 @@ impl ControlPanel {
             .unwrap();
     }
 
+    pub fn update_status(&mut self, status: Status) {
+        match status {
+            Status::Convergence { iterations } => self.converged_after = iterations,
+            status => self.shader_status = Some(status),
+        }
+    }
+
     pub fn resize(&mut self, device: &wgpu::Device, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.config.width = new_size.width;
@@ -261,12 +313,17 @@ impl ControlPanel {
                         };
                     });
                     self.create_resolution_ui(ui, commands);
+                    self.create_presentation_ui(ui, commands);
+                    self.create_tone_mapping_ui(ui, commands);
+                    self.create_denoise_ui(ui, commands);
                     self.create_scene_selection_ui(ui, commands);
+                    self.create_shader_status_ui(ui);
                     //self.create_path_ui(ui, commands, has_focus, redraw_gui);
                     self.create_basic_scene_ui(ui, commands);
                     self.create_texture_ui(ui, commands);
                     self.create_pixel_subdivision_ui(ui, commands);
                     self.create_max_sample_ui(ui, commands);
+                    self.create_tiling_ui(ui, commands);
                 });
             });
         });
@@ -360,6 +417,30 @@ impl ControlPanel {
         });
     }
 
+    fn create_shader_status_ui(&mut self, ui: &mut Ui) {
+        match &self.shader_status {
+            Some(Status::ShaderLoaded { path }) => {
+                ui.label(format!("Shader: {}", path.display()));
+            }
+            Some(Status::ShaderError { path, message }) => {
+                ui.colored_label(
+                    ui.visuals().error_fg_color,
+                    format!("Shader {} failed to compile, still using the previous version", path.display()),
+                );
+                // diagnostics are long and their snippets should not be wrapped
+                ui.group(|ui| {
+                    ScrollArea::both()
+                        .id_source("shader_error")
+                        .max_height(SHADER_ERROR_HEIGHT)
+                        .show(ui, |ui| {
+                            ui.add(egui::Label::new(egui::RichText::new(message).monospace()).wrap(false));
+                        });
+                });
+            }
+            Some(Status::Convergence { .. }) | None => {}
+        }
+    }
+
     fn create_texture_ui(&mut self, ui: &mut Ui, commands: &Sender<Command>) {
         ui.horizontal(|ui| {
             let uv_x: Response = ui.add(
@@ -444,6 +525,193 @@ impl ControlPanel {
         });
     }
 
+    fn create_presentation_ui(&mut self, ui: &mut Ui, commands: &Sender<Command>) {
+        ui.horizontal(|ui: &mut Ui| {
+            let filter_changed = egui::ComboBox::from_label("Filter")
+                .selected_text(format!("{:?}", self.present_filter))
+                .show_ui(ui, |ui| {
+                    PresentFilter::iter().map(|filter| {
+                        let type_str: &'static str = filter.into();
+                        ui.selectable_value(&mut self.present_filter, filter, type_str).changed()
+                }).fold(false, |acc, elem| acc || elem)
+            }).inner.unwrap_or(false);
+
+            ui.label("Zoom");
+            let zoom: Response = ui.add(
+                egui::widgets::DragValue::new(&mut self.present_zoom)
+                    .clamp_range(1.0..=crate::bindings::present::MAX_ZOOM)
+                    .fixed_decimals(1)
+                    .speed(0.1),
+            );
+
+            if filter_changed || zoom.changed() {
+                self.send_presentation(commands);
+            }
+        });
+
+        ui.horizontal(|ui: &mut Ui| {
+            ui.label("Pan");
+            let pan_x: Response = ui.add(
+                egui::widgets::DragValue::new(&mut self.present_pan.0)
+                    .clamp_range(-0.5..=0.5)
+                    .fixed_decimals(3)
+                    .speed(0.001),
+            );
+            let pan_y: Response = ui.add(
+                egui::widgets::DragValue::new(&mut self.present_pan.1)
+                    .clamp_range(-0.5..=0.5)
+                    .fixed_decimals(3)
+                    .speed(0.001),
+            );
+            let reset = ui.button("Reset view");
+            if reset.clicked() {
+                self.present_zoom = 1.0;
+                self.present_pan = (0.0, 0.0);
+            }
+
+            if pan_x.changed() || pan_y.changed() || reset.clicked() {
+                self.send_presentation(commands);
+            }
+        });
+
+        ui.horizontal(|ui: &mut Ui| {
+            let aov_changed = egui::ComboBox::from_label("View")
+                .selected_text(format!("{:?}", self.present_aov))
+                .show_ui(ui, |ui| {
+                    Aov::iter().map(|aov| {
+                        let type_str: &'static str = aov.into();
+                        ui.selectable_value(&mut self.present_aov, aov, type_str).changed()
+                }).fold(false, |acc, elem| acc || elem)
+            }).inner.unwrap_or(false);
+
+            if aov_changed {
+                self.send_presentation(commands);
+            }
+        });
+
+        ui.horizontal(|ui: &mut Ui| {
+            ui.add(egui::TextEdit::singleline(&mut self.save_prefix).desired_width(160.0));
+            if ui.button("Save images").clicked() {
+                commands
+                    .send(Command::SaveImages {
+                        prefix: self.save_prefix.clone(),
+                    })
+                    .unwrap();
+            }
+        });
+    }
+
+    fn send_presentation(&self, commands: &Sender<Command>) {
+        commands
+            .send(Command::SetPresentation {
+                filter: self.present_filter,
+                zoom: self.present_zoom,
+                pan: self.present_pan,
+                aov: self.present_aov,
+            })
+            .unwrap();
+    }
+
+    fn create_tone_mapping_ui(&mut self, ui: &mut Ui, commands: &Sender<Command>) {
+        ui.horizontal(|ui: &mut Ui| {
+            let operator_changed = egui::ComboBox::from_label("Tone Mapping")
+                .selected_text(format!("{:?}", self.tone_mapping))
+                .show_ui(ui, |ui| {
+                    ToneMapping::iter().map(|operator| {
+                        let type_str: &'static str = operator.into();
+                        ui.selectable_value(&mut self.tone_mapping, operator, type_str).changed()
+                }).fold(false, |acc, elem| acc || elem)
+            }).inner.unwrap_or(false);
+
+            if operator_changed {
+                self.send_tone_mapping(commands);
+            }
+        });
+
+        ui.horizontal(|ui: &mut Ui| {
+            ui.label("Exposure (EV)");
+            let exposure: Response = ui.add(
+                egui::widgets::DragValue::new(&mut self.exposure)
+                    .clamp_range(-16.0..=16.0)
+                    .fixed_decimals(2)
+                    .speed(0.05),
+            );
+            ui.label("White Point");
+            let white_point: Response = ui.add(
+                egui::widgets::DragValue::new(&mut self.white_point)
+                    .clamp_range(crate::bindings::present::MIN_WHITE_POINT..=1000.0)
+                    .fixed_decimals(2)
+                    .speed(0.05),
+            );
+
+            if exposure.changed() || white_point.changed() {
+                self.send_tone_mapping(commands);
+            }
+        });
+    }
+
+    fn send_tone_mapping(&self, commands: &Sender<Command>) {
+        commands
+            .send(Command::SetToneMapping {
+                operator: self.tone_mapping,
+                exposure: self.exposure,
+                white_point: self.white_point,
+            })
+            .unwrap();
+    }
+
+    fn create_denoise_ui(&mut self, ui: &mut Ui, commands: &Sender<Command>) {
+        ui.horizontal(|ui: &mut Ui| {
+            let enabled = ui.checkbox(&mut self.denoise_enabled, "Denoise");
+            ui.label("Iterations");
+            let iterations = ui.add(
+                egui::widgets::DragValue::new(&mut self.denoise_params.iterations)
+                    .clamp_range(1..=MAX_ITERATIONS)
+                    .speed(0.1),
+            );
+
+            if enabled.changed() || iterations.changed() {
+                self.send_denoise(commands);
+            }
+        });
+
+        ui.horizontal(|ui: &mut Ui| {
+            let params = &mut self.denoise_params;
+            let sigmas = [
+                ("σ Color", &mut params.sigma_color),
+                ("σ Albedo", &mut params.sigma_albedo),
+                ("σ Normal", &mut params.sigma_normal),
+                ("σ Depth", &mut params.sigma_depth),
+            ];
+            let changed = sigmas
+                .into_iter()
+                .map(|(label, sigma)| {
+                    ui.label(label);
+                    ui.add(
+                        egui::widgets::DragValue::new(sigma)
+                            .clamp_range(0.0..=10.0)
+                            .fixed_decimals(2)
+                            .speed(0.01),
+                    )
+                    .changed()
+                })
+                .fold(false, |acc, elem| acc || elem);
+
+            if changed {
+                self.send_denoise(commands);
+            }
+        });
+    }
+
+    fn send_denoise(&self, commands: &Sender<Command>) {
+        commands
+            .send(Command::SetDenoise {
+                enabled: self.denoise_enabled,
+                params: self.denoise_params,
+            })
+            .unwrap();
+    }
+
     fn create_max_sample_ui(&mut self, ui: &mut Ui, commands: &Sender<Command>) {
         ui.horizontal(|ui: &mut Ui| {
             ui.label("Max Samples");
@@ -465,6 +733,82 @@ impl ControlPanel {
                 .unwrap();
             }
         });
+
+        ui.horizontal(|ui: &mut Ui| {
+            let checkbox = ui.checkbox(&mut self.adaptive_enabled, "Adaptive");
+            ui.label("Relative Error");
+            let threshold = ui.add(
+                egui::widgets::DragValue::new(&mut self.adaptive_threshold)
+                    .clamp_range(0.001..=1.0)
+                    .fixed_decimals(3)
+                    .speed(0.001),
+            );
+            ui.label("Min Samples");
+            let min_samples = ui.add(
+                egui::widgets::DragValue::new(&mut self.adaptive_min_samples)
+                    .clamp_range(2..=4096)
+                    .fixed_decimals(0)
+                    .speed(1),
+            );
+
+            if checkbox.changed() || threshold.changed() || min_samples.changed() {
+                self.send_adaptive_sampling(commands);
+            }
+        });
+
+        if let Some(iterations) = self.converged_after {
+            ui.label(format!("All pixels converged after {iterations} samples"));
+        }
+    }
+
+    fn send_adaptive_sampling(&self, commands: &Sender<Command>) {
+        commands
+            .send(Command::SetAdaptiveSampling {
+                enabled: self.adaptive_enabled,
+                threshold: self.adaptive_threshold,
+                min_samples: self.adaptive_min_samples,
+            })
+            .unwrap();
+    }
+
+    fn create_tiling_ui(&mut self, ui: &mut Ui, commands: &Sender<Command>) {
+        ui.horizontal(|ui: &mut Ui| {
+            let checkbox = ui.checkbox(&mut self.tiling_enabled, "Tiles");
+            ui.label("Size");
+            let tile_size = ui.add(
+                egui::widgets::DragValue::new(&mut self.tile_size)
+                    .clamp_range(16..=4096)
+                    .fixed_decimals(0)
+                    .speed(8),
+            );
+            ui.label("Per Submission");
+            let tiles_per_submission = ui.add(
+                egui::widgets::DragValue::new(&mut self.tiles_per_submission)
+                    .clamp_range(1..=1024)
+                    .fixed_decimals(0)
+                    .speed(1),
+            );
+            let progress = ui.checkbox(&mut self.show_tile_progress, "Show Progress");
+
+            let changed = [checkbox, tile_size, tiles_per_submission, progress]
+                .iter()
+                .map(Response::changed)
+                .fold(false, |acc, elem| acc || elem);
+            if changed {
+                self.send_tiling(commands);
+            }
+        });
+    }
+
+    fn send_tiling(&self, commands: &Sender<Command>) {
+        commands
+            .send(Command::SetTiling {
+                enabled: self.tiling_enabled,
+                tile_size: self.tile_size,
+                tiles_per_submission: self.tiles_per_submission,
+                show_progress: self.show_tile_progress,
+            })
+            .unwrap();
     }
 
     /// Send all messages corresponding to every state variable we are holding
@@ -488,5 +832,10 @@ impl ControlPanel {
         commands.send(
             Command::SetResolution { resolution: self.render_resolution, display_mode: self.display_mode }
         ).unwrap();
+        self.send_presentation(commands);
+        self.send_tone_mapping(commands);
+        self.send_denoise(commands);
+        self.send_adaptive_sampling(commands);
+        self.send_tiling(commands);
     }
 }
\ No newline at end of file
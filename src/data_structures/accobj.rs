@@ -1,19 +1,45 @@
 use super::bbox::Bbox;
 
+/// Set in the references of analytic primitives, see primitives.wgsl
+pub const ANALYTIC_PRIMITIVE: u32 = 1 << 31;
+
+/// What the index of an `AccObj` points to
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum PrimitiveKind {
+    /// A triangle in the index buffer
+    #[default]
+    Triangle,
+    /// An analytic primitive in `Mesh::primitives`
+    Analytic,
+}
+
 /// Intermediate data structure to pass
 /// indexed bounding boxes to the BSP Tree and BVH
 ///
-/// Each index points towards the primitive (in this case the triangle)
-/// in the index buffer that corresponds to the bbox
+/// Each index points towards the primitive of its kind,
+/// a triangle in the index buffer or an analytic primitive
 #[derive(Debug, Copy, Clone)]
 pub struct AccObj {
     pub idx: u32,
     pub bbox: Bbox,
+    pub kind: PrimitiveKind,
 }
 
 impl AccObj {
     pub fn new(idx: u32, bbox: Bbox) -> Self {
-        Self { idx, bbox }
+        Self { idx, bbox, kind: PrimitiveKind::Triangle }
+    }
+
+    pub fn analytic(idx: u32, bbox: Bbox) -> Self {
+        Self { idx, bbox, kind: PrimitiveKind::Analytic }
+    }
+
+    /// The index the GPU leaves store, with `ANALYTIC_PRIMITIVE` set for analytic primitives
+    pub fn reference(&self) -> u32 {
+        match self.kind {
+            PrimitiveKind::Triangle => self.idx,
+            PrimitiveKind::Analytic => self.idx | ANALYTIC_PRIMITIVE,
+        }
     }
 }
 
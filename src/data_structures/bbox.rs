@@ -154,6 +154,14 @@ impl Bbox {
         !(other.min.2 > self.max.2 || other.max.2 < self.min.2)
     }
 
+    /// Get the box included in both bounding boxes, `None` if they do not intersect
+    pub fn intersection(&self, other: &Bbox) -> Option<Bbox> {
+        self.intersects(other).then(|| Bbox {
+            min: vec3f(self.min.0.max(other.min.0), self.min.1.max(other.min.1), self.min.2.max(other.min.2)),
+            max: vec3f(self.max.0.min(other.max.0), self.max.1.min(other.max.1), self.max.2.min(other.max.2)),
+        })
+    }
+
     pub fn distance_center(&self, other: &Bbox) -> f32 {
         let center1 = self.center();
         let center2 = other.center();
@@ -9,12 +9,11 @@ use std::fmt::Debug;
 use super::{
     bbox::{Bbox, BboxGpu},
     vector::Vec4u32, accobj::{AccObj, Split},
+    hlbvh::{SAH_INTERSECTION_COST, SAH_TRAVERSAL_COST},
 };
 
 const NODE_TYPE_LEAF: u32 = 3u32;
 
-const F_EPS: f32 = 1e-6;
-
 #[derive(Debug)]
 pub struct BspTree {
     root: Node,
@@ -41,8 +40,44 @@ enum NodeType {
     },
 }
 
+/// Build settings of a `BspTree`. Planes are chosen by the surface area heuristic with
+/// the costs of stepping through a split and of intersecting an object, the cost of
+/// splits that leave a side empty is reduced by `empty_bonus` in [0, 1).
+/// Nodes above `parallel_depth` subdivide their children in parallel, the tree
+/// does not depend on it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BspParams {
+    pub max_depth: u32,
+    pub max_objects_on_leaf: u32,
+    pub traversal_cost: f32,
+    pub intersection_cost: f32,
+    pub empty_bonus: f32,
+    pub parallel_depth: u32,
+}
+
+impl Default for BspParams {
+    fn default() -> Self {
+        Self {
+            max_depth: 20,
+            max_objects_on_leaf: 4,
+            traversal_cost: 1.0,
+            intersection_cost: 80.0,
+            empty_bonus: 0.5,
+            parallel_depth: 8,
+        }
+    }
+}
+
 impl BspTree {
-    pub fn new(objects: Vec<AccObj>, max_depth: u32, max_objects_on_leaf: u32) -> Self {
+    pub fn new(objects: Vec<AccObj>, params: BspParams) -> Self {
+        let BspParams {
+            max_depth,
+            max_objects_on_leaf,
+            traversal_cost,
+            intersection_cost,
+            empty_bonus,
+            ..
+        } = params;
         assert!(
             objects.len() < u32::MAX as usize,
             "We cannot deal with trees that contain more than 4 billion objects
@@ -56,14 +91,22 @@ impl BspTree {
             max_objects_on_leaf > 0,
             "Leaf objects must be positive, got: {max_objects_on_leaf}"
         );
+        assert!(
+            traversal_cost > 0.0 && intersection_cost > 0.0,
+            "BspTree costs should be positive, got: {traversal_cost} and {intersection_cost}"
+        );
+        assert!(
+            (0.0..1.0).contains(&empty_bonus),
+            "Empty bonus should be in [0, 1), got: {empty_bonus}"
+        );
         let mut bbox = Bbox::new();
         // Extend the root node bounding box to include every other box
         objects.iter().for_each(|elem| {
             bbox.include_bbox(&elem.bbox);
         });
 
-        let obj_refer = objects.iter().map(|obj| obj).collect();
-        let root = Node::subdivide_node(bbox, 0, max_depth, max_objects_on_leaf, &obj_refer);
+        let obj_refer = objects.iter().collect();
+        let root = Node::subdivide_node(bbox, 0, 0, &params, obj_refer);
 
         Self {
             root,
@@ -76,13 +119,38 @@ impl BspTree {
         self.root.count
     }
 
+    /// Expected cost of a ray through the tree by the surface area heuristic relative to
+    /// its box, like `Bvh::sah_cost`, objects in several leaves are counted in each of them
+    pub fn sah_cost(&self) -> f32 {
+        fn sah_cost_recursive(node: &Node, bbox: Bbox) -> f32 {
+            match &node.node_type {
+                NodeType::Leaf { objects } => bbox.area() * objects.len() as f32 * SAH_INTERSECTION_COST,
+                NodeType::Split {
+                    split,
+                    plane,
+                    left,
+                    right,
+                } => {
+                    let axis = *split as u32;
+                    let (mut left_bbox, mut right_bbox) = (bbox, bbox);
+                    left_bbox.max[axis] = *plane;
+                    right_bbox.min[axis] = *plane;
+                    bbox.area() * SAH_TRAVERSAL_COST
+                        + sah_cost_recursive(left, left_bbox)
+                        + sah_cost_recursive(right, right_bbox)
+                }
+            }
+        }
+        sah_cost_recursive(&self.root, self.bbox) / self.bbox.area()
+    }
+
     pub fn primitive_ids(&self) -> Vec<u32> {
         let mut ids = Vec::with_capacity(self.count() as usize);
 
         fn primitive_ids_recursive(node: &Node, array: &mut Vec<u32>) {
             match &node.node_type {
                 NodeType::Leaf { objects } => {
-                    objects.iter().for_each(|obj| array.push(obj.idx));
+                    objects.iter().for_each(|obj| array.push(obj.reference()));
                 }
                 NodeType::Split {
                     left,
@@ -189,137 +257,129 @@ impl BspTree {
     }
 }
 
+/// Start or end of the extent of an object along the axis of a sweep
+#[derive(Debug, Copy, Clone)]
+struct BoundEdge {
+    t: f32,
+    object: usize,
+    start: bool,
+}
+
 impl Node {
+    fn leaf(objects: Vec<&AccObj>) -> Node {
+        Node {
+            count: objects.len(),
+            node_type: NodeType::Leaf {
+                objects: objects.into_iter().cloned().collect(),
+            },
+        }
+    }
+
     ///
-    /// Create a complete Node hierarchy using subdivision
+    /// Create a complete Node hierarchy using subdivision, the plane with the lowest
+    /// SAH cost is found by sweeping over the sorted object bounds along every axis.
+    /// `bad_refines` counts the splits above that cost more than a leaf.
     fn subdivide_node(
         bbox: Bbox,
         depth: u32,
-        max_depth: u32,
-        max_objects_on_leaf: u32,
-        objects: &Vec<&AccObj>,
+        mut bad_refines: u32,
+        params: &BspParams,
+        objects: Vec<&AccObj>,
     ) -> Node {
-        let tests = 4;
-
-        if objects.len() as u32 <= max_objects_on_leaf || depth == max_depth {
-            let node = Node {
-                count: objects.len(),
-                node_type: NodeType::Leaf {
-                    objects: objects.iter().map(|elem| (*elem).clone()).collect(),
-                },
-            };
-            node
-        } else {
-            // split the objects
-            let mut axis_leaf = 0;
-            let mut plane: f32 = 0.0;
-            let mut left_node_count = 0;
-            let mut right_node_count = 0;
-            let mut _debug = false;
-            let mut min_cost = 1E+27;
-            for i in 0..3 {
-                for k in 1..tests {
-                    let mut left_bbox = bbox.clone();
-                    let mut right_bbox = bbox.clone();
-                    let max_corner = bbox.max[i];
-                    let min_corner = bbox.min[i];
-                    let center =
-                        (max_corner - min_corner) * (k as f32) / (tests as f32) + min_corner;
-                    left_bbox.max[i] = center;
-                    right_bbox.min[i] = center;
-
-                    let mut left_count = 0;
-                    let mut right_count = 0;
-                    for obj in objects {
-                        left_count += left_bbox.intersects(&obj.bbox) as i32;
-                        right_count += right_bbox.intersects(&obj.bbox) as i32;
-                    }
-
-                    let cost = left_count as f32 * left_bbox.area()
-                        + right_count as f32 * right_bbox.area();
-                    if cost < min_cost {
-                        min_cost = cost;
+        let count = objects.len();
+        let total_area = bbox.area();
+        if count as u32 <= params.max_objects_on_leaf || depth == params.max_depth || total_area <= 0.0 {
+            return Self::leaf(objects);
+        }
 
-                        axis_leaf = i;
-                        plane = center;
-                        left_node_count = left_count;
-                        right_node_count = right_count;
-                    }
-                }
+        let leaf_cost = params.intersection_cost * count as f32;
+        let mut best: Option<(f32, u32, usize)> = None;
+        let mut best_edges = vec![];
+        let mut edges = Vec::with_capacity(2 * count);
+        for axis in 0..3u32 {
+            if bbox.extent_dim(axis) <= 0.0 {
+                continue;
             }
-
-            // Choose the splitting plane
-            let max_corner = bbox.max[axis_leaf];
-            let min_corner = bbox.min[axis_leaf];
-            let size = max_corner - min_corner;
-            let diff = if F_EPS < (size / 8.0) {
-                size / 8.0
-            } else {
-                F_EPS
-            };
-            let mut center = plane;
-
-            if left_node_count == 0 {
-                center = max_corner;
-                for obj in objects {
-                    let obj_min_corner = obj.bbox.min[axis_leaf];
-                    if obj_min_corner < center {
-                        center = obj_min_corner;
-                    }
+            edges.clear();
+            edges.extend(objects.iter().enumerate().flat_map(|(object, obj)| {
+                [
+                    BoundEdge { t: obj.bbox.min[axis], object, start: true },
+                    BoundEdge { t: obj.bbox.max[axis], object, start: false },
+                ]
+            }));
+            // objects that start on a plane are below the ends on it
+            edges.sort_by(|a, b| a.t.total_cmp(&b.t).then(b.start.cmp(&a.start)));
+
+            let (mut below, mut above) = (0, count);
+            for (i, edge) in edges.iter().enumerate() {
+                if !edge.start {
+                    above -= 1;
                 }
-                center -= diff;
-            }
-            if right_node_count == 0 {
-                center = min_corner;
-                for obj in objects {
-                    let obj_max_corner = obj.bbox.max[axis_leaf];
-                    if obj_max_corner > center {
-                        center = obj_max_corner;
+                if edge.t > bbox.min[axis] && edge.t < bbox.max[axis] {
+                    let (mut below_bbox, mut above_bbox) = (bbox, bbox);
+                    below_bbox.max[axis] = edge.t;
+                    above_bbox.min[axis] = edge.t;
+                    let bonus = if below == 0 || above == 0 { params.empty_bonus } else { 0.0 };
+                    let cost = params.traversal_cost
+                        + params.intersection_cost
+                            * (1.0 - bonus)
+                            * (below_bbox.area() * below as f32 + above_bbox.area() * above as f32)
+                            / total_area;
+                    if best.is_none_or(|(best_cost, ..)| cost < best_cost) {
+                        best = Some((cost, axis, i));
                     }
                 }
-
-                center += diff;
-            }
-
-            plane = center;
-            let mut left_bbox = bbox.clone();
-            let mut right_bbox = bbox.clone();
-            left_bbox.max[axis_leaf] = center;
-            right_bbox.min[axis_leaf] = center;
-
-            let mut left_objects = vec![];
-            let mut right_objects = vec![];
-
-            for obj in objects {
-                if left_bbox.intersects(&obj.bbox) {
-                    left_objects.push(*obj);
-                }
-                if right_bbox.intersects(&obj.bbox) {
-                    right_objects.push(*obj);
+                if edge.start {
+                    below += 1;
                 }
             }
-            Node {
-                count: objects.len(),
-                node_type: NodeType::Split {
-                    left: Box::new(Self::subdivide_node(
-                        left_bbox,
-                        depth + 1,
-                        max_depth,
-                        max_objects_on_leaf,
-                        &left_objects,
-                    )),
-                    right: Box::new(Self::subdivide_node(
-                        right_bbox,
-                        depth + 1,
-                        max_depth,
-                        max_objects_on_leaf,
-                        &right_objects,
-                    )),
-                    split: axis_leaf.into(),
-                    plane: plane,
-                },
+            // keep the sorted edges of the best plane for the partition
+            if best.is_some_and(|(_, best_axis, _)| best_axis == axis) {
+                std::mem::swap(&mut best_edges, &mut edges);
             }
         }
+
+        let Some((cost, axis, offset)) = best else {
+            return Self::leaf(objects);
+        };
+        if cost > leaf_cost {
+            bad_refines += 1;
+        }
+        if (cost > 4.0 * leaf_cost && count < 16) || bad_refines == 3 {
+            return Self::leaf(objects);
+        }
+
+        // objects starting before the plane are below it, the ones ending after it above
+        let below_objects = best_edges[..offset]
+            .iter()
+            .filter(|edge| edge.start)
+            .map(|edge| objects[edge.object])
+            .collect();
+        let above_objects = best_edges[offset + 1..]
+            .iter()
+            .filter(|edge| !edge.start)
+            .map(|edge| objects[edge.object])
+            .collect();
+        let plane = best_edges[offset].t;
+        let (mut below_bbox, mut above_bbox) = (bbox, bbox);
+        below_bbox.max[axis] = plane;
+        above_bbox.min[axis] = plane;
+        let below = || Self::subdivide_node(below_bbox, depth + 1, bad_refines, params, below_objects);
+        let above = || Self::subdivide_node(above_bbox, depth + 1, bad_refines, params, above_objects);
+        let (left, right) = if depth < params.parallel_depth {
+            rayon::join(below, above)
+        } else {
+            (below(), above())
+        };
+        Node {
+            count,
+            node_type: NodeType::Split {
+                left: Box::new(left),
+                right: Box::new(right),
+                split: axis.into(),
+                plane,
+            },
+        }
     }
 }
 
@@ -357,7 +417,7 @@ mod bsp_tree_test {
     fn bsp_tree_new() {
         let model = Mesh::from_obj("res/models/test_object.obj").expect("Failed to load model");
         let bboxes = model.bboxes();
-        let bsp_tree = BspTree::new(bboxes, 20, 4);
+        let bsp_tree = BspTree::new(bboxes, Default::default());
 
         let mut set = HashSet::new();
         fn recurse(node: &Node, set: &mut HashSet<u32>) {
@@ -397,7 +457,7 @@ mod bsp_tree_test {
         let mut model = Mesh::from_obj("res/models/CornellBox.obj").expect("Failed to load model");
         model.scale(1.0 / 500.0);
         let bboxes = model.bboxes();
-        let bsp_tree = BspTree::new(bboxes, 20, 4);
+        let bsp_tree = BspTree::new(bboxes, Default::default());
         let (_, bsp_array) = bsp_tree.bsp_array();
         let mut test_map: HashSet<u32> = HashSet::new();
         let mut id: usize = 0;
@@ -418,4 +478,56 @@ mod bsp_tree_test {
             }
         }
     }
+
+    #[test]
+    fn bsp_tree_leaves_hold_overlapping_objects() {
+        let model = Mesh::from_obj("res/models/CornellBoxWithBlocks.obj").expect("Failed to load model");
+        let bboxes = model.bboxes();
+        let bsp_tree = BspTree::new(bboxes.clone(), Default::default());
+
+        // a ray through a leaf has to find every object that reaches into the leaf box
+        fn recurse(node: &Node, bbox: Bbox, bboxes: &[AccObj]) {
+            match &node.node_type {
+                NodeType::Leaf { objects } => {
+                    let ids: HashSet<u32> = objects.iter().map(|obj| obj.idx).collect();
+                    for obj in bboxes {
+                        // flat objects on the box boundary are only kept on one side
+                        let overlap = (0..3).all(|axis| {
+                            obj.bbox.min[axis] < bbox.max[axis] && obj.bbox.max[axis] > bbox.min[axis]
+                                || obj.bbox.min[axis] == obj.bbox.max[axis]
+                                    && obj.bbox.min[axis] > bbox.min[axis]
+                                    && obj.bbox.max[axis] < bbox.max[axis]
+                        });
+                        assert!(!overlap || ids.contains(&obj.idx), "object {} missing in leaf {bbox:?}", obj.idx);
+                    }
+                }
+                NodeType::Split { split, plane, left, right } => {
+                    let axis = *split as u32;
+                    assert!(*plane > bbox.min[axis] && *plane < bbox.max[axis]);
+                    let (mut left_bbox, mut right_bbox) = (bbox, bbox);
+                    left_bbox.max[axis] = *plane;
+                    right_bbox.min[axis] = *plane;
+                    recurse(left, left_bbox, bboxes);
+                    recurse(right, right_bbox, bboxes);
+                }
+            }
+        }
+        recurse(&bsp_tree.root, bsp_tree.bbox, &bboxes);
+    }
+
+    #[test]
+    fn bsp_tree_parallel_matches_serial() {
+        let model = Mesh::from_obj("res/models/teapot.obj").expect("Failed to load model");
+        let serial = BspTree::new(model.bboxes(), BspParams { parallel_depth: 0, ..Default::default() });
+        let parallel = BspTree::new(model.bboxes(), BspParams { parallel_depth: 20, ..Default::default() });
+
+        let (serial_planes, serial_array) = serial.bsp_array();
+        let (parallel_planes, parallel_array) = parallel.bsp_array();
+        assert_eq!(serial_array, parallel_array);
+        assert_eq!(
+            serial_planes.iter().map(|plane| plane.to_bits()).collect::<Vec<_>>(),
+            parallel_planes.iter().map(|plane| plane.to_bits()).collect::<Vec<_>>()
+        );
+        assert_eq!(serial.primitive_ids(), parallel.primitive_ids());
+    }
 }
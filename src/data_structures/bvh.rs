@@ -1,4 +1,4 @@
-use crate::{mesh::Mesh, data_structures::hlbvh::GpuNode};
+use crate::{mesh::Mesh, data_structures::hlbvh::{GpuNode, NODE_AXIS_SHIFT}};
 
 use super::bbox::Bbox;
 
@@ -140,13 +140,19 @@ impl Bvh {
             let node_offset = *offset;
             match &cluster.cluster_type {
                 ClusterType::Leaf { start_idx: primitive } => {
-                    linear_node.number_of_prims = cluster.boxes;
+                    linear_node.prims_axis = cluster.boxes;
                     linear_node.offset_ptr = *primitive;
                 },
                 ClusterType::Interior { left, right } => {
-                    linear_node.number_of_prims = 0;
-                    flatten_recursive(nodes, left, offset);
-                    linear_node.offset_ptr = flatten_recursive(nodes, right, offset);
+                    // clusters have no split, use the axis their centers are furthest apart on
+                    let distance = right.bbox.center() - left.bbox.center();
+                    let axis = (0..3u32)
+                        .max_by(|&a, &b| distance[a].abs().total_cmp(&distance[b].abs()))
+                        .unwrap();
+                    let (first, second) = if distance[axis] < 0.0 { (right, left) } else { (left, right) };
+                    linear_node.prims_axis = axis << NODE_AXIS_SHIFT;
+                    flatten_recursive(nodes, first, offset);
+                    linear_node.offset_ptr = flatten_recursive(nodes, second, offset);
                 },
             }
             nodes[current_offset as usize] = linear_node;
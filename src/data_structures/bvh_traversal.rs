@@ -0,0 +1,350 @@
+/// Traversal of the flattened BVH layouts on the CPU, mirroring bvh.wgsl, bvh_threaded.wgsl,
+/// bvh_wide.wgsl, bvh_quantized.wgsl and bvh_instanced.wgsl. Used to check that all layouts find
+/// the same hits and to count the nodes a ray visits, see the `bvh` binary.
+
+use rand::{Rng, SeedableRng};
+
+use crate::mesh::Mesh;
+
+use super::{
+    accobj::ANALYTIC_PRIMITIVE,
+    bbox::Bbox,
+    hlbvh::{BvhLayout, GpuNode, GpuWideNode},
+    instancing::FlatTwoLevelBvh,
+    quantized_bvh::{GpuQuantizedNode, QUANTIZED_INTERIOR, QUANTIZED_WIDTH},
+    vector::{cross, dot, Vec3f32},
+};
+
+#[derive(Debug, Copy, Clone)]
+pub struct Ray {
+    pub origin: Vec3f32,
+    pub direction: Vec3f32,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct TraversalResult {
+    /// Distance to the closest hit, or to any hit for shadow rays
+    pub distance: Option<f32>,
+    /// Number of nodes fetched, a node of a wide BVH
+    /// tests the boxes of all of its children at once
+    pub visited_nodes: u32,
+}
+
+/// Distance along the ray to the triangle, like intersect_triangle_indexed
+pub fn intersect_triangle(mesh: &Mesh, triangle: u32, ray: &Ray) -> Option<f32> {
+    let index = mesh.indices[triangle as usize];
+    let vertex = |i: u32| -> Vec3f32 { mesh.vertices[i as usize].xyz() };
+    let v0 = vertex(index.0);
+    let e0 = vertex(index.1) - v0;
+    let e1 = vertex(index.2) - v0;
+    let p = cross(ray.direction, e1);
+    let det = dot(e0, p);
+    if det.abs() < 1e-10 {
+        return None;
+    }
+    let t = ray.origin - v0;
+    let beta = dot(t, p) / det;
+    let q = cross(t, e0);
+    let gamma = dot(ray.direction, q) / det;
+    let distance = dot(e1, q) / det;
+    (beta >= 0.0 && gamma >= 0.0 && beta + gamma <= 1.0 && distance > 0.0).then_some(distance)
+}
+
+/// Distance to the primitive a leaf references, like intersect_primitive
+pub fn intersect_reference(mesh: &Mesh, reference: u32, ray: &Ray) -> Option<f32> {
+    match reference & ANALYTIC_PRIMITIVE {
+        0 => intersect_triangle(mesh, reference, ray),
+        _ => mesh.primitives[(reference & !ANALYTIC_PRIMITIVE) as usize].intersect(ray),
+    }
+}
+
+/// Closest hit by testing every triangle and bounded analytic primitive
+pub fn brute_force(mesh: &Mesh, ray: &Ray) -> Option<f32> {
+    let primitives = mesh.primitives.iter().filter(|primitive| primitive.bbox().is_some());
+    (0..mesh.indices.len() as u32)
+        .filter_map(|triangle| intersect_triangle(mesh, triangle, ray))
+        .chain(primitives.filter_map(|primitive| primitive.intersect(ray)))
+        .min_by(f32::total_cmp)
+}
+
+/// Slab test against the box between 0 and `t_max`, like intersect_bb2
+fn intersect_box(min: Vec3f32, max: Vec3f32, ray: &Ray, t_max: f32) -> bool {
+    let (mut t0, mut t1) = (0.0f32, t_max);
+    for axis in 0..3 {
+        let near = (min[axis] - ray.origin[axis]) / ray.direction[axis];
+        let far = (max[axis] - ray.origin[axis]) / ray.direction[axis];
+        t0 = t0.max(near.min(far));
+        t1 = t1.min(near.max(far));
+    }
+    t0 <= t1
+}
+
+/// Tests the primitives of a leaf, returns true if the traversal can stop
+fn intersect_leaf(
+    first: u32,
+    count: u32,
+    triangles: &[u32],
+    mesh: &Mesh,
+    ray: &Ray,
+    any_hit: bool,
+    result: &mut TraversalResult,
+) -> bool {
+    for &reference in &triangles[first as usize..(first + count) as usize] {
+        let t_max = result.distance.unwrap_or(f32::MAX);
+        if let Some(distance) = intersect_reference(mesh, reference, ray).filter(|&d| d < t_max) {
+            result.distance = Some(distance);
+            if any_hit {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Traverses nodes flattened with `layout`, the stack layout visits the near child first
+pub fn traverse(nodes: &[GpuNode], triangles: &[u32], mesh: &Mesh, ray: &Ray, layout: BvhLayout, any_hit: bool) -> TraversalResult {
+    match layout {
+        BvhLayout::Stack => traverse_stack(nodes, triangles, mesh, ray, true, any_hit),
+        BvhLayout::Threaded => traverse_threaded(nodes, triangles, mesh, ray, any_hit),
+    }
+}
+
+/// Mirrors intersect_bvh in bvh.wgsl, without `near_first` the children are visited in order
+pub fn traverse_stack(nodes: &[GpuNode], triangles: &[u32], mesh: &Mesh, ray: &Ray, near_first: bool, any_hit: bool) -> TraversalResult {
+    let mut result = TraversalResult::default();
+    traverse_subtree(nodes, 0, triangles, mesh, ray, near_first, any_hit, &mut result);
+    result
+}
+
+/// Stack traversal below `root` that adds to `result`, returns true if the traversal can stop
+fn traverse_subtree(
+    nodes: &[GpuNode],
+    root: u32,
+    triangles: &[u32],
+    mesh: &Mesh,
+    ray: &Ray,
+    near_first: bool,
+    any_hit: bool,
+    result: &mut TraversalResult,
+) -> bool {
+    let mut stack = vec![root];
+    while let Some(index) = stack.pop() {
+        result.visited_nodes += 1;
+        let node = &nodes[index as usize];
+        if !intersect_box(node.min, node.max, ray, result.distance.unwrap_or(f32::MAX)) {
+            continue;
+        }
+        if node.is_leaf() {
+            if intersect_leaf(node.offset_ptr, node.number_of_prims(), triangles, mesh, ray, any_hit, result) {
+                return true;
+            }
+        } else if near_first && ray.direction[node.axis()] < 0.0 {
+            stack.extend([index + 1, node.offset_ptr]);
+        } else {
+            stack.extend([node.offset_ptr, index + 1]);
+        }
+    }
+    false
+}
+
+/// Mirrors intersect_bvh_threaded in bvh_threaded.wgsl
+pub fn traverse_threaded(nodes: &[GpuNode], triangles: &[u32], mesh: &Mesh, ray: &Ray, any_hit: bool) -> TraversalResult {
+    let mut result = TraversalResult::default();
+    let mut index = 0;
+    while index < nodes.len() {
+        result.visited_nodes += 1;
+        let node = &nodes[index];
+        let hit_box = intersect_box(node.min, node.max, ray, result.distance.unwrap_or(f32::MAX));
+        if hit_box
+            && node.is_leaf()
+            && intersect_leaf(node.offset_ptr, node.number_of_prims(), triangles, mesh, ray, any_hit, &mut result)
+        {
+            break;
+        }
+        index = match hit_box || node.is_leaf() {
+            true => index + 1,
+            false => node.offset_ptr as usize,
+        };
+    }
+    result
+}
+
+/// Mirrors intersect_bvh_wide in bvh_wide.wgsl, the interior
+/// children that are hit are pushed in order
+pub fn traverse_wide(nodes: &[GpuWideNode], triangles: &[u32], mesh: &Mesh, ray: &Ray, any_hit: bool) -> TraversalResult {
+    let mut result = TraversalResult::default();
+    // ranges of records, starting with the root
+    let mut stack = vec![(0, 1)];
+    while let Some((first, count)) = stack.pop() {
+        result.visited_nodes += 1;
+        for node in &nodes[first as usize..(first + count) as usize] {
+            if !intersect_box(node.min, node.max, ray, result.distance.unwrap_or(f32::MAX)) {
+                continue;
+            }
+            if !node.is_leaf() {
+                stack.push((node.offset_ptr, node.entries()));
+            } else if intersect_leaf(node.offset_ptr, node.entries(), triangles, mesh, ray, any_hit, &mut result) {
+                return result;
+            }
+        }
+    }
+    result
+}
+
+/// Mirrors intersect_bvh_quantized in bvh_quantized.wgsl, the child
+/// boxes are decoded when their node is fetched
+pub fn traverse_quantized(nodes: &[GpuQuantizedNode], triangles: &[u32], mesh: &Mesh, ray: &Ray, any_hit: bool) -> TraversalResult {
+    let mut result = TraversalResult::default();
+    let mut stack = vec![0];
+    while let Some(index) = stack.pop() {
+        result.visited_nodes += 1;
+        let node = &nodes[index];
+        for slot in 0..QUANTIZED_WIDTH {
+            let info = node.child_info(slot);
+            let bbox = node.child_box(slot);
+            if info == 0 || !intersect_box(bbox.min, bbox.max, ray, result.distance.unwrap_or(f32::MAX)) {
+                continue;
+            }
+            if info == QUANTIZED_INTERIOR {
+                stack.push(node.children[slot] as usize);
+            } else if intersect_leaf(node.children[slot], info, triangles, mesh, ray, any_hit, &mut result) {
+                return result;
+            }
+        }
+    }
+    result
+}
+
+/// Mirrors intersect_bvh_instanced in bvh_instanced.wgsl, the ray is moved into the
+/// object space of every instance whose box it hits, the distances stay the same
+pub fn traverse_instanced(bvh: &FlatTwoLevelBvh, mesh: &Mesh, ray: &Ray, any_hit: bool) -> TraversalResult {
+    let mut result = TraversalResult::default();
+    let mut stack = vec![0];
+    while let Some(index) = stack.pop() {
+        result.visited_nodes += 1;
+        let node = &bvh.tlas_nodes[index as usize];
+        if !intersect_box(node.min, node.max, ray, result.distance.unwrap_or(f32::MAX)) {
+            continue;
+        }
+        if node.is_leaf() {
+            let first = node.offset_ptr as usize;
+            for instance in &bvh.instances[first..first + node.number_of_prims() as usize] {
+                let object_ray = Ray {
+                    origin: instance.to_object(ray.origin, 1.0),
+                    direction: instance.to_object(ray.direction, 0.0),
+                };
+                let (nodes, triangles) = (&bvh.blas_nodes, &bvh.blas_triangles);
+                if traverse_subtree(nodes, instance.blas_root, triangles, mesh, &object_ray, true, any_hit, &mut result) {
+                    return result;
+                }
+            }
+        } else if ray.direction[node.axis()] < 0.0 {
+            stack.extend([index + 1, node.offset_ptr]);
+        } else {
+            stack.extend([node.offset_ptr, index + 1]);
+        }
+    }
+    result
+}
+
+/// Rays between two random points in the box scaled by 2 around its center
+pub fn random_rays(bbox: &Bbox, count: usize, seed: u64) -> Vec<Ray> {
+    let mut rng = rand_pcg::Pcg32::seed_from_u64(seed);
+    let center = bbox.center();
+    let radius = bbox.max_extent();
+    let mut point = || {
+        let mut point = center;
+        for axis in 0..3 {
+            point[axis] += rng.gen_range(-1.0..1.0) * radius;
+        }
+        point
+    };
+    (0..count)
+        .map(|_| {
+            let origin = point();
+            Ray { origin, direction: point() - origin }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod bvh_traversal_test {
+    use super::*;
+    use crate::{
+        data_structures::{hlbvh::Bvh, vector::vec3f},
+        mesh::Material,
+        primitives::Shape,
+    };
+
+    #[test]
+    fn layouts_find_the_closest_hit() {
+        let model = Mesh::from_obj("res/models/teapot.obj").expect("Failed to load model");
+        let bvh = Bvh::new(&model, 4, false);
+        let triangles = bvh.triangles();
+        let stack = bvh.flatten_layout(BvhLayout::Stack);
+        let threaded = bvh.flatten_layout(BvhLayout::Threaded);
+        assert_eq!(stack.len(), threaded.len());
+
+        for ray in random_rays(&bvh.bbox(), 300, 3) {
+            let closest = brute_force(&model, &ray);
+            let threaded_hit = traverse(&threaded, &triangles, &model, &ray, BvhLayout::Threaded, false);
+            assert_eq!(threaded_hit.distance, closest);
+            assert!(threaded_hit.visited_nodes <= threaded.len() as u32);
+            let any = traverse(&threaded, &triangles, &model, &ray, BvhLayout::Threaded, true);
+            assert_eq!(any.distance.is_some(), closest.is_some());
+            assert_eq!(traverse(&stack, &triangles, &model, &ray, BvhLayout::Stack, false).distance, closest);
+        }
+    }
+
+    #[test]
+    fn wide_bvh_finds_the_closest_hit() {
+        let model = Mesh::from_obj("res/models/teapot.obj").expect("Failed to load model");
+        let bvh = Bvh::new(&model, 4, false);
+        let triangles = bvh.triangles();
+        let binary = bvh.flatten();
+        let rays = random_rays(&bvh.bbox(), 300, 5);
+
+        for width in [2, 4, 8] {
+            let nodes = bvh.collapse(width);
+            let interior = nodes.iter().filter(|node| !node.is_leaf());
+            assert!(interior.clone().all(|node| (2..=width as u32).contains(&node.entries())));
+            // collapsing to width 2 keeps every binary node
+            assert_eq!(nodes.len() == binary.len(), width == 2);
+            assert_eq!(interior.map(|node| node.entries() as usize).sum::<usize>() + 1, nodes.len());
+
+            for ray in &rays {
+                let closest = brute_force(&model, ray);
+                assert_eq!(traverse_wide(&nodes, &triangles, &model, ray, false).distance, closest);
+                let any = traverse_wide(&nodes, &triangles, &model, ray, true);
+                assert_eq!(any.distance.is_some(), closest.is_some());
+            }
+        }
+    }
+    #[test]
+    fn analytic_primitives_are_bounded_with_the_triangles() {
+        let mut model = Mesh::from_obj("res/models/test_object.obj").expect("Failed to load model");
+        let bbox = Bvh::new(&model, 4, false).bbox();
+        let (center, extent) = (bbox.center(), bbox.max_extent());
+        let shapes = [
+            Shape::Sphere { center, radius: extent * 0.3 },
+            Shape::Sphere { center: bbox.max, radius: extent * 0.1 },
+            Shape::Quad { corner: bbox.min, u: vec3f(extent, 0.0, 0.0), v: vec3f(0.0, extent * 0.5, extent) },
+            Shape::Box { min: bbox.min, max: center },
+            Shape::Plane { point: bbox.min, normal: vec3f(0.0, 1.0, 0.0) },
+        ];
+        shapes.into_iter().for_each(|shape| model.add_primitive(shape, Material::default()));
+
+        let bvh = Bvh::new(&model, 4, false);
+        let triangles = bvh.triangles();
+        // the plane is left out
+        let analytic = triangles.iter().filter(|&&reference| reference & ANALYTIC_PRIMITIVE != 0);
+        assert_eq!(analytic.count(), shapes.len() - 1);
+        assert_eq!(triangles.len(), model.indices.len() + shapes.len() - 1);
+
+        let nodes = bvh.flatten();
+        for ray in random_rays(&bvh.bbox(), 300, 7) {
+            let closest = brute_force(&model, &ray);
+            assert_eq!(traverse_stack(&nodes, &triangles, &model, &ray, true, false).distance, closest);
+        }
+    }
+}
@@ -1,11 +1,12 @@
+use bytemuck::Zeroable;
 use rayon::prelude::*;
 use rdst::{RadixKey, RadixSort};
 use std::{
     cmp::Ord,
-    sync::atomic::{AtomicU32, Ordering}, time::{Duration, Instant},
+    time::{Duration, Instant},
 };
 
-use crate::mesh::Mesh;
+use crate::{bindings::wgsl_struct::wgsl_struct, mesh::Mesh};
 
 use super::{
     accobj::{AccObj, Split},
@@ -13,6 +14,26 @@ use super::{
     vector::Vec3f32, bvh_util::BvhConstructionTime,
 };
 
+/// How the nodes are laid out by `Bvh::flatten_layout`, both are in depth-first order
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BvhLayout {
+    /// Interior nodes point to their second child, traversed with a stack (bvh.wgsl)
+    #[default]
+    Stack,
+    /// Interior nodes point to the node after their subtree, where the traversal
+    /// continues when their box is missed, so no stack is needed (bvh_threaded.wgsl)
+    Threaded,
+}
+
+/// SAH costs of visiting a node and of intersecting a primitive, see `Bvh::sah_cost`
+pub const SAH_TRAVERSAL_COST: f32 = 1.0;
+pub const SAH_INTERSECTION_COST: f32 = 1.0;
+/// `Bvh::degradation` above which `Bvh::needs_rebuild`, refitted boxes
+/// overlap more and more as the primitives move away from where they were
+pub const REBUILD_DEGRADATION: f32 = 1.5;
+/// Subtrees are refitted in parallel down to this depth
+const PARALLEL_REFIT_DEPTH: u32 = 8;
+
 /// Bounding Volume Hierarchy type
 #[derive(Debug)]
 pub struct Bvh {
@@ -25,6 +46,8 @@ pub struct Bvh {
     primitives: Vec<AccObj>,
     // total number of nodes in the BVH
     total_nodes: u32,
+    /// `sah_cost` when the BVH was built, refitting keeps the topology and only gets worse
+    build_cost: f32,
     /// For benchmarking
     pub time: BvhConstructionTime,
 }
@@ -34,9 +57,14 @@ impl Bvh {
     /// https://www.pbr-book.org/4ed/Primitives_and_Intersection_Acceleration/Bounding_Volume_Hierarchies
     ///
     pub fn new(model: &Mesh, max_prims: u32, single_threaded: bool) -> Self {
+        Self::from_primitives(model.bboxes(), max_prims, single_threaded)
+    }
+
+    /// Construct a BVH over any boxes, the `reference` of every primitive is
+    /// what `triangles` returns for it, e.g. the instances of a `TwoLevelBvh`
+    pub fn from_primitives(primitives: Vec<AccObj>, max_prims: u32, single_threaded: bool) -> Self {
         let mut now = Instant::now();
 
-        let primitives = model.bboxes();
         // calculate the overall boundary for morton code generation
         let mut bound = Bbox::new();
         for bbox in &primitives {
@@ -71,7 +99,8 @@ impl Bvh {
         let time_morton_code = now.elapsed();
         now = Instant::now();
 
-        // Sort primitives using morton codes
+        // Sort primitives using morton codes, primitives with the same code are ordered by
+        // index so that every sort and thread count produces the same BVH
         if cfg!(debug_assertions) {
             morton_primitives.sort_unstable();
         } else {
@@ -121,8 +150,7 @@ impl Bvh {
         //println!("Initialized treelets: {}", treelets_to_build.len());
 
         // Create subtrees from treelets in parallel.
-        let total_nodes = AtomicU32::new(0);
-        let treelets = if !single_threaded {
+        let (treelets, treelet_nodes): (Vec<_>, Vec<_>) = if !single_threaded {
             treelets_to_build
                 .par_iter_mut()
                 .map(|treelet| {
@@ -138,8 +166,7 @@ impl Bvh {
                         first_bit_index,
                         max_prims as usize,
                     );
-                    total_nodes.fetch_add(nodes_created, Ordering::Relaxed);
-                    node
+                    (node, nodes_created)
                 })
                 .collect()
         } else {
@@ -159,8 +186,7 @@ impl Bvh {
                         first_bit_index,
                         max_prims as usize,
                     );
-                    total_nodes.fetch_add(nodes_created, Ordering::Relaxed);
-                    node
+                    (node, nodes_created)
                 })
                 .collect()
         };
@@ -170,72 +196,195 @@ impl Bvh {
         //println!("Built treelets");
 
         // Use SAH or some other method to collapse nodes into a single BVH
-        let mut total_nodes = total_nodes.fetch_add(0, Ordering::Relaxed);
+        let mut total_nodes = treelet_nodes.iter().sum();
         let root = build_upper_tree(treelets, &mut total_nodes, &mut ordered_primitives);
 
         let time_upper_tree = now.elapsed();
         //println!("Successfully built BVH");
 
-        Self {
+        Self::from_build(
             root,
-            primitives: ordered_primitives,
+            ordered_primitives,
             total_nodes,
-            time: BvhConstructionTime {
+            BvhConstructionTime {
                 morton_codes: time_morton_code,
                 radix_sort: time_radix_sort,
                 treelet_init: time_treelet_init,
                 treelet_build: time_treelet_build,
                 upper_tree: time_upper_tree,
                 flattening: Duration::from_secs(0),
-            }
-        }
+            },
+        )
     }
 
-    /// Flatten the BVH into a compact GPU representation
+    /// A BVH from a finished build, the leaves of `root` point into `primitives`
+    pub(super) fn from_build(root: BvhBuildNode, primitives: Vec<AccObj>, total_nodes: u32, time: BvhConstructionTime) -> Self {
+        let mut bvh = Self {
+            root,
+            primitives,
+            total_nodes,
+            build_cost: 0.0,
+            time,
+        };
+        bvh.build_cost = bvh.sah_cost();
+        bvh
+    }
+
+    /// Flatten the BVH into a compact GPU representation, the first child
+    /// of every interior node follows it and is on the negative side of
+    /// its split axis, so the near child can be picked by the ray direction
     pub fn flatten(&self) -> Vec<GpuNode> {
+        self.flatten_layout(BvhLayout::Stack)
+    }
+
+    /// Flatten the BVH with the given layout, the nodes are in the same
+    /// order for both, only the pointers of the interior nodes differ
+    pub fn flatten_layout(&self, layout: BvhLayout) -> Vec<GpuNode> {
         let mut nodes = vec![GpuNode::new(&self.root.bbox); self.total_nodes as usize];
 
         fn flatten_recursive(
             nodes: &mut Vec<GpuNode>,
             cluster: &BvhBuildNode,
             offset: &mut usize,
+            layout: BvhLayout,
         ) -> usize {
             let current_offset = *offset;
             *offset += 1;
-            let (num_primitives, offset_ptr) = match &cluster.node_type {
+            nodes[current_offset] = match &cluster.node_type {
                 BvhBuildNodeType::Leaf {
                     num_primitives,
                     first_prim_offset,
-                } => {
-                    (*num_primitives, *first_prim_offset)
-                }
-                // We do not use the split right now
+                } => GpuNode::leaf(&cluster.bbox, *first_prim_offset, *num_primitives),
+                // both the LBVH and the upper tree put the lower half left
                 BvhBuildNodeType::Interior {
-                    _split: _,
+                    split,
                     left,
                     right,
                 } => {
-                    flatten_recursive(nodes, left, offset);
-                    let offset_ptr = flatten_recursive(nodes, right, offset);
-                    (0, offset_ptr as u32)
+                    flatten_recursive(nodes, left, offset, layout);
+                    let second_child = flatten_recursive(nodes, right, offset, layout);
+                    let offset_ptr = match layout {
+                        BvhLayout::Stack => second_child,
+                        // the whole subtree has been flattened by now
+                        BvhLayout::Threaded => *offset,
+                    };
+                    GpuNode::interior(&cluster.bbox, offset_ptr as u32, *split)
                 }
             };
-            nodes[current_offset] = GpuNode {
-                max: cluster.bbox.max,
-                min: cluster.bbox.min,
-                number_of_prims: num_primitives,
-                offset_ptr,
-            };
             current_offset
         }
-        flatten_recursive(&mut nodes, &self.root, &mut 0);
+        let mut used_nodes = 0;
+        flatten_recursive(&mut nodes, &self.root, &mut used_nodes, layout);
+        // the node count of the build is an upper bound, the threaded
+        // traversal runs until the end and must not find unused nodes
+        nodes.truncate(used_nodes);
+
+        nodes
+    }
+
+    /// Collapse the binary BVH into one with up to `width` children per node.
+    /// The interior child with the largest surface area is the most likely
+    /// to be hit (SAH), so it is opened first until a node has `width`
+    /// children. The root record comes first, see `GpuWideNode`.
+    pub fn collapse(&self, width: usize) -> Vec<GpuWideNode> {
+        assert!(
+            (2..=MAX_BVH_WIDTH).contains(&width),
+            "BVH width {width} is not between 2 and {MAX_BVH_WIDTH}"
+        );
+
+        fn wide_children(node: &BvhBuildNode, width: usize) -> Vec<&BvhBuildNode> {
+            let mut children = vec![node];
+            while children.len() < width {
+                let largest = children
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, child)| match &child.node_type {
+                        BvhBuildNodeType::Interior { left, right, .. } => {
+                            Some((i, left.as_ref(), right.as_ref(), child.bbox.area()))
+                        }
+                        BvhBuildNodeType::Leaf { .. } => None,
+                    })
+                    .max_by(|a, b| a.3.total_cmp(&b.3));
+                let Some((i, left, right, _)) = largest else {
+                    break;
+                };
+                children[i] = left;
+                children.insert(i + 1, right);
+            }
+            children
+        }
+
+        fn collapse_recursive(
+            nodes: &mut Vec<GpuWideNode>,
+            cluster: &BvhBuildNode,
+            index: usize,
+            width: usize,
+        ) {
+            let record = match &cluster.node_type {
+                BvhBuildNodeType::Leaf {
+                    num_primitives,
+                    first_prim_offset,
+                } => GpuWideNode::leaf(&cluster.bbox, *first_prim_offset, *num_primitives),
+                BvhBuildNodeType::Interior { .. } => {
+                    let children = wide_children(cluster, width);
+                    let first_child = nodes.len();
+                    nodes.resize(first_child + children.len(), GpuWideNode::zeroed());
+                    for (i, child) in children.iter().enumerate() {
+                        collapse_recursive(nodes, child, first_child + i, width);
+                    }
+                    GpuWideNode::interior(&cluster.bbox, first_child as u32, children.len() as u32)
+                }
+            };
+            nodes[index] = record;
+        }
 
+        let mut nodes = vec![GpuWideNode::zeroed()];
+        collapse_recursive(&mut nodes, &self.root, 0, width);
         nodes
     }
 
-    /// Get the primitive indices for the GPU Nodes
+    /// Bounds of the whole BVH
+    pub fn bbox(&self) -> Bbox {
+        self.root.bbox
+    }
+
+    /// Recompute the bounds of all nodes bottom-up for the moved vertices of `mesh`,
+    /// the topology and thus the flattened pointers and `triangles` stay the same.
+    /// The mesh must have the primitives the BVH was built over.
+    pub fn refit(&mut self, mesh: &Mesh) {
+        self.primitives
+            .par_iter_mut()
+            .for_each(|accobj| accobj.bbox = mesh.primitive_bbox(accobj));
+        self.root.refit(&self.primitives, 0);
+    }
+
+    /// Expected cost of a ray through the BVH by the surface area heuristic,
+    /// relative to the area of the root, so it does not change with the scale.
+    /// A root without area, around primitives on a line or a point, costs as
+    /// much as one leaf with all of them instead of dividing by zero
+    pub fn sah_cost(&self) -> f32 {
+        let area = self.root.bbox.area();
+        if area > 0.0 {
+            self.root.sah_cost() / area
+        } else {
+            self.primitives.len() as f32 * SAH_INTERSECTION_COST
+        }
+    }
+
+    /// How much worse the BVH got by refitting, the current `sah_cost`
+    /// over the one it was built with, 1 for a fresh BVH
+    pub fn degradation(&self) -> f32 {
+        self.sah_cost() / self.build_cost
+    }
+
+    /// Whether the BVH degraded enough by refitting that rebuilding it pays off
+    pub fn needs_rebuild(&self) -> bool {
+        self.degradation() > REBUILD_DEGRADATION
+    }
+
+    /// Get the primitive references for the GPU Nodes, see `AccObj::reference`
     pub fn triangles(&self) -> Vec<u32> {
-        self.primitives.iter().map(|accobj| accobj.idx).collect()
+        self.primitives.iter().map(|accobj| accobj.reference()).collect()
     }
 }
 
@@ -308,7 +457,7 @@ enum BvhBuildNodeType {
     },
     /// Interior nodes have ownership over child nodes
     Interior {
-        _split: Split,
+        split: Split,
         left: Box<BvhBuildNode>,
         right: Box<BvhBuildNode>,
     },
@@ -317,7 +466,7 @@ enum BvhBuildNodeType {
 impl BvhBuildNode {
     #[inline]
     /// Create a new leaf nodes
-    fn new_leaf(first_prim_offset: u32, num_primitives: u32, bbox: Bbox) -> Self {
+    pub(super) fn new_leaf(first_prim_offset: u32, num_primitives: u32, bbox: Bbox) -> Self {
         Self {
             bbox,
             node_type: BvhBuildNodeType::Leaf {
@@ -329,14 +478,14 @@ impl BvhBuildNode {
 
     #[inline]
     /// Create a new internal node
-    fn new_internal(axis: Split, child0: BvhBuildNode, child1: BvhBuildNode) -> Self {
+    pub(super) fn new_internal(axis: Split, child0: BvhBuildNode, child1: BvhBuildNode) -> Self {
         let mut bbox = child0.bbox;
         bbox.include_bbox(&child1.bbox);
         Self {
             bbox,
             //num_primitives: child0.num_primitives + child1.num_primitives,
             node_type: BvhBuildNodeType::Interior {
-                _split: axis,
+                split: axis,
                 left: Box::new(child0),
                 right: Box::new(child1),
             },
@@ -344,6 +493,63 @@ impl BvhBuildNode {
     }
 }
 
+impl BvhBuildNode {
+    /// Recompute the bounds of the subtree from the boxes of `primitives`
+    fn refit(&mut self, primitives: &[AccObj], depth: u32) {
+        let Self { bbox, node_type } = self;
+        *bbox = match node_type {
+            BvhBuildNodeType::Leaf {
+                num_primitives,
+                first_prim_offset,
+            } => {
+                let first = *first_prim_offset as usize;
+                primitives[first..first + *num_primitives as usize]
+                    .iter()
+                    .fold(Bbox::new(), |mut bbox, accobj| {
+                        bbox.include_bbox(&accobj.bbox);
+                        bbox
+                    })
+            }
+            BvhBuildNodeType::Interior { left, right, .. } => {
+                if depth < PARALLEL_REFIT_DEPTH {
+                    rayon::join(|| left.refit(primitives, depth + 1), || right.refit(primitives, depth + 1));
+                } else {
+                    left.refit(primitives, depth + 1);
+                    right.refit(primitives, depth + 1);
+                }
+                let mut bbox = left.bbox;
+                bbox.include_bbox(&right.bbox);
+                bbox
+            }
+        };
+    }
+
+    /// Shifts the first primitive of every leaf of the subtree by `offset`
+    pub(super) fn offset_primitives(&mut self, offset: u32) {
+        match &mut self.node_type {
+            BvhBuildNodeType::Leaf { first_prim_offset, .. } => *first_prim_offset += offset,
+            BvhBuildNodeType::Interior { left, right, .. } => {
+                left.offset_primitives(offset);
+                right.offset_primitives(offset);
+            }
+        }
+    }
+
+    /// SAH cost of the subtree, weighted by the absolute areas of the nodes
+    fn sah_cost(&self) -> f32 {
+        match &self.node_type {
+            // the box of an empty leaf is inverted and has no meaningful area
+            BvhBuildNodeType::Leaf { num_primitives: 0, .. } => 0.0,
+            BvhBuildNodeType::Leaf { num_primitives, .. } => {
+                self.bbox.area() * *num_primitives as f32 * SAH_INTERSECTION_COST
+            }
+            BvhBuildNodeType::Interior { left, right, .. } => {
+                self.bbox.area() * SAH_TRAVERSAL_COST + left.sah_cost() + right.sah_cost()
+            }
+        }
+    }
+}
+
 /// Create an LBVH subtree
 fn emit_lbvh(
     primitives: &[AccObj],
@@ -441,7 +647,8 @@ fn emit_lbvh(
     }
 }
 
-/// Morton primitive just wraps an index with a morton code
+/// Morton primitive just wraps an index with a morton code, it is ordered by the
+/// code and then by the index
 #[derive(Copy, Clone, Debug)]
 struct MortonPrimitive {
     pub index: u32,
@@ -450,11 +657,15 @@ struct MortonPrimitive {
 
 /// Allow radix_sort
 impl RadixKey for MortonPrimitive {
-    const LEVELS: usize = 4;
+    const LEVELS: usize = 8;
 
     #[inline]
     fn get_level(&self, level: usize) -> u8 {
-        (self.morton_code >> (level * 8)) as u8
+        if level < 4 {
+            (self.index >> (level * 8)) as u8
+        } else {
+            (self.morton_code >> ((level - 4) * 8)) as u8
+        }
     }
 }
 
@@ -465,19 +676,19 @@ impl Eq for MortonPrimitive {}
 
 impl Ord for MortonPrimitive {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.morton_code.cmp(&other.morton_code)
+        (self.morton_code, self.index).cmp(&(other.morton_code, other.index))
     }
 }
 
 impl PartialEq for MortonPrimitive {
     fn eq(&self, other: &Self) -> bool {
-        self.morton_code == other.morton_code
+        (self.morton_code, self.index) == (other.morton_code, other.index)
     }
 }
 
 impl PartialOrd for MortonPrimitive {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.morton_code.cmp(&other.morton_code))
+        Some(self.cmp(other))
     }
 }
 
@@ -502,8 +713,15 @@ fn encode_morton_3(x: f32, y: f32, z: f32) -> u32 {
     (left_shift_3(z as u32) << 2) | (left_shift_3(y as u32) << 1) | left_shift_3(x as u32)
 }
 
+/// Bits of `GpuNode::prims_axis` below the split axis,
+/// has to match NODE_AXIS_SHIFT in bvh.wgsl
+pub const NODE_AXIS_SHIFT: u32 = 30;
+
 /// GPU Node
 ///
+/// Leaves point to their first primitive, interior nodes to their second
+/// child or the node after their subtree, depending on the `BvhLayout`.
+/// The first child is stored right after them.
 
 #[repr(C, align(16))]
 #[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
@@ -511,27 +729,251 @@ pub struct GpuNode {
     pub min: Vec3f32,
     pub offset_ptr: u32,
     pub max: Vec3f32,
-    pub number_of_prims: u32,
+    /// Number of primitives, 0 for interior nodes, with the split axis
+    /// of interior nodes in the upper two bits
+    pub prims_axis: u32,
 }
 
+wgsl_struct!(GpuNode as BvhNode {
+    bbox_min: vec3f = min,
+    offset_ptr: u32,
+    bbox_max: vec3f = max,
+    n_primitives_axis: u32 = prims_axis,
+});
+
 impl GpuNode {
     pub fn new(bbox: &Bbox) -> Self {
         GpuNode {
             min: bbox.min,
             offset_ptr: 9999,
             max: bbox.max,
-            number_of_prims: 9999,
+            prims_axis: 9999,
+        }
+    }
+
+    pub fn leaf(bbox: &Bbox, first_prim_offset: u32, num_primitives: u32) -> Self {
+        debug_assert!(num_primitives < 1 << NODE_AXIS_SHIFT);
+        GpuNode {
+            min: bbox.min,
+            offset_ptr: first_prim_offset,
+            max: bbox.max,
+            prims_axis: num_primitives,
+        }
+    }
+
+    pub fn interior(bbox: &Bbox, second_child: u32, axis: Split) -> Self {
+        GpuNode {
+            min: bbox.min,
+            offset_ptr: second_child,
+            max: bbox.max,
+            prims_axis: (axis as u32) << NODE_AXIS_SHIFT,
         }
     }
+
+    pub fn number_of_prims(&self) -> u32 {
+        self.prims_axis & ((1 << NODE_AXIS_SHIFT) - 1)
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.number_of_prims() > 0
+    }
+
+    /// Split axis of interior nodes
+    pub fn axis(&self) -> u32 {
+        self.prims_axis >> NODE_AXIS_SHIFT
+    }
 }
 
 static_assertions::assert_eq_size!(GpuNode, [u32; 8]);
 
+/// Most children a node of `Bvh::collapse` can have, has to fit
+/// into WIDE_COUNT_BITS in bvh_wide.wgsl
+pub const MAX_BVH_WIDTH: usize = 8;
+
+/// Marks interior nodes in `GpuWideNode::count`,
+/// has to match WIDE_NODE_INTERIOR in bvh_wide.wgsl
+pub const WIDE_NODE_INTERIOR: u32 = 1 << 31;
+
+/// GPU Node of a wide BVH
+///
+/// The records of all children of a node are next to each other, interior
+/// nodes point to the first one and store how many there are, leaves point
+/// to their first primitive. The binary interior nodes that were collapsed
+/// have no record, which is where the wide BVH saves memory.
+#[repr(C, align(16))]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct GpuWideNode {
+    pub min: Vec3f32,
+    pub offset_ptr: u32,
+    pub max: Vec3f32,
+    /// Number of primitives of leaves or children of interior nodes,
+    /// interior nodes have WIDE_NODE_INTERIOR set
+    pub count: u32,
+}
+
+wgsl_struct!(GpuWideNode as BvhWideNode {
+    bbox_min: vec3f = min,
+    offset_ptr: u32,
+    bbox_max: vec3f = max,
+    count: u32,
+});
+
+impl GpuWideNode {
+    pub fn leaf(bbox: &Bbox, first_prim_offset: u32, num_primitives: u32) -> Self {
+        debug_assert!(num_primitives < WIDE_NODE_INTERIOR);
+        GpuWideNode {
+            min: bbox.min,
+            offset_ptr: first_prim_offset,
+            max: bbox.max,
+            count: num_primitives,
+        }
+    }
+
+    pub fn interior(bbox: &Bbox, first_child: u32, num_children: u32) -> Self {
+        GpuWideNode {
+            min: bbox.min,
+            offset_ptr: first_child,
+            max: bbox.max,
+            count: num_children | WIDE_NODE_INTERIOR,
+        }
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.count & WIDE_NODE_INTERIOR == 0
+    }
+
+    /// Number of primitives of a leaf or children of an interior node
+    pub fn entries(&self) -> u32 {
+        self.count & !WIDE_NODE_INTERIOR
+    }
+}
+
+static_assertions::assert_eq_size!(GpuWideNode, GpuNode);
+
 #[cfg(test)]
 mod bvh_test {
 
     use super::*;
 
+    #[test]
+    fn near_first_traversal_finds_the_closest_hit() {
+        use crate::data_structures::bvh_traversal::{brute_force, random_rays, traverse_stack};
+
+        let model = Mesh::from_obj("res/models/teapot.obj").expect("Failed to load model");
+        let bvh = Bvh::new(&model, 4, false);
+        let nodes = bvh.flatten();
+        let triangles = bvh.triangles();
+        assert!(nodes.iter().all(|node| node.is_leaf() || node.axis() < 3));
+
+        let (mut visited_near_first, mut visited_fixed) = (0, 0);
+        for ray in random_rays(&bvh.bbox(), 500, 7) {
+            let closest = brute_force(&model, &ray);
+            let near_first = traverse_stack(&nodes, &triangles, &model, &ray, true, false);
+            let fixed = traverse_stack(&nodes, &triangles, &model, &ray, false, false);
+            let any = traverse_stack(&nodes, &triangles, &model, &ray, true, true);
+            assert_eq!(near_first.distance, closest);
+            assert_eq!(fixed.distance, closest);
+            assert_eq!(any.distance.is_some(), closest.is_some());
+            visited_near_first += near_first.visited_nodes;
+            visited_fixed += fixed.visited_nodes;
+        }
+        assert!(visited_near_first < visited_fixed, "{visited_near_first} >= {visited_fixed}");
+    }
+
+    #[test]
+    fn refitting_moved_vertices_finds_the_closest_hit() {
+        use crate::data_structures::bvh_traversal::{brute_force, random_rays, traverse_stack};
+
+        let mut model = Mesh::from_obj("res/models/teapot.obj").expect("Failed to load model");
+        let mut bvh = Bvh::new(&model, 4, false);
+        let triangles = bvh.triangles();
+        bvh.refit(&model);
+        assert_eq!(bvh.degradation(), 1.0);
+
+        // stretch the teapot and bend its top sideways
+        for vertex in &mut model.vertices {
+            vertex.0 += 0.3 * vertex.1 * vertex.1;
+            vertex.1 *= 1.5;
+        }
+        bvh.refit(&model);
+        let nodes = bvh.flatten();
+        assert_eq!(bvh.triangles(), triangles);
+        assert!(bvh.degradation() > 1.0);
+        for ray in random_rays(&bvh.bbox(), 300, 11) {
+            let closest = brute_force(&model, &ray);
+            assert_eq!(traverse_stack(&nodes, &triangles, &model, &ray, true, false).distance, closest);
+        }
+
+        let rebuilt = Bvh::new(&model, 4, false);
+        assert!(rebuilt.sah_cost() < bvh.sah_cost(), "{} >= {}", rebuilt.sah_cost(), bvh.sah_cost());
+    }
+
+    #[test]
+    fn flat_meshes_have_a_finite_sah_cost() {
+        use crate::data_structures::vector::vec3f;
+
+        let mut plane = Mesh::from_obj("res/models/plane.obj").expect("Failed to load model");
+        let mut bvh = Bvh::new(&plane, 4, false);
+        assert!(bvh.sah_cost().is_finite());
+        assert_eq!(bvh.degradation(), 1.0);
+
+        // squash the plane onto a line, its root has no area left
+        plane.vertices.iter_mut().for_each(|vertex| vertex.2 = 0.0);
+        bvh.refit(&plane);
+        assert_eq!(bvh.root.bbox.area(), 0.0);
+        assert_eq!(bvh.sah_cost(), plane.indices.len() as f32 * SAH_INTERSECTION_COST);
+        assert!(bvh.degradation().is_finite());
+
+        let segments = (0..10)
+            .map(|i| {
+                let mut bbox = Bbox::new();
+                bbox.include_vertex(vec3f(i as f32, 0.0, 0.0));
+                bbox.include_vertex(vec3f(i as f32 + 0.5, 0.0, 0.0));
+                AccObj::new(i, bbox)
+            })
+            .collect();
+        let bvh = Bvh::from_primitives(segments, 4, false);
+        assert_eq!(bvh.sah_cost(), 10.0 * SAH_INTERSECTION_COST);
+        assert_eq!(bvh.degradation(), 1.0);
+        assert!(!bvh.needs_rebuild());
+    }
+
+    #[test]
+    fn builds_do_not_depend_on_the_thread_count() {
+        use crate::data_structures::vector::vec3f;
+
+        let mut inputs = vec![];
+        for path in ["res/models/teapot.obj", "res/models/CornellBoxWithBlocks.obj"] {
+            inputs.push((path, Mesh::from_obj(path).expect("Failed to load model").bboxes()));
+        }
+        // many primitives with the same morton code, enough for the parallel radix sort
+        let grid = (0..400_000u32)
+            .map(|idx| {
+                let cell = |prime: u32, cells: u32| (idx.wrapping_mul(prime) % cells) as f32;
+                let min = vec3f(cell(7919, 97), cell(104729, 89), cell(31, 83));
+                AccObj::new(idx, Bbox { min, max: min + vec3f(1.0, 1.0, 1.0) })
+            })
+            .collect();
+        inputs.push(("grid", grid));
+
+        for (name, primitives) in inputs {
+            let single = Bvh::from_primitives(primitives.clone(), 4, true);
+            let nodes = single.flatten();
+            let triangles = single.triangles();
+            for threads in [1, 2, 3, 8] {
+                let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+                let bvh = pool.install(|| Bvh::from_primitives(primitives.clone(), 4, false));
+                assert_eq!(
+                    bytemuck::cast_slice::<_, u8>(&bvh.flatten()),
+                    bytemuck::cast_slice::<_, u8>(&nodes),
+                    "{name} with {threads} threads"
+                );
+                assert_eq!(bvh.triangles(), triangles, "{name} with {threads} threads");
+                assert_eq!(bvh.total_nodes, single.total_nodes);
+            }
+        }
+    }
+
     #[test]
     fn bvh_new() {
         let model = Mesh::from_obj("res/models/test_object.obj").expect("Failed to load model");
@@ -0,0 +1,253 @@
+/// Two-level acceleration structure for instancing, a top-level BVH over the
+/// instances of a scene and a bottom-level BVH for every mesh they reference.
+/// A mesh is stored once however often it is placed, see bvh_instanced.wgsl.
+
+use cgmath::{Matrix4, Point3, SquareMatrix, Transform};
+
+use crate::{bindings::wgsl_struct::wgsl_struct, mesh::Mesh};
+
+use super::{
+    accobj::{AccObj, ANALYTIC_PRIMITIVE},
+    bbox::Bbox,
+    hlbvh::{Bvh, GpuNode},
+    vector::{vec3f, vec4f32, Vec3f32, Vec4f32},
+};
+
+/// Triangles per leaf of the bottom-level BVHs, like `Mesh::bvh`
+const BLAS_MAX_PRIMS: u32 = 4;
+/// Instances per leaf of the top-level BVH, every instance
+/// starts a traversal of its own, so they are not grouped
+const TLAS_MAX_PRIMS: u32 = 1;
+
+/// A mesh placed in the world
+#[derive(Debug, Copy, Clone)]
+pub struct Instance {
+    /// Index of the mesh in the meshes the `TwoLevelBvh` is built over
+    pub mesh: usize,
+    pub object_to_world: Matrix4<f32>,
+}
+
+/// GPU instance, the instances are stored in the order of the top-level leaves
+#[repr(C, align(16))]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct GpuInstance {
+    /// Affine transform into object space, the columns of a WGSL mat4x3f
+    pub world_to_object: [Vec4f32; 4],
+    /// Root of the bottom-level BVH of the mesh in the node buffer
+    pub blas_root: u32,
+    _padding: [u32; 3],
+}
+
+wgsl_struct!(GpuInstance as Instance {
+    world_to_object: mat4x3f,
+    blas_root: u32,
+});
+
+static_assertions::assert_eq_size!(GpuInstance, [u32; 20]);
+
+impl GpuInstance {
+    pub fn new(world_to_object: &Matrix4<f32>, blas_root: u32) -> Self {
+        Self {
+            world_to_object: [world_to_object.x, world_to_object.y, world_to_object.z, world_to_object.w]
+                .map(|column| vec4f32(column.x, column.y, column.z, 0.0)),
+            blas_root,
+            _padding: [0; 3],
+        }
+    }
+
+    /// Moves `v` into object space, `w` is 1 for points and 0 for directions
+    pub fn to_object(&self, v: Vec3f32, w: f32) -> Vec3f32 {
+        let [x, y, z, translation] = self.world_to_object;
+        (x * v.0 + y * v.1 + z * v.2 + translation * w).xyz()
+    }
+}
+
+/// Bottom-level BVHs of the meshes and the top-level BVH of the instances
+pub struct TwoLevelBvh {
+    pub blas: Vec<Bvh>,
+    /// BVH over the world space boxes of the instances
+    pub tlas: Bvh,
+    pub instances: Vec<Instance>,
+    /// Inverses of the instance transforms, checked when building
+    world_to_object: Vec<Matrix4<f32>>,
+    /// First triangle and first analytic primitive of every mesh after `Mesh::merge`
+    offsets: Vec<(u32, u32)>,
+}
+
+/// Buffers of a `TwoLevelBvh`, both levels are flattened with the stack layout
+pub struct FlatTwoLevelBvh {
+    /// Nodes of all bottom-level BVHs, with pointers into the whole buffers
+    pub blas_nodes: Vec<GpuNode>,
+    /// Primitive references of the bottom-level leaves, into the merged mesh
+    pub blas_triangles: Vec<u32>,
+    /// Nodes of the top-level BVH, the leaves point into `instances`
+    pub tlas_nodes: Vec<GpuNode>,
+    pub instances: Vec<GpuInstance>,
+}
+
+impl TwoLevelBvh {
+    /// The primitives of the meshes are addressed as in `Mesh::merge(meshes)`,
+    /// fails for instances with a transform that can not be inverted
+    pub fn new(meshes: &[Mesh], instances: Vec<Instance>) -> anyhow::Result<Self> {
+        let world_to_object = instances
+            .iter()
+            .enumerate()
+            .map(|(idx, instance)| {
+                instance
+                    .object_to_world
+                    .invert()
+                    .ok_or_else(|| anyhow::anyhow!("The transform of instance {idx} can not be inverted"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let blas = meshes
+            .iter()
+            .map(|mesh| Bvh::new(mesh, BLAS_MAX_PRIMS, false))
+            .collect::<Vec<_>>();
+        let offsets = meshes
+            .iter()
+            .scan((0, 0), |(triangles, primitives), mesh| {
+                let first = (*triangles, *primitives);
+                *triangles += mesh.indices.len() as u32;
+                *primitives += mesh.primitives.len() as u32;
+                Some(first)
+            })
+            .collect();
+        let boxes = instances
+            .iter()
+            .enumerate()
+            .map(|(idx, instance)| {
+                AccObj::new(idx as u32, transform_bbox(&blas[instance.mesh].bbox(), &instance.object_to_world))
+            })
+            .collect();
+        let tlas = Bvh::from_primitives(boxes, TLAS_MAX_PRIMS, false);
+
+        Ok(Self {
+            blas,
+            tlas,
+            instances,
+            world_to_object,
+            offsets,
+        })
+    }
+
+    /// Concatenates the bottom-level BVHs and orders the instances like the top-level leaves
+    pub fn flatten(&self) -> FlatTwoLevelBvh {
+        let mut blas_nodes = vec![];
+        let mut blas_triangles = vec![];
+        let blas_roots = self
+            .blas
+            .iter()
+            .zip(&self.offsets)
+            .map(|(bvh, &(triangle_offset, primitive_offset))| {
+                let root = blas_nodes.len() as u32;
+                let first_triangle = blas_triangles.len() as u32;
+                blas_nodes.extend(bvh.flatten().into_iter().map(|mut node| {
+                    node.offset_ptr += if node.is_leaf() { first_triangle } else { root };
+                    node
+                }));
+                blas_triangles.extend(bvh.triangles().into_iter().map(|reference| match reference & ANALYTIC_PRIMITIVE {
+                    0 => reference + triangle_offset,
+                    _ => reference + primitive_offset,
+                }));
+                root
+            })
+            .collect::<Vec<_>>();
+
+        let instances = self
+            .tlas
+            .triangles()
+            .into_iter()
+            .map(|idx| {
+                let instance = &self.instances[idx as usize];
+                GpuInstance::new(&self.world_to_object[idx as usize], blas_roots[instance.mesh])
+            })
+            .collect();
+
+        FlatTwoLevelBvh {
+            blas_nodes,
+            blas_triangles,
+            tlas_nodes: self.tlas.flatten(),
+            instances,
+        }
+    }
+
+    /// Bounds of all instances in world space
+    pub fn bbox(&self) -> Bbox {
+        self.tlas.bbox()
+    }
+}
+
+/// World space box around the corners of `bbox` moved by `object_to_world`
+fn transform_bbox(bbox: &Bbox, object_to_world: &Matrix4<f32>) -> Bbox {
+    (0..8).fold(Bbox::new(), |mut world, corner| {
+        let pick = |axis: u32| match corner >> axis & 1 {
+            0 => bbox.min[axis],
+            _ => bbox.max[axis],
+        };
+        let p = object_to_world.transform_point(Point3::new(pick(0), pick(1), pick(2)));
+        world.include_vertex(vec3f(p.x, p.y, p.z));
+        world
+    })
+}
+
+#[cfg(test)]
+mod instancing_test {
+    use cgmath::{Deg, Vector3};
+
+    use super::*;
+    use crate::data_structures::bvh_traversal::{brute_force, random_rays, traverse_instanced};
+
+    #[test]
+    fn instances_find_the_closest_hit() {
+        let meshes = [
+            Mesh::from_obj("res/models/teapot.obj").expect("Failed to load model"),
+            Mesh::from_obj("res/models/test_object.obj").expect("Failed to load model"),
+        ];
+        let instances = (0..12)
+            .map(|i| Instance {
+                mesh: i % 2,
+                object_to_world: Matrix4::from_translation(Vector3::new((i % 4) as f32, (i / 4) as f32, (i % 3) as f32 * 0.5))
+                    * Matrix4::from_angle_y(Deg(30.0 * i as f32))
+                    * Matrix4::from_nonuniform_scale(0.5, 0.2 + 0.1 * i as f32, 0.4),
+            })
+            .collect::<Vec<_>>();
+        let bvh = TwoLevelBvh::new(&meshes, instances.clone()).unwrap();
+        let flat = bvh.flatten();
+        let merged = Mesh::merge(&meshes);
+        assert_eq!(flat.instances.len(), instances.len());
+        assert_eq!(flat.blas_triangles.len(), merged.indices.len());
+
+        // every instance baked into world space
+        let world = Mesh::merge(
+            &instances
+                .iter()
+                .map(|instance| {
+                    let mut mesh = meshes[instance.mesh].clone();
+                    mesh.transform(&instance.object_to_world).unwrap();
+                    mesh
+                })
+                .collect::<Vec<_>>(),
+        );
+        for ray in random_rays(&bvh.bbox(), 300, 17) {
+            let closest = brute_force(&world, &ray);
+            let hit = traverse_instanced(&flat, &merged, &ray, false).distance;
+            match (hit, closest) {
+                (Some(hit), Some(closest)) => assert!((hit - closest).abs() <= 1e-4 * closest.max(1.0), "{hit} != {closest}"),
+                _ => assert_eq!(hit, closest),
+            }
+            let any = traverse_instanced(&flat, &merged, &ray, true);
+            assert_eq!(any.distance.is_some(), closest.is_some());
+        }
+    }
+
+    #[test]
+    fn instances_with_a_singular_transform_are_rejected() {
+        let meshes = [Mesh::from_obj("res/models/test_object.obj").expect("Failed to load model")];
+        let instances = vec![
+            Instance { mesh: 0, object_to_world: Matrix4::from_scale(1.0) },
+            Instance { mesh: 0, object_to_world: Matrix4::from_nonuniform_scale(1.0, 0.0, 1.0) },
+        ];
+        let err = TwoLevelBvh::new(&meshes, instances).err().expect("a zero scale has no inverse");
+        assert!(err.to_string().contains("instance 1"), "{err}");
+    }
+}
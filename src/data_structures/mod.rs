@@ -3,5 +3,9 @@ pub mod bsp_tree;
 pub mod vector;
 pub mod bvh;
 pub mod hlbvh;
+pub mod sbvh;
 pub mod bvh_util;
+pub mod bvh_traversal;
+pub mod quantized_bvh;
+pub mod instancing;
 pub mod accobj;
\ No newline at end of file
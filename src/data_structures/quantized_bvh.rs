@@ -0,0 +1,350 @@
+/// Compressed BVH4 nodes, the boxes of the children are stored in 8 bits
+/// per bound relative to the box of their parent, see bvh_quantized.wgsl.
+/// A node takes 64 bytes for up to four children, where the binary BVH
+/// needs 32 bytes for every node.
+
+use std::ops::Range;
+
+use bytemuck::Zeroable;
+
+use crate::bindings::wgsl_struct::wgsl_struct;
+
+use super::{
+    bbox::Bbox,
+    hlbvh::{Bvh, GpuWideNode},
+    vector::{vec3f, Vec3f32},
+};
+
+/// Children of a quantized node, the BVH is collapsed to this width
+pub const QUANTIZED_WIDTH: usize = 4;
+
+/// `GpuQuantizedNode::child_info` of an interior child,
+/// has to match QUANTIZED_INTERIOR in bvh_quantized.wgsl
+pub const QUANTIZED_INTERIOR: u32 = 255;
+/// Most primitives of a leaf child, larger leaves are split by `quantize_leaf`
+const QUANTIZED_MAX_LEAF: u32 = QUANTIZED_INTERIOR - 1;
+
+/// Bias of the exponents in `GpuQuantizedNode::exponents`
+const EXPONENT_BIAS: i32 = 127;
+
+/// Quantization of the boxes inside a parent box, a bound `q` decodes to
+/// `origin + q * 2^exponent`. The power of two steps make the decoding
+/// exact, so only the final addition rounds, the same way on the GPU.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct QuantizationFrame {
+    pub origin: Vec3f32,
+    pub exponents: [i32; 3],
+}
+
+impl QuantizationFrame {
+    /// Smallest steps that still reach the maximum of `parent` with 255
+    pub fn new(parent: &Bbox) -> Self {
+        let exponents = [0, 1, 2].map(|axis| {
+            let extent = parent.max[axis] - parent.min[axis];
+            let mut exponent = if extent > 0.0 {
+                ((extent / 255.0).log2().ceil() as i32).clamp(1 - EXPONENT_BIAS, EXPONENT_BIAS)
+            } else {
+                0
+            };
+            // the rounding of the decoding can still end below the maximum
+            while parent.min[axis] + 255.0 * step(exponent) < parent.max[axis] {
+                exponent += 1;
+            }
+            exponent
+        });
+        Self {
+            origin: parent.min,
+            exponents,
+        }
+    }
+
+    fn decode_bound(&self, axis: u32, q: u8) -> f32 {
+        self.origin[axis] + q as f32 * step(self.exponents[axis as usize])
+    }
+
+    /// Rounds the bounds of `child` outward, the decoded box always contains it
+    /// as long as `child` is inside the parent box of the frame
+    pub fn encode(&self, child: &Bbox) -> ([u8; 3], [u8; 3]) {
+        let (mut min, mut max) = ([0; 3], [0; 3]);
+        for axis in 0..3 {
+            let step = step(self.exponents[axis as usize]);
+            let mut lo = ((child.min[axis] - self.origin[axis]) / step).floor().clamp(0.0, 255.0) as u8;
+            let mut hi = ((child.max[axis] - self.origin[axis]) / step).ceil().clamp(0.0, 255.0) as u8;
+            // the division rounds, move by a step until the decoded bounds are outside
+            while lo > 0 && self.decode_bound(axis, lo) > child.min[axis] {
+                lo -= 1;
+            }
+            while hi < 255 && self.decode_bound(axis, hi) < child.max[axis] {
+                hi += 1;
+            }
+            min[axis as usize] = lo;
+            max[axis as usize] = hi;
+        }
+        (min, max)
+    }
+
+    pub fn decode(&self, min: [u8; 3], max: [u8; 3]) -> Bbox {
+        Bbox {
+            min: vec3f(self.decode_bound(0, min[0]), self.decode_bound(1, min[1]), self.decode_bound(2, min[2])),
+            max: vec3f(self.decode_bound(0, max[0]), self.decode_bound(1, max[1]), self.decode_bound(2, max[2])),
+        }
+    }
+}
+
+/// 2^exponent, built from the bits like quantized_steps in bvh_quantized.wgsl
+fn step(exponent: i32) -> f32 {
+    f32::from_bits(((exponent + EXPONENT_BIAS) as u32) << 23)
+}
+
+/// GPU Node of the quantized BVH4
+///
+/// Byte `i` of `child_min`, `child_max` and `child_info` belongs to child
+/// `i`, an info of 0 is an unused slot, QUANTIZED_INTERIOR an interior
+/// child, anything else the number of primitives of a leaf. Interior
+/// children point to their node, leaves to their first primitive.
+#[repr(C, align(16))]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct GpuQuantizedNode {
+    /// Minimum of the box of the node
+    pub origin: Vec3f32,
+    /// Exponents of the steps of x, y and z in bytes 0 to 2, biased by 127
+    pub exponents: u32,
+    pub children: [u32; 4],
+    pub child_min: [u32; 3],
+    pub child_info: u32,
+    pub child_max: [u32; 3],
+    _padding: u32,
+}
+
+wgsl_struct!(GpuQuantizedNode as BvhQuantizedNode {
+    origin: vec3f,
+    exponents: u32,
+    children: vec4u,
+    child_min: vec3u,
+    child_info: u32,
+    child_max: vec3u,
+});
+
+static_assertions::assert_eq_size!(GpuQuantizedNode, [u32; 16]);
+
+impl GpuQuantizedNode {
+    fn new(frame: &QuantizationFrame) -> Self {
+        Self {
+            origin: frame.origin,
+            exponents: frame
+                .exponents
+                .iter()
+                .enumerate()
+                .fold(0, |exponents, (axis, e)| exponents | ((e + EXPONENT_BIAS) as u32) << (8 * axis)),
+            ..Self::zeroed()
+        }
+    }
+
+    pub fn frame(&self) -> QuantizationFrame {
+        QuantizationFrame {
+            origin: self.origin,
+            exponents: [0, 1, 2].map(|axis| ((self.exponents >> (8 * axis)) & 0xff) as i32 - EXPONENT_BIAS),
+        }
+    }
+
+    fn set_child(&mut self, slot: usize, (min, max): ([u8; 3], [u8; 3]), pointer: u32, info: u32) {
+        let shift = 8 * slot;
+        for axis in 0..3 {
+            self.child_min[axis] |= (min[axis] as u32) << shift;
+            self.child_max[axis] |= (max[axis] as u32) << shift;
+        }
+        self.child_info |= info << shift;
+        self.children[slot] = pointer;
+    }
+
+    /// 0 for unused slots, QUANTIZED_INTERIOR or the number of primitives
+    pub fn child_info(&self, slot: usize) -> u32 {
+        (self.child_info >> (8 * slot)) & 0xff
+    }
+
+    /// Decoded box of the child in `slot`
+    pub fn child_box(&self, slot: usize) -> Bbox {
+        let byte = |v: u32| (v >> (8 * slot)) as u8;
+        self.frame().decode(self.child_min.map(byte), self.child_max.map(byte))
+    }
+}
+
+impl Bvh {
+    /// Collapses the BVH to `QUANTIZED_WIDTH` and quantizes the child boxes
+    pub fn quantize(&self) -> Vec<GpuQuantizedNode> {
+        quantize_wide(&self.collapse(QUANTIZED_WIDTH))
+    }
+}
+
+/// Quantizes the records of a wide BVH of at most `QUANTIZED_WIDTH`, the root
+/// node comes first. A root that is a leaf becomes the only child of node 0,
+/// leaves with more primitives than a child can count become interior children.
+pub fn quantize_wide(records: &[GpuWideNode]) -> Vec<GpuQuantizedNode> {
+    let root = &records[0];
+    let root_children = match root.is_leaf() {
+        true => 0..1,
+        false => root.offset_ptr as usize..(root.offset_ptr + root.entries()) as usize,
+    };
+
+    fn quantize_recursive(
+        nodes: &mut Vec<GpuQuantizedNode>,
+        records: &[GpuWideNode],
+        bbox: &Bbox,
+        children: Range<usize>,
+    ) -> u32 {
+        assert!(children.len() <= QUANTIZED_WIDTH, "nodes of a quantized BVH have at most {QUANTIZED_WIDTH} children");
+        let index = nodes.len();
+        nodes.push(GpuQuantizedNode::zeroed());
+        let frame = QuantizationFrame::new(bbox);
+        let mut node = GpuQuantizedNode::new(&frame);
+        for (slot, record) in records[children].iter().enumerate() {
+            let child_box = Bbox { min: record.min, max: record.max };
+            let (pointer, info) = match record.is_leaf() {
+                true if record.entries() > QUANTIZED_MAX_LEAF => {
+                    (quantize_leaf(nodes, &child_box, record.offset_ptr, record.entries()), QUANTIZED_INTERIOR)
+                }
+                true => (record.offset_ptr, record.entries()),
+                false => {
+                    let first = record.offset_ptr as usize;
+                    let child_children = first..first + record.entries() as usize;
+                    (quantize_recursive(nodes, records, &child_box, child_children), QUANTIZED_INTERIOR)
+                }
+            };
+            node.set_child(slot, frame.encode(&child_box), pointer, info);
+        }
+        nodes[index] = node;
+        index as u32
+    }
+
+    /// Node with the `count` primitives from `first` split evenly between its children,
+    /// a leaf of many primitives sharing one Morton code does not fit into `child_info`
+    fn quantize_leaf(nodes: &mut Vec<GpuQuantizedNode>, bbox: &Bbox, first: u32, count: u32) -> u32 {
+        let index = nodes.len();
+        nodes.push(GpuQuantizedNode::zeroed());
+        let frame = QuantizationFrame::new(bbox);
+        let mut node = GpuQuantizedNode::new(&frame);
+        let per_child = count.div_ceil(QUANTIZED_WIDTH as u32);
+        for (slot, start) in (0..count).step_by(per_child as usize).enumerate() {
+            let entries = per_child.min(count - start);
+            let (pointer, info) = match entries > QUANTIZED_MAX_LEAF {
+                true => (quantize_leaf(nodes, bbox, first + start, entries), QUANTIZED_INTERIOR),
+                false => (first + start, entries),
+            };
+            // the children keep the box of the whole leaf
+            node.set_child(slot, frame.encode(bbox), pointer, info);
+        }
+        nodes[index] = node;
+        index as u32
+    }
+
+    let mut nodes = Vec::new();
+    quantize_recursive(&mut nodes, records, &Bbox { min: root.min, max: root.max }, root_children);
+    nodes
+}
+
+#[cfg(test)]
+mod quantized_bvh_test {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        data_structures::bvh_traversal::{brute_force, random_rays, traverse_quantized},
+        mesh::Mesh,
+    };
+
+    fn contains(outer: &Bbox, inner: &Bbox) -> bool {
+        (0..3).all(|axis| outer.min[axis] <= inner.min[axis] && outer.max[axis] >= inner.max[axis])
+    }
+
+    #[test]
+    fn decoded_boxes_contain_the_originals() {
+        let mut rng = rand_pcg::Pcg32::seed_from_u64(11);
+        for _ in 0..10_000 {
+            // flat, tiny and far away parents as well
+            let scale = 10f32.powi(rng.gen_range(-6..6));
+            let offset = rng.gen_range(-1e5..1e5);
+            let mut corner = || {
+                let flat = rng.gen_bool(0.1);
+                let c = [0, 1, 2].map(|_| offset + if flat { 0.0 } else { rng.gen::<f32>() * scale });
+                vec3f(c[0], c[1], c[2])
+            };
+            let mut parent = Bbox::new();
+            parent.include_vertex(corner());
+            parent.include_vertex(corner());
+            let mut child = Bbox::new();
+            for _ in 0..2 {
+                let t = [0, 1, 2].map(|_| rng.gen::<f32>());
+                child.include_vertex(vec3f(
+                    parent.min.0 + t[0] * (parent.max.0 - parent.min.0),
+                    parent.min.1 + t[1] * (parent.max.1 - parent.min.1),
+                    parent.min.2 + t[2] * (parent.max.2 - parent.min.2),
+                ));
+            }
+
+            let frame = QuantizationFrame::new(&parent);
+            let (min, max) = frame.encode(&child);
+            let decoded = frame.decode(min, max);
+            assert!(contains(&decoded, &child), "{decoded:?} does not contain {child:?} in {parent:?}");
+            assert!(contains(&frame.decode([0; 3], [255; 3]), &parent));
+        }
+    }
+
+    #[test]
+    fn quantized_bvh_finds_the_closest_hit() {
+        let model = Mesh::from_obj("res/models/teapot.obj").expect("Failed to load model");
+        let bvh = Bvh::new(&model, 4, false);
+        let records = bvh.collapse(QUANTIZED_WIDTH);
+        let nodes = quantize_wide(&records);
+        let triangles = bvh.triangles();
+        assert!(std::mem::size_of_val(nodes.as_slice()) < std::mem::size_of_val(records.as_slice()));
+
+        assert_eq!(nodes.len(), records.iter().filter(|record| !record.is_leaf()).count());
+
+        // every decoded child box contains the one of its record
+        fn check(nodes: &[GpuQuantizedNode], records: &[GpuWideNode], node: usize, first: usize) {
+            let node = &nodes[node];
+            for slot in (0..QUANTIZED_WIDTH).filter(|&slot| node.child_info(slot) != 0) {
+                let record = &records[first + slot];
+                assert!(contains(&node.child_box(slot), &Bbox { min: record.min, max: record.max }));
+                if !record.is_leaf() {
+                    check(nodes, records, node.children[slot] as usize, record.offset_ptr as usize);
+                }
+            }
+        }
+        check(&nodes, &records, 0, records[0].offset_ptr as usize);
+
+        for ray in random_rays(&bvh.bbox(), 300, 13) {
+            let closest = brute_force(&model, &ray);
+            assert_eq!(traverse_quantized(&nodes, &triangles, &model, &ray, false).distance, closest);
+            let any = traverse_quantized(&nodes, &triangles, &model, &ray, true);
+            assert_eq!(any.distance.is_some(), closest.is_some());
+        }
+    }
+
+    #[test]
+    fn leaves_of_many_identical_triangles_are_split() {
+        let mut model = Mesh::from_obj("res/models/teapot.obj").expect("Failed to load model");
+        // one Morton code for all copies, the build ends them in a single leaf
+        let copies = 1000;
+        model.indices.extend(vec![model.indices[0]; copies]);
+        let bvh = Bvh::new(&model, 4, false);
+        let records = bvh.collapse(QUANTIZED_WIDTH);
+        assert!(records.iter().any(|record| record.is_leaf() && record.entries() > copies as u32));
+
+        let nodes = bvh.quantize();
+        let triangles = bvh.triangles();
+        // the split leaves still reference every primitive once
+        let mut referenced = nodes
+            .iter()
+            .flat_map(|node| (0..QUANTIZED_WIDTH).map(move |slot| (node, slot)))
+            .filter(|&(node, slot)| !matches!(node.child_info(slot), 0 | QUANTIZED_INTERIOR))
+            .flat_map(|(node, slot)| node.children[slot]..node.children[slot] + node.child_info(slot))
+            .collect::<Vec<_>>();
+        referenced.sort_unstable();
+        assert_eq!(referenced, (0..triangles.len() as u32).collect::<Vec<_>>());
+        for ray in random_rays(&bvh.bbox(), 300, 17) {
+            let closest = brute_force(&model, &ray);
+            assert_eq!(traverse_quantized(&nodes, &triangles, &model, &ray, false).distance, closest);
+        }
+    }
+}
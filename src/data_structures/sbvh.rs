@@ -0,0 +1,416 @@
+/// Spatial split BVH (SBVH) after Stich et al., "Spatial Splits in Bounding Volume
+/// Hierarchies" (2009). Nodes are split by the SAH over binned objects, and where the
+/// children of the best object split overlap by more than `alpha` times the area of the
+/// root, also by planes that chop the references crossing them into a part on either side.
+/// Leaves can share primitives then, but their boxes hug the long thin triangles an
+/// object split can only wrap in overlapping boxes. The result is an `hlbvh::Bvh`,
+/// flattened to the same `GpuNode`s and traversed by bvh.wgsl.
+
+use std::time::Instant;
+
+use crate::mesh::Mesh;
+
+use super::{
+    accobj::{AccObj, PrimitiveKind},
+    bbox::Bbox,
+    bvh_util::BvhConstructionTime,
+    hlbvh::{Bvh, BvhBuildNode},
+};
+
+/// Bins of the object and the spatial split candidates on every axis
+const BINS: usize = 32;
+/// Below this depth nodes are only split by objects, so references
+/// in degenerate clusters are not duplicated without bound
+const MAX_SPATIAL_DEPTH: u32 = 48;
+/// Nodes with more references build their children in parallel
+const PARALLEL_REFERENCES: usize = 4096;
+/// Overlap threshold of Stich et al., spatial splits are only tried where the
+/// children of the best object split overlap by more than this part of the root
+pub const SBVH_ALPHA: f32 = 1e-5;
+
+impl Bvh {
+    /// Construct a BVH with spatial splits over the triangles and the bounded primitives
+    /// of `model`, `alpha` is the overlap threshold, see `SBVH_ALPHA`. The same primitive
+    /// can appear several times in `triangles`. Refitting keeps the topology but bounds
+    /// the whole primitives again instead of their chopped parts.
+    pub fn new_sbvh(model: &Mesh, max_prims: u32, alpha: f32) -> Self {
+        let now = Instant::now();
+        let references = model.bboxes();
+        let bbox = bounds(&references);
+        let builder = SbvhBuilder {
+            mesh: model,
+            max_prims: max_prims.max(1) as usize,
+            min_overlap: alpha * bbox.area(),
+        };
+        let (root, references, total_nodes) = builder.build(references, bbox, 0);
+        // the build is top-down, there are no treelets
+        let time = BvhConstructionTime {
+            upper_tree: now.elapsed(),
+            ..Default::default()
+        };
+        Self::from_build(root, references, total_nodes, time)
+    }
+}
+
+struct SbvhBuilder<'a> {
+    mesh: &'a Mesh,
+    max_prims: usize,
+    /// `alpha` times the area of the root
+    min_overlap: f32,
+}
+
+/// References in a bin, the spatial splits count the references starting
+/// in a bin as `entries` and those ending in it as `exits`
+#[derive(Debug, Copy, Clone)]
+struct Bin {
+    bounds: Bbox,
+    entries: usize,
+    exits: usize,
+}
+
+impl Default for Bin {
+    fn default() -> Self {
+        Self {
+            bounds: Bbox::new(),
+            entries: 0,
+            exits: 0,
+        }
+    }
+}
+
+/// Cheapest split of a node found so far with the bounds of its children
+struct SplitCandidate {
+    /// SAH cost without the constant factors, the areas weighted by the reference counts
+    cost: f32,
+    axis: u32,
+    kind: SplitKind,
+    left: Bbox,
+    right: Bbox,
+}
+
+enum SplitKind {
+    /// References with their centroid in a bin below `bin` go left
+    Object { bin: usize, centroid_bounds: Bbox },
+    /// References crossing `position` are chopped in two
+    Spatial {
+        position: f32,
+        left_count: usize,
+        right_count: usize,
+    },
+}
+
+impl SbvhBuilder<'_> {
+    /// Subtree over `references` bounded by `bbox`, its leaves point into the
+    /// returned references, which can hold chopped parts of the same primitive
+    fn build(&self, references: Vec<AccObj>, bbox: Bbox, depth: u32) -> (BvhBuildNode, Vec<AccObj>, u32) {
+        if references.len() <= self.max_prims {
+            return (BvhBuildNode::new_leaf(0, references.len() as u32, bbox), references, 1);
+        }
+
+        let parallel = references.len() > PARALLEL_REFERENCES;
+        let split = self.find_split(&references, &bbox, depth);
+        let (axis, left, right) = match split {
+            Some(split) => {
+                let (left, right) = self.partition(references, &split);
+                if left.is_empty() || right.is_empty() {
+                    // unsplitting moved every reference to one side
+                    median_split(left.into_iter().chain(right).collect())
+                } else {
+                    (split.axis, left, right)
+                }
+            }
+            // the centroids coincide and there is nothing to chop
+            None => median_split(references),
+        };
+
+        let (left_bbox, right_bbox) = (bounds(&left), bounds(&right));
+        let ((left, mut references, left_nodes), (mut right, right_references, right_nodes)) = if parallel {
+            rayon::join(
+                || self.build(left, left_bbox, depth + 1),
+                || self.build(right, right_bbox, depth + 1),
+            )
+        } else {
+            (
+                self.build(left, left_bbox, depth + 1),
+                self.build(right, right_bbox, depth + 1),
+            )
+        };
+        right.offset_primitives(references.len() as u32);
+        references.extend(right_references);
+
+        (
+            BvhBuildNode::new_internal(axis.into(), left, right),
+            references,
+            left_nodes + right_nodes + 1,
+        )
+    }
+
+    /// The object split, or a spatial split if the children of the object split
+    /// overlap too much and chopping the references is cheaper by the SAH
+    fn find_split(&self, references: &[AccObj], bbox: &Bbox, depth: u32) -> Option<SplitCandidate> {
+        let object = object_split(references);
+        let overlap = object
+            .as_ref()
+            .and_then(|split| split.left.intersection(&split.right))
+            .map_or(0.0, |overlap| overlap.area());
+        if depth >= MAX_SPATIAL_DEPTH || (object.is_some() && overlap <= self.min_overlap) {
+            return object;
+        }
+        match (object, self.spatial_split(references, bbox)) {
+            (Some(object), Some(spatial)) if spatial.cost < object.cost => Some(spatial),
+            (object, spatial) => object.or(spatial),
+        }
+    }
+
+    /// Chops the references into bins along every axis of `bbox` and sweeps the planes between them
+    fn spatial_split(&self, references: &[AccObj], bbox: &Bbox) -> Option<SplitCandidate> {
+        let mut best = None;
+        for axis in 0..3 {
+            let (origin, extent) = (bbox.min[axis], bbox.extent_dim(axis));
+            if extent <= 0.0 {
+                continue;
+            }
+            let width = extent / BINS as f32;
+            let bin_of = |x: f32| (((x - origin) / width) as usize).min(BINS - 1);
+            let mut bins = [Bin::default(); BINS];
+            for reference in references {
+                let (first, last) = (bin_of(reference.bbox.min[axis]), bin_of(reference.bbox.max[axis]));
+                bins[first].entries += 1;
+                bins[last].exits += 1;
+                let mut rest = Some(*reference);
+                // chopped at the planes after the bins it spans
+                for (plane, bin) in (first + 1..).zip(&mut bins[first..last]) {
+                    let Some(piece) = rest else {
+                        break;
+                    };
+                    let (left, right) = self.split_reference(&piece, axis, origin + width * plane as f32);
+                    if let Some(left) = left {
+                        bin.bounds.include_bbox(&left.bbox);
+                    }
+                    rest = right;
+                }
+                if let Some(rest) = rest {
+                    bins[last].bounds.include_bbox(&rest.bbox);
+                }
+            }
+            best = sweep(&bins, axis, best, |plane, left_count, right_count| SplitKind::Spatial {
+                position: origin + width * plane as f32,
+                left_count,
+                right_count,
+            });
+        }
+        best
+    }
+
+    /// Sorts the references into the children of `split`. A reference crossing a spatial
+    /// split is kept whole on one side instead when that is cheaper than duplicating it.
+    fn partition(&self, references: Vec<AccObj>, split: &SplitCandidate) -> (Vec<AccObj>, Vec<AccObj>) {
+        let axis = split.axis;
+        match split.kind {
+            SplitKind::Object { bin, centroid_bounds } => references
+                .into_iter()
+                .partition(|reference| object_bin(&centroid_bounds, axis, reference.bbox.center()[axis]) < bin),
+            SplitKind::Spatial {
+                position,
+                mut left_count,
+                mut right_count,
+            } => {
+                let (mut left_bounds, mut right_bounds) = (split.left, split.right);
+                let (mut left, mut right) = (vec![], vec![]);
+                for reference in references {
+                    if reference.bbox.max[axis] <= position {
+                        left.push(reference);
+                    } else if reference.bbox.min[axis] >= position {
+                        right.push(reference);
+                    } else {
+                        let with = |bounds: &Bbox| {
+                            let mut bounds = *bounds;
+                            bounds.include_bbox(&reference.bbox);
+                            bounds.area()
+                        };
+                        let split_cost = left_bounds.area() * left_count as f32 + right_bounds.area() * right_count as f32;
+                        let left_cost = with(&left_bounds) * left_count as f32
+                            + right_bounds.area() * right_count.saturating_sub(1) as f32;
+                        let right_cost = left_bounds.area() * left_count.saturating_sub(1) as f32
+                            + with(&right_bounds) * right_count as f32;
+                        if left_cost < split_cost && left_cost <= right_cost {
+                            left_bounds.include_bbox(&reference.bbox);
+                            right_count = right_count.saturating_sub(1);
+                            left.push(reference);
+                        } else if right_cost < split_cost {
+                            right_bounds.include_bbox(&reference.bbox);
+                            left_count = left_count.saturating_sub(1);
+                            right.push(reference);
+                        } else {
+                            let (left_part, right_part) = self.split_reference(&reference, axis, position);
+                            left.extend(left_part);
+                            right.extend(right_part);
+                        }
+                    }
+                }
+                (left, right)
+            }
+        }
+    }
+
+    /// Chops a reference at `position` on `axis`, the parts of a triangle are bounded
+    /// by the polygon clipped to either side, `None` for a side it does not reach
+    fn split_reference(&self, reference: &AccObj, axis: u32, position: f32) -> (Option<AccObj>, Option<AccObj>) {
+        let (mut left, mut right) = match reference.kind {
+            PrimitiveKind::Triangle => {
+                let triangle = self.mesh.indices[reference.idx as usize];
+                let vertices = [triangle.0, triangle.1, triangle.2].map(|v| self.mesh.vertices[v as usize].xyz());
+                let (mut left, mut right) = (Bbox::new(), Bbox::new());
+                for (i, &a) in vertices.iter().enumerate() {
+                    let b = vertices[(i + 1) % 3];
+                    if a[axis] <= position {
+                        left.include_vertex(a);
+                    }
+                    if a[axis] >= position {
+                        right.include_vertex(a);
+                    }
+                    if (a[axis] < position && position < b[axis]) || (b[axis] < position && position < a[axis]) {
+                        let mut crossing = a + (b - a) * ((position - a[axis]) / (b[axis] - a[axis]));
+                        crossing[axis] = position;
+                        left.include_vertex(crossing);
+                        right.include_vertex(crossing);
+                    }
+                }
+                (left, right)
+            }
+            // the box of an analytic primitive is only cut
+            PrimitiveKind::Analytic => (reference.bbox, reference.bbox),
+        };
+        left.max[axis] = left.max[axis].min(position);
+        right.min[axis] = right.min[axis].max(position);
+        // a reference is already clipped to the nodes it was chopped for
+        let part = |bbox: Bbox| bbox.intersection(&reference.bbox).map(|bbox| AccObj { bbox, ..*reference });
+        (part(left), part(right))
+    }
+}
+
+/// Bins the centroids of the references along every axis and sweeps the planes between them
+fn object_split(references: &[AccObj]) -> Option<SplitCandidate> {
+    let centroid_bounds = references.iter().fold(Bbox::new(), |mut bounds, reference| {
+        bounds.include_vertex(reference.bbox.center());
+        bounds
+    });
+    let mut best = None;
+    for axis in 0..3 {
+        if centroid_bounds.extent_dim(axis) <= 0.0 {
+            continue;
+        }
+        let mut bins = [Bin::default(); BINS];
+        for reference in references {
+            let bin = &mut bins[object_bin(&centroid_bounds, axis, reference.bbox.center()[axis])];
+            bin.bounds.include_bbox(&reference.bbox);
+            bin.entries += 1;
+            bin.exits += 1;
+        }
+        best = sweep(&bins, axis, best, |bin, _, _| SplitKind::Object { bin, centroid_bounds });
+    }
+    best
+}
+
+fn object_bin(centroid_bounds: &Bbox, axis: u32, centroid: f32) -> usize {
+    let offset = (centroid - centroid_bounds.min[axis]) / centroid_bounds.extent_dim(axis);
+    ((offset * BINS as f32) as usize).min(BINS - 1)
+}
+
+/// The cheapest plane between the bins by the SAH if it beats `best`,
+/// `kind` describes the plane before a bin from the counts on either side
+fn sweep(
+    bins: &[Bin; BINS],
+    axis: u32,
+    best: Option<SplitCandidate>,
+    kind: impl Fn(usize, usize, usize) -> SplitKind,
+) -> Option<SplitCandidate> {
+    // bounds and counts right of every plane
+    let mut right = [(Bbox::new(), 0); BINS];
+    for plane in (1..BINS).rev() {
+        let (mut bounds, count) = right.get(plane + 1).copied().unwrap_or((Bbox::new(), 0));
+        bounds.include_bbox(&bins[plane].bounds);
+        right[plane] = (bounds, count + bins[plane].exits);
+    }
+
+    let mut best = best;
+    let (mut left_bounds, mut left_count) = (Bbox::new(), 0);
+    for plane in 1..BINS {
+        left_bounds.include_bbox(&bins[plane - 1].bounds);
+        left_count += bins[plane - 1].entries;
+        let (right_bounds, right_count) = right[plane];
+        if left_count == 0 || right_count == 0 {
+            continue;
+        }
+        let cost = left_bounds.area() * left_count as f32 + right_bounds.area() * right_count as f32;
+        if best.as_ref().is_none_or(|best| cost < best.cost) {
+            best = Some(SplitCandidate {
+                cost,
+                axis,
+                kind: kind(plane, left_count, right_count),
+                left: left_bounds,
+                right: right_bounds,
+            });
+        }
+    }
+    best
+}
+
+/// Halves the references along the longest axis of their centroids, when no split separates them
+fn median_split(mut references: Vec<AccObj>) -> (u32, Vec<AccObj>, Vec<AccObj>) {
+    let axis = references
+        .iter()
+        .fold(Bbox::new(), |mut bounds, reference| {
+            bounds.include_vertex(reference.bbox.center());
+            bounds
+        })
+        .longest_axis();
+    let mid = references.len() / 2;
+    references.select_nth_unstable_by(mid, |a, b| a.bbox.center()[axis].total_cmp(&b.bbox.center()[axis]));
+    let right = references.split_off(mid);
+    (axis, references, right)
+}
+
+fn bounds(references: &[AccObj]) -> Bbox {
+    references.iter().fold(Bbox::new(), |mut bounds, reference| {
+        bounds.include_bbox(&reference.bbox);
+        bounds
+    })
+}
+
+#[cfg(test)]
+mod sbvh_test {
+    use super::*;
+    use crate::data_structures::bvh_traversal::{brute_force, random_rays, traverse_stack};
+
+    #[test]
+    fn spatial_splits_find_the_closest_hit() {
+        for path in ["res/models/teapot.obj", "res/models/CornellBoxWithBlocks.obj"] {
+            let model = Mesh::from_obj(path).expect("Failed to load model");
+            let sbvh = Bvh::new_sbvh(&model, 4, SBVH_ALPHA);
+            let nodes = sbvh.flatten();
+            let triangles = sbvh.triangles();
+            let mut referenced = vec![false; model.indices.len()];
+            triangles.iter().for_each(|&triangle| referenced[triangle as usize] = true);
+            assert!(referenced.iter().all(|&referenced| referenced), "{path}");
+
+            for ray in random_rays(&sbvh.bbox(), 500, 5) {
+                let closest = brute_force(&model, &ray);
+                assert_eq!(traverse_stack(&nodes, &triangles, &model, &ray, true, false).distance, closest, "{path}");
+            }
+        }
+    }
+
+    #[test]
+    fn spatial_splits_chop_the_teapot() {
+        let model = Mesh::from_obj("res/models/teapot.obj").expect("Failed to load model");
+        let sbvh = Bvh::new_sbvh(&model, 4, SBVH_ALPHA);
+        assert!(sbvh.triangles().len() > model.indices.len());
+        let hlbvh = Bvh::new(&model, 4, false);
+        assert!(sbvh.sah_cost() < hlbvh.sah_cost(), "{} >= {}", sbvh.sah_cost(), hlbvh.sah_cost());
+
+        // without spatial splits every triangle is referenced once
+        let object_splits = Bvh::new_sbvh(&model, 4, f32::INFINITY);
+        assert_eq!(object_splits.triangles().len(), model.indices.len());
+    }
+}
@@ -50,6 +50,12 @@ where T: Mul<Output = T> + Add<Output = T>
     v1.0 * v2.0 + v1.1 * v2.1 + v1.2 * v2.2
 }
 
+pub fn cross<T>(v1: Vec3<T>, v2: Vec3<T>) -> Vec3<T>
+where T: Copy + Mul<Output = T> + Sub<Output = T>
+{
+    Vec3(v1.1 * v2.2 - v1.2 * v2.1, v1.2 * v2.0 - v1.0 * v2.2, v1.0 * v2.1 - v1.1 * v2.0)
+}
+
 /// Vec3 Methods
 ///
 
@@ -0,0 +1,218 @@
+/// Edge-avoiding à-trous wavelet denoiser
+/// [Dammertz et al., Edge-Avoiding À-Trous Wavelet Transform
+///  for fast Global Illumination Filtering, HPG 2010]
+///
+/// Each iteration applies a 5x5 B3 spline kernel with holes, the distance
+/// between taps doubles every iteration. Taps are weighted down when their
+/// color, albedo, normal or depth differs from the center pixel.
+/// This is the CPU version of res/shaders/atrous.wgsl and works on float
+/// images, e.g. the AOVs read back from the GPU.
+
+use rayon::prelude::*;
+
+pub const MAX_ITERATIONS: u32 = 8;
+
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Parameters of the edge-stopping functions. A sigma of zero
+/// or less disables the corresponding guide.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DenoiseParams {
+    pub iterations: u32,
+    /// Halved every iteration as the color gets smoother
+    pub sigma_color: f32,
+    pub sigma_albedo: f32,
+    pub sigma_normal: f32,
+    /// Relative to the depth of the center pixel
+    pub sigma_depth: f32,
+}
+
+impl Default for DenoiseParams {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            sigma_color: 1.0,
+            sigma_albedo: 0.1,
+            sigma_normal: 0.3,
+            sigma_depth: 0.1,
+        }
+    }
+}
+
+/// Row major image with N float channels per pixel
+#[derive(Clone, Debug, PartialEq)]
+pub struct FloatImage<const N: usize> {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[f32; N]>,
+}
+
+impl<const N: usize> FloatImage<N> {
+    pub fn new(width: usize, height: usize, pixels: Vec<[f32; N]>) -> Self {
+        assert_eq!(width * height, pixels.len(), "Image size does not match the number of pixels");
+        Self { width, height, pixels }
+    }
+
+    pub fn filled(width: usize, height: usize, value: [f32; N]) -> Self {
+        Self::new(width, height, vec![value; width * height])
+    }
+}
+
+/// Feature buffers guiding the filter, all of the same size as the color
+pub struct Guides<'a> {
+    pub albedo: &'a FloatImage<3>,
+    pub normal: &'a FloatImage<3>,
+    pub depth: &'a FloatImage<1>,
+}
+
+pub fn atrous(color: &FloatImage<3>, guides: &Guides, params: &DenoiseParams) -> FloatImage<3> {
+    for guide in [
+        (guides.albedo.width, guides.albedo.height),
+        (guides.normal.width, guides.normal.height),
+        (guides.depth.width, guides.depth.height),
+    ] {
+        assert_eq!((color.width, color.height), guide, "Guides must match the color size");
+    }
+
+    (0..params.iterations.min(MAX_ITERATIONS)).fold(color.clone(), |image, iteration| {
+        atrous_iteration(&image, guides, params, iteration)
+    })
+}
+
+fn atrous_iteration(
+    color: &FloatImage<3>,
+    guides: &Guides,
+    params: &DenoiseParams,
+    iteration: u32,
+) -> FloatImage<3> {
+    let (width, height) = (color.width as i64, color.height as i64);
+    let step = 1i64 << iteration;
+    let sigma_color = params.sigma_color * 0.5f32.powi(iteration as i32);
+
+    let pixels = (0..color.pixels.len())
+        .into_par_iter()
+        .map(|idx| {
+            let (x, y) = ((idx as i64) % width, (idx as i64) / width);
+            let color_p = color.pixels[idx];
+            let albedo_p = guides.albedo.pixels[idx];
+            let normal_p = guides.normal.pixels[idx];
+            let depth_p = guides.depth.pixels[idx][0];
+
+            let mut sum = [0.0f32; 3];
+            let mut weight_sum = 0.0f32;
+            for (j, kernel_y) in KERNEL.iter().enumerate() {
+                for (i, kernel_x) in KERNEL.iter().enumerate() {
+                    let qx = x + (i as i64 - 2) * step;
+                    let qy = y + (j as i64 - 2) * step;
+                    if qx < 0 || qy < 0 || qx >= width || qy >= height {
+                        continue;
+                    }
+                    let q = (qy * width + qx) as usize;
+                    let color_q = color.pixels[q];
+                    let depth_q = guides.depth.pixels[q][0];
+                    let relative_depth = (depth_p - depth_q).abs() / depth_p.abs().max(1e-4);
+
+                    let weight = kernel_x
+                        * kernel_y
+                        * edge_stop(distance_squared(&color_p, &color_q), sigma_color)
+                        * edge_stop(distance_squared(&albedo_p, &guides.albedo.pixels[q]), params.sigma_albedo)
+                        * edge_stop(distance_squared(&normal_p, &guides.normal.pixels[q]), params.sigma_normal)
+                        * edge_stop(relative_depth * relative_depth, params.sigma_depth);
+
+                    sum.iter_mut().zip(color_q).for_each(|(s, c)| *s += weight * c);
+                    weight_sum += weight;
+                }
+            }
+            // the center tap always has a weight of at least 9/64
+            sum.map(|s| s / weight_sum)
+        })
+        .collect();
+
+    FloatImage::new(color.width, color.height, pixels)
+}
+
+fn distance_squared(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+}
+
+fn edge_stop(distance_squared: f32, sigma: f32) -> f32 {
+    if sigma > 0.0 {
+        (-distance_squared / (sigma * sigma)).exp()
+    } else {
+        1.0
+    }
+}
+
+#[cfg(test)]
+mod denoise_test {
+    use super::*;
+    use rand::Rng;
+    use rand::SeedableRng;
+    use rand_pcg::Lcg64Xsh32;
+
+    fn flat_guides(width: usize, height: usize) -> (FloatImage<3>, FloatImage<3>, FloatImage<1>) {
+        (
+            FloatImage::filled(width, height, [0.5; 3]),
+            FloatImage::filled(width, height, [0.0, 1.0, 0.0]),
+            FloatImage::filled(width, height, [1.0]),
+        )
+    }
+
+    fn variance(image: &FloatImage<3>) -> f32 {
+        let n = image.pixels.len() as f32;
+        let mean = image.pixels.iter().map(|p| p[0]).sum::<f32>() / n;
+        image.pixels.iter().map(|p| (p[0] - mean) * (p[0] - mean)).sum::<f32>() / n
+    }
+
+    #[test]
+    fn constant_image_is_unchanged() {
+        let (albedo, normal, depth) = flat_guides(17, 9);
+        let guides = Guides { albedo: &albedo, normal: &normal, depth: &depth };
+        let color = FloatImage::filled(17, 9, [0.25, 1.5, 3.0]);
+        let result = atrous(&color, &guides, &DenoiseParams::default());
+        for pixel in result.pixels {
+            for (c, expected) in pixel.iter().zip([0.25, 1.5, 3.0]) {
+                assert!((c - expected).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn noise_is_reduced() {
+        let mut rng = Lcg64Xsh32::seed_from_u64(42);
+        let (albedo, normal, depth) = flat_guides(32, 32);
+        let guides = Guides { albedo: &albedo, normal: &normal, depth: &depth };
+        let pixels = (0..32 * 32)
+            .map(|_| [rng.gen_range(0.0..1.0); 3])
+            .collect();
+        let color = FloatImage::new(32, 32, pixels);
+        let result = atrous(&color, &guides, &DenoiseParams::default());
+        assert!(variance(&result) < 0.1 * variance(&color));
+    }
+
+    #[test]
+    fn normal_edges_are_kept() {
+        let (width, height) = (16, 8);
+        let (albedo, _, depth) = flat_guides(width, height);
+        let left = |idx: usize| idx % width < width / 2;
+        let normal = FloatImage::new(
+            width,
+            height,
+            (0..width * height)
+                .map(|idx| if left(idx) { [1.0, 0.0, 0.0] } else { [0.0, 1.0, 0.0] })
+                .collect(),
+        );
+        let color = FloatImage::new(
+            width,
+            height,
+            (0..width * height)
+                .map(|idx| if left(idx) { [0.0; 3] } else { [1.0; 3] })
+                .collect(),
+        );
+        let guides = Guides { albedo: &albedo, normal: &normal, depth: &depth };
+        let result = atrous(&color, &guides, &DenoiseParams::default());
+        for (denoised, original) in result.pixels.iter().zip(&color.pixels) {
+            assert!((denoised[0] - original[0]).abs() < 1e-4);
+        }
+    }
+}
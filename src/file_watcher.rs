@@ -0,0 +1,86 @@
+/// Watches a set of files for changes by polling their modification times.
+/// Used to hot reload shaders, a handful of files does not need an OS watcher.
+
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Saving a file every frame is not a thing, so the file system is left alone in between
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub struct FileWatcher {
+    /// Files and their modification time when last polled, `None` if they could not be read
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    last_poll: Instant,
+}
+
+impl FileWatcher {
+    pub fn new(files: impl IntoIterator<Item = PathBuf>) -> Self {
+        let mut watcher = Self {
+            files: vec![],
+            last_poll: Instant::now(),
+        };
+        watcher.watch(files);
+        watcher
+    }
+
+    /// Replaces the watched files, their current state is the new reference
+    pub fn watch(&mut self, files: impl IntoIterator<Item = PathBuf>) {
+        self.files = files
+            .into_iter()
+            .map(|file| {
+                let modified = Self::modified(&file);
+                (file, modified)
+            })
+            .collect();
+    }
+
+    /// Whether any file was modified, created or removed since the last poll
+    pub fn changed(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.poll()
+    }
+
+    fn poll(&mut self) -> bool {
+        self.last_poll = Instant::now();
+        self.files
+            .iter_mut()
+            .map(|(file, modified)| {
+                let current = Self::modified(file);
+                let changed = current != *modified;
+                *modified = current;
+                changed
+            })
+            // every file has to be polled, so no short circuiting
+            .fold(false, |acc, elem| acc || elem)
+    }
+
+    fn modified(file: &PathBuf) -> Option<SystemTime> {
+        std::fs::metadata(file).and_then(|metadata| metadata.modified()).ok()
+    }
+}
+
+#[cfg(test)]
+mod file_watcher_test {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn modifications_are_reported_once() {
+        let path = std::env::temp_dir().join("file_watcher_test.wgsl");
+        let file = File::create(&path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+        let mut watcher = FileWatcher::new([path.clone(), path.with_extension("missing")]);
+        assert!(!watcher.poll());
+
+        file.set_modified(SystemTime::now()).unwrap();
+        assert!(watcher.poll());
+        assert!(!watcher.poll());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(watcher.poll());
+    }
+}
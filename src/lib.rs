@@ -3,15 +3,20 @@
 /// https://github.com/absorensen/the-guide/tree/main/m2_concurrency/code/egui-winit-wgpu-template
 /// Apache License 2.0
 
+pub mod animation;
 mod bindings;
 mod camera;
 mod command;
 mod control_panel;
+pub mod denoise;
 pub mod data_structures;
+mod file_watcher;
 mod gpu_handles;
 pub mod mesh;
+pub mod primitives;
 mod render_state;
 mod scenes;
+mod tiles;
 mod tools;
 
 use std::{path::Path, sync::Arc, thread, time::Instant};
@@ -26,8 +31,9 @@ use crate::{
 Boilerplate code from https://sotrh.github.io/learn-wgpu/
 */
 
-use command::Command;
+use command::{Command, Status};
 use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use file_watcher::FileWatcher;
 use gpu_handles::GPUHandles;
 use tools::RenderStats;
 #[cfg(target_arch = "wasm32")]
@@ -36,10 +42,14 @@ use wasm_bindgen::prelude::*;
 use winit::{
     dpi::PhysicalSize,
     event::*,
-    event_loop::{ControlFlow, EventLoop},
+    event_loop::{ControlFlow, EventLoop, EventLoopProxy},
     window::WindowId,
 };
 
+/// Reading back the number of traced pixels waits for the GPU,
+/// so convergence is only checked every few iterations
+const CONVERGENCE_CHECK_INTERVAL: u32 = 8;
+
 // Simple wrapper to handle different window ids.
 struct WindowSelector {
     control_panel_id: u64,
@@ -75,6 +85,7 @@ fn main_thread(
     window_selector: WindowSelector,
     mut control_panel: ControlPanel,
     transmitter: Sender<Command>,
+    status_receiver: Receiver<Status>,
 ) {
     let start_time: Instant = Instant::now();
 
@@ -202,6 +213,21 @@ fn main_thread(
                 }
             }
 
+            // The rendering thread reported something to show in the control panel
+            Event::UserEvent(()) => {
+                status_receiver
+                    .try_iter()
+                    .for_each(|status| control_panel.update_status(status));
+                control_panel.redraw(
+                    transmitter,
+                    &mut gui_has_focus,
+                    &mut redraw_gui,
+                    &start_time,
+                    &gpu_handles.device,
+                    &gpu_handles.queue,
+                );
+            }
+
             // This event happens once all the other events have been cleared.
             // The additional redraws are for when a GUI element has focus and
             // needs to be constantly redrawn. It could for example be the
@@ -297,6 +323,9 @@ pub async fn run() {
     let mut render_state = RenderState::new(&event_loop, render_state_window, &scenes[0]).await;
 
     let (transmitter, receiver): (Sender<Command>, Receiver<Command>) = unbounded::<Command>();
+    let (status_transmitter, status_receiver): (Sender<Status>, Receiver<Status>) = unbounded::<Status>();
+    // wakes up the event loop, so the control panel shows the status right away
+    let event_loop_proxy = event_loop.create_proxy();
     // Create the window selector which will be used for
     // matching events to the relevant window.
     let window_selector: WindowSelector =
@@ -304,7 +333,9 @@ pub async fn run() {
 
     let _render_thread = thread::Builder::new()
         .name("Render Thread".into())
-        .spawn(move || rendering_thread(&mut render_state, receiver, scenes.clone()));
+        .spawn(move || {
+            rendering_thread(&mut render_state, receiver, status_transmitter, event_loop_proxy, scenes.clone())
+        });
 
     // Set initial state
     control_panel.force_send_all(&transmitter);
@@ -315,28 +346,47 @@ pub async fn run() {
         window_selector,
         control_panel,
         transmitter,
+        status_receiver,
     );
 }
 
 fn rendering_thread(
     render_state: &mut RenderState,
     receiver: Receiver<Command>,
+    status_transmitter: Sender<Status>,
+    event_loop_proxy: EventLoopProxy<()>,
     scenes: Arc<[SceneDescriptor]>,
 ) {
     let mut should_render = true;
     let mut progressive = false;
+    // every pixel reached the adaptive sampling threshold
+    let mut converged = false;
+    // the convergence last shown in the control panel
+    let mut converged_reported = false;
+    // presentation settings changed while no new frames are rendered
+    let mut present_pending = false;
 
     let mut render_statistics = RenderStats::new();
 
+    // saving the shader of the current scene or one of its includes reloads it
+    let mut shader_watcher = FileWatcher::new(render_state.shader_files().unwrap_or_default());
+    let send_status = |status: Status| {
+        status_transmitter.send(status).unwrap();
+        // the event loop is gone when shutting down
+        let _ = event_loop_proxy.send_event(());
+    };
+
     loop {
         let current_iter = render_state.uniform.get_iteration();
         let max_iter = render_state.uniform.max_iterations;
-        if should_render && (progressive && current_iter < max_iter) || (should_render && !progressive) {
+        if should_render && (progressive && current_iter < max_iter && !converged) || (should_render && !progressive) {
             render_statistics.begin_capture();
             thread::scope(|s| {
                 s.spawn(|| {
+                    // with tiled rendering a sample takes several submissions
+                    let mut sample_done = false;
                     match render_state.render() {
-                        Ok(_) => {}
+                        Ok(done) => sample_done = done,
                         // Reconfigure the surface if lost
                         Err(wgpu::SurfaceError::Lost) => render_state.resize(render_state.size),
                         // The system is out of memory, we should probably quit
@@ -348,8 +398,13 @@ fn rendering_thread(
                     }
                     render_statistics.end_capture();
 
-                    if progressive {
+                    if progressive && sample_done {
                         println!("Current iter: {}/{}", current_iter, max_iter);
+                        // no MSRV is declared, is_multiple_of needs Rust 1.87
+                        #[allow(clippy::manual_is_multiple_of)]
+                        if current_iter % CONVERGENCE_CHECK_INTERVAL == 0 && render_state.converged() {
+                            converged = true;
+                        }
                         render_state.uniform.increase_iteration();
                     }
 
@@ -360,6 +415,32 @@ fn rendering_thread(
                 });
             });
             render_state.update();
+            present_pending = false;
+        } else if present_pending {
+            match render_state.present() {
+                Ok(_) => present_pending = false,
+                Err(wgpu::SurfaceError::Lost) => render_state.resize(render_state.size),
+                Err(wgpu::SurfaceError::OutOfMemory) => panic!("out of memory"),
+                Err(_) => {}
+            }
+        }
+
+        if shader_watcher.changed() {
+            let path = render_state.shader_path().to_path_buf();
+            match load_shader(render_state, &path) {
+                Ok(_) => {
+                    // includes may have been added or removed
+                    shader_watcher.watch(render_state.shader_files().unwrap_or_default());
+                    render_state.uniform.reset_iteration();
+                    converged = false;
+                    eprintln!("Reloaded shader {}", path.display());
+                    send_status(Status::ShaderLoaded { path });
+                }
+                Err(err) => {
+                    eprintln!("{err}");
+                    send_status(Status::ShaderError { path, message: err.to_string() });
+                }
+            }
         }
 
         loop {
@@ -372,6 +453,7 @@ fn rendering_thread(
                     match command {
                         Command::Resize { new_size } => {
                             render_state.resize(new_size);
+                            present_pending = true;
                         }
                         Command::KeyEvent {
                             key,
@@ -379,7 +461,11 @@ fn rendering_thread(
                         } => match key {
                             VirtualKeyCode::Space => {
                                 match render_state.load_scene(&scenes[0]) {
-                                    Ok(_) => eprintln!("Successfully loaded default scene."),
+                                    Ok(_) => {
+                                        shader_watcher.watch(render_state.shader_files().unwrap_or_default());
+                                        send_status(Status::ShaderLoaded { path: scenes[0].shader.clone() });
+                                        eprintln!("Successfully loaded default scene.")
+                                    }
                                     Err(err) => eprintln!("Failed to load default scene: {}", err),
                                 }
                             }
@@ -428,6 +514,8 @@ fn rendering_thread(
                                 command::DisplayMode::Exact => {
                                     (PhysicalSize::new(resolution.0, resolution.1), resolution)
                                 }
+                                // the window keeps its size, the image is scaled to it
+                                command::DisplayMode::Letterbox => (render_state.window().inner_size(), resolution),
                                 command::DisplayMode::Stretch => (RENDER_WINDOW_SIZE, resolution),
                                 command::DisplayMode::FitAuto => {
                                     let max_aspect_ratio = RENDER_WINDOW_SIZE.width as f32
@@ -460,18 +548,49 @@ fn rendering_thread(
 
                             render_state.window().set_inner_size(new_window_size);
                         }
+                        Command::SetPresentation { filter, zoom, pan, aov } => {
+                            render_state.update_presentation(filter, zoom, pan, aov);
+                            present_pending = true;
+                        }
+                        Command::SetAdaptiveSampling { enabled, threshold, min_samples } => {
+                            render_state.update_adaptive_sampling(enabled, threshold, min_samples);
+                            // a lower threshold lets converged pixels continue
+                            converged = false;
+                        }
+                        Command::SetTiling { enabled, tile_size, tiles_per_submission, show_progress } => {
+                            let tile_size = if enabled { tile_size } else { 0 };
+                            render_state.update_tiling(tile_size, tiles_per_submission, show_progress);
+                            present_pending = true;
+                        }
+                        Command::SetDenoise { enabled, params } => {
+                            render_state.update_denoise(enabled, params);
+                            present_pending = true;
+                        }
+                        Command::SaveImages { prefix } => match render_state.save_images(&prefix) {
+                            Ok(paths) => eprintln!("Saved images: {paths:?}"),
+                            Err(err) => eprintln!("Could not save images: {err}"),
+                        },
+                        Command::SetToneMapping { operator, exposure, white_point } => {
+                            // only affects presentation, the accumulation is kept
+                            render_state.update_tone_mapping(operator, exposure, white_point);
+                            present_pending = true;
+                        }
                         Command::LoadScene { idx } => match render_state.load_scene(&scenes[idx]) {
                             Ok(_) => 
                             {
                                 render_statistics.reset();
                                 render_state.uniform.reset_iteration();
                                 render_state.uniform.max_iterations = max_iter;
+                                converged = false;
+                                shader_watcher.watch(render_state.shader_files().unwrap_or_default());
+                                send_status(Status::ShaderLoaded { path: scenes[idx].shader.clone() });
                                 eprintln!("Successfully loaded new scene: {:?}", scenes[idx])
                             }
                             Err(err) => eprintln!("{err}"),
                         },
                         Command::SetSamples { samples, enabled } => {
                             progressive = enabled;
+                            converged = false;
                             if enabled {
                                 render_state.uniform.max_iterations = samples;
                             } else {
@@ -486,20 +605,83 @@ fn rendering_thread(
                 }
             }
         }
+
+        if converged != converged_reported {
+            let iterations = converged.then(|| render_state.uniform.get_iteration());
+            send_status(Status::Convergence { iterations });
+            converged_reported = converged;
+        }
+    }
+}
+
+/// Compiles the shader and swaps it into the render pipeline, the old pipeline is kept on errors
+fn load_shader(render_state: &mut RenderState, shader_path: &Path) -> anyhow::Result<()> {
+    let (shader_module, written) = pollster::block_on(render_state.create_shader_module_from_file(shader_path))?;
+    render_state.recreate_render_pipeline(&shader_module, &written)
+}
+
+/// Validates the shader of every scene and of the presentation pass without a GPU, see
+/// `cargo run --bin validate_shaders`. All failing shaders are reported at once, each
+/// with the file and line of the error.
+pub fn validate_shaders() -> anyhow::Result<()> {
+    let failures = get_scenes()
+        .iter()
+        .filter_map(|scene| {
+            RenderState::validate_scene_shader(scene)
+                .err()
+                .map(|err| format!("{} ({}):\n{err:#}", scene.name, scene.shader.display()))
+        })
+        .chain(RenderState::validate_present_shader().err().map(|err| format!("Presentation:\n{err:#}")))
+        .collect::<Vec<_>>();
+    match failures.is_empty() {
+        true => Ok(()),
+        false => Err(anyhow::anyhow!("{} invalid scene shader(s)\n\n{}", failures.len(), failures.join("\n\n"))),
     }
 }
 
-#[allow(dead_code)]
-fn load_shader(render_state: &mut RenderState, shader_path: &str) {
-    let shader_module =
-        pollster::block_on(render_state.create_shader_module_from_file(Path::new(shader_path)));
-    match shader_module {
-        Ok(module) => {
-            render_state.recreate_render_pipeline(&module);
-            eprintln!("Successfully loaded shader.")
+/// Measures the CPU time per frame saved by splitting the bindings of the scene shader
+/// into bind groups by update frequency, see `cargo run --release --bin bind_groups`.
+/// Scenes whose model is missing or that can not be set up on the adapter are skipped.
+pub fn benchmark_bind_groups(frames: u32) -> anyhow::Result<()> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+        .ok_or_else(|| anyhow::anyhow!("No graphics adapter found"))?;
+    let (device, queue) = pollster::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            features: wgpu::Features::empty(),
+            limits: adapter.limits(),
+            label: None,
+        },
+        None,
+    ))?;
+    println!("Benchmarking with {frames} frames on {}.\n", adapter.get_info().name);
+
+    for scene in get_scenes().iter() {
+        if scene.model_paths().any(|model| !model.exists()) {
+            println!("{}: skipped, the model is missing", scene.name);
+            continue;
         }
-        Err(err) => {
-            eprintln!("{err}")
+        match pollster::block_on(RenderState::benchmark_bind_groups(&device, &queue, scene, frames)) {
+            Ok((single_group, split_groups)) => println!(
+                "{}: {single_group:?} -> {split_groups:?}, saves {:?} per frame",
+                scene.name,
+                single_group.saturating_sub(split_groups),
+            ),
+            // e.g. the adapter does not support what the shader needs
+            Err(err) => println!("{}: skipped, {err:#}", scene.name),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod shader_test {
+    use super::*;
+
+    #[test]
+    fn scene_shaders_are_valid() {
+        if let Err(err) = validate_shaders() {
+            panic!("{err}");
         }
     }
 }
@@ -1,12 +1,15 @@
 use std::{path::Path, io::BufRead};
 
+use cgmath::{InnerSpace, Matrix, Matrix4, Point3, SquareMatrix, Transform, Vector3};
+
 use crate::{
-    bindings::storage_mesh::StorageMeshGpu,
+    bindings::{storage_mesh::StorageMeshGpu, wgsl_struct::wgsl_struct},
     data_structures::{
         bbox::Bbox,
-        bsp_tree::BspTree,
-        vector::{vec3f32, Vec4f32, Vec4u32, vec4u32, vec4f32}, hlbvh::{Bvh, self}, accobj::AccObj,
+        bsp_tree::{BspParams, BspTree},
+        vector::{vec3f32, Vec3f32, Vec4f32, Vec4u32, vec4u32, vec4f32}, hlbvh::{Bvh, self}, accobj::{AccObj, PrimitiveKind}, sbvh::SBVH_ALPHA,
     },
+    primitives::{Primitive, Shape},
 };
 
 #[repr(C, align(16))]
@@ -19,6 +22,33 @@ pub struct Material {
     _padding0: [u32; 3],
 }
 
+wgsl_struct!(Material as Material {
+    diffuse: vec4f,
+    ambient: vec4f,
+    specular: vec4f,
+    emissive: u32,
+});
+
+impl Material {
+    /// Lambertian surface of the given color
+    pub fn diffuse(color: Vec3f32) -> Self {
+        Self {
+            diffuse: color.vec4(),
+            ..Default::default()
+        }
+    }
+
+    /// Area light, its triangles are sampled by the path tracer
+    pub fn light(radiance: Vec3f32) -> Self {
+        Self {
+            diffuse: vec3f32(0.0, 0.0, 0.0).vec4(),
+            ambient: radiance.vec4(),
+            emissive: 1,
+            ..Default::default()
+        }
+    }
+}
+
 impl Default for Material {
     fn default() -> Self {
         Self { 
@@ -32,12 +62,15 @@ impl Default for Material {
 
 ///
 /// Mesh type containing vertices and indices in two vecs
+#[derive(Clone)]
 pub struct Mesh {
     pub vertices: Vec<Vec4f32>,
     pub normals: Vec<Vec4f32>,
     /// last index in the indices contains material type
     pub indices: Vec<Vec4u32>,
     pub materials: Vec<Material>,
+    /// Analytic primitives of the scene, they follow the triangles in `bboxes`
+    pub primitives: Vec<Primitive>,
 }
 
 impl std::fmt::Display for Mesh {
@@ -198,6 +231,7 @@ impl Mesh {
             normals: normals_flat,
             indices: indices_flat,
             materials,
+            primitives: vec![],
         })
     }
 
@@ -210,24 +244,37 @@ impl Mesh {
     }
 
     pub fn bboxes(&self) -> Vec<AccObj> {
-        self.indices
-            .iter()
-            .enumerate()
-            .map(|(idx, triangle)| {
-                AccObj::new(
-                    idx.try_into().unwrap(),
-                    Bbox::from_triangle(
-                        self.vertices[triangle.0 as usize].xyz().into(),
-                        self.vertices[triangle.1 as usize].xyz().into(),
-                        self.vertices[triangle.2 as usize].xyz().into(),
-                    ),
-                )
-            })
+        (0..self.indices.len())
+            .map(|idx| AccObj::new(idx.try_into().unwrap(), self.triangle_bbox(idx)))
+            .chain(self.primitives.iter().enumerate().filter_map(|(idx, primitive)| {
+                // planes are tested for every ray instead
+                primitive.bbox().map(|bbox| AccObj::analytic(idx as u32, bbox))
+            }))
             .collect()
     }
 
-    pub fn bsp_tree(&self) -> BspTree {
-        BspTree::new(self.bboxes(), 20, 4)
+    /// Bounds of the triangle `idx` of the index buffer
+    pub fn triangle_bbox(&self, idx: usize) -> Bbox {
+        let triangle = self.indices[idx];
+        Bbox::from_triangle(
+            self.vertices[triangle.0 as usize].xyz().into(),
+            self.vertices[triangle.1 as usize].xyz().into(),
+            self.vertices[triangle.2 as usize].xyz().into(),
+        )
+    }
+
+    /// Current bounds of the primitive `accobj` was created for by `bboxes`
+    pub fn primitive_bbox(&self, accobj: &AccObj) -> Bbox {
+        match accobj.kind {
+            PrimitiveKind::Triangle => self.triangle_bbox(accobj.idx as usize),
+            PrimitiveKind::Analytic => self.primitives[accobj.idx as usize]
+                .bbox()
+                .expect("planes are not bounded by acceleration structures"),
+        }
+    }
+
+    pub fn bsp_tree(&self, params: &BspParams) -> BspTree {
+        BspTree::new(self.bboxes(), *params)
     }
 
     pub fn bvh(&self) -> Bvh {
@@ -238,6 +285,14 @@ impl Mesh {
         bvh
     }
 
+    pub fn sbvh(&self) -> Bvh {
+        let start = std::time::Instant::now();
+        let bvh = hlbvh::Bvh::new_sbvh(self, 4, SBVH_ALPHA);
+        let passed = start.elapsed();
+        println!("built SBVH in {:?}", passed);
+        bvh
+    }
+
     #[allow(dead_code)]
     pub fn index_count(&self) -> u32 {
         self.indices.len() as u32
@@ -250,12 +305,77 @@ impl Mesh {
             vert.2 = vert.2 * factor;
         });
     }
+
+    /// Moves the vertices by `object_to_world` and the normals by its inverse transpose,
+    /// fails for a transform that can not be inverted or a mesh with analytic primitives,
+    /// as spheres and axis aligned boxes do not stay the same shapes under every transform
+    pub fn transform(&mut self, object_to_world: &Matrix4<f32>) -> anyhow::Result<()> {
+        anyhow::ensure!(self.primitives.is_empty(), "Analytic primitives can not be transformed");
+        let normal_matrix = object_to_world
+            .invert()
+            .ok_or_else(|| anyhow::anyhow!("The transform can not be inverted"))?
+            .transpose();
+        self.vertices.iter_mut().for_each(|vert| {
+            let p = object_to_world.transform_point(Point3::new(vert.0, vert.1, vert.2));
+            (vert.0, vert.1, vert.2) = (p.x, p.y, p.z);
+        });
+        self.normals.iter_mut().for_each(|normal| {
+            let n = normal_matrix.transform_vector(Vector3::new(normal.0, normal.1, normal.2));
+            // models without normals have zero ones
+            let n = if n.magnitude2() > 0.0 { n.normalize() } else { n };
+            (normal.0, normal.1, normal.2) = (n.x, n.y, n.z);
+        });
+        Ok(())
+    }
+
+    /// Replaces the materials, every triangle gets `material`
+    pub fn set_material(&mut self, material: Material) {
+        self.materials = vec![material];
+        self.indices.iter_mut().for_each(|index| index.3 = 0);
+        self.primitives.iter_mut().for_each(|primitive| primitive.material = 0);
+    }
+
+    /// Adds an analytic primitive with a material of its own
+    pub fn add_primitive(&mut self, shape: Shape, material: Material) {
+        self.primitives.push(Primitive { shape, material: self.materials.len() as u32 });
+        self.materials.push(material);
+    }
+
+    /// Concatenates the meshes in order, the vertex and material indices of
+    /// every mesh are moved past the ones of the meshes before it
+    pub fn merge(meshes: &[Mesh]) -> Mesh {
+        meshes.iter().fold(
+            Mesh { vertices: vec![], normals: vec![], indices: vec![], materials: vec![], primitives: vec![] },
+            |mut merged, mesh| {
+                let vertex_offset = merged.vertices.len() as u32;
+                let material_offset = merged.materials.len() as u32;
+                merged.indices.extend(mesh.indices.iter().map(|index| {
+                    vec4u32(
+                        index.0 + vertex_offset,
+                        index.1 + vertex_offset,
+                        index.2 + vertex_offset,
+                        // triangles without a material keep pointing nowhere
+                        index.3.saturating_add(material_offset),
+                    )
+                }));
+                merged.primitives.extend(mesh.primitives.iter().map(|primitive| Primitive {
+                    material: primitive.material + material_offset,
+                    ..*primitive
+                }));
+                merged.vertices.extend_from_slice(&mesh.vertices);
+                merged.normals.extend_from_slice(&mesh.normals);
+                merged.materials.extend_from_slice(&mesh.materials);
+                merged
+            },
+        )
+    }
 }
 
 #[cfg(test)]
 mod mesh_test {
 
     use super::*;
+    use crate::data_structures::vector::vec3u32;
 
     #[test]
     fn bsp_tree_new() {
@@ -263,4 +383,60 @@ mod mesh_test {
         println!("{:?}", _model.materials);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn merge_moves_the_indices_after_the_meshes_before() {
+        let cornell_box = Mesh::from_obj("res/models/CornellBox.obj").expect("Failed to load model");
+        let mut teapot = Mesh::from_obj("res/models/teapot.obj").expect("Failed to load model");
+        teapot.transform(&Matrix4::from_translation(Vector3::new(100.0, 0.0, 0.0))).unwrap();
+        teapot.set_material(Material::diffuse(vec3f32(0.2, 0.4, 0.8)));
+        let merged = Mesh::merge(&[cornell_box.clone(), teapot.clone()]);
+
+        let (box_triangles, box_vertices) = (cornell_box.indices.len(), cornell_box.vertices.len() as u32);
+        assert_eq!(merged.indices.len(), box_triangles + teapot.indices.len());
+        assert_eq!(merged.materials.len(), cornell_box.materials.len() + 1);
+        assert_eq!(&merged.indices[..box_triangles], cornell_box.indices.as_slice());
+        for (merged, index) in merged.indices[box_triangles..].iter().zip(&teapot.indices) {
+            assert_eq!(merged.xyz(), index.xyz() + vec3u32(box_vertices, box_vertices, box_vertices));
+            assert_eq!(merged.3, cornell_box.materials.len() as u32);
+        }
+        assert_eq!(merged.materials.last().unwrap().diffuse, vec3f32(0.2, 0.4, 0.8).vec4());
+    }
+
+    #[test]
+    fn transform_moves_vertices_and_normals() {
+        let mut teapot = Mesh::from_obj("res/models/teapot.obj").expect("Failed to load model");
+        let original = teapot.clone();
+        let object_to_world = Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0))
+            * Matrix4::from_angle_y(cgmath::Deg(90.0))
+            * Matrix4::from_nonuniform_scale(2.0, 1.0, 1.0);
+        teapot.transform(&object_to_world).unwrap();
+
+        for (moved, vert) in teapot.vertices.iter().zip(&original.vertices) {
+            // x is scaled by 2 and turned onto -z
+            let expected = vec3f32(1.0 + vert.2, 2.0 + vert.1, 3.0 - 2.0 * vert.0);
+            assert!((moved.xyz() - expected).magnitude() < 1e-4, "{moved:?} != {expected:?}");
+        }
+        for (moved, normal) in teapot.normals.iter().zip(&original.normals) {
+            let n = normal.xyz();
+            if n.magnitude() == 0.0 {
+                continue;
+            }
+            let expected = vec3f32(n.2, n.1, -0.5 * n.0).normalize();
+            assert!((moved.xyz() - expected).magnitude() < 1e-4, "{moved:?} != {expected:?}");
+        }
+    }
+
+
+    #[test]
+    fn transform_rejects_singular_matrices_and_primitives() {
+        let mut teapot = Mesh::from_obj("res/models/teapot.obj").expect("Failed to load model");
+        let original = teapot.clone();
+        assert!(teapot.transform(&Matrix4::from_nonuniform_scale(1.0, 1.0, 0.0)).is_err());
+        assert_eq!(teapot.vertices, original.vertices);
+
+        let sphere = Shape::Sphere { center: vec3f32(0.0, 0.0, 0.0), radius: 1.0 };
+        teapot.add_primitive(sphere, Material::diffuse(vec3f32(0.5, 0.5, 0.5)));
+        assert!(teapot.transform(&Matrix4::from_scale(2.0)).is_err());
+        assert_eq!(teapot.primitives[0].shape, sphere);
+    }
+}
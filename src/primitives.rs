@@ -0,0 +1,199 @@
+/// Analytic primitives placed in a scene next to its triangles. They are part of the `Mesh`,
+/// so every acceleration structure bounds them like triangles, see primitives.wgsl
+/// for the intersections on the GPU and `Primitive::intersect` for the CPU mirror.
+
+use crate::{
+    bindings::wgsl_struct::wgsl_struct,
+    data_structures::{
+        bbox::Bbox,
+        bvh_traversal::Ray,
+        vector::{cross, dot, Vec3f32},
+    },
+};
+
+/// `GpuPrimitive::kind` of the shapes, like the constants in primitives.wgsl
+pub const PRIMITIVE_SPHERE: u32 = 0;
+pub const PRIMITIVE_PLANE: u32 = 1;
+pub const PRIMITIVE_QUAD: u32 = 2;
+pub const PRIMITIVE_BOX: u32 = 3;
+
+/// Geometry of an analytic primitive in world space
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Shape {
+    Sphere { center: Vec3f32, radius: f32 },
+    /// Infinite plane, it has no bounds and is tested for every ray
+    Plane { point: Vec3f32, normal: Vec3f32 },
+    /// Parallelogram spanned by `u` and `v` from `corner`
+    Quad { corner: Vec3f32, u: Vec3f32, v: Vec3f32 },
+    /// Axis aligned box
+    Box { min: Vec3f32, max: Vec3f32 },
+}
+
+/// An analytic primitive of a mesh
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Primitive {
+    pub shape: Shape,
+    /// Index into the materials of the mesh
+    pub material: u32,
+}
+
+impl Primitive {
+    /// Bounds of the primitive, `None` for planes
+    pub fn bbox(&self) -> Option<Bbox> {
+        let mut bbox = Bbox::new();
+        match self.shape {
+            Shape::Sphere { center, radius } => {
+                let extent = Vec3f32::from([radius; 3]);
+                bbox.include_vertex(center - extent);
+                bbox.include_vertex(center + extent);
+            }
+            Shape::Plane { .. } => return None,
+            Shape::Quad { corner, u, v } => {
+                [corner, corner + u, corner + v, corner + u + v]
+                    .into_iter()
+                    .for_each(|p| bbox.include_vertex(p));
+            }
+            Shape::Box { min, max } => {
+                bbox.include_vertex(min);
+                bbox.include_vertex(max);
+            }
+        }
+        Some(bbox)
+    }
+
+    /// Distance along the ray to the primitive, like intersect_analytic
+    pub fn intersect(&self, ray: &Ray) -> Option<f32> {
+        let (origin, direction) = (ray.origin, ray.direction);
+        let distance = match self.shape {
+            Shape::Sphere { center, radius } => {
+                let oc = origin - center;
+                let a = dot(direction, direction);
+                let half_b = dot(oc, direction);
+                let c = dot(oc, oc) - radius * radius;
+                let discriminant = half_b * half_b - a * c;
+                if discriminant < 0.0 {
+                    return None;
+                }
+                let near = (-half_b - discriminant.sqrt()) / a;
+                if near > 0.0 {
+                    near
+                } else {
+                    (-half_b + discriminant.sqrt()) / a
+                }
+            }
+            Shape::Plane { point, normal } => intersect_plane(point, normal, ray)?,
+            Shape::Quad { corner, u, v } => {
+                let normal = cross(u, v);
+                let distance = intersect_plane(corner, normal, ray)?;
+                let q = origin + direction * distance - corner;
+                let w = normal / dot(normal, normal);
+                let alpha = dot(w, cross(q, v));
+                let beta = dot(w, cross(u, q));
+                if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+                    return None;
+                }
+                distance
+            }
+            Shape::Box { min, max } => {
+                let (mut t0, mut t1) = (f32::MIN, f32::MAX);
+                for axis in 0..3 {
+                    let near = (min[axis] - origin[axis]) / direction[axis];
+                    let far = (max[axis] - origin[axis]) / direction[axis];
+                    t0 = t0.max(near.min(far));
+                    t1 = t1.min(near.max(far));
+                }
+                if t0 > t1 {
+                    return None;
+                }
+                if t0 > 0.0 {
+                    t0
+                } else {
+                    t1
+                }
+            }
+        };
+        (distance > 0.0).then_some(distance)
+    }
+}
+
+fn intersect_plane(point: Vec3f32, normal: Vec3f32, ray: &Ray) -> Option<f32> {
+    let denom = dot(ray.direction, normal);
+    (denom.abs() >= 1e-10).then(|| dot(point - ray.origin, normal) / denom)
+}
+
+/// GPU primitive, the meaning of `a`, `b` and `c` depends on `kind`
+#[repr(C, align(16))]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct GpuPrimitive {
+    /// Sphere center, plane point, quad corner or box minimum
+    pub a: Vec3f32,
+    pub kind: u32,
+    /// Sphere radius in x, plane normal, first quad edge or box maximum
+    pub b: Vec3f32,
+    pub material: u32,
+    /// Second quad edge
+    pub c: Vec3f32,
+    _padding: u32,
+}
+
+wgsl_struct!(GpuPrimitive as Primitive {
+    a: vec3f,
+    kind: u32,
+    b: vec3f,
+    material: u32,
+    c: vec3f,
+});
+
+static_assertions::assert_eq_size!(GpuPrimitive, [u32; 12]);
+
+impl From<&Primitive> for GpuPrimitive {
+    fn from(primitive: &Primitive) -> Self {
+        let zero = Vec3f32::default();
+        let (kind, a, b, c) = match primitive.shape {
+            Shape::Sphere { center, radius } => (PRIMITIVE_SPHERE, center, Vec3f32::from([radius, 0.0, 0.0]), zero),
+            Shape::Plane { point, normal } => (PRIMITIVE_PLANE, point, normal.normalize(), zero),
+            Shape::Quad { corner, u, v } => (PRIMITIVE_QUAD, corner, u, v),
+            Shape::Box { min, max } => (PRIMITIVE_BOX, min, max, zero),
+        };
+        Self {
+            a,
+            kind,
+            b,
+            material: primitive.material,
+            c,
+            _padding: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod primitives_test {
+    use super::*;
+    use crate::data_structures::{bvh_traversal::random_rays, vector::vec3f};
+
+    #[test]
+    fn hits_are_inside_the_bounds() {
+        let shapes = [
+            Shape::Sphere { center: vec3f(1.0, 2.0, 3.0), radius: 1.5 },
+            Shape::Quad { corner: vec3f(-1.0, 0.0, 0.0), u: vec3f(2.0, 0.5, 0.0), v: vec3f(0.0, 1.0, 3.0) },
+            Shape::Box { min: vec3f(-2.0, -1.0, 0.5), max: vec3f(0.0, 1.0, 1.0) },
+        ];
+        let mut scene = Bbox::new();
+        shapes.iter().for_each(|&shape| scene.include_bbox(&Primitive { shape, material: 0 }.bbox().unwrap()));
+        for shape in shapes {
+            let primitive = Primitive { shape, material: 0 };
+            let mut bbox = primitive.bbox().unwrap();
+            // room for rounding in the hit points
+            bbox.include_vertex(bbox.min - vec3f(1e-4, 1e-4, 1e-4));
+            bbox.include_vertex(bbox.max + vec3f(1e-4, 1e-4, 1e-4));
+            let hits = random_rays(&scene, 500, 3)
+                .iter()
+                .filter_map(|ray| primitive.intersect(ray).map(|distance| ray.origin + ray.direction * distance))
+                .inspect(|&p| assert!(p.ge(bbox.min).all() && p.le(bbox.max).all(), "{p:?} outside {bbox:?} of {shape:?}"))
+                .count();
+            assert!(hits > 0, "no ray hit {shape:?}");
+        }
+        let plane = Primitive { shape: Shape::Plane { point: vec3f(0.0, 0.0, 0.0), normal: vec3f(0.0, 1.0, 0.0) }, material: 0 };
+        assert!(plane.bbox().is_none());
+    }
+}
@@ -1,13 +1,26 @@
+use crate::animation::{AnimatedMesh, AnimatedVertices};
 use crate::bindings::bsp_tree::TraversalStructure;
-use crate::bindings::create_bind_group_layouts;
+use crate::bindings::{create_bind_group_layouts, BindGroupFrequency};
+use crate::bindings::adaptive::AdaptiveSamplingGpu;
+use crate::bindings::aov::AovTargets;
+use crate::bindings::denoise::DenoiserGpu;
+use crate::bindings::preprocess::{preprocess_file, PreprocessedShader};
+use crate::bindings::present::PresentUniformGpu;
 use crate::bindings::storage_mesh::StorageMeshGpu;
-use crate::bindings::texture::{RenderSource, TextureInfo};
-use crate::command::DisplayMode;
+use crate::bindings::texture::{RenderFrame, RenderSource, TextureInfo};
+use crate::bindings::validate::{fragment_locations, validate_shader};
+use crate::bindings::wavefront::{WavefrontGpu, WavefrontPipelines};
+use crate::command::{Aov, DisplayMode, PresentFilter, ToneMapping};
+use crate::denoise::DenoiseParams;
+use crate::data_structures::hlbvh::BvhLayout;
+use crate::data_structures::instancing::{Instance, TwoLevelBvh};
 use crate::mesh::Mesh;
+use crate::scenes::{Backend, TraverseType};
+use crate::tiles::TileSchedule;
 use crate::SceneDescriptor;
 use crate::{
     bindings::{
-        create_bind_groups, create_shader_definitions,
+        append_shader_definitions, create_bind_groups,
         mesh::MeshGpu,
         texture::{RenderDestination, Texture},
         uniform::UniformGpu,
@@ -17,6 +30,7 @@ use crate::{
     camera::{Camera, CameraController},
     command::Command,
 };
+use cgmath::{Matrix4, SquareMatrix};
 use wgpu;
 use winit::{
     event_loop::EventLoop,
@@ -25,31 +39,62 @@ use winit::{
 
 use anyhow::*;
 
-use std::fs::File;
-use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 const CAMERA_SPEED: f32 = 0.05;
 
+const PRESENT_SHADER: &str = "res/shaders/present.wgsl";
+const DENOISE_SHADER: &str = "res/shaders/atrous.wgsl";
+/// The scene shaders write linear radiance, tone mapping happens when presenting
+const FRAME_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
 pub struct RenderState {
     surface: wgpu::Surface,
+    render_frame: RenderFrame,
+    aov_targets: AovTargets,
+    denoiser: DenoiserGpu,
+    denoise_pipeline: wgpu::RenderPipeline,
+    denoise_bind_group_layout: wgpu::BindGroupLayout,
+    /// One bind group per iteration of the denoiser, they only change
+    /// with the resolution or the number of iterations
+    denoise_bind_groups: Vec<wgpu::BindGroup>,
+    adaptive: AdaptiveSamplingGpu,
     render_source: RenderSource,
     render_destination: RenderDestination,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     display_mode: DisplayMode,
+    /// Rendering resolution, independent of the window (surface) size
+    resolution: (u32, u32),
     pub size: winit::dpi::PhysicalSize<u32>,
     window: Window,
     render_pipeline_layout: wgpu::PipelineLayout,
     render_pipeline: wgpu::RenderPipeline,
+    present_uniform: PresentUniformGpu,
+    present_pipeline: wgpu::RenderPipeline,
+    present_bind_group: wgpu::BindGroup,
     mesh_direct: MeshGpu,
     camera: Camera,
     pub uniform: UniformGpu,
     textures: Vec<Texture>,
     mesh_handle: Option<StorageMeshGpu>,
     traversal_structure_handle: TraversalStructure,
+    /// Animated objects of the scene with the time their animation started
+    animation: Option<(AnimatedMesh, Instant)>,
+    /// Path state and pipelines of the wavefront backend
+    wavefront: Option<(WavefrontGpu, WavefrontPipelines)>,
+    backend: Backend,
+    /// Layouts and bind groups of the scene shader, indexed by `BindGroupFrequency`
+    bind_group_layouts: Vec<wgpu::BindGroupLayout>,
     bind_groups: Vec<wgpu::BindGroup>,
     camera_controller: CameraController,
+    /// Splits the samples into tiles traced by separate submissions
+    tiles: TileSchedule,
+    show_tile_progress: bool,
+    /// Shader of the current scene, kept for hot reloading
+    shader: PathBuf,
 }
 
 impl RenderState {
@@ -106,7 +151,7 @@ impl RenderState {
             .unwrap();
 
         let surface_caps = surface.get_capabilities(&adapter);
-        // This assumes sRGB surface
+        // The presentation pass encodes to sRGB itself if the surface does not
         let surface_format = surface_caps
             .formats
             .iter()
@@ -116,8 +161,8 @@ impl RenderState {
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
-            width: scene.res.0,
-            height: scene.res.1,
+            width: size.width,
+            height: size.height,
             present_mode: wgpu::PresentMode::Immediate, //surface_caps.present_modes[0],
             alpha_mode: surface_caps.alpha_modes[0],
             view_formats: vec![],
@@ -128,61 +173,113 @@ impl RenderState {
 
         let mesh_direct = MeshGpu::new(&device, vertex::VERTICES, vertex::INDICES);
 
+        let render_frame = RenderFrame::new(&device, scene.res, FRAME_FORMAT);
+        let aov_targets = AovTargets::new(&device, scene.res);
+        let denoiser = DenoiserGpu::new(&device, scene.res, FRAME_FORMAT);
+        let adaptive = AdaptiveSamplingGpu::new(&device, scene.res);
         let render_source = RenderSource::new(&device, scene.res);
         let render_destination = RenderDestination::new(&device, scene.res);
 
-        let handles = Self::setup_rendering(&device, &queue, &config, &scene, &render_destination)
-            .await
-            .unwrap();
+        let handles = Self::setup_rendering(
+            &device,
+            &queue,
+            &render_frame,
+            &scene,
+            &render_destination,
+            &adaptive,
+        )
+        .await
+        .unwrap();
+
+        let (denoise_pipeline, denoise_bind_group_layout) =
+            Self::setup_denoise(&device, &denoiser, &render_frame, &aov_targets)
+                .await
+                .unwrap();
+        let denoise_bind_groups = Self::create_denoise_bind_groups(
+            &device,
+            &denoise_bind_group_layout,
+            &denoiser,
+            &render_frame,
+            &aov_targets,
+        );
+
+        let present_uniform = PresentUniformGpu::new(&device, config.format);
+        let (present_pipeline, present_bind_group) = Self::setup_presentation(
+            &device,
+            &config,
+            &present_uniform,
+            &render_frame,
+            &aov_targets,
+            &denoiser,
+        )
+        .await
+        .unwrap();
 
         Self {
             window,
             surface,
+            render_frame,
+            aov_targets,
+            denoiser,
+            denoise_pipeline,
+            denoise_bind_group_layout,
+            denoise_bind_groups,
+            adaptive,
             render_source,
             render_destination,
-            display_mode: DisplayMode::Exact, // this should be fairly safe
+            display_mode: DisplayMode::default(),
+            resolution: scene.res,
             device,
             queue,
             config,
             size,
             render_pipeline_layout: handles.0,
             render_pipeline: handles.1,
+            present_uniform,
+            present_pipeline,
+            present_bind_group,
             mesh_direct,
             camera,
-            bind_groups: handles.2,
+            bind_group_layouts: handles.2.0,
+            bind_groups: handles.2.1,
             uniform: handles.3,
             textures: handles.4,
             mesh_handle: handles.5,
             traversal_structure_handle: handles.6,
+            wavefront: handles.7,
+            animation: handles.8.map(|animated| (animated, Instant::now())),
+            backend: scene.backend,
             camera_controller,
+            tiles: TileSchedule::default(),
+            show_tile_progress: false,
+            shader: scene.shader.clone(),
         }
     }
 
     async fn setup_rendering(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        config: &wgpu::SurfaceConfiguration,
+        render_frame: &RenderFrame,
         scene: &SceneDescriptor,
         render_destination: &RenderDestination,
+        adaptive: &AdaptiveSamplingGpu,
     ) -> Result<(
         wgpu::PipelineLayout,
         wgpu::RenderPipeline,
-        Vec<wgpu::BindGroup>,
+        (Vec<wgpu::BindGroupLayout>, Vec<wgpu::BindGroup>),
         UniformGpu,
         Vec<Texture>,
         Option<StorageMeshGpu>,
         TraversalStructure,
+        Option<(WavefrontGpu, WavefrontPipelines)>,
+        Option<AnimatedMesh>,
     )> {
         // Uniform variables
         let uniform = UniformGpu::new(&device);
         // load texture
         let texture_bytes = include_bytes!("../res/textures/grass.jpg");
         let mut textures = vec![Texture::from_bytes(
-            TextureInfo {
-                name: "texture0".into(),
-                sampler_name: "sampler0".into(),
-                samplers: [true, true, true],
-            },
+            Self::texture_info(),
             &device,
             &queue,
             texture_bytes
@@ -192,11 +289,7 @@ impl RenderState {
         if let Some(path) = &scene.background_hdri {
             
             let background = Texture::from_file(
-                TextureInfo {
-                    name: "hdri0".into(),
-                    sampler_name: "hdri0_sampler".into(),
-                    samplers: [true, false, false],
-                },
+                Self::background_info(),
                 &device,
                 &queue,
                 path
@@ -205,8 +298,27 @@ impl RenderState {
             textures.push(background);
         }
 
-        // load model
-        let model = &scene.model.as_ref().and_then(|m| Mesh::from_obj(m).ok());
+        // load model, instanced scenes merge it with the models of the instances
+        ensure!(
+            scene.traverse_type == TraverseType::Bvh || scene.objects.iter().all(|object| object.animation.is_none()),
+            "{} animates objects, which is only supported with TraverseType::Bvh",
+            scene.name
+        );
+        let (model, two_level, animated) = match scene.traverse_type {
+            TraverseType::Instanced => {
+                let (mesh, bvh) = Self::load_instances(scene)?;
+                (Some(mesh), Some(bvh), None)
+            }
+            _ => match Self::load_animated_model(scene)? {
+                Some((model, animated)) if !animated.is_empty() => {
+                    let animated = AnimatedMesh::new(model.clone(), animated)
+                        .with_context(|| format!("Could not animate the objects of {}", scene.name))?;
+                    (Some(model), None, Some(animated))
+                }
+                model => (model.map(|(model, _)| model), None, None),
+            },
+        };
+        let model = &model;
         let mesh_handle = model.as_ref().and_then(|m| match scene.vertex_type {
             crate::scenes::VertexType::Split => Some(m.into_gpu_split(&device)),
             crate::scenes::VertexType::Combined => Some(m.into_gpu_combined(&device)),
@@ -214,160 +326,558 @@ impl RenderState {
         // Create traversal structures
         let traversal_structure = if let Some(model) = model {
             match scene.traverse_type {
-                crate::scenes::TraverseType::Bsp => TraversalStructure::Bsp(model.bsp_tree().into_gpu(device)),
-                crate::scenes::TraverseType::Bvh => TraversalStructure::Bvh(model.bvh().into_gpu(device)),
+                crate::scenes::TraverseType::Bsp => TraversalStructure::Bsp(model.bsp_tree(&scene.bsp).into_gpu(device)),
+                crate::scenes::TraverseType::Bvh => TraversalStructure::Bvh(match &animated {
+                    Some(animated) => animated.bvh.into_gpu(device),
+                    None => model.bvh().into_gpu(device),
+                }),
+                crate::scenes::TraverseType::Sbvh => TraversalStructure::Bvh(model.sbvh().into_gpu(device)),
+                crate::scenes::TraverseType::ThreadedBvh => TraversalStructure::Bvh(model.bvh().into_gpu_layout(device, BvhLayout::Threaded)),
+                crate::scenes::TraverseType::Bvh4 => TraversalStructure::WideBvh(model.bvh().into_gpu_wide(device, 4)),
+                crate::scenes::TraverseType::Bvh8 => TraversalStructure::WideBvh(model.bvh().into_gpu_wide(device, 8)),
+                crate::scenes::TraverseType::QuantizedBvh => TraversalStructure::WideBvh(model.bvh().into_gpu_quantized(device)),
+                crate::scenes::TraverseType::Instanced => TraversalStructure::Instanced(
+                    two_level.as_ref().expect("instanced scenes are loaded with a two-level BVH").into_gpu(device),
+                ),
             }
         } else {
             TraversalStructure::None
         };
+        let wavefront = (scene.backend == Backend::Wavefront).then(|| WavefrontGpu::new(device, scene.res));
 
         // generate bind group layouts
-        let handles = [
-            Some(&uniform as &dyn Bindable),
-            mesh_handle
-                .as_ref()
-                .and_then(|mesh| Some(mesh as &dyn Bindable)),
-            Some(&traversal_structure as &dyn Bindable),
-            Some(render_destination as &dyn Bindable),
-        ]
-        .into_iter()
-        .flatten()
-        .chain(textures.iter().map(|texture| texture as &dyn Bindable))
-        .collect::<Vec<&dyn Bindable>>();
+        let handles = Self::group_handles(
+            &uniform,
+            mesh_handle.as_ref(),
+            &traversal_structure,
+            render_destination,
+            adaptive,
+            wavefront.as_ref(),
+            &textures,
+        );
 
-        let (render_pipeline_layout, bind_groups) =
+        let (render_pipeline_layout, bind_group_layouts, bind_groups) =
             Self::recreate_bind_groups_impl(device, &handles);
 
-        let mut shader_defs = Self::create_shader_defs_impl(&handles);
-
-        let mut file = File::open(&scene.shader)?;
-        let mut shader_source = String::new();
-        file.read_to_string(&mut shader_source)?;
-
-        let shader = Self::create_shader_module(&device, &mut shader_defs, &shader_source).await?;
+        let shader_source = Self::assemble_shader(&scene.shader, &handles)?;
+        let shader = Self::create_shader_module(&device, &shader_source).await?;
+        let written = fragment_locations(&shader_source, scene.backend.fragment_entry_point())?;
 
         let render_pipeline = RenderState::create_render_pipeline(
             &device,
             Some(&render_pipeline_layout),
             &shader,
-            &config,
+            render_frame.format(),
+            scene.backend,
+            &written,
         );
+        let wavefront = wavefront.map(|wavefront| {
+            let pipelines = WavefrontPipelines::new(device, &render_pipeline_layout, &shader);
+            (wavefront, pipelines)
+        });
 
         Ok((
             render_pipeline_layout,
             render_pipeline,
-            bind_groups,
+            (bind_group_layouts, bind_groups),
             uniform,
             textures,
             mesh_handle,
             traversal_structure,
+            wavefront,
+            animated,
         ))
     }
 
-    fn get_handles(&self) -> Vec<&dyn Bindable> {
-        [
-            Some(&self.uniform as &dyn Bindable),
-            self.mesh_handle
-                .as_ref()
-                .and_then(|mesh| Some(mesh as &dyn Bindable)),
-            Some(&self.traversal_structure_handle as &dyn Bindable),
-            Some(&self.render_destination as &dyn Bindable),
+    /// The model of the scene merged with its objects and
+    /// followed by its primitives, `None` without a model or objects
+    fn load_model(scene: &SceneDescriptor) -> Result<Option<Mesh>> {
+        Ok(Self::load_animated_model(scene)?.map(|(model, _)| model))
+    }
+
+    /// `load_model` with the vertices of the animated objects in the merged mesh
+    fn load_animated_model(scene: &SceneDescriptor) -> Result<Option<(Mesh, Vec<AnimatedVertices>)>> {
+        let load = |path: &PathBuf| Mesh::from_obj(path).with_context(|| format!("Could not load model {}", path.display()));
+        let mut meshes = scene.model.iter().map(load).collect::<Result<Vec<_>>>()?;
+        let mut animated = vec![];
+        for object in &scene.objects {
+            let mut mesh = load(&object.model)?;
+            mesh.transform(&object.transform.object_to_world())
+                .with_context(|| format!("Could not place object {}", object.model.display()))?;
+            if let Some(material) = object.material {
+                mesh.set_material(material);
+            }
+            if let Some(animation) = object.animation {
+                // `Mesh::merge` concatenates the vertices
+                let first = meshes.iter().map(|mesh| mesh.vertices.len()).sum::<usize>();
+                animated.push(AnimatedVertices { vertices: first..first + mesh.vertices.len(), animation });
+            }
+            meshes.push(mesh);
+        }
+        let mut model = match meshes.len() {
+            0 => None,
+            1 => meshes.pop(),
+            _ => Some(Mesh::merge(&meshes)),
+        };
+        if let Some(model) = model.as_mut() {
+            scene.primitives.iter().for_each(|primitive| model.add_primitive(primitive.shape, primitive.material));
+        }
+        ensure!(model.is_some() || scene.primitives.is_empty(), "{} has primitives but no model to add them to", scene.name);
+        Ok(model.map(|model| (model, animated)))
+    }
+
+    /// Loads every model file of an instanced scene once, the model of
+    /// the scene and its objects are placed as they are, before the instances
+    fn load_instances(scene: &SceneDescriptor) -> Result<(Mesh, TwoLevelBvh)> {
+        let mut meshes = Self::load_model(scene)?.into_iter().collect::<Vec<_>>();
+        // the model and the objects are already in world space
+        let mut instances = (0..meshes.len())
+            .map(|mesh| Instance { mesh, object_to_world: Matrix4::identity() })
+            .collect::<Vec<_>>();
+
+        let first_instanced = meshes.len();
+        let mut paths: Vec<&PathBuf> = vec![];
+        for instance in &scene.instances {
+            let path = &instance.model;
+            let mesh = match paths.iter().position(|loaded| *loaded == path) {
+                Some(loaded) => first_instanced + loaded,
+                None => {
+                    meshes.push(Mesh::from_obj(path).with_context(|| format!("Could not load model {}", path.display()))?);
+                    paths.push(path);
+                    meshes.len() - 1
+                }
+            };
+            instances.push(Instance { mesh, object_to_world: instance.transform.object_to_world() });
+        }
+        ensure!(!instances.is_empty(), "{} has neither a model nor instances", scene.name);
+
+        let bvh = TwoLevelBvh::new(&meshes, instances).with_context(|| format!("Invalid instances in {}", scene.name))?;
+        Ok((Mesh::merge(&meshes), bvh))
+    }
+
+    fn texture_info() -> TextureInfo {
+        TextureInfo {
+            name: "texture0".into(),
+            sampler_name: "sampler0".into(),
+            samplers: [true, true, true],
+        }
+    }
+
+    fn background_info() -> TextureInfo {
+        TextureInfo {
+            name: "hdri0".into(),
+            sampler_name: "hdri0_sampler".into(),
+            samplers: [true, false, false],
+        }
+    }
+
+    /// The shader `setup_rendering` assembles for a scene, without creating
+    /// any resources. Keep the order in sync with the handles there.
+    fn scene_shader(scene: &SceneDescriptor) -> Result<PreprocessedShader> {
+        let textures = [Some(Self::texture_info()), scene.background_hdri.as_ref().map(|_| Self::background_info())]
+            .into_iter()
+            .flatten()
+            .map(|info| (info.sampler_names(), info))
+            .collect::<Vec<_>>();
+
+        // scenes without a model have neither a mesh nor a traversal structure
+        let has_model = scene.has_model();
+        let descriptors = [
+            vec![UniformGpu::bind_descriptor()],
+            [
+                has_model.then(|| StorageMeshGpu::bind_descriptor(scene.vertex_type, !scene.primitives.is_empty())),
+                has_model.then(|| TraversalStructure::bind_descriptor(scene.traverse_type, scene.bsp.max_depth)),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            [
+                Some(RenderDestination::bind_descriptor()),
+                Some(AdaptiveSamplingGpu::bind_descriptor()),
+                (scene.backend == Backend::Wavefront).then(WavefrontGpu::bind_descriptor),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            textures
+                .iter()
+                .map(|(sampler_names, info)| {
+                    Texture::bind_descriptor(&info.name, sampler_names.iter().flatten().map(String::as_str))
+                })
+                .collect(),
+        ];
+
+        let mut shader = preprocess_file(&scene.shader)?;
+        append_shader_definitions(&mut shader, &descriptors)?;
+        Ok(shader)
+    }
+
+    /// Average CPU time per frame spent on the bindings of `scene`: with all of
+    /// them in a single bind group that is recreated every frame, and split by
+    /// `BindGroupFrequency` where only the buffers of the frame are written
+    pub async fn benchmark_bind_groups(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &SceneDescriptor,
+        frames: u32,
+    ) -> Result<(Duration, Duration)> {
+        let frames = frames.max(1);
+        let render_frame = RenderFrame::new(device, scene.res, FRAME_FORMAT);
+        let mut render_destination = RenderDestination::new(device, scene.res);
+        let adaptive = AdaptiveSamplingGpu::new(device, scene.res);
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let handles = Self::setup_rendering(device, queue, &render_frame, scene, &render_destination, &adaptive).await;
+        if let Some(err) = device.pop_error_scope().await {
+            return Err(anyhow!("{err}"));
+        }
+        let (_, _, _, uniform, textures, mesh_handle, traversal_structure, wavefront, _) = handles?;
+        let wavefront = wavefront.map(|(wavefront, _)| wavefront);
+
+        let start = Instant::now();
+        for _ in 0..frames {
+            uniform.update_buffer(queue);
+            render_destination.update_view();
+            let handles = Self::group_handles(
+                &uniform,
+                mesh_handle.as_ref(),
+                &traversal_structure,
+                &render_destination,
+                &adaptive,
+                wavefront.as_ref(),
+                &textures,
+            )
+            .concat();
+            Self::recreate_bind_groups_impl(device, &[handles]);
+            device.poll(wgpu::Maintain::Poll);
+        }
+        let single_group = start.elapsed() / frames;
+
+        let start = Instant::now();
+        for _ in 0..frames {
+            uniform.update_buffer(queue);
+            device.poll(wgpu::Maintain::Poll);
+        }
+        let split_groups = start.elapsed() / frames;
+        Ok((single_group, split_groups))
+    }
+
+    /// Validates the shader of a scene the way `setup_rendering` would compile
+    /// it, but without a device and without loading the model or textures
+    pub fn validate_scene_shader(scene: &SceneDescriptor) -> Result<()> {
+        validate_shader(&Self::scene_shader(scene)?)
+    }
+
+    /// The shader `setup_presentation` assembles, without creating any
+    /// resources. Keep the order in sync with `get_present_handles_impl`.
+    pub(crate) fn present_shader() -> Result<PreprocessedShader> {
+        let descriptors = [vec![
+            PresentUniformGpu::bind_descriptor(),
+            RenderFrame::bind_descriptor(),
+            AovTargets::bind_descriptor(),
+            DenoiserGpu::bind_descriptor(),
+        ]];
+        let mut shader = preprocess_file(Path::new(PRESENT_SHADER))?;
+        append_shader_definitions(&mut shader, &descriptors)?;
+        Ok(shader)
+    }
+
+    /// Validates the shader of the presentation pass like `validate_scene_shader`
+    pub fn validate_present_shader() -> Result<()> {
+        validate_shader(&Self::present_shader()?)
+    }
+
+    /// Create the pipeline and bind group for the pass that scales
+    /// the rendered frame to the window.
+    async fn setup_presentation(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        present_uniform: &PresentUniformGpu,
+        render_frame: &RenderFrame,
+        aov_targets: &AovTargets,
+        denoiser: &DenoiserGpu,
+    ) -> Result<(wgpu::RenderPipeline, wgpu::BindGroup)> {
+        let handles = Self::get_present_handles_impl(present_uniform, render_frame, aov_targets, denoiser);
+        let handles = [handles];
+        let (pipeline_layout, _, mut bind_groups) = Self::recreate_bind_groups_impl(device, &handles);
+        let shader_source = Self::assemble_shader(Path::new(PRESENT_SHADER), &handles)?;
+        let shader = Self::create_shader_module(device, &shader_source).await?;
+        let present_pipeline = Self::create_present_pipeline(device, &pipeline_layout, &shader, config.format);
+
+        Ok((present_pipeline, bind_groups.remove(0)))
+    }
+
+    /// Create the pipeline for one iteration of the à-trous filter and the layout
+    /// of its bind group, the bindings only depend on the layout so the first step is used
+    async fn setup_denoise(
+        device: &wgpu::Device,
+        denoiser: &DenoiserGpu,
+        render_frame: &RenderFrame,
+        aov_targets: &AovTargets,
+    ) -> Result<(wgpu::RenderPipeline, wgpu::BindGroupLayout)> {
+        let steps = denoiser.steps(render_frame);
+        let handles = [vec![&steps[0].0 as &dyn Bindable, aov_targets as &dyn Bindable]];
+        let (pipeline_layout, mut bind_group_layouts, _) = Self::recreate_bind_groups_impl(device, &handles);
+        let shader_source = Self::assemble_shader(Path::new(DENOISE_SHADER), &handles)?;
+        let shader = Self::create_shader_module(device, &shader_source).await?;
+        let pipeline = Self::create_present_pipeline(device, &pipeline_layout, &shader, FRAME_FORMAT);
+        Ok((pipeline, bind_group_layouts.remove(0)))
+    }
+
+    /// The bind groups of all iterations of the denoiser, in the order of `DenoiserGpu::steps`
+    fn create_denoise_bind_groups(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        denoiser: &DenoiserGpu,
+        render_frame: &RenderFrame,
+        aov_targets: &AovTargets,
+    ) -> Vec<wgpu::BindGroup> {
+        denoiser
+            .steps(render_frame)
+            .iter()
+            .map(|(step, _)| Self::create_bind_group(device, &[step as &dyn Bindable, aov_targets as &dyn Bindable], layout))
+            .collect()
+    }
+
+    fn recreate_denoise_bind_groups(&mut self) {
+        self.denoise_bind_groups = Self::create_denoise_bind_groups(
+            &self.device,
+            &self.denoise_bind_group_layout,
+            &self.denoiser,
+            &self.render_frame,
+            &self.aov_targets,
+        );
+    }
+
+    fn get_present_handles_impl<'a>(
+        present_uniform: &'a PresentUniformGpu,
+        render_frame: &'a RenderFrame,
+        aov_targets: &'a AovTargets,
+        denoiser: &'a DenoiserGpu,
+    ) -> Vec<&'a dyn Bindable> {
+        vec![
+            present_uniform as &dyn Bindable,
+            render_frame as &dyn Bindable,
+            aov_targets as &dyn Bindable,
+            denoiser as &dyn Bindable,
         ]
-        .into_iter()
-        .flatten()
-        .chain(self.textures.iter().map(|texture| texture as &dyn Bindable))
-        .collect::<Vec<_>>()
     }
 
-    fn recreate_bind_groups(&mut self) {
-        (self.render_pipeline_layout, self.bind_groups) = Self::recreate_bind_groups_impl(&self.device, &self.get_handles());
+    fn recreate_present_bind_group(&mut self) {
+        let handles = Self::get_present_handles_impl(
+            &self.present_uniform,
+            &self.render_frame,
+            &self.aov_targets,
+            &self.denoiser,
+        );
+        let (_, _, mut bind_groups) = Self::recreate_bind_groups_impl(&self.device, &[handles]);
+        self.present_bind_group = bind_groups.remove(0);
+    }
+
+    fn get_handles(&self) -> Vec<Vec<&dyn Bindable>> {
+        Self::group_handles(
+            &self.uniform,
+            self.mesh_handle.as_ref(),
+            &self.traversal_structure_handle,
+            &self.render_destination,
+            &self.adaptive,
+            self.wavefront.as_ref().map(|(wavefront, _)| wavefront),
+            &self.textures,
+        )
+    }
+
+    /// The bindings of the scene shader in their bind groups, indexed by `BindGroupFrequency`.
+    /// Keep the order in sync with `scene_shader`.
+    fn group_handles<'a>(
+        uniform: &'a UniformGpu,
+        mesh_handle: Option<&'a StorageMeshGpu>,
+        traversal_structure: &'a TraversalStructure,
+        render_destination: &'a RenderDestination,
+        adaptive: &'a AdaptiveSamplingGpu,
+        wavefront: Option<&'a WavefrontGpu>,
+        textures: &'a [Texture],
+    ) -> Vec<Vec<&'a dyn Bindable>> {
+        BindGroupFrequency::ALL
+            .iter()
+            .map(|frequency| match frequency {
+                BindGroupFrequency::Frame => vec![uniform as &dyn Bindable],
+                BindGroupFrequency::Scene => [
+                    mesh_handle.map(|mesh| mesh as &dyn Bindable),
+                    Some(traversal_structure as &dyn Bindable),
+                ]
+                .into_iter()
+                .flatten()
+                .collect(),
+                BindGroupFrequency::Targets => [
+                    Some(render_destination as &dyn Bindable),
+                    Some(adaptive as &dyn Bindable),
+                    wavefront.map(|wavefront| wavefront as &dyn Bindable),
+                ]
+                .into_iter()
+                .flatten()
+                .collect(),
+                BindGroupFrequency::Textures => textures
+                    .iter()
+                    .map(|texture| texture as &dyn Bindable)
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Recreates a single bind group of the scene shader with the layout it
+    /// was created with, e.g. after the resources in it were resized
+    fn recreate_bind_group(&mut self, frequency: BindGroupFrequency) {
+        let group_id = frequency.group_id();
+        let bind_group = Self::create_bind_group(
+            &self.device,
+            &self.get_handles()[group_id],
+            &self.bind_group_layouts[group_id],
+        );
+        self.bind_groups[group_id] = bind_group;
     }
 
+    fn create_bind_group(
+        device: &wgpu::Device,
+        handles: &[&dyn Bindable],
+        bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::BindGroup {
+        let mut bind_group_entries = handles
+            .iter()
+            .flat_map(|&handle| handle.get_bind_group_entries())
+            .collect::<Vec<_>>();
+        create_bind_groups(device, &mut bind_group_entries, bind_group_layout)
+    }
+
+    /// One bind group per element of `groups`, the group ids are the indices
     fn recreate_bind_groups_impl(
         device: &wgpu::Device,
-        handles: &Vec<&dyn Bindable>,
-    ) -> (wgpu::PipelineLayout, Vec<wgpu::BindGroup>) {
-        let bind_group_layout_entries = handles
+        groups: &[Vec<&dyn Bindable>],
+    ) -> (wgpu::PipelineLayout, Vec<wgpu::BindGroupLayout>, Vec<wgpu::BindGroup>) {
+        let bind_group_layouts = groups
             .iter()
-            .flat_map(|&handle| handle.get_layout_entries())
+            .map(|handles| {
+                let bind_group_layout_entries = handles
+                    .iter()
+                    .flat_map(|&handle| handle.get_layout_entries())
+                    .collect::<Vec<_>>();
+                create_bind_group_layouts(device, bind_group_layout_entries)
+            })
             .collect::<Vec<_>>();
-        let mut bind_group_entries = handles
+        let bind_groups = groups
             .iter()
-            .flat_map(|&handle| handle.get_bind_group_entries())
+            .zip(&bind_group_layouts)
+            .map(|(handles, bind_group_layout)| Self::create_bind_group(device, handles, bind_group_layout))
             .collect::<Vec<_>>();
-        let bind_group_layout = create_bind_group_layouts(&device, bind_group_layout_entries);
-        let bind_group = create_bind_groups(device, &mut bind_group_entries, &bind_group_layout);
         let render_pipeline_layout =
-            Self::create_render_pipeline_layout(&device, &vec![bind_group_layout]);
-        (render_pipeline_layout, vec![bind_group])
+            Self::create_render_pipeline_layout(device, &bind_group_layouts);
+        (render_pipeline_layout, bind_group_layouts, bind_groups)
     }
 
-    fn create_shader_defs_impl(handles: &Vec<&dyn Bindable>) -> String {
-        create_shader_definitions(
-            &handles
+    /// Preprocesses the shader at `path` and appends the definitions of the bindings,
+    /// given per bind group
+    fn assemble_shader(path: &Path, groups: &[Vec<&dyn Bindable>]) -> Result<PreprocessedShader> {
+        let mut shader = preprocess_file(path)?;
+        append_shader_definitions(
+            &mut shader,
+            &groups
                 .iter()
-                .map(|&handle| handle.get_bind_descriptor())
+                .map(|handles| {
+                    handles
+                        .iter()
+                        .map(|&handle| handle.get_bind_descriptor())
+                        .collect::<Vec<_>>()
+                })
                 .collect::<Vec<_>>(),
-        )
+        )?;
+        Ok(shader)
     }
 
     pub fn load_scene(&mut self, scene: &SceneDescriptor) -> Result<()> {
         let handles = pollster::block_on(Self::setup_rendering(
             &self.device,
             &self.queue,
-            &self.config,
+            &self.render_frame,
             scene,
             &self.render_destination,
+            &self.adaptive,
         ))?;
         self.render_pipeline_layout = handles.0;
         self.render_pipeline = handles.1;
-        self.bind_groups = handles.2;
+        (self.bind_group_layouts, self.bind_groups) = handles.2;
         self.uniform = handles.3;
         self.textures = handles.4;
         self.mesh_handle = handles.5;
         self.traversal_structure_handle = handles.6;
+        self.wavefront = handles.7;
+        self.animation = handles.8.map(|animated| (animated, Instant::now()));
+        self.backend = scene.backend;
+        self.shader = scene.shader.clone();
         // update uniforms
         self.camera = scene.camera.to_owned();
         // update resolution
-        self.set_display_mode(scene.res, crate::command::DisplayMode::Stretch)?;
+        self.set_display_mode(scene.res, self.display_mode)?;
         Ok(())
     }
 
-    pub fn recreate_render_pipeline<'a>(&'a mut self, shader: &wgpu::ShaderModule) {
-        self.render_pipeline = Self::create_render_pipeline(
+    /// Swaps in the pipelines using `shader`, which writes the color targets at the
+    /// `written` locations. The current ones are kept if they can not be created.
+    pub fn recreate_render_pipeline(&mut self, shader: &wgpu::ShaderModule, written: &[u32]) -> Result<()> {
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let render_pipeline = Self::create_render_pipeline(
             &self.device,
             Some(&self.render_pipeline_layout),
             shader,
-            &self.config,
+            self.render_frame.format(),
+            self.backend,
+            written,
         );
+        let wavefront_pipelines = self
+            .wavefront
+            .as_ref()
+            .map(|_| WavefrontPipelines::new(&self.device, &self.render_pipeline_layout, shader));
+        if let Some(err) = pollster::block_on(self.device.pop_error_scope()) {
+            return Err(anyhow!("{err}"));
+        }
+        self.render_pipeline = render_pipeline;
+        if let (Some((_, pipelines)), Some(new_pipelines)) = (&mut self.wavefront, wavefront_pipelines) {
+            *pipelines = new_pipelines;
+        }
+        Ok(())
+    }
+
+    pub fn shader_path(&self) -> &Path {
+        &self.shader
     }
 
+    /// Every file the shader of the current scene is assembled from,
+    /// including the code pasted in by the bindings
+    pub fn shader_files(&self) -> Result<Vec<PathBuf>> {
+        let shader = Self::assemble_shader(&self.shader, &self.get_handles())?;
+        Ok(shader.source_map.files().into_iter().map(Path::to_path_buf).collect())
+    }
+
+    /// The shader module and the locations of the color targets its fragment shader writes
     pub async fn create_shader_module_from_file(
         &self,
         shader_location: &std::path::Path,
-    ) -> Result<wgpu::ShaderModule> {
-        let mut file = File::open(shader_location)?;
-        let mut shader_source = String::new();
-        file.read_to_string(&mut shader_source)?;
-        let shader_defs = Self::create_shader_defs_impl(&self.get_handles());
-        Self::create_shader_module(&self.device, shader_defs.as_str(), &mut shader_source).await
+    ) -> Result<(wgpu::ShaderModule, Vec<u32>)> {
+        let shader_source = Self::assemble_shader(shader_location, &self.get_handles())?;
+        let written = fragment_locations(&shader_source, self.backend.fragment_entry_point())?;
+        Ok((Self::create_shader_module(&self.device, &shader_source).await?, written))
     }
 
     async fn create_shader_module(
         device: &wgpu::Device,
-        shader_defs: &str,
-        shader_source: &str,
+        shader_source: &PreprocessedShader,
     ) -> Result<wgpu::ShaderModule> {
-        let mut everything = String::from(shader_source);
-        everything.push_str(shader_defs);
         device.push_error_scope(wgpu::ErrorFilter::Validation);
         let shader_maybe = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(everything.as_str().into()),
+            source: wgpu::ShaderSource::Wgsl(shader_source.source.as_str().into()),
         });
         let error_maybe = device.pop_error_scope().await;
         if let Some(err) = error_maybe {
-            return Err(anyhow!(err.to_string()));
+            // point the error back at the file the code was included from
+            return Err(anyhow!(shader_source.source_map.remap_errors(&err.to_string())));
         }
 
         Ok(shader_maybe)
@@ -390,12 +900,40 @@ impl RenderState {
         render_pipeline_layout
     }
 
+    /// The frame, accumulation, AOV and variance targets are declared for every scene,
+    /// targets at locations that are not `written` by the shader get an empty write mask
+    /// and keep their clear values. Only the shaders importing `output` write the AOVs.
     pub fn create_render_pipeline(
         device: &wgpu::Device,
         render_pipeline_layout: Option<&wgpu::PipelineLayout>,
         shader: &wgpu::ShaderModule,
-        config: &wgpu::SurfaceConfiguration,
+        frame_format: wgpu::TextureFormat,
+        backend: Backend,
+        written: &[u32],
     ) -> wgpu::RenderPipeline {
+        let targets = [
+            Some(wgpu::ColorTargetState {
+                format: frame_format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
+            Some(wgpu::ColorTargetState {
+                format: wgpu::TextureFormat::Rgba32Float,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
+        ]
+        .into_iter()
+        .chain(AovTargets::color_targets())
+        .chain([AdaptiveSamplingGpu::color_target()])
+        .enumerate()
+        .map(|(location, target)| {
+            target.map(|target| match written.contains(&(location as u32)) {
+                true => target,
+                false => wgpu::ColorTargetState { write_mask: wgpu::ColorWrites::empty(), ..target },
+            })
+        })
+        .collect::<Vec<_>>();
         let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Render Pipeline"),
             layout: render_pipeline_layout,
@@ -406,19 +944,8 @@ impl RenderState {
             },
             fragment: Some(wgpu::FragmentState {
                 module: &shader,
-                entry_point: "fs_main",
-                targets: &[
-                    Some(wgpu::ColorTargetState {
-                        format: config.format,
-                        blend: Some(wgpu::BlendState::REPLACE),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    }),
-                    Some(wgpu::ColorTargetState {
-                        format: wgpu::TextureFormat::Rgba32Float,
-                        blend: None,
-                        write_mask: wgpu::ColorWrites::ALL,
-                    }),
-                ],
+                entry_point: backend.fragment_entry_point(),
+                targets: &targets,
             }),
             primitive: wgpu::PrimitiveState {
                 topology: wgpu::PrimitiveTopology::TriangleList,
@@ -444,6 +971,48 @@ impl RenderState {
         render_pipeline
     }
 
+    fn create_present_pipeline(
+        device: &wgpu::Device,
+        present_pipeline_layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        surface_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Present Pipeline"),
+            layout: Some(present_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        })
+    }
+
     pub fn window(&self) -> &Window {
         &self.window
     }
@@ -453,6 +1022,12 @@ impl RenderState {
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        if new_size.width > 0 && new_size.height > 0 {
+            self.size = new_size;
+            self.config.width = new_size.width;
+            self.config.height = new_size.height;
+            self.surface.configure(&self.device, &self.config);
+        }
         if self.display_mode == DisplayMode::Window {
             self.set_render_resolution((new_size.width, new_size.height));
         }
@@ -465,6 +1040,7 @@ impl RenderState {
     }
 
     pub fn update(&mut self) {
+        self.animate();
         self.camera.aspect = self.aspect_ratio();
         self.camera_controller.update_camera(&mut self.camera);
         self.uniform.update(
@@ -472,20 +1048,62 @@ impl RenderState {
             None,
             None,
             None, // TODO
-            Some((self.config.width, self.config.height)),
+            Some(self.resolution),
         );
         self.uniform.update_buffer(&self.queue);
-        self.render_destination.update_view();
-        self.render_source.update_view();
-        self.recreate_bind_groups();
+        self.present_uniform
+            .update_sample_budget(self.uniform.get_iteration());
+        self.present_uniform.update_buffer(&self.queue);
+        self.denoiser.update_buffer(&self.queue);
+        self.adaptive.update_buffer(&self.queue);
+    }
+
+    /// Moves the animated objects to the current time before a sample starts and
+    /// refits the BVH, the samples are traced from scratch as the accumulated
+    /// ones show the objects where they were
+    fn animate(&mut self) {
+        if !self.tiles.starts_sample(self.resolution, self.uniform.get_iteration()) {
+            return;
+        }
+        let Some((animated, start)) = &mut self.animation else {
+            return;
+        };
+        let rebuilt = animated.update(start.elapsed().as_secs_f32());
+        if let Some(mesh_handle) = &self.mesh_handle {
+            mesh_handle.write_vertices(&self.queue, &animated.mesh);
+        }
+        if rebuilt {
+            // the node count may differ, so the buffers are replaced
+            self.traversal_structure_handle = TraversalStructure::Bvh(animated.bvh.into_gpu(&self.device));
+            self.recreate_bind_group(BindGroupFrequency::Scene);
+        } else if let TraversalStructure::Bvh(bvh) = &self.traversal_structure_handle {
+            bvh.write_refitted(&self.queue, &animated.bvh);
+        }
+        self.uniform.reset_iteration();
     }
 
-    pub fn render(&self) -> Result<(), wgpu::SurfaceError> {
+    /// Traces the next tiles of the current sample and presents the frame,
+    /// returns true once every tile of the sample has been traced
+    pub fn render(&mut self) -> Result<bool, wgpu::SurfaceError> {
         let output = self.surface.get_current_texture()?;
 
+        let iteration = self.uniform.get_iteration();
+        let first_tiles = self.tiles.starts_sample(self.resolution, iteration);
+        let (tiles, sample_done) = self.tiles.next_tiles(self.resolution, iteration);
+        if let Some((wavefront, _)) = &mut self.wavefront {
+            wavefront.write_tiles(&self.device, &self.queue, &tiles);
+        }
+        let tile_size = if self.show_tile_progress { self.tiles.tile_size() } else { 0 };
+        self.present_uniform.update_tile_progress(tile_size, self.tiles.tiles_done());
+        self.present_uniform.update_buffer(&self.queue);
+
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
+        let frame_view = self
+            .render_frame
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
 
         let mut encoder = self
             .device
@@ -496,31 +1114,52 @@ impl RenderState {
             .render_source
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
-        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("Render Pass"),
-            color_attachments: &[
-                Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
+        // Converged pixels are discarded by the shaders, so everything
+        // written before has to be kept after the first iteration,
+        // just like the tiles traced before in the first iteration
+        let clear = iteration == 0 && first_tiles;
+        let color_attachments = [
+            Some(wgpu::RenderPassColorAttachment {
+                view: &frame_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: match clear {
+                        true => wgpu::LoadOp::Clear(wgpu::Color {
                             r: 0.0,
                             g: 0.0,
                             b: 0.0,
                             a: 1.0,
                         }),
-                        store: true,
+                        false => wgpu::LoadOp::Load,
                     },
-                }),
-                Some(wgpu::RenderPassColorAttachment {
-                    view: &source_view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load,
-                        store: true,
-                    },
-                }),
-            ],
+                    store: true,
+                },
+            }),
+            Some(wgpu::RenderPassColorAttachment {
+                view: &source_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }),
+        ]
+        .into_iter()
+        .chain(self.aov_targets.color_attachments(clear))
+        .chain([self.adaptive.color_attachment(clear)])
+        .collect::<Vec<_>>();
+        // the active pixels of all tiles of a sample are counted together
+        if first_tiles {
+            self.adaptive.encode_reset(&mut encoder);
+        }
+        if let Some((wavefront, pipelines)) = &self.wavefront {
+            for (index, tile) in tiles.iter().enumerate() {
+                pipelines.encode(&mut encoder, wavefront, &self.bind_groups, index, tile);
+            }
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &color_attachments,
             depth_stencil_attachment: None,
         });
 
@@ -534,7 +1173,10 @@ impl RenderState {
         for (idx, group) in self.bind_groups.iter().enumerate() {
             render_pass.set_bind_group(idx as u32, group, &[]);
         }
-        render_pass.draw_indexed(0..self.mesh_direct.num_indices, 0, 0..1);
+        for tile in &tiles {
+            render_pass.set_scissor_rect(tile.x, tile.y, tile.width, tile.height);
+            render_pass.draw_indexed(0..self.mesh_direct.num_indices, 0, 0..1);
+        }
 
         drop(render_pass);
 
@@ -553,6 +1195,39 @@ impl RenderState {
             },
             self.render_source.texture.size(),
         );
+        self.adaptive.encode_copy(&mut encoder);
+
+        if self.denoiser.enabled() {
+            self.encode_denoise_passes(&mut encoder);
+        }
+        self.encode_present_pass(&mut encoder, &view);
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+        output.present();
+
+        std::result::Result::Ok(sample_done)
+    }
+
+    /// Presents the last rendered frame again without rendering a new one,
+    /// used when only the presentation settings have changed
+    pub fn present(&self) -> Result<(), wgpu::SurfaceError> {
+        let output = self.surface.get_current_texture()?;
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Present Encoder"),
+            });
+        self.present_uniform.update_buffer(&self.queue);
+        if self.denoiser.enabled() {
+            // the parameters may have changed
+            self.denoiser.update_buffer(&self.queue);
+            self.encode_denoise_passes(&mut encoder);
+        }
+        self.encode_present_pass(&mut encoder, &view);
 
         self.queue.submit(std::iter::once(encoder.finish()));
         output.present();
@@ -560,9 +1235,66 @@ impl RenderState {
         std::result::Result::Ok(())
     }
 
+    /// Filters the frame into the denoiser output, one pass per iteration
+    fn encode_denoise_passes(&self, encoder: &mut wgpu::CommandEncoder) {
+        let steps = self.denoiser.steps(&self.render_frame);
+        for ((_, target), bind_group) in steps.into_iter().zip(&self.denoise_bind_groups) {
+            let mut denoise_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Denoise Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            denoise_pass.set_pipeline(&self.denoise_pipeline);
+            denoise_pass.set_vertex_buffer(0, self.mesh_direct.vertex_buffer.slice(..));
+            denoise_pass.set_index_buffer(
+                self.mesh_direct.index_buffer.slice(..),
+                wgpu::IndexFormat::Uint16,
+            );
+            denoise_pass.set_bind_group(0, bind_group, &[]);
+            denoise_pass.draw_indexed(0..self.mesh_direct.num_indices, 0, 0..1);
+        }
+    }
+
+    fn encode_present_pass(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        // Scale the frame to the window, everything outside of the
+        // viewport is left black
+        let mut present_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Present Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        let [x, y, width, height] = self
+            .display_mode
+            .present_viewport((self.config.width, self.config.height), self.resolution);
+        present_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+        present_pass.set_pipeline(&self.present_pipeline);
+        present_pass.set_vertex_buffer(0, self.mesh_direct.vertex_buffer.slice(..));
+        present_pass.set_index_buffer(
+            self.mesh_direct.index_buffer.slice(..),
+            wgpu::IndexFormat::Uint16,
+        );
+        present_pass.set_bind_group(0, &self.present_bind_group, &[]);
+        present_pass.draw_indexed(0..self.mesh_direct.num_indices, 0, 0..1);
+    }
+
     pub fn aspect_ratio(&self) -> f32 {
-        //self.config.width as f32 / self.config.height as f32
-        self.window.inner_size().width as f32 / self.window.inner_size().height as f32
+        // The camera projects to the rendering, only Stretch distorts it when presenting
+        self.resolution.0 as f32 / self.resolution.1 as f32
     }
 
     pub fn set_display_mode(
@@ -571,23 +1303,84 @@ impl RenderState {
         display_mode: DisplayMode,
     ) -> Result<()> {
         self.set_render_resolution(resolution);
-        // TODO: use display_mode to do funny things
+        // The scaling itself happens in the presentation pass
         self.display_mode = display_mode;
         Ok(())
     }
 
     fn set_render_resolution(&mut self, resolution: (u32, u32)) {
-        if resolution.0 > 0 && resolution.1 > 0 {
-            self.config.width = resolution.0;
-            self.config.height = resolution.1;
-            self.surface.configure(&self.device, &self.config);
+        if resolution.0 > 0 && resolution.1 > 0 && resolution != self.resolution {
+            self.resolution = resolution;
+            self.render_frame.change_dimension(&self.device, resolution);
+            self.aov_targets.change_dimension(&self.device, resolution);
+            self.denoiser.change_dimension(&self.device, resolution);
+            self.adaptive.change_dimension(&self.device, resolution);
+            if let Some((wavefront, _)) = &mut self.wavefront {
+                wavefront.change_dimension(&self.device, resolution);
+            }
             self.render_destination
-                .change_dimension(&self.device, (self.config.width, self.config.height));
+                .change_dimension(&self.device, resolution);
             self.render_source
-                .change_dimension(&self.device, (self.config.width, self.config.height));
+                .change_dimension(&self.device, resolution);
+            self.recreate_bind_group(BindGroupFrequency::Targets);
+            self.recreate_present_bind_group();
+            self.recreate_denoise_bind_groups();
         }
     }
 
+    pub fn update_presentation(&mut self, filter: PresentFilter, zoom: f32, pan: (f32, f32), aov: Aov) {
+        self.present_uniform.update_filter(filter);
+        self.present_uniform.update_aov(aov);
+        self.present_uniform.update_zoom(zoom);
+        self.present_uniform.update_pan(pan);
+    }
+
+    pub fn update_tone_mapping(&mut self, operator: ToneMapping, exposure: f32, white_point: f32) {
+        self.present_uniform.update_tone_mapping(operator, exposure, white_point);
+    }
+
+    pub fn update_adaptive_sampling(&mut self, enabled: bool, threshold: f32, min_samples: u32) {
+        self.adaptive.update(enabled, threshold, min_samples);
+    }
+
+    /// True when adaptive sampling is enabled and no pixel was traced in the
+    /// last frame. Waits for the last frame to finish.
+    pub fn converged(&self) -> bool {
+        if !self.adaptive.enabled() || self.uniform.get_iteration() == 0 {
+            return false;
+        }
+        match self.adaptive.active_pixels(&self.device) {
+            std::result::Result::Ok(active_pixels) => active_pixels == 0,
+            Err(err) => {
+                eprintln!("Could not read back the active pixels: {err}");
+                false
+            }
+        }
+    }
+
+    /// A tile size of 0 traces every sample in one submission
+    pub fn update_tiling(&mut self, tile_size: u32, tiles_per_submission: u32, show_progress: bool) {
+        self.tiles.update(tile_size, tiles_per_submission);
+        self.show_tile_progress = show_progress;
+    }
+
+    pub fn update_denoise(&mut self, enabled: bool, params: DenoiseParams) {
+        let iterations = self.denoiser.params().iterations;
+        self.denoiser.update(enabled, params);
+        self.present_uniform.update_denoised(enabled);
+        if self.denoiser.params().iterations != iterations {
+            self.recreate_denoise_bind_groups();
+        }
+    }
+
+    /// Writes the beauty image and all AOVs of the last frame to disk,
+    /// with the denoiser enabled a denoised image is filtered on the CPU
+    pub fn save_images(&self, prefix: &str) -> Result<Vec<std::path::PathBuf>> {
+        let denoise = self.denoiser.enabled().then_some(self.denoiser.params());
+        self.aov_targets
+            .save(&self.device, &self.queue, &self.render_frame, prefix, denoise)
+    }
+
     pub fn update_camera_constant(&mut self, constant: f32) {
         self.camera.constant = constant;
     }
@@ -1,6 +1,8 @@
 use std::{path::PathBuf, sync::Arc};
 
-use crate::camera::Camera;
+use cgmath::{Deg, Matrix4, Vector3};
+
+use crate::{animation::VertexAnimation, camera::Camera, data_structures::{bsp_tree::BspParams, vector::vec3f32}, mesh::Material, primitives::Shape};
 
 #[derive(Default, Debug, Copy, Clone)]
 pub enum VertexType {
@@ -9,11 +11,45 @@ pub enum VertexType {
     Combined,
 }
 
-#[derive(Default, Debug, Copy, Clone)]
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
 pub enum TraverseType {
     #[default]
     Bsp,
     Bvh,
+    /// BVH with spatial splits, which can reference a triangle from several leaves
+    Sbvh,
+    /// BVH traversed without a stack by following skip pointers
+    ThreadedBvh,
+    /// BVH collapsed to 4 children per node
+    Bvh4,
+    /// BVH collapsed to 8 children per node
+    Bvh8,
+    /// BVH4 with the boxes of the children quantized to 8 bits
+    QuantizedBvh,
+    /// Top-level BVH over `SceneDescriptor::instances` and the
+    /// model, with a bottom-level BVH for every model file
+    Instanced,
+}
+
+/// How the paths of a scene are traced
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub enum Backend {
+    /// One fragment shader invocation traces the whole path of a pixel
+    #[default]
+    Fragment,
+    /// Compute passes over queues of rays, one bounce at a time,
+    /// the shader has to work with res/shaders/wavefront.wgsl
+    Wavefront,
+}
+
+impl Backend {
+    /// Fragment shader of the render pass
+    pub fn fragment_entry_point(&self) -> &'static str {
+        match self {
+            Backend::Fragment => "fs_main",
+            Backend::Wavefront => "fs_wavefront",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -22,10 +58,19 @@ pub struct SceneDescriptor {
     pub shader: PathBuf,
     pub vertex_type: VertexType,
     pub model: Option<PathBuf>,
+    /// Models merged with `model` before the acceleration structure is built
+    pub objects: Vec<ObjectDescriptor>,
+    /// Placed models, only traced with `TraverseType::Instanced`
+    pub instances: Vec<InstanceDescriptor>,
+    /// Analytic primitives added to the mesh of the model and the objects
+    pub primitives: Vec<PrimitiveDescriptor>,
     pub background_hdri: Option<PathBuf>,
     pub camera: Camera,
     pub res: (u32, u32),
     pub traverse_type: TraverseType,
+    /// Build settings of the tree traced with `TraverseType::Bsp`
+    pub bsp: BspParams,
+    pub backend: Backend,
 }
 
 impl Default for SceneDescriptor {
@@ -36,13 +81,95 @@ impl Default for SceneDescriptor {
             vertex_type: Default::default(),
             background_hdri: None,
             model: Default::default(),
+            objects: Default::default(),
+            instances: Default::default(),
+            primitives: Default::default(),
             camera: Default::default(),
             res: (512, 512),
             traverse_type: Default::default(),
+            bsp: Default::default(),
+            backend: Default::default(),
         }
     }
 }
 
+impl SceneDescriptor {
+    /// Every model file the scene loads
+    pub fn model_paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.model
+            .iter()
+            .chain(self.objects.iter().map(|object| &object.model))
+            .chain(self.instances.iter().map(|instance| &instance.model))
+    }
+
+    /// Whether the scene has a mesh and a traversal structure
+    pub fn has_model(&self) -> bool {
+        self.model.is_some()
+            || !self.objects.is_empty()
+            || (self.traverse_type == TraverseType::Instanced && !self.instances.is_empty())
+    }
+}
+
+/// Where a model is placed, it is scaled, rotated and then translated
+#[derive(Debug, Copy, Clone)]
+pub struct ObjectTransform {
+    pub translation: Vector3<f32>,
+    /// Angles in degrees around x, y and z, applied in that order
+    pub rotation: Vector3<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for ObjectTransform {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Vector3::new(0.0, 0.0, 0.0),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl ObjectTransform {
+    pub fn object_to_world(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from_angle_z(Deg(self.rotation.z))
+            * Matrix4::from_angle_y(Deg(self.rotation.y))
+            * Matrix4::from_angle_x(Deg(self.rotation.x))
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+/// A model merged into the mesh of the scene, like editing it into the OBJ file
+#[derive(Debug, Clone)]
+pub struct ObjectDescriptor {
+    pub model: PathBuf,
+    pub transform: ObjectTransform,
+    /// Replaces every material of the model
+    pub material: Option<Material>,
+    /// Deforms the object in world space every frame, only with `TraverseType::Bvh`
+    pub animation: Option<VertexAnimation>,
+}
+
+/// A model placed in the scene, every model file is loaded once
+/// and shared by all of its instances.
+/// Lights are sampled in object space, so emissive triangles belong
+/// in the model or the objects of the scene.
+#[derive(Debug, Clone)]
+pub struct InstanceDescriptor {
+    pub model: PathBuf,
+    pub transform: ObjectTransform,
+}
+
+/// An analytic primitive of the scene with a material of its own. The shader has to
+/// intersect them, which takes a HitRecord with a material and an object like in
+/// path_tracer.wgsl, and to call intersect_unbounded for the planes.
+/// Emissive primitives are not sampled as lights.
+#[derive(Debug, Clone)]
+pub struct PrimitiveDescriptor {
+    pub shape: Shape,
+    pub material: Material,
+}
+
 pub fn get_scenes() -> Arc<[SceneDescriptor]> {
     let basic_scene_camera = Camera {
         eye: (2.0, 1.5, 2.0).into(),
@@ -92,6 +219,18 @@ pub fn get_scenes() -> Arc<[SceneDescriptor]> {
     let teapot_path = PathBuf::from("res/models/teapot.obj");
     let dragon_path = PathBuf::from("res/models/dragon.obj");
 
+    // a 3x3 grid of teapots on the floor of the Cornell box, each turned a bit further
+    let teapot_instances = (0..9)
+        .map(|i| InstanceDescriptor {
+            model: teapot_path.clone(),
+            transform: ObjectTransform {
+                translation: Vector3::new(120.0 + 158.0 * (i % 3) as f32, 0.0, 130.0 + 150.0 * (i / 3) as f32),
+                rotation: Vector3::new(0.0, 40.0 * i as f32, 0.0),
+                scale: Vector3::new(22.0, 22.0, 22.0),
+            },
+        })
+        .collect::<Vec<_>>();
+
     let campus_background_path = PathBuf::from("res/textures/luxo_pxr_campus.jpg");
     let campus_background_hdr_path = PathBuf::from("res/textures/luxo_pxr_campus.hdr.png");
 
@@ -484,5 +623,212 @@ pub fn get_scenes() -> Arc<[SceneDescriptor]> {
             traverse_type: TraverseType::Bsp,
             ..Default::default()
         },
+        SceneDescriptor {
+            name: String::from("Path Tracer: Cornell Box"),
+            shader: PathBuf::from("res/shaders/path_tracer.wgsl"),
+            model: Some(cornell_box_with_blocks_path.clone()),
+            camera: cornell_box_camera.clone(),
+            res: (512, 512),
+            vertex_type: VertexType::Combined,
+            traverse_type: TraverseType::Bvh,
+            backend: Backend::Fragment,
+            ..Default::default()
+        },
+        SceneDescriptor {
+            name: String::from("Path Tracer: Cornell Box Wavefront"),
+            shader: PathBuf::from("res/shaders/path_tracer.wgsl"),
+            model: Some(cornell_box_with_blocks_path.clone()),
+            camera: cornell_box_camera.clone(),
+            res: (512, 512),
+            vertex_type: VertexType::Combined,
+            traverse_type: TraverseType::Bvh,
+            backend: Backend::Wavefront,
+            ..Default::default()
+        },
+        SceneDescriptor {
+            name: String::from("Path Tracer: Cornell Box Stackless"),
+            shader: PathBuf::from("res/shaders/path_tracer.wgsl"),
+            model: Some(cornell_box_with_blocks_path.clone()),
+            camera: cornell_box_camera.clone(),
+            res: (512, 512),
+            vertex_type: VertexType::Combined,
+            traverse_type: TraverseType::ThreadedBvh,
+            backend: Backend::Fragment,
+            ..Default::default()
+        },
+        SceneDescriptor {
+            name: String::from("Path Tracer: Cornell Box BVH4"),
+            shader: PathBuf::from("res/shaders/path_tracer.wgsl"),
+            model: Some(cornell_box_with_blocks_path.clone()),
+            camera: cornell_box_camera.clone(),
+            res: (512, 512),
+            vertex_type: VertexType::Combined,
+            traverse_type: TraverseType::Bvh4,
+            backend: Backend::Fragment,
+            ..Default::default()
+        },
+        SceneDescriptor {
+            name: String::from("Path Tracer: Cornell Box Quantized"),
+            shader: PathBuf::from("res/shaders/path_tracer.wgsl"),
+            model: Some(cornell_box_with_blocks_path.clone()),
+            camera: cornell_box_camera.clone(),
+            res: (512, 512),
+            vertex_type: VertexType::Combined,
+            traverse_type: TraverseType::QuantizedBvh,
+            backend: Backend::Fragment,
+            ..Default::default()
+        },
+        SceneDescriptor {
+            name: String::from("Path Tracer: Cornell Box Teapot"),
+            shader: PathBuf::from("res/shaders/path_tracer.wgsl"),
+            model: Some(cornell_box_path.clone()),
+            objects: vec![ObjectDescriptor {
+                model: teapot_path.clone(),
+                transform: ObjectTransform {
+                    translation: Vector3::new(278.0, 0.0, 280.0),
+                    rotation: Vector3::new(0.0, -30.0, 0.0),
+                    scale: Vector3::new(55.0, 55.0, 55.0),
+                },
+                material: Some(Material::diffuse(vec3f32(0.2, 0.35, 0.7))),
+                animation: None,
+            }],
+            camera: cornell_box_camera.clone(),
+            res: (512, 512),
+            vertex_type: VertexType::Combined,
+            traverse_type: TraverseType::Bvh,
+            backend: Backend::Fragment,
+            ..Default::default()
+        },
+        SceneDescriptor {
+            name: String::from("Path Tracer: Cornell Box Teapot SBVH"),
+            shader: PathBuf::from("res/shaders/path_tracer.wgsl"),
+            model: Some(cornell_box_path.clone()),
+            objects: vec![ObjectDescriptor {
+                model: teapot_path.clone(),
+                transform: ObjectTransform {
+                    translation: Vector3::new(278.0, 0.0, 280.0),
+                    rotation: Vector3::new(0.0, -30.0, 0.0),
+                    scale: Vector3::new(55.0, 55.0, 55.0),
+                },
+                material: Some(Material::diffuse(vec3f32(0.2, 0.35, 0.7))),
+                animation: None,
+            }],
+            camera: cornell_box_camera.clone(),
+            res: (512, 512),
+            vertex_type: VertexType::Combined,
+            traverse_type: TraverseType::Sbvh,
+            backend: Backend::Fragment,
+            ..Default::default()
+        },
+        SceneDescriptor {
+            name: String::from("Path Tracer: Cornell Box Twisting Teapot"),
+            shader: PathBuf::from("res/shaders/path_tracer.wgsl"),
+            model: Some(cornell_box_path.clone()),
+            objects: vec![ObjectDescriptor {
+                model: teapot_path.clone(),
+                transform: ObjectTransform {
+                    translation: Vector3::new(278.0, 0.0, 280.0),
+                    rotation: Vector3::new(0.0, -30.0, 0.0),
+                    scale: Vector3::new(55.0, 55.0, 55.0),
+                },
+                material: Some(Material::diffuse(vec3f32(0.2, 0.35, 0.7))),
+                animation: Some(VertexAnimation::Twist { max_angle: 1.0, period: 4.0 }),
+            }],
+            camera: cornell_box_camera.clone(),
+            res: (512, 512),
+            vertex_type: VertexType::Combined,
+            traverse_type: TraverseType::Bvh,
+            backend: Backend::Fragment,
+            ..Default::default()
+        },
+        SceneDescriptor {
+            name: String::from("Path Tracer: Cornell Box Balls"),
+            shader: PathBuf::from("res/shaders/path_tracer.wgsl"),
+            model: Some(cornell_box_path.clone()),
+            // the balls of W8 E1
+            primitives: vec![
+                PrimitiveDescriptor {
+                    shape: Shape::Sphere { center: vec3f32(420.0, 90.0, 370.0), radius: 90.0 },
+                    material: Material::diffuse(vec3f32(0.8, 0.8, 0.8)),
+                },
+                PrimitiveDescriptor {
+                    shape: Shape::Sphere { center: vec3f32(130.0, 90.0, 250.0), radius: 90.0 },
+                    material: Material::diffuse(vec3f32(0.7, 0.5, 0.2)),
+                },
+            ],
+            camera: cornell_box_camera.clone(),
+            res: (512, 512),
+            vertex_type: VertexType::Combined,
+            traverse_type: TraverseType::Bvh,
+            backend: Backend::Fragment,
+            ..Default::default()
+        },
+        SceneDescriptor {
+            name: String::from("Path Tracer: Cornell Box Teapots"),
+            shader: PathBuf::from("res/shaders/path_tracer.wgsl"),
+            model: Some(cornell_box_path.clone()),
+            instances: teapot_instances,
+            camera: cornell_box_camera.clone(),
+            res: (512, 512),
+            vertex_type: VertexType::Combined,
+            traverse_type: TraverseType::Instanced,
+            backend: Backend::Fragment,
+            ..Default::default()
+        },
+        SceneDescriptor {
+            name: String::from("Path Tracer: Dragon"),
+            shader: PathBuf::from("res/shaders/path_tracer.wgsl"),
+            model: Some(dragon_path.clone()),
+            camera: dragon_camera.clone(),
+            res: (800, 450),
+            vertex_type: VertexType::Combined,
+            traverse_type: TraverseType::Bvh,
+            backend: Backend::Fragment,
+            ..Default::default()
+        },
+        SceneDescriptor {
+            name: String::from("Path Tracer: Dragon Wavefront"),
+            shader: PathBuf::from("res/shaders/path_tracer.wgsl"),
+            model: Some(dragon_path.clone()),
+            camera: dragon_camera.clone(),
+            res: (800, 450),
+            vertex_type: VertexType::Combined,
+            traverse_type: TraverseType::Bvh,
+            backend: Backend::Wavefront,
+            ..Default::default()
+        },
+        SceneDescriptor {
+            name: String::from("Path Tracer: Dragon Stackless"),
+            shader: PathBuf::from("res/shaders/path_tracer.wgsl"),
+            model: Some(dragon_path.clone()),
+            camera: dragon_camera.clone(),
+            res: (800, 450),
+            vertex_type: VertexType::Combined,
+            traverse_type: TraverseType::ThreadedBvh,
+            backend: Backend::Fragment,
+            ..Default::default()
+        },
+        SceneDescriptor {
+            name: String::from("Path Tracer: Dragon BVH8"),
+            shader: PathBuf::from("res/shaders/path_tracer.wgsl"),
+            model: Some(dragon_path.clone()),
+            camera: dragon_camera.clone(),
+            res: (800, 450),
+            vertex_type: VertexType::Combined,
+            traverse_type: TraverseType::Bvh8,
+            backend: Backend::Fragment,
+            ..Default::default()
+        },
+        SceneDescriptor {
+            name: String::from("Path Tracer: Dragon Quantized"),
+            shader: PathBuf::from("res/shaders/path_tracer.wgsl"),
+            model: Some(dragon_path.clone()),
+            camera: dragon_camera.clone(),
+            res: (800, 450),
+            vertex_type: VertexType::Combined,
+            traverse_type: TraverseType::QuantizedBvh,
+            backend: Backend::Fragment,
+            ..Default::default()
+        },
     ])
 }
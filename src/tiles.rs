@@ -0,0 +1,190 @@
+/// Tiled rendering: every sample of the frame is split into tiles that are
+/// traced by separate submissions, so a single submission never has to trace
+/// the whole frame. Drivers reset the GPU when one takes too long (watchdog),
+/// which happens at high resolutions with deep paths.
+/// A sample only counts once all of its tiles have been traced, so the
+/// progressive accumulation sees every pixel exactly once per iteration.
+
+pub const DEFAULT_TILE_SIZE: u32 = 256;
+pub const DEFAULT_TILES_PER_SUBMISSION: u32 = 4;
+
+/// A rectangle of the frame in pixels
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    pub fn pixels(&self) -> u32 {
+        self.width * self.height
+    }
+}
+
+/// Which tiles of the current sample are traced by the next submission
+#[derive(Clone, Debug)]
+pub struct TileSchedule {
+    /// Edge length of the tiles, 0 traces the whole frame at once
+    tile_size: u32,
+    tiles_per_submission: u32,
+    /// First tile of the next submission
+    next: u32,
+    /// Iteration the tiles traced so far belong to
+    iteration: u32,
+}
+
+impl Default for TileSchedule {
+    fn default() -> Self {
+        Self::new(0, DEFAULT_TILES_PER_SUBMISSION)
+    }
+}
+
+impl TileSchedule {
+    pub fn new(tile_size: u32, tiles_per_submission: u32) -> Self {
+        Self {
+            tile_size,
+            tiles_per_submission: tiles_per_submission.max(1),
+            next: 0,
+            iteration: 0,
+        }
+    }
+
+    /// Changes the tiling, the current sample starts over with the first tile as the
+    /// tiles traced so far do not match the new ones, so they are traced again
+    pub fn update(&mut self, tile_size: u32, tiles_per_submission: u32) {
+        *self = Self::new(tile_size, tiles_per_submission);
+    }
+
+    pub fn tile_size(&self) -> u32 {
+        self.tile_size
+    }
+
+    /// Tiles of the current sample traced so far
+    pub fn tiles_done(&self) -> u32 {
+        self.next
+    }
+
+    /// Number of tiles horizontally and vertically
+    pub fn grid(&self, resolution: (u32, u32)) -> (u32, u32) {
+        match self.tile_size {
+            0 => (1, 1),
+            size => (resolution.0.div_ceil(size), resolution.1.div_ceil(size)),
+        }
+    }
+
+    pub fn tile_count(&self, resolution: (u32, u32)) -> u32 {
+        let (columns, rows) = self.grid(resolution);
+        columns * rows
+    }
+
+    /// Tiles in row major order, the last column and row are cut off at the frame
+    pub fn tile(&self, resolution: (u32, u32), index: u32) -> Tile {
+        let size = match self.tile_size {
+            0 => resolution.0.max(resolution.1),
+            size => size,
+        };
+        let (columns, _) = self.grid(resolution);
+        let x = (index % columns) * size;
+        let y = (index / columns) * size;
+        Tile {
+            x,
+            y,
+            width: size.min(resolution.0 - x),
+            height: size.min(resolution.1 - y),
+        }
+    }
+
+    /// The tiles of the next submission and whether the sample is complete
+    /// with them. Changing the iteration in between, e.g. by resetting the
+    /// accumulation, starts over with the first tile.
+    pub fn next_tiles(&mut self, resolution: (u32, u32), iteration: u32) -> (Vec<Tile>, bool) {
+        let count = self.tile_count(resolution);
+        if iteration != self.iteration || self.next >= count {
+            self.next = 0;
+            self.iteration = iteration;
+        }
+        let end = (self.next + self.tiles_per_submission).min(count);
+        let tiles = (self.next..end).map(|index| self.tile(resolution, index)).collect();
+        self.next = end;
+        let done = end == count;
+        (tiles, done)
+    }
+
+    /// True if the next submission starts a new sample
+    pub fn starts_sample(&self, resolution: (u32, u32), iteration: u32) -> bool {
+        iteration != self.iteration || self.next == 0 || self.next >= self.tile_count(resolution)
+    }
+}
+
+#[cfg(test)]
+mod tiles_test {
+    use super::*;
+
+    #[test]
+    fn tiles_cover_the_frame_once() {
+        let resolution = (300, 130);
+        let schedule = TileSchedule::new(64, 1);
+        assert_eq!(schedule.grid(resolution), (5, 3));
+
+        let mut covered = vec![0; (resolution.0 * resolution.1) as usize];
+        for index in 0..schedule.tile_count(resolution) {
+            let tile = schedule.tile(resolution, index);
+            for y in tile.y..tile.y + tile.height {
+                for x in tile.x..tile.x + tile.width {
+                    covered[(y * resolution.0 + x) as usize] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|&count| count == 1));
+        assert_eq!(schedule.tile(resolution, 14), Tile { x: 256, y: 128, width: 44, height: 2 });
+    }
+
+    #[test]
+    fn samples_complete_after_all_tiles() {
+        let resolution = (100, 100);
+        let mut schedule = TileSchedule::new(50, 3);
+
+        assert!(schedule.starts_sample(resolution, 0));
+        let (tiles, done) = schedule.next_tiles(resolution, 0);
+        assert_eq!((tiles.len(), done), (3, false));
+        assert!(!schedule.starts_sample(resolution, 0));
+        let (tiles, done) = schedule.next_tiles(resolution, 0);
+        assert_eq!((tiles.len(), done), (1, true));
+        assert_eq!(tiles[0], Tile { x: 50, y: 50, width: 50, height: 50 });
+
+        // a new sample starts with the first tile
+        assert!(schedule.starts_sample(resolution, 1));
+        let (tiles, _) = schedule.next_tiles(resolution, 1);
+        assert_eq!(tiles[0], Tile { x: 0, y: 0, width: 50, height: 50 });
+        // and so does a reset accumulation
+        let (tiles, done) = schedule.next_tiles(resolution, 0);
+        assert_eq!((tiles[0].x, tiles[0].y, done), (0, 0, false));
+    }
+
+    #[test]
+    fn changing_the_tiling_starts_the_sample_over() {
+        let resolution = (100, 100);
+        let mut schedule = TileSchedule::new(50, 3);
+        schedule.next_tiles(resolution, 4);
+        assert_eq!(schedule.tiles_done(), 3);
+
+        schedule.update(25, 2);
+        assert_eq!(schedule.tiles_done(), 0);
+        assert!(schedule.starts_sample(resolution, 4));
+        let (tiles, done) = schedule.next_tiles(resolution, 4);
+        assert_eq!(tiles, vec![schedule.tile(resolution, 0), schedule.tile(resolution, 1)]);
+        assert_eq!((tiles[0].width, done), (25, false));
+    }
+
+    #[test]
+    fn without_tiles_every_submission_is_a_sample() {
+        let resolution = (640, 480);
+        let mut schedule = TileSchedule::default();
+        let (tiles, done) = schedule.next_tiles(resolution, 0);
+        assert_eq!(tiles, vec![Tile { x: 0, y: 0, width: 640, height: 480 }]);
+        assert!(done);
+        assert!(schedule.starts_sample(resolution, 0));
+    }
+}